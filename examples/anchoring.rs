@@ -16,12 +16,18 @@ extern crate exonum;
 extern crate exonum_configuration;
 extern crate exonum_btc_anchoring;
 
+use std::{env, process};
+
 use exonum::helpers::fabric::NodeBuilder;
 use exonum::helpers;
 use exonum_configuration::ConfigurationServiceFactory;
-use exonum_btc_anchoring::AnchoringServiceFactory;
+use exonum_btc_anchoring::{AnchoringServiceFactory, VerifyAnchoringProof};
 
 fn main() {
+    if let Some(is_valid) = VerifyAnchoringProof::run(env::args_os()) {
+        process::exit(if is_valid { 0 } else { 1 });
+    }
+
     exonum::crypto::init();
     helpers::init_logger().unwrap();
     let node = NodeBuilder::new()
@@ -18,6 +18,10 @@ extern crate tempdir;
 
 use std::thread;
 use std::env;
+#[cfg(feature = "simulated_bitcoin")]
+use std::mem;
+#[cfg(feature = "simulated_bitcoin")]
+use std::time::Duration;
 
 use tempdir::TempDir;
 
@@ -27,20 +31,30 @@ use exonum::helpers::{generate_testnet_config, init_logger};
 
 use exonum_btc_anchoring::{AnchoringRpcConfig, AnchoringService, BitcoinNetwork,
                            gen_anchoring_testnet_config, RpcClient};
+#[cfg(feature = "simulated_bitcoin")]
+use exonum_btc_anchoring::{SimulatedBitcoin, SimulatedBitcoind};
+
+/// Interval between the blocks mined by the simulated bitcoind.
+#[cfg(feature = "simulated_bitcoin")]
+const SIMULATED_BLOCK_INTERVAL: u64 = 10;
 
 fn main() {
     // Init crypto engine and pretty logger.
     exonum::crypto::init();
     init_logger().unwrap();
 
-    // Get rpc config from env variables
-    let rpc_config = AnchoringRpcConfig {
-        host: env::var("ANCHORING_RELAY_HOST")
-            .expect("Env variable ANCHORING_RELAY_HOST needs to be setted")
-            .parse()
-            .unwrap(),
-        username: env::var("ANCHORING_USER").ok(),
-        password: env::var("ANCHORING_PASSWORD").ok(),
+    // Get rpc config from env variables or start the simulated bitcoind
+    let rpc_config = match env::var("ANCHORING_RELAY_HOST") {
+        Ok(host) => AnchoringRpcConfig {
+            host,
+            username: env::var("ANCHORING_USER").ok(),
+            password: env::var("ANCHORING_PASSWORD").ok(),
+            backend: Default::default(),
+            utxo_discovery: Default::default(),
+            credentials: Default::default(),
+            tls: None,
+        },
+        Err(_) => simulated_rpc_config(),
     };
 
     // Blockchain params
@@ -87,3 +101,25 @@ fn main() {
         node_thread.join().unwrap();
     }
 }
+
+/// Starts the simulated bitcoind which mines the blocks periodically.
+#[cfg(feature = "simulated_bitcoin")]
+fn simulated_rpc_config() -> AnchoringRpcConfig {
+    let bitcoind = SimulatedBitcoind::start(SimulatedBitcoin::new())
+        .expect("Unable to start the simulated bitcoind");
+    let bitcoin = bitcoind.bitcoin().clone();
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(SIMULATED_BLOCK_INTERVAL));
+        bitcoin.mine_blocks(1);
+    });
+    let config = bitcoind.config();
+    // The server must serve the nodes until the process exits. Dropping the handle would not
+    // stop it anyway, since `Listening::close` of hyper 0.10 leaves the server running.
+    mem::forget(bitcoind);
+    config
+}
+
+#[cfg(not(feature = "simulated_bitcoin"))]
+fn simulated_rpc_config() -> AnchoringRpcConfig {
+    panic!("Env variable ANCHORING_RELAY_HOST needs to be setted")
+}
@@ -21,12 +21,16 @@ use exonum::api::ApiError;
 #[derive(Debug)]
 pub enum Error {
     UnknownValidatorId(u32),
+    MissingBlock(u64),
+    UnknownAnchoringTxKind(u8),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::UnknownValidatorId(id) => write!(f, "Unknown validator id={}", id),
+            Error::MissingBlock(height) => write!(f, "Missing block at height={}", height),
+            Error::UnknownAnchoringTxKind(id) => write!(f, "Unknown anchoring tx kind id={}", id),
         }
     }
 }
@@ -35,6 +39,8 @@ impl error::Error for Error {
     fn description(&self) -> &str {
         match *self {
             Error::UnknownValidatorId(_) => "UnknownValidatorId",
+            Error::MissingBlock(_) => "MissingBlock",
+            Error::UnknownAnchoringTxKind(_) => "UnknownAnchoringTxKind",
         }
     }
 }
@@ -45,6 +51,14 @@ impl Into<ApiError> for Error {
             Error::UnknownValidatorId(id) => {
                 ApiError::Storage(StorageError::new(format!("Unknown validator id={}", id)))
             }
+            Error::MissingBlock(height) => {
+                let msg = format!("Missing block at height={}", height);
+                ApiError::Storage(StorageError::new(msg))
+            }
+            Error::UnknownAnchoringTxKind(id) => {
+                let msg = format!("Unknown anchoring tx kind id={}", id);
+                ApiError::Storage(StorageError::new(msg))
+            }
         }
     }
 }
@@ -16,17 +16,19 @@
 
 use router::Router;
 use iron::prelude::*;
-use bitcoin::util::base58::ToBase58;
 
-use exonum::blockchain::Blockchain;
+use exonum::blockchain::{Block, Blockchain, Schema};
 use exonum::crypto::Hash;
 use exonum::api::{Api, ApiError};
+use exonum::encoding::serialize::FromHex;
+use exonum::helpers::Height;
+use exonum::storage::{ListProof, StorageValue};
 
 use details::btc;
 use details::btc::TxId;
 use details::btc::transactions::{AnchoringTx, BitcoinTx, TxKind};
 use blockchain::schema::AnchoringSchema;
-use blockchain::dto::LectContent;
+use blockchain::dto::{AnchoringTxKind, LectContent, TxOutProof};
 
 pub use details::btc::payload::Payload;
 
@@ -57,6 +59,79 @@ pub struct LectInfo {
     pub content: AnchoringInfo,
 }
 
+/// Exonum block anchored by the bitcoin transaction and the configuration it belongs to.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct AnchoringTxInfo {
+    /// `Txid` of anchoring transaction.
+    pub txid: TxId,
+    /// Position of the transaction in the anchoring chain.
+    pub kind: AnchoringTxKind,
+    /// Anchoring transaction payload.
+    pub payload: Payload,
+    /// Address of the anchoring output.
+    pub address: btc::Address,
+    /// Amount of the anchoring output in satoshis.
+    pub amount: u64,
+    /// Hash of the configuration with the address of the anchoring output, which is
+    /// the following configuration for the transition transaction.
+    pub config_hash: Hash,
+    /// Height since which the configuration is actual.
+    pub config_actual_from: Height,
+}
+
+/// Proof of the block inclusion into the blocks root of the anchoring transaction.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct AnchoredBlockProof {
+    /// Anchoring transaction which payload commits to the block.
+    pub tx: AnchoringTx,
+    /// Height of the first block committed by the blocks root.
+    pub first_block_height: Height,
+    /// Number of blocks committed by the blocks root.
+    pub blocks_count: u64,
+    /// Proof for the block hash against the blocks root from the transaction payload.
+    pub proof: ListProof<Hash>,
+}
+
+/// Link from the requested block to the anchored one.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BlockLink {
+    /// Proof for the requested block hash against the blocks root from the anchoring
+    /// transaction payload.
+    BlocksRoot {
+        /// Height of the first block committed by the blocks root.
+        first_block_height: Height,
+        /// Number of blocks committed by the blocks root.
+        blocks_count: u64,
+        /// Proof for the block hash.
+        proof: ListProof<Hash>,
+    },
+    /// Headers of the blocks following the requested one up to the anchored block.
+    /// Each header refers to the previous block by its `prev_hash`.
+    Headers {
+        /// Block headers in the ascending order of heights.
+        headers: Vec<Block>,
+    },
+}
+
+/// Self-contained proof that the block is anchored to the bitcoin blockchain.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct AnchoringProof {
+    /// Height of the requested block.
+    pub block_height: Height,
+    /// Hash of the requested block.
+    pub block_hash: Hash,
+    /// Payload of the anchoring transaction with the anchored block hash.
+    pub payload: Payload,
+    /// Link from the requested block to the anchored one.
+    pub link: BlockLink,
+    /// Anchoring transaction.
+    pub tx: AnchoringTx,
+    /// Proof of the anchoring transaction inclusion into the bitcoin block
+    /// if it has been agreed by the validators along with the transaction finalization.
+    pub tx_out_proof: Option<TxOutProof>,
+}
+
 impl From<BitcoinTx> for AnchoringInfo {
     fn from(tx: BitcoinTx) -> AnchoringInfo {
         match TxKind::from(tx) {
@@ -134,22 +209,141 @@ impl PublicApi {
         Ok(following_addr)
     }
 
-    /// Returns hex of the anchoring transaction for the nearest block with a height greater
-    /// or equal than the given.
+    /// Returns hex of the finalized anchoring transaction for the nearest block with a height
+    /// greater or equal than the given.
     ///
     /// `GET /{api_prefix}/v1/nearest_lect/:height`
     pub fn nearest_lect(&self, height: u64) -> Result<Option<AnchoringTx>, ApiError> {
         let snapshot = self.blockchain.snapshot();
         let anchoring_schema = AnchoringSchema::new(&snapshot);
-        let tx_chain = anchoring_schema.anchoring_tx_chain();
+        Ok(anchoring_schema.nearest_anchoring_tx(height))
+    }
 
-        // TODO use binary find.
-        for (tx_height, tx) in &tx_chain {
-            if tx_height >= height {
-                return Ok(Some(tx));
-            }
+    /// Returns the Exonum block anchored by the known anchoring transaction with the given
+    /// `txid`.
+    ///
+    /// `GET /{api_prefix}/v1/anchoring_tx/:txid`
+    pub fn anchoring_tx(&self, txid: &TxId) -> Result<Option<AnchoringTxInfo>, ApiError> {
+        let snapshot = self.blockchain.snapshot();
+        let anchoring_schema = AnchoringSchema::new(&snapshot);
+        let record = match anchoring_schema.anchoring_tx_records().get(txid) {
+            Some(record) => record,
+            None => return Ok(None),
+        };
+        let tx = match anchoring_schema.known_txs().get(txid).map(TxKind::from) {
+            Some(TxKind::Anchoring(tx)) => tx,
+            _ => return Ok(None),
+        };
+
+        let kind = match record.kind() {
+            Some(kind) => kind,
+            None => return Err(error::Error::UnknownAnchoringTxKind(record.kind_id()).into()),
+        };
+        let height = Height(record.block_height());
+        let anchoring_cfg = anchoring_schema.anchoring_config_by_height(height);
+        let address = tx.output_address(anchoring_cfg.network);
+        // The transition transaction belongs to the following configuration.
+        let stored_cfg = match anchoring_schema.configuration_by_address(&address, height) {
+            Some(stored_cfg) => stored_cfg,
+            None => Schema::new(&snapshot).configuration_by_height(height),
+        };
+        Ok(Some(AnchoringTxInfo {
+            txid: *txid,
+            kind,
+            payload: tx.payload(),
+            address,
+            amount: tx.amount(),
+            config_hash: stored_cfg.hash(),
+            config_actual_from: stored_cfg.actual_from,
+        }))
+    }
+
+    /// Returns the proof of the block inclusion into the blocks root of the nearest
+    /// anchoring transaction, if it commits to the block with the given height.
+    ///
+    /// `GET /{api_prefix}/v1/block_proof/:height`
+    pub fn anchored_block_proof(
+        &self,
+        height: u64,
+    ) -> Result<Option<AnchoredBlockProof>, ApiError> {
+        let tx = match self.nearest_lect(height)? {
+            Some(tx) => tx,
+            None => return Ok(None),
+        };
+
+        let snapshot = self.blockchain.snapshot();
+        let anchoring_schema = AnchoringSchema::new(&snapshot);
+        let payload = tx.payload();
+        if payload.blocks_root.is_none() {
+            return Ok(None);
         }
-        Ok(None)
+        let first_block_height = match anchoring_schema.anchored_blocks_start(&tx) {
+            Some(start) => start,
+            None => return Ok(None),
+        };
+        if height < first_block_height.0 || height > payload.block_height.0 {
+            return Ok(None);
+        }
+        Ok(Some(AnchoredBlockProof {
+            proof: anchoring_schema.blocks_root_proof(
+                first_block_height,
+                payload.block_height,
+                Height(height),
+            ),
+            blocks_count: payload.block_height.0 - first_block_height.0 + 1,
+            first_block_height,
+            tx,
+        }))
+    }
+
+    /// Returns the proof that the block with the given height is anchored by the nearest
+    /// anchoring transaction.
+    ///
+    /// `GET /{api_prefix}/v1/anchoring_proof/:height`
+    pub fn anchoring_proof(&self, height: u64) -> Result<Option<AnchoringProof>, ApiError> {
+        let tx = match self.nearest_lect(height)? {
+            Some(tx) => tx,
+            None => return Ok(None),
+        };
+        let payload = tx.payload();
+
+        let snapshot = self.blockchain.snapshot();
+        let schema = Schema::new(&snapshot);
+        let block_hashes = schema.block_hashes_by_height();
+        let block_hash = match block_hashes.get(height) {
+            Some(block_hash) => block_hash,
+            None => return Ok(None),
+        };
+
+        let link = match self.anchored_block_proof(height)? {
+            Some(proof) => BlockLink::BlocksRoot {
+                first_block_height: proof.first_block_height,
+                blocks_count: proof.blocks_count,
+                proof: proof.proof,
+            },
+            None => {
+                let blocks = schema.blocks();
+                let headers = (height + 1..payload.block_height.0 + 1)
+                    .map(|height| {
+                        block_hashes
+                            .get(height)
+                            .and_then(|hash| blocks.get(&hash))
+                            .ok_or_else(|| error::Error::MissingBlock(height).into())
+                    })
+                    .collect::<Result<Vec<_>, ApiError>>()?;
+                BlockLink::Headers { headers }
+            }
+        };
+
+        let tx_out_proof = AnchoringSchema::new(&snapshot).tx_out_proofs().get(&tx.id());
+        Ok(Some(AnchoringProof {
+            block_height: Height(height),
+            block_hash,
+            payload,
+            link,
+            tx,
+            tx_out_proof,
+        }))
     }
 }
 
@@ -185,13 +379,13 @@ impl Api for PublicApi {
 
         let _self = self.clone();
         let actual_address = move |_: &mut Request| -> IronResult<Response> {
-            let addr = _self.actual_address()?.to_base58check();
+            let addr = _self.actual_address()?.to_string();
             _self.ok_response(&json!(addr))
         };
 
         let _self = self.clone();
         let following_address = move |_: &mut Request| -> IronResult<Response> {
-            let addr = _self.following_address()?.map(|addr| addr.to_base58check());
+            let addr = _self.following_address()?.map(|addr| addr.to_string());
             _self.ok_response(&json!(addr))
         };
 
@@ -217,6 +411,72 @@ impl Api for PublicApi {
             }
         };
 
+        let _self = self.clone();
+        let anchored_block_proof = move |req: &mut Request| -> IronResult<Response> {
+            let map = req.extensions.get::<Router>().unwrap();
+            match map.find("height") {
+                Some(height_str) => {
+                    let height: u64 = height_str.parse().map_err(|e| {
+                        let msg = format!(
+                            "An error during parsing of the block height occurred: {}",
+                            e
+                        );
+                        ApiError::IncorrectRequest(msg.into())
+                    })?;
+                    let proof = _self.anchored_block_proof(height)?;
+                    _self.ok_response(&json!(proof))
+                }
+                None => {
+                    let msg = "The block height is not specified.";
+                    Err(ApiError::IncorrectRequest(msg.into()))?
+                }
+            }
+        };
+
+        let _self = self.clone();
+        let anchoring_proof = move |req: &mut Request| -> IronResult<Response> {
+            let map = req.extensions.get::<Router>().unwrap();
+            match map.find("height") {
+                Some(height_str) => {
+                    let height: u64 = height_str.parse().map_err(|e| {
+                        let msg = format!(
+                            "An error during parsing of the block height occurred: {}",
+                            e
+                        );
+                        ApiError::IncorrectRequest(msg.into())
+                    })?;
+                    let proof = _self.anchoring_proof(height)?;
+                    _self.ok_response(&json!(proof))
+                }
+                None => {
+                    let msg = "The block height is not specified.";
+                    Err(ApiError::IncorrectRequest(msg.into()))?
+                }
+            }
+        };
+
+        let _self = self.clone();
+        let anchoring_tx = move |req: &mut Request| -> IronResult<Response> {
+            let map = req.extensions.get::<Router>().unwrap();
+            match map.find("txid") {
+                Some(txid_str) => {
+                    let txid = TxId::from_hex(txid_str).map_err(|e| {
+                        let msg = format!(
+                            "An error during parsing of the transaction id occurred: {}",
+                            e
+                        );
+                        ApiError::IncorrectRequest(msg.into())
+                    })?;
+                    let info = _self.anchoring_tx(&txid)?;
+                    _self.ok_response(&json!(info))
+                }
+                None => {
+                    let msg = "The transaction id is not specified.";
+                    Err(ApiError::IncorrectRequest(msg.into()))?
+                }
+            }
+        };
+
         router.get("/v1/address/actual", actual_address, "actual_address");
         router.get(
             "/v1/address/following",
@@ -230,5 +490,16 @@ impl Api for PublicApi {
             "current_lect_of_validator",
         );
         router.get("/v1/nearest_lect/:height", nearest_lect, "nearest_lect");
+        router.get(
+            "/v1/block_proof/:height",
+            anchored_block_proof,
+            "anchored_block_proof",
+        );
+        router.get(
+            "/v1/anchoring_proof/:height",
+            anchoring_proof,
+            "anchoring_proof",
+        );
+        router.get("/v1/anchoring_tx/:txid", anchoring_tx, "anchoring_tx");
     }
 }
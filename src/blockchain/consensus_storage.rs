@@ -33,17 +33,95 @@ pub struct AnchoringConfig {
     /// If the anchoring transactions chain is empty, it will be the first transaction in the chain.
     /// Note: you must specify a suitable transaction before the network launching.
     pub funding_tx: Option<FundingTx>,
-    /// Fee for each transaction in chain.
+    /// Additional funding transactions to top up the anchoring wallet.
+    /// Each of their unspent outputs to the anchoring address is spent
+    /// by the next anchoring transaction, as well as the outputs of `funding_tx`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_funding_txs: Vec<FundingTx>,
+    /// Fee for each transaction in chain if the `fee_policy` is flat.
     pub fee: u64,
+    /// The policy used to calculate fees for the anchoring transactions.
+    #[serde(default, skip_serializing_if = "FeePolicy::is_flat")]
+    pub fee_policy: FeePolicy,
     /// The frequency in blocks with which the generation of new anchoring
     /// transactions in the chain occurs.
     pub frequency: u64,
+    /// The number of bitcoin blocks since the lect has entered the mempool in which it should
+    /// be confirmed. Otherwise it is replaced by the transaction with the doubled fee.
+    /// Replacement is disabled if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fee_bump_timeout: Option<u64>,
     /// The minimum number of confirmations in bitcoin network for the transition to a
     /// new anchoring address.
     pub utxo_confirmations: u64,
     /// The current bitcoin network type.
     #[serde(serialize_with = "btc_network_to_str", deserialize_with = "btc_network_from_str")]
     pub network: btc::Network,
+    /// The type of the anchoring multisig address, legacy `p2sh` by default.
+    #[serde(default, skip_serializing_if = "is_legacy_address_type")]
+    pub address_type: btc::AddressType,
+    /// The version of the anchoring transactions payload, `v1` by default.
+    /// The `v2` payload additionally commits to the identifier of the Exonum network.
+    #[serde(default, skip_serializing_if = "is_legacy_payload_version")]
+    pub payload_version: btc::PayloadVersion,
+    /// Commit the anchoring transactions payload to the root of the Merkle tree over
+    /// the hashes of all blocks since the previous anchored block.
+    #[serde(default, skip_serializing_if = "is_disabled")]
+    pub commit_blocks_root: bool,
+}
+
+/// Policy to calculate fees for the anchoring transactions.
+///
+/// Fees for the rate-based policies are calculated from the estimated virtual size
+/// of the signed anchoring transaction.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FeePolicy {
+    /// Uses the flat `fee` from the configuration for each transaction.
+    Flat,
+    /// Uses the fixed fee rate.
+    Rate {
+        /// Fee rate in satoshis per virtual byte.
+        sat_per_vbyte: u64,
+    },
+    /// Uses the median of fee rates estimated by the validators' bitcoind nodes,
+    /// bounded by the given limits.
+    ///
+    /// Validators commit their estimations to the blockchain by the `MsgAnchoringFeeRate`
+    /// messages, so all of them agree on the fee rate for a proposal.
+    /// Until the majority of validators commit their estimations, the minimal fee rate is used.
+    Estimate {
+        /// Confirmation target in blocks for the `estimatesmartfee` request.
+        conf_target: u16,
+        /// Minimal fee rate in satoshis per virtual byte.
+        min_sat_per_vbyte: u64,
+        /// Maximal fee rate in satoshis per virtual byte.
+        max_sat_per_vbyte: u64,
+    },
+}
+
+impl Default for FeePolicy {
+    fn default() -> FeePolicy {
+        FeePolicy::Flat
+    }
+}
+
+impl FeePolicy {
+    /// Returns `true` if this is the flat fee policy.
+    pub fn is_flat(&self) -> bool {
+        *self == FeePolicy::Flat
+    }
+
+    /// Returns the maximal fee rate in satoshis per virtual byte allowed by this policy.
+    pub fn max(&self) -> Option<u64> {
+        match *self {
+            FeePolicy::Flat => None,
+            FeePolicy::Rate { sat_per_vbyte } => Some(sat_per_vbyte),
+            FeePolicy::Estimate {
+                max_sat_per_vbyte, ..
+            } => Some(max_sat_per_vbyte),
+        }
+    }
 }
 
 impl Default for AnchoringConfig {
@@ -51,10 +129,16 @@ impl Default for AnchoringConfig {
         AnchoringConfig {
             anchoring_keys: vec![],
             funding_tx: None,
+            extra_funding_txs: Vec::new(),
             fee: 1000,
+            fee_policy: FeePolicy::default(),
             frequency: 500,
+            fee_bump_timeout: None,
             utxo_confirmations: 5,
             network: btc::Network::Testnet,
+            address_type: btc::AddressType::default(),
+            payload_version: btc::PayloadVersion::default(),
+            commit_blocks_root: false,
         }
     }
 }
@@ -94,14 +178,15 @@ impl AnchoringConfig {
         }
     }
 
+    /// Creates compressed `RedeemScript` from public keys in config
+    /// and the anchoring address of the configured type.
     #[doc(hidden)]
-    /// Creates compressed `RedeemScript` from public keys in config.
     pub fn redeem_script(&self) -> (btc::RedeemScript, btc::Address) {
         let majority_count = self.majority_count();
         let redeem_script =
-            btc::RedeemScript::from_pubkeys(self.anchoring_keys.iter(), majority_count)
-                .compressed(self.network);
-        let addr = btc::Address::from_script(&redeem_script, self.network);
+            btc::RedeemScript::from_pubkeys(self.anchoring_keys.iter(), majority_count);
+        let addr =
+            btc::Address::from_redeem_script(&redeem_script, self.address_type, self.network);
         (redeem_script, addr)
     }
 
@@ -127,16 +212,41 @@ impl AnchoringConfig {
              funding_tx",
         )
     }
+
+    /// Returns outputs of the funding transactions to the given address in the order
+    /// in which they are spent by the anchoring transaction.
+    pub fn funding_outpoints(&self, addr: &btc::Address) -> Vec<(FundingTx, u32)> {
+        let mut outpoints: Vec<(FundingTx, u32)> = Vec::new();
+        for tx in self.funding_tx.iter().chain(&self.extra_funding_txs) {
+            if outpoints.iter().any(|outpoint| &outpoint.0 == tx) {
+                continue;
+            }
+            for out in tx.find_outs(addr) {
+                outpoints.push((tx.clone(), out));
+            }
+        }
+        outpoints
+    }
+}
+
+// Keeps the serialized form of the legacy configurations unchanged.
+fn is_legacy_address_type(address_type: &btc::AddressType) -> bool {
+    *address_type == btc::AddressType::P2sh
+}
+
+fn is_legacy_payload_version(payload_version: &btc::PayloadVersion) -> bool {
+    *payload_version == btc::PayloadVersion::V1
+}
+
+fn is_disabled(flag: &bool) -> bool {
+    !*flag
 }
 
 fn btc_network_to_str<S>(network: &btc::Network, ser: S) -> Result<S::Ok, S::Error>
 where
     S: ::serde::Serializer,
 {
-    match *network {
-        btc::Network::Bitcoin => ser.serialize_str("bitcoin"),
-        btc::Network::Testnet => ser.serialize_str("testnet"),
-    }
+    ser.serialize_str(&network.to_string())
 }
 
 fn btc_network_from_str<'de, D>(deserializer: D) -> Result<btc::Network, D::Error>
@@ -145,12 +255,9 @@ where
 {
     let s: String = Deserialize::deserialize(deserializer)?;
 
-    const VARIANTS: &[&str] = &["bitcoin", "testnet"];
-    match s.as_str() {
-        "bitcoin" => Ok(btc::Network::Bitcoin),
-        "testnet" => Ok(btc::Network::Testnet),
-        other => Err(::serde::de::Error::unknown_variant(other, VARIANTS)),
-    }
+    s.parse().map_err(|_| {
+        ::serde::de::Error::unknown_variant(&s, btc::Network::NAMES)
+    })
 }
 
 impl StorageValue for AnchoringConfig {
@@ -20,6 +20,8 @@ use service::ANCHORING_SERVICE_ID;
 
 pub const ANCHORING_MESSAGE_SIGNATURE: u16 = 0;
 pub const ANCHORING_MESSAGE_LATEST: u16 = 1;
+pub const ANCHORING_MESSAGE_FEE_RATE: u16 = 2;
+pub const ANCHORING_MESSAGE_FINALIZED_TX: u16 = 3;
 
 message! {
     /// Exonum message with the signature for the given input of the anchoring transaction.
@@ -59,6 +61,104 @@ message! {
     }
 }
 
+message! {
+    /// Exonum message with the fee rate estimated by the validator's bitcoind node.
+    struct MsgAnchoringFeeRate {
+        const TYPE = ANCHORING_SERVICE_ID;
+        const ID = ANCHORING_MESSAGE_FEE_RATE;
+        const SIZE = 42;
+
+        /// Public key of validator.
+        field from:           &PublicKey   [00 => 32]
+        /// Public key index in anchoring public keys list.
+        field validator:      ValidatorId  [32 => 34]
+        /// Estimated fee rate in satoshis per virtual byte.
+        field fee_rate:       u64          [34 => 42]
+    }
+}
+
+message! {
+    /// Exonum message with the validator's vote for the anchoring transaction which has got
+    /// enough confirmations in the bitcoin network.
+    struct MsgAnchoringFinalizedTx {
+        const TYPE = ANCHORING_SERVICE_ID;
+        const ID = ANCHORING_MESSAGE_FINALIZED_TX;
+        const SIZE = 50;
+
+        /// Public key of validator.
+        field from:           &PublicKey   [00 => 32]
+        /// Public key index in anchoring public keys list.
+        field validator:      ValidatorId  [32 => 34]
+        /// Confirmed anchoring transaction.
+        field tx:             AnchoringTx  [34 => 42]
+        /// Proof of the transaction inclusion into the bitcoin block.
+        field tx_out_proof:   TxOutProof   [42 => 50]
+    }
+}
+
+encoding_struct! {
+    /// Proof of the transaction inclusion into the bitcoin block.
+    struct TxOutProof {
+        const SIZE = 16;
+
+        /// Hex-encoded hash of the bitcoin block with the transaction.
+        field block_hash:     &str        [00 => 08]
+        /// Hex-encoded `merkleblock` returned by the `gettxoutproof` rpc.
+        field proof:          &str        [08 => 16]
+    }
+}
+
+encoding_struct! {
+    /// Exonum block anchored by the bitcoin transaction.
+    struct AnchoringTxRecord {
+        const SIZE = 41;
+
+        /// Height of the anchored block.
+        field block_height:   u64         [00 => 08]
+        /// Hash of the anchored block.
+        field block_hash:     &Hash       [08 => 40]
+        /// Identifier of the [`AnchoringTxKind`](enum.AnchoringTxKind.html).
+        field kind_id:        u8          [40 => 41]
+    }
+}
+
+impl AnchoringTxRecord {
+    /// Returns the kind of the anchoring transaction or `None` if the kind identifier
+    /// is unknown.
+    pub fn kind(&self) -> Option<AnchoringTxKind> {
+        match self.kind_id() {
+            0 => Some(AnchoringTxKind::Regular),
+            1 => Some(AnchoringTxKind::Transition),
+            2 => Some(AnchoringTxKind::Recovery),
+            _ => None,
+        }
+    }
+}
+
+/// Position of the anchoring transaction in the anchoring chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnchoringTxKind {
+    /// Transaction spends the previous anchoring output to the same address.
+    Regular,
+    /// Transaction moves the funds to the anchoring address of the new configuration.
+    Transition,
+    /// Transaction starts the new anchoring chain, which refers to the previous one
+    /// by the `prev_tx_chain` payload field.
+    Recovery,
+}
+
+impl AnchoringTxKind {
+    /// Returns the identifier of the kind in the `AnchoringTxRecord`.
+    pub fn id(&self) -> u8 {
+        match *self {
+            AnchoringTxKind::Regular => 0,
+            AnchoringTxKind::Transition => 1,
+            AnchoringTxKind::Recovery => 2,
+        }
+    }
+}
+
 encoding_struct! {
     /// Lect content
     struct LectContent {
@@ -26,4 +26,5 @@ pub mod consensus_storage;
 mod tests;
 
 pub use self::schema::{AnchoringSchema, KnownSignatureId};
-pub use self::dto::{LectContent, MsgAnchoringSignature, MsgAnchoringUpdateLatest};
+pub use self::dto::{AnchoringTxKind, AnchoringTxRecord, LectContent, MsgAnchoringFeeRate,
+                    MsgAnchoringFinalizedTx, MsgAnchoringSignature, MsgAnchoringUpdateLatest};
@@ -18,14 +18,16 @@ use byteorder::{BigEndian, ByteOrder};
 use serde_json::value::from_value;
 
 use exonum::blockchain::{gen_prefix, Schema, StoredConfiguration};
-use exonum::storage::{Fork, ListIndex, MapIndex, ProofListIndex, Snapshot, StorageKey};
-use exonum::crypto::Hash;
+use exonum::storage::{Fork, KeySetIndex, ListIndex, ListProof, MapIndex, ProofListIndex,
+                      Snapshot, StorageKey};
+use exonum::crypto::{hash, Hash, HashStream};
 use exonum::helpers::{Height, ValidatorId};
 
-use blockchain::consensus_storage::AnchoringConfig;
-use blockchain::dto::{LectContent, MsgAnchoringSignature};
+use blockchain::consensus_storage::{AnchoringConfig, FeePolicy};
+use blockchain::dto::{AnchoringTxKind, AnchoringTxRecord, LectContent, MsgAnchoringSignature,
+                      TxOutProof};
 use details::btc;
-use details::btc::transactions::{AnchoringTx, BitcoinTx};
+use details::btc::transactions::{AnchoringTx, BitcoinTx, TxKind};
 use service::ANCHORING_SERVICE_NAME;
 
 /// Unique identifier of signature for the `AnchoringTx`.
@@ -120,9 +122,72 @@ where
         MapIndex::new("btc_anchoring.known_txs", &self.view)
     }
 
-    /// Returns table that maps anchoring transactions to their heights.
-    pub fn anchoring_tx_chain(&self) -> MapIndex<&T, u64, AnchoringTx> {
-        MapIndex::new("btc_anchoring.tx_chain", &self.view)
+    /// Returns table that keeps the finalized anchoring transactions in the ascending order
+    /// of the anchored heights.
+    ///
+    /// Transactions are appended when the majority of validators vote for them
+    /// by the `MsgAnchoringFinalizedTx` messages.
+    pub fn anchoring_tx_chain(&self) -> ProofListIndex<&T, AnchoringTx> {
+        ProofListIndex::new("btc_anchoring.anchoring_tx_chain", &self.view)
+    }
+
+    /// Returns table that maps the anchored heights to the positions of the finalized
+    /// anchoring transactions in the [`anchoring_tx_chain`][1] table.
+    ///
+    /// [1]: struct.AnchoringSchema.html#method.anchoring_tx_chain
+    pub fn anchoring_tx_heights(&self) -> MapIndex<&T, u64, u64> {
+        MapIndex::new("btc_anchoring.anchoring_tx_heights", &self.view)
+    }
+
+    /// Returns table that maps the txids of the finalized anchoring transactions to their
+    /// positions in the [`anchoring_tx_chain`][1] table.
+    ///
+    /// [1]: struct.AnchoringSchema.html#method.anchoring_tx_chain
+    pub fn anchoring_tx_positions(&self) -> MapIndex<&T, btc::TxId, u64> {
+        MapIndex::new("btc_anchoring.anchoring_tx_positions", &self.view)
+    }
+
+    /// Returns table that keeps the anchoring keys of validators which have voted
+    /// for the finalization of the anchoring transaction with the given `txid`
+    /// and the proof of its inclusion with the given `proof_hash`.
+    pub fn finalized_tx_votes(
+        &self,
+        txid: &btc::TxId,
+        proof_hash: &Hash,
+    ) -> KeySetIndex<&T, btc::PublicKey> {
+        KeySetIndex::with_prefix(
+            "btc_anchoring.finalized_tx_votes",
+            finalized_tx_votes_prefix(txid, proof_hash),
+            &self.view,
+        )
+    }
+
+    /// Returns table that keeps the anchored block and the kind of every known
+    /// anchoring transaction.
+    pub fn anchoring_tx_records(&self) -> MapIndex<&T, btc::TxId, AnchoringTxRecord> {
+        MapIndex::new("btc_anchoring.anchoring_tx_records", &self.view)
+    }
+
+    /// Returns table that keeps the proofs of the anchoring transactions inclusion
+    /// into the bitcoin blocks.
+    ///
+    /// The proof is taken from the `MsgAnchoringFinalizedTx` votes once the majority
+    /// of validators has voted for the transaction with the same proof.
+    pub fn tx_out_proofs(&self) -> MapIndex<&T, btc::TxId, TxOutProof> {
+        MapIndex::new("btc_anchoring.tx_out_proofs", &self.view)
+    }
+
+    /// Returns table that keeps the latest fee rate estimation for every validator
+    /// with the given `validator_key`.
+    pub fn fee_rates(&self) -> MapIndex<&T, btc::PublicKey, u64> {
+        MapIndex::new("btc_anchoring.fee_rates", &self.view)
+    }
+
+    /// Returns table that keeps the verified blocks roots for the ranges of blocks
+    /// starting from the given height. The table is indexed by the last block height
+    /// in the range.
+    pub fn blocks_roots(&self, from: Height) -> MapIndex<&T, u64, Hash> {
+        MapIndex::with_prefix("btc_anchoring.blocks_roots", gen_prefix(&from.0), &self.view)
     }
 
     /// Returns the actual anchoring configuration.
@@ -165,6 +230,24 @@ where
         self.parse_config(&stored)
     }
 
+    /// Returns the configuration with the given anchoring `address`, which is actual for the block
+    /// with the given `height` or becomes actual later.
+    pub fn configuration_by_address(
+        &self,
+        address: &btc::Address,
+        height: Height,
+    ) -> Option<StoredConfiguration> {
+        let schema = Schema::new(&self.view);
+        let actual_from = schema.configuration_by_height(height).actual_from;
+        let stored = schema
+            .configs_actual_from()
+            .iter()
+            .filter(|reference| reference.actual_from() >= actual_from)
+            .filter_map(|reference| schema.configuration_by_hash(reference.cfg_hash()))
+            .find(|stored| &self.parse_config(stored).redeem_script().1 == address);
+        stored
+    }
+
     /// Returns `lect` for validator with the given `public_key`.
     pub fn lect(&self, validator_key: &btc::PublicKey) -> Option<BitcoinTx> {
         self.lects(validator_key).last().map(|x| x.tx())
@@ -209,6 +292,24 @@ where
         }
     }
 
+    /// Returns the finalized anchoring transaction for the block with the given `height`.
+    pub fn anchoring_tx_by_height(&self, height: u64) -> Option<AnchoringTx> {
+        let position = self.anchoring_tx_heights().get(&height)?;
+        self.anchoring_tx_chain().get(position)
+    }
+
+    /// Returns the finalized anchoring transaction for the nearest block with a height
+    /// greater or equal than the given.
+    pub fn nearest_anchoring_tx(&self, height: u64) -> Option<AnchoringTx> {
+        let position = self.anchoring_tx_heights().values_from(&height).next()?;
+        self.anchoring_tx_chain().get(position)
+    }
+
+    /// Returns `true` if the given anchoring transaction is in the `anchoring_tx_chain` table.
+    pub fn is_finalized_tx(&self, tx: &AnchoringTx) -> bool {
+        self.anchoring_tx_positions().contains(&tx.id())
+    }
+
     /// Returns position in `lects` table of validator with the given `anchoring_key`
     /// for transaction with the given `txid`.
     pub fn find_lect_position(
@@ -219,16 +320,157 @@ where
         self.lect_indexes(anchoring_key).get(txid)
     }
 
+    /// Returns the transaction spent by the given `input` of the anchoring transaction
+    /// if it is known.
+    ///
+    /// Spent transaction is looked up among the known transactions and the funding
+    /// transactions of the actual and following configurations.
+    pub fn spent_tx(&self, tx: &AnchoringTx, input: u32) -> Option<BitcoinTx> {
+        let txin = tx.input.get(input as usize)?;
+        let prev_txid = btc::TxId::from(txin.prev_hash);
+        self.known_txs().get(&prev_txid).or_else(|| {
+            let actual = self.actual_anchoring_config();
+            let following = self.following_anchoring_config();
+            let following = following
+                .into_iter()
+                .flat_map(|cfg| cfg.funding_tx.into_iter().chain(cfg.extra_funding_txs));
+            actual
+                .funding_tx
+                .into_iter()
+                .chain(actual.extra_funding_txs)
+                .chain(following)
+                .find(|funding_tx| funding_tx.id() == prev_txid)
+                .map(BitcoinTx::from)
+        })
+    }
+
+    /// Returns the value of the output spent by the given `input` of the anchoring transaction
+    /// if the spent transaction is known.
+    pub fn spent_output_value(&self, tx: &AnchoringTx, input: u32) -> Option<u64> {
+        let prev_index = tx.input.get(input as usize)?.prev_index;
+        self.spent_tx(tx, input)?
+            .output
+            .get(prev_index as usize)
+            .map(|output| output.value)
+    }
+
+    /// Returns the kind of the given anchoring transaction.
+    ///
+    /// The transaction is a transition one if its output address differs from the address
+    /// of the output spent by its first input, which is considered the same if the spent
+    /// transaction is unknown.
+    pub fn anchoring_tx_kind(&self, tx: &AnchoringTx) -> AnchoringTxKind {
+        if tx.payload().prev_tx_chain.is_some() {
+            return AnchoringTxKind::Recovery;
+        }
+        let prev_index = tx.input[0].prev_index as usize;
+        let spent_script = self.spent_tx(tx, 0).and_then(|prev_tx| {
+            prev_tx
+                .output
+                .get(prev_index)
+                .map(|output| output.script_pubkey.clone())
+        });
+        match spent_script {
+            Some(ref script) if *script != tx.output[0].script_pubkey => {
+                AnchoringTxKind::Transition
+            }
+            _ => AnchoringTxKind::Regular,
+        }
+    }
+
+    /// Returns the height of the first block committed by the blocks root of the given
+    /// anchoring transaction if the transaction spent by its first input is known.
+    ///
+    /// This is the next block after the block anchored by the spent transaction
+    /// or the genesis block if the funding transaction is spent.
+    pub fn anchored_blocks_start(&self, tx: &AnchoringTx) -> Option<Height> {
+        let height = match TxKind::from(self.spent_tx(tx, 0)?) {
+            TxKind::Anchoring(prev_tx) => prev_tx.payload().block_height.next(),
+            _ => Height::zero(),
+        };
+        Some(height)
+    }
+
+    /// Returns the root hash of the Merkle tree over the hashes of blocks with heights
+    /// in the range `from..=to`. The tree has the same structure as `ProofListIndex`.
+    ///
+    /// The root is computed only if it is not found among the verified ones.
+    pub fn blocks_root(&self, from: Height, to: Height) -> Hash {
+        if let Some(root) = self.blocks_roots(from).get(&to.0) {
+            return root;
+        }
+        let block_hashes = self.block_hashes(from, to);
+        let capacity = block_hashes.len().next_power_of_two();
+        merkle_root(&block_hashes, capacity)
+    }
+
+    /// Returns the proof of the block with the given `height` inclusion into the blocks root
+    /// of the range `from..=to`. The proof is the same as the `ProofListIndex` one.
+    ///
+    /// # Panics
+    ///
+    /// If the `height` is out of the range.
+    pub fn blocks_root_proof(&self, from: Height, to: Height, height: Height) -> ListProof<Hash> {
+        assert!(
+            from <= height && height <= to,
+            "Block is not committed by the blocks root"
+        );
+        let block_hashes = self.block_hashes(from, to);
+        let capacity = block_hashes.len().next_power_of_two();
+        merkle_proof(&block_hashes, (height.0 - from.0) as usize, capacity)
+    }
+
+    /// Returns the fee rate in satoshis per virtual byte for the anchoring transactions
+    /// according to the fee policy of the given `cfg` or `None` for the flat fee policy.
+    ///
+    /// For the estimate policy this is the median of the validators' estimations
+    /// bounded by the policy limits.
+    pub fn fee_rate(&self, cfg: &AnchoringConfig) -> Option<u64> {
+        match cfg.fee_policy {
+            FeePolicy::Flat => None,
+            FeePolicy::Rate { sat_per_vbyte } => Some(sat_per_vbyte),
+            FeePolicy::Estimate {
+                min_sat_per_vbyte,
+                max_sat_per_vbyte,
+                ..
+            } => {
+                let fee_rates = self.fee_rates();
+                let mut estimations = cfg.anchoring_keys
+                    .iter()
+                    .filter_map(|key| fee_rates.get(key))
+                    .collect::<Vec<_>>();
+                if estimations.len() < cfg.majority_count() as usize {
+                    return Some(min_sat_per_vbyte);
+                }
+                estimations.sort();
+                let median = estimations[estimations.len() / 2];
+                Some(median.max(min_sat_per_vbyte).min(max_sat_per_vbyte))
+            }
+        }
+    }
+
     /// Returns the `state_hash` for anchoring tables.
     ///
-    /// It contains a list of `root_hash` of the actual `lects` tables.
+    /// It contains a list of `root_hash` of the actual `lects` tables followed by
+    /// the `root_hash` of the `anchoring_tx_chain` table.
     pub fn state_hash(&self) -> Vec<Hash> {
         let cfg = self.actual_anchoring_config();
-        let mut lect_hashes = Vec::new();
+        let mut hashes = Vec::new();
         for key in &cfg.anchoring_keys {
-            lect_hashes.push(self.lects(key).root_hash());
+            hashes.push(self.lects(key).root_hash());
         }
-        lect_hashes
+        hashes.push(self.anchoring_tx_chain().root_hash());
+        hashes
+    }
+
+    fn block_hashes(&self, from: Height, to: Height) -> Vec<Hash> {
+        let schema = Schema::new(&self.view);
+        let block_hashes = schema.block_hashes_by_height();
+        (from.0..to.0 + 1)
+            .map(|height| {
+                block_hashes.get(height).expect("Block is not committed yet")
+            })
+            .collect()
     }
 
     fn parse_config(&self, cfg: &StoredConfiguration) -> AnchoringConfig {
@@ -292,11 +534,74 @@ impl<'a> AnchoringSchema<&'a mut Fork> {
         MapIndex::new("btc_anchoring.known_txs", &mut self.view)
     }
 
-    /// Mutable variant of the [`signatures`][1] index.
+    /// Mutable variant of the [`blocks_roots`][1] index.
+    ///
+    /// [1]: struct.AnchoringSchema.html#method.blocks_roots
+    pub fn blocks_roots_mut(&mut self, from: Height) -> MapIndex<&mut Fork, u64, Hash> {
+        MapIndex::with_prefix(
+            "btc_anchoring.blocks_roots",
+            gen_prefix(&from.0),
+            &mut self.view,
+        )
+    }
+
+    /// Mutable variant of the [`fee_rates`][1] index.
+    ///
+    /// [1]: struct.AnchoringSchema.html#method.fee_rates
+    pub fn fee_rates_mut(&mut self) -> MapIndex<&mut Fork, btc::PublicKey, u64> {
+        MapIndex::new("btc_anchoring.fee_rates", &mut self.view)
+    }
+
+    /// Mutable variant of the [`anchoring_tx_records`][1] index.
+    ///
+    /// [1]: struct.AnchoringSchema.html#method.anchoring_tx_records
+    pub fn anchoring_tx_records_mut(
+        &mut self,
+    ) -> MapIndex<&mut Fork, btc::TxId, AnchoringTxRecord> {
+        MapIndex::new("btc_anchoring.anchoring_tx_records", &mut self.view)
+    }
+
+    /// Mutable variant of the [`tx_out_proofs`][1] index.
+    ///
+    /// [1]: struct.AnchoringSchema.html#method.tx_out_proofs
+    pub fn tx_out_proofs_mut(&mut self) -> MapIndex<&mut Fork, btc::TxId, TxOutProof> {
+        MapIndex::new("btc_anchoring.tx_out_proofs", &mut self.view)
+    }
+
+    /// Mutable variant of the [`anchoring_tx_chain`][1] index.
     ///
     /// [1]: struct.AnchoringSchema.html#method.anchoring_tx_chain
-    pub fn anchoring_tx_chain_mut(&mut self) -> MapIndex<&mut Fork, u64, AnchoringTx> {
-        MapIndex::new("btc_anchoring.tx_chain", &mut self.view)
+    pub fn anchoring_tx_chain_mut(&mut self) -> ProofListIndex<&mut Fork, AnchoringTx> {
+        ProofListIndex::new("btc_anchoring.anchoring_tx_chain", &mut self.view)
+    }
+
+    /// Mutable variant of the [`anchoring_tx_heights`][1] index.
+    ///
+    /// [1]: struct.AnchoringSchema.html#method.anchoring_tx_heights
+    pub fn anchoring_tx_heights_mut(&mut self) -> MapIndex<&mut Fork, u64, u64> {
+        MapIndex::new("btc_anchoring.anchoring_tx_heights", &mut self.view)
+    }
+
+    /// Mutable variant of the [`anchoring_tx_positions`][1] index.
+    ///
+    /// [1]: struct.AnchoringSchema.html#method.anchoring_tx_positions
+    pub fn anchoring_tx_positions_mut(&mut self) -> MapIndex<&mut Fork, btc::TxId, u64> {
+        MapIndex::new("btc_anchoring.anchoring_tx_positions", &mut self.view)
+    }
+
+    /// Mutable variant of the [`finalized_tx_votes`][1] index.
+    ///
+    /// [1]: struct.AnchoringSchema.html#method.finalized_tx_votes
+    pub fn finalized_tx_votes_mut(
+        &mut self,
+        txid: &btc::TxId,
+        proof_hash: &Hash,
+    ) -> KeySetIndex<&mut Fork, btc::PublicKey> {
+        KeySetIndex::with_prefix(
+            "btc_anchoring.finalized_tx_votes",
+            finalized_tx_votes_prefix(txid, proof_hash),
+            &mut self.view,
+        )
     }
 
     /// Creates and commits the genesis anchoring configuration from the proposed `cfg`.
@@ -321,7 +626,79 @@ impl<'a> AnchoringSchema<&'a mut Fork> {
         };
 
         self.known_txs_mut().put(&txid, tx.clone());
-        self.lect_indexes_mut(validator_key).put(&txid, idx)
+        self.lect_indexes_mut(validator_key).put(&txid, idx);
+        if let TxKind::Anchoring(tx) = TxKind::from(tx) {
+            self.add_anchoring_tx_record(&tx);
+        }
+    }
+
+    /// Saves the anchored block and the kind of the given anchoring transaction
+    /// if it has not been done yet.
+    pub fn add_anchoring_tx_record(&mut self, tx: &AnchoringTx) {
+        let txid = tx.id();
+        if self.anchoring_tx_records().contains(&txid) {
+            return;
+        }
+        let payload = tx.payload();
+        let record = AnchoringTxRecord::new(
+            payload.block_height.0,
+            &payload.block_hash,
+            self.anchoring_tx_kind(tx).id(),
+        );
+        self.anchoring_tx_records_mut().put(&txid, record);
+    }
+
+    /// Saves the blocks root of the given anchoring transaction, so it is not computed again
+    /// for the other transactions anchoring the same blocks.
+    ///
+    /// The blocks root of the transaction should be verified beforehand.
+    pub fn add_blocks_root(&mut self, tx: &AnchoringTx) {
+        let payload = tx.payload();
+        let blocks_root = match payload.blocks_root {
+            Some(blocks_root) => blocks_root,
+            None => return,
+        };
+        if let Some(start) = self.anchored_blocks_start(tx) {
+            self.blocks_roots_mut(start)
+                .put(&payload.block_height.0, blocks_root);
+        }
+    }
+
+    /// Appends the given finalized anchoring transaction to the anchoring chain along with
+    /// its known predecessors, which are not finalized yet.
+    ///
+    /// Predecessors are looked up among the known transactions, they are confirmed
+    /// in the bitcoin network as long as the given transaction is.
+    pub fn add_finalized_tx(&mut self, tx: AnchoringTx) {
+        let mut txs = Vec::new();
+        let mut next_tx = Some(tx);
+        while let Some(tx) = next_tx.take() {
+            if self.is_finalized_tx(&tx) {
+                break;
+            }
+            let payload = tx.payload();
+            let prev_txid = payload.prev_tx_chain.unwrap_or_else(|| tx.prev_hash());
+            if let Some(prev_tx) = self.known_txs().get(&prev_txid) {
+                if let TxKind::Anchoring(prev_tx) = TxKind::from(prev_tx) {
+                    // Heights in the chain do not decrease.
+                    if prev_tx.payload().block_height <= payload.block_height {
+                        next_tx = Some(prev_tx);
+                    }
+                }
+            }
+            txs.push(tx);
+        }
+
+        for tx in txs.into_iter().rev() {
+            let height = tx.payload().block_height.0;
+            let position = self.anchoring_tx_chain().len();
+            self.anchoring_tx_positions_mut().put(&tx.id(), position);
+            self.anchoring_tx_chain_mut().push(tx);
+            // The height is mapped to the first transaction which has anchored it.
+            if self.anchoring_tx_heights().get(&height).is_none() {
+                self.anchoring_tx_heights_mut().put(&height, position);
+            }
+        }
     }
 
     /// Adds signature to known if it is correct.
@@ -340,6 +717,57 @@ impl<'a> AnchoringSchema<&'a mut Fork> {
     }
 }
 
+fn finalized_tx_votes_prefix(txid: &btc::TxId, proof_hash: &Hash) -> Vec<u8> {
+    let mut prefix = gen_prefix(txid);
+    prefix.extend(gen_prefix(proof_hash));
+    prefix
+}
+
+// Computes the root hash of the subtree with the given `capacity` in the same way as
+// `ProofListIndex`: the hashes of `Hash` values are the values themselves, and a node without
+// the right child hashes the left one.
+fn merkle_root(hashes: &[Hash], mut capacity: usize) -> Hash {
+    if hashes.is_empty() {
+        return Hash::default();
+    }
+    let mut hashes = hashes.to_vec();
+    while capacity > 1 {
+        hashes = hashes
+            .chunks(2)
+            .map(|pair| if pair.len() == 2 {
+                HashStream::new()
+                    .update(pair[0].as_ref())
+                    .update(pair[1].as_ref())
+                    .hash()
+            } else {
+                hash(pair[0].as_ref())
+            })
+            .collect();
+        capacity /= 2;
+    }
+    hashes[0]
+}
+
+// Builds the proof of the value with the given `index` inclusion into the subtree
+// with the given `capacity`.
+fn merkle_proof(hashes: &[Hash], index: usize, capacity: usize) -> ListProof<Hash> {
+    if capacity == 1 {
+        return ListProof::Leaf(hashes[0]);
+    }
+    let half = capacity / 2;
+    if hashes.len() <= half {
+        return ListProof::Left(Box::new(merkle_proof(hashes, index, half)), None);
+    }
+    let (left, right) = hashes.split_at(half);
+    if index < half {
+        let right_root = merkle_root(right, half);
+        ListProof::Left(Box::new(merkle_proof(left, index, half)), Some(right_root))
+    } else {
+        let left_root = merkle_root(left, half);
+        ListProof::Right(left_root, Box::new(merkle_proof(right, index - half, half)))
+    }
+}
+
 impl<T> AnchoringSchema<T> {
     /// Converts schema back into snapshot.
     pub fn into_snapshot(self) -> T {
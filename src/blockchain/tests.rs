@@ -12,18 +12,26 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use exonum::crypto::{PublicKey, Signature, hash};
-use exonum::storage::StorageValue;
-use exonum::helpers::ValidatorId;
+use std::collections::HashMap;
+
+use exonum::crypto::{gen_keypair, hash, PublicKey, Signature};
+use exonum::storage::{Database, MemoryDB, ProofListIndex, StorageValue};
+use exonum::blockchain::{Schema, Transaction};
+use exonum::helpers::{Height, ValidatorId};
 use exonum::encoding::serialize::FromHex;
 
-use bitcoin::blockdata::transaction::SigHashType;
-use bitcoin::network::constants::Network;
+use bitcoin::blockdata::script::Script;
+use bitcoin::blockdata::transaction::{SigHashType, TxIn, TxOut};
+use details::btc::{Network, PayloadVersion};
 use serde_json;
 
 use details::btc;
-use details::btc::transactions::{AnchoringTx, BitcoinTx};
-use blockchain::dto::{LectContent, MsgAnchoringSignature, MsgAnchoringUpdateLatest};
+use details::btc::transactions::{AnchoringTx, BitcoinTx, FundingTx, RawBitcoinTx,
+                                 TransactionBuilder};
+use blockchain::consensus_storage::{AnchoringConfig, FeePolicy};
+use blockchain::dto::{LectContent, MsgAnchoringFeeRate, MsgAnchoringFinalizedTx,
+                      MsgAnchoringSignature, MsgAnchoringUpdateLatest, TxOutProof};
+use blockchain::schema::AnchoringSchema;
 use details::tests::{dummy_anchoring_tx, gen_anchoring_keys, make_signatures};
 
 #[test]
@@ -116,7 +124,9 @@ fn test_sighash_type_single_in_msg_signature() {
 #[test]
 fn test_signed_input_in_msg_signature_tx_body() {
     let (pub_keys, priv_keys) = gen_anchoring_keys(4);
-    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3).compressed(Network::Bitcoin);
+    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3)
+        .compressed(Network::Bitcoin)
+        .unwrap();
 
     let tx = dummy_anchoring_tx(&redeem_script);
     let btc_signatures = make_signatures(&redeem_script, &tx, &[0], &priv_keys);
@@ -136,10 +146,317 @@ fn test_signed_input_in_msg_signature_tx_body() {
     assert!(!msg.verify_content());
 }
 
+#[test]
+fn test_witness_in_msg_signature_tx_body() {
+    let (pub_keys, priv_keys) = gen_anchoring_keys(4);
+    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3)
+        .compressed(Network::Bitcoin)
+        .unwrap();
+
+    let tx = dummy_anchoring_tx(&redeem_script);
+    let btc_signature = tx.sign_witness_input(&redeem_script, 0, 3000, &priv_keys[0]);
+    let mut btc_signatures = HashMap::new();
+    btc_signatures.insert(0, vec![btc_signature.clone()]);
+    let signed_tx =
+        tx.clone()
+            .finalize_with_type(btc::AddressType::P2wsh, &redeem_script, btc_signatures);
+
+    assert!(signed_tx.input[0].script_sig.is_empty());
+    assert_eq!(signed_tx.id(), tx.id());
+
+    let msg = MsgAnchoringSignature::new_with_signature(
+        &PublicKey::zero(),
+        ValidatorId(0),
+        signed_tx,
+        0,
+        &btc_signature,
+        &Signature::zero(),
+    );
+    assert!(!msg.verify_content());
+}
+
+#[test]
+fn test_anchoring_config_address_type_serde() {
+    let (pub_keys, _) = gen_anchoring_keys(4);
+    let cfg = AnchoringConfig::new(Network::Testnet, pub_keys);
+
+    // Legacy configurations keep their serialized form
+    let json = serde_json::to_value(&cfg).unwrap();
+    assert!(json.get("address_type").is_none());
+    let cfg2: AnchoringConfig = serde_json::from_value(json).unwrap();
+    assert_eq!(cfg2.address_type, btc::AddressType::P2sh);
+
+    let mut cfg = cfg;
+    cfg.address_type = btc::AddressType::P2shP2wsh;
+    let json = serde_json::to_value(&cfg).unwrap();
+    assert_eq!(json["address_type"], "p2sh-p2wsh");
+    let cfg2: AnchoringConfig = serde_json::from_value(json).unwrap();
+    assert_eq!(cfg2, cfg);
+    assert!(cfg2.redeem_script().1.script_pubkey().is_p2sh());
+}
+
+#[test]
+fn test_anchoring_config_network_serde() {
+    for network in &[Network::Bitcoin, Network::Testnet, Network::Regtest, Network::Signet] {
+        let (pub_keys, _) = gen_anchoring_keys(4);
+        let cfg = AnchoringConfig::new(*network, pub_keys);
+
+        let json = serde_json::to_value(&cfg).unwrap();
+        assert_eq!(json["network"], network.to_string());
+        let cfg2: AnchoringConfig = serde_json::from_value(json).unwrap();
+        assert_eq!(cfg2, cfg);
+    }
+
+    let (pub_keys, _) = gen_anchoring_keys(4);
+    let mut json = serde_json::to_value(&AnchoringConfig::new(Network::Testnet, pub_keys)).unwrap();
+    json["network"] = "mainnet".into();
+    assert!(serde_json::from_value::<AnchoringConfig>(json).is_err());
+}
+
+#[test]
+fn test_anchoring_config_fee_policy_serde() {
+    let (pub_keys, _) = gen_anchoring_keys(4);
+    let cfg = AnchoringConfig::new(Network::Testnet, pub_keys);
+
+    // Configurations with the flat fee keep their serialized form
+    let json = serde_json::to_value(&cfg).unwrap();
+    assert!(json.get("fee_policy").is_none());
+    let cfg2: AnchoringConfig = serde_json::from_value(json).unwrap();
+    assert_eq!(cfg2.fee_policy, FeePolicy::Flat);
+
+    let mut cfg = cfg;
+    cfg.fee_policy = FeePolicy::Estimate {
+        conf_target: 6,
+        min_sat_per_vbyte: 1,
+        max_sat_per_vbyte: 100,
+    };
+    let json = serde_json::to_value(&cfg).unwrap();
+    assert_eq!(
+        json["fee_policy"],
+        json!({
+            "type": "estimate",
+            "conf_target": 6,
+            "min_sat_per_vbyte": 1,
+            "max_sat_per_vbyte": 100,
+        })
+    );
+    let cfg2: AnchoringConfig = serde_json::from_value(json).unwrap();
+    assert_eq!(cfg2, cfg);
+
+    let policy: FeePolicy = serde_json::from_value(json!({
+        "type": "rate",
+        "sat_per_vbyte": 10,
+    })).unwrap();
+    assert_eq!(policy, FeePolicy::Rate { sat_per_vbyte: 10 });
+}
+
+#[test]
+fn test_anchoring_config_fee_bump_timeout_serde() {
+    let (pub_keys, _) = gen_anchoring_keys(4);
+    let mut cfg = AnchoringConfig::new(Network::Testnet, pub_keys);
+
+    let json = serde_json::to_value(&cfg).unwrap();
+    assert!(json.get("fee_bump_timeout").is_none());
+    let cfg2: AnchoringConfig = serde_json::from_value(json).unwrap();
+    assert_eq!(cfg2.fee_bump_timeout, None);
+
+    cfg.fee_bump_timeout = Some(6);
+    let json = serde_json::to_value(&cfg).unwrap();
+    assert_eq!(json["fee_bump_timeout"], 6);
+    let cfg2: AnchoringConfig = serde_json::from_value(json).unwrap();
+    assert_eq!(cfg2, cfg);
+}
+
+#[test]
+fn test_anchoring_config_payload_version_serde() {
+    let (pub_keys, _) = gen_anchoring_keys(4);
+    let mut cfg = AnchoringConfig::new(Network::Testnet, pub_keys);
+
+    let json = serde_json::to_value(&cfg).unwrap();
+    assert!(json.get("payload_version").is_none());
+    let cfg2: AnchoringConfig = serde_json::from_value(json).unwrap();
+    assert_eq!(cfg2.payload_version, PayloadVersion::V1);
+
+    cfg.payload_version = PayloadVersion::V2;
+    let json = serde_json::to_value(&cfg).unwrap();
+    assert_eq!(json["payload_version"], "v2");
+    let cfg2: AnchoringConfig = serde_json::from_value(json).unwrap();
+    assert_eq!(cfg2, cfg);
+}
+
+#[test]
+fn test_anchoring_config_funding_outpoints() {
+    let (pub_keys, _) = gen_anchoring_keys(4);
+    let mut cfg = AnchoringConfig::new(Network::Testnet, pub_keys);
+    let addr = cfg.redeem_script().1;
+
+    let json = serde_json::to_value(&cfg).unwrap();
+    assert!(json.get("extra_funding_txs").is_none());
+    assert!(cfg.funding_outpoints(&addr).is_empty());
+
+    let funding_tx = |values: &[u64]| {
+        let outputs = values
+            .iter()
+            .map(|&value| {
+                TxOut {
+                    value,
+                    script_pubkey: addr.script_pubkey(),
+                }
+            })
+            .chain(Some(TxOut {
+                value: 1000,
+                script_pubkey: Script::new(),
+            }))
+            .collect();
+        FundingTx::from(RawBitcoinTx {
+            version: 1,
+            lock_time: 0,
+            input: vec![
+                TxIn {
+                    prev_hash: Default::default(),
+                    prev_index: 0,
+                    script_sig: Script::new(),
+                    sequence: 0xFFFF_FFFF,
+                },
+            ],
+            output: outputs,
+            witness: vec![],
+        })
+    };
+    let tx1 = funding_tx(&[4000]);
+    let tx2 = funding_tx(&[2000, 3000]);
+    cfg.funding_tx = Some(tx1.clone());
+    cfg.extra_funding_txs = vec![tx2.clone(), tx1.clone()];
+
+    assert_eq!(
+        cfg.funding_outpoints(&addr),
+        vec![(tx1, 0), (tx2.clone(), 0), (tx2, 1)]
+    );
+    let json = serde_json::to_value(&cfg).unwrap();
+    let cfg2: AnchoringConfig = serde_json::from_value(json).unwrap();
+    assert_eq!(cfg2, cfg);
+}
+
+#[test]
+fn test_msg_fee_rate_json_serde() {
+    let msg = MsgAnchoringFeeRate::new_with_signature(
+        &PublicKey::zero(),
+        ValidatorId(1),
+        15,
+        &Signature::zero(),
+    );
+    let json = serde_json::to_value(&msg).unwrap();
+    let msg2: MsgAnchoringFeeRate = serde_json::from_value(json).unwrap();
+    assert_eq!(msg2, msg);
+    assert_eq!(msg2.fee_rate(), 15);
+}
+
+#[test]
+fn test_msg_fee_rate_verify() {
+    let (public_key, secret_key) = gen_keypair();
+    let msg = MsgAnchoringFeeRate::new(&public_key, ValidatorId(0), 15, &secret_key);
+    assert!(msg.verify());
+    let msg = MsgAnchoringFeeRate::new(&public_key, ValidatorId(0), 0, &secret_key);
+    assert!(!msg.verify());
+}
+
+#[test]
+fn test_msg_finalized_tx_json_serde() {
+    let (pub_keys, _) = gen_anchoring_keys(4);
+    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3)
+        .compressed(Network::Testnet)
+        .unwrap();
+    let tx = dummy_anchoring_tx(&redeem_script);
+
+    let proof = TxOutProof::new(
+        "00000000000000f1a8c2b7e3d4f5a6b7c8d9e0f1a2b3c4d5e6f7a8b9c0d1e2f3",
+        "0000002001",
+    );
+
+    let msg = MsgAnchoringFinalizedTx::new_with_signature(
+        &PublicKey::zero(),
+        ValidatorId(2),
+        tx.clone(),
+        proof.clone(),
+        &Signature::zero(),
+    );
+    let json = serde_json::to_value(&msg).unwrap();
+    let msg2: MsgAnchoringFinalizedTx = serde_json::from_value(json).unwrap();
+    assert_eq!(msg2, msg);
+    assert_eq!(msg2.tx(), tx);
+    assert_eq!(msg2.tx_out_proof(), proof);
+}
+
+#[test]
+fn test_fee_rate_estimate_policy() {
+    let (pub_keys, _) = gen_anchoring_keys(4);
+    let mut cfg = AnchoringConfig::new(Network::Testnet, pub_keys.clone());
+    cfg.fee_policy = FeePolicy::Estimate {
+        conf_target: 6,
+        min_sat_per_vbyte: 2,
+        max_sat_per_vbyte: 50,
+    };
+
+    let db = MemoryDB::new();
+    let mut fork = db.fork();
+    let mut schema = AnchoringSchema::new(&mut fork);
+    // Not enough estimations
+    assert_eq!(schema.fee_rate(&cfg), Some(2));
+    schema.fee_rates_mut().put(&pub_keys[0], 10);
+    schema.fee_rates_mut().put(&pub_keys[1], 30);
+    assert_eq!(schema.fee_rate(&cfg), Some(2));
+    // Median of the estimations
+    schema.fee_rates_mut().put(&pub_keys[2], 20);
+    assert_eq!(schema.fee_rate(&cfg), Some(20));
+    // Estimations are bounded by the policy limits
+    schema.fee_rates_mut().put(&pub_keys[1], 1000);
+    schema.fee_rates_mut().put(&pub_keys[2], 100);
+    assert_eq!(schema.fee_rate(&cfg), Some(50));
+
+    assert_eq!(cfg.fee_policy.max(), Some(50));
+
+    cfg.fee_policy = FeePolicy::Rate { sat_per_vbyte: 5 };
+    assert_eq!(schema.fee_rate(&cfg), Some(5));
+    assert_eq!(cfg.fee_policy.max(), Some(5));
+    cfg.fee_policy = FeePolicy::Flat;
+    assert_eq!(schema.fee_rate(&cfg), None);
+    assert_eq!(cfg.fee_policy.max(), None);
+}
+
+#[test]
+fn test_anchoring_schema_blocks_root() {
+    let db = MemoryDB::new();
+    let mut fork = db.fork();
+    let block_hashes = (0..10u8).map(|i| hash(&[i])).collect::<Vec<_>>();
+    Schema::new(&mut fork)
+        .block_hashes_by_height_mut()
+        .extend(block_hashes.iter().cloned());
+
+    for from in 0..3 {
+        for to in from..10 {
+            let mut index_fork = db.fork();
+            let mut index = ProofListIndex::new("blocks", &mut index_fork);
+            index.extend(block_hashes[from..to + 1].iter().cloned());
+
+            let schema = AnchoringSchema::new(&fork);
+            let (from, to) = (Height(from as u64), Height(to as u64));
+            assert_eq!(schema.blocks_root(from, to), index.root_hash());
+            for height in from.0..to.0 + 1 {
+                assert_eq!(
+                    schema.blocks_root_proof(from, to, Height(height)),
+                    index.get_proof(height - from.0)
+                );
+            }
+        }
+    }
+}
+
 #[test]
 fn test_nonexistent_input_in_msg_signature_tx_body() {
     let (pub_keys, priv_keys) = gen_anchoring_keys(4);
-    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3).compressed(Network::Bitcoin);
+    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3)
+        .compressed(Network::Bitcoin)
+        .unwrap();
 
     let tx = dummy_anchoring_tx(&redeem_script);
     let btc_signatures = make_signatures(&redeem_script, &tx, &[0], &priv_keys);
@@ -154,3 +471,55 @@ fn test_nonexistent_input_in_msg_signature_tx_body() {
     );
     assert!(!msg.verify_content());
 }
+
+#[test]
+fn test_anchoring_schema_add_finalized_tx() {
+    let (pub_keys, _) = gen_anchoring_keys(4);
+    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3)
+        .compressed(Network::Testnet)
+        .unwrap();
+    let addr = btc::Address::from_script(&redeem_script, Network::Testnet);
+
+    let mut txs = vec![dummy_anchoring_tx(&redeem_script)];
+    for height in &[10, 20] {
+        let tx = TransactionBuilder::with_prev_tx(txs.last().unwrap(), 0)
+            .fee(500)
+            .payload(Height(*height), hash(&[*height as u8]))
+            .send_to(addr.clone())
+            .into_transaction()
+            .unwrap();
+        txs.push(tx);
+    }
+
+    let db = MemoryDB::new();
+    let mut fork = db.fork();
+    let mut schema = AnchoringSchema::new(&mut fork);
+    for tx in &txs {
+        schema.known_txs_mut().put(&tx.id(), tx.clone().into());
+    }
+
+    // The finalized transaction is appended along with its known predecessors.
+    schema.add_finalized_tx(txs[1].clone());
+    assert_eq!(schema.anchoring_tx_chain().len(), 2);
+    assert_eq!(schema.anchoring_tx_by_height(0), Some(txs[0].clone()));
+    assert_eq!(schema.anchoring_tx_by_height(10), Some(txs[1].clone()));
+    assert_eq!(schema.nearest_anchoring_tx(1), Some(txs[1].clone()));
+    assert_eq!(schema.nearest_anchoring_tx(11), None);
+
+    // The finalized predecessors are not appended again.
+    schema.add_finalized_tx(txs[2].clone());
+    schema.add_finalized_tx(txs[1].clone());
+    let chain = schema.anchoring_tx_chain().iter().collect::<Vec<_>>();
+    assert_eq!(chain, txs);
+    assert_eq!(schema.nearest_anchoring_tx(11), Some(txs[2].clone()));
+
+    // Transactions are finalized by their txids rather than the anchored heights.
+    let other_tx = TransactionBuilder::with_prev_tx(&txs[0], 0)
+        .fee(1000)
+        .payload(Height(5), hash(&[5]))
+        .send_to(addr.clone())
+        .into_transaction()
+        .unwrap();
+    assert!(txs.iter().all(|tx| schema.is_finalized_tx(tx)));
+    assert!(!schema.is_finalized_tx(&other_tx));
+}
@@ -19,10 +19,12 @@ use exonum::messages::Message;
 use exonum::storage::{Fork, Snapshot};
 use exonum::helpers::Height;
 
-use blockchain::dto::{MsgAnchoringSignature, MsgAnchoringUpdateLatest};
+use blockchain::dto::{MsgAnchoringFeeRate, MsgAnchoringFinalizedTx, MsgAnchoringSignature,
+                      MsgAnchoringUpdateLatest};
 use blockchain::schema::AnchoringSchema;
-use blockchain::consensus_storage::AnchoringConfig;
+use blockchain::consensus_storage::{AnchoringConfig, FeePolicy};
 use details::btc;
+use details::btc::payload;
 use details::btc::transactions::{AnchoringTx, BitcoinTx, FundingTx, TxKind};
 
 impl MsgAnchoringSignature {
@@ -55,6 +57,14 @@ impl MsgAnchoringSignature {
                 return false;
             }
         }
+        // Check that input witnesses are empty
+        if tx.witness.iter().any(|witness| !witness.is_empty()) {
+            warn!(
+                "Received msg with non empty input witnesses, content={:#?}",
+                self
+            );
+            return false;
+        }
         true
     }
 
@@ -89,11 +99,26 @@ impl MsgAnchoringSignature {
                 );
                 return false;
             }
-            if !verify_anchoring_tx_payload(&tx, &core_schema) {
+            if !verify_anchoring_tx_payload(&tx, &core_schema, &anchoring_schema) {
                 warn!("Received msg with incorrect payload, content={:#?}", self);
                 return false;
             }
-            if !tx.verify_input(&redeem_script, self.input(), pub_key, self.signature()) {
+            let is_valid_signature = if anchoring_cfg.address_type.is_witness() {
+                anchoring_schema
+                    .spent_output_value(&tx, self.input())
+                    .map_or(false, |value| {
+                        tx.verify_witness_input(
+                            &redeem_script,
+                            self.input(),
+                            value,
+                            pub_key,
+                            self.signature(),
+                        )
+                    })
+            } else {
+                tx.verify_input(&redeem_script, self.input(), pub_key, self.signature())
+            };
+            if !is_valid_signature {
                 warn!("Received msg with incorrect signature, content={:#?}", self);
                 return false;
             }
@@ -115,6 +140,7 @@ impl Transaction for MsgAnchoringSignature {
         }
 
         let mut anchoring_schema = AnchoringSchema::new(fork);
+        anchoring_schema.add_blocks_root(&self.tx());
         anchoring_schema.add_known_signature(self.clone())
     }
 }
@@ -138,7 +164,7 @@ impl MsgAnchoringUpdateLatest {
         let key = &anchoring_cfg.anchoring_keys[id];
         match TxKind::from(tx.clone()) {
             TxKind::Anchoring(tx) => {
-                if !verify_anchoring_tx_payload(&tx, &core_schema) {
+                if !verify_anchoring_tx_payload(&tx, &core_schema, &anchoring_schema) {
                     warn!("Received lect with incorrect payload, content={:#?}", self);
                     return None;
                 }
@@ -185,6 +211,127 @@ impl Transaction for MsgAnchoringUpdateLatest {
     }
 }
 
+impl MsgAnchoringFeeRate {
+    pub fn validate(&self, view: &Fork) -> Option<btc::PublicKey> {
+        let anchoring_schema = AnchoringSchema::new(view);
+        let core_schema = Schema::new(view);
+
+        let id = self.validator().0 as usize;
+        let actual_cfg = core_schema.actual_configuration();
+        if actual_cfg.validator_keys.get(id).map(|k| k.service_key) != Some(*self.from()) {
+            warn!("Received fee rate from non validator, content={:#?}", self);
+            return None;
+        }
+
+        let anchoring_cfg = anchoring_schema.actual_anchoring_config();
+        // Only the estimate policy uses the fee rates of the validators.
+        let (min_fee_rate, max_fee_rate) = match anchoring_cfg.fee_policy {
+            FeePolicy::Estimate {
+                min_sat_per_vbyte,
+                max_sat_per_vbyte,
+                ..
+            } => (min_sat_per_vbyte, max_sat_per_vbyte),
+            _ => {
+                warn!(
+                    "Received fee rate which is not used by the fee policy, content={:#?}",
+                    self
+                );
+                return None;
+            }
+        };
+        if self.fee_rate() < min_fee_rate || self.fee_rate() > max_fee_rate {
+            warn!(
+                "Received fee rate beyond the fee policy limits, content={:#?}",
+                self
+            );
+            return None;
+        }
+        anchoring_cfg.anchoring_keys.get(id).cloned()
+    }
+}
+
+impl Transaction for MsgAnchoringFeeRate {
+    fn verify(&self) -> bool {
+        self.fee_rate() > 0 && self.verify_signature(self.from())
+    }
+
+    fn execute(&self, view: &mut Fork) {
+        if let Some(key) = self.validate(view) {
+            let mut anchoring_schema = AnchoringSchema::new(view);
+            anchoring_schema.fee_rates_mut().put(&key, self.fee_rate())
+        }
+    }
+}
+
+impl MsgAnchoringFinalizedTx {
+    pub fn validate(&self, view: &Fork) -> Option<btc::PublicKey> {
+        let anchoring_schema = AnchoringSchema::new(view);
+        let core_schema = Schema::new(view);
+
+        let tx = self.tx();
+        let id = self.validator().0 as usize;
+        let actual_cfg = core_schema.actual_configuration();
+        if actual_cfg.validator_keys.get(id).map(|k| k.service_key) != Some(*self.from()) {
+            warn!(
+                "Received finalized tx from non validator, content={:#?}",
+                self
+            );
+            return None;
+        }
+
+        // Only the agreed anchoring transactions can be finalized
+        if anchoring_schema.known_txs().get(&tx.id()) != Some(tx.clone().into()) {
+            warn!("Received unknown finalized tx, content={:#?}", self);
+            return None;
+        }
+        // Votes for the finalized transaction are still needed to agree on its proof.
+        let has_proof = anchoring_schema.tx_out_proofs().contains(&tx.id());
+        if anchoring_schema.is_finalized_tx(&tx) && has_proof {
+            trace!("Received already finalized tx, content={:#?}", self);
+            return None;
+        }
+
+        let anchoring_cfg = anchoring_schema.actual_anchoring_config();
+        anchoring_cfg.anchoring_keys.get(id).cloned()
+    }
+}
+
+impl Transaction for MsgAnchoringFinalizedTx {
+    fn verify(&self) -> bool {
+        self.verify_signature(self.from())
+    }
+
+    fn execute(&self, view: &mut Fork) {
+        if let Some(key) = self.validate(view) {
+            let tx = self.tx();
+            let proof = self.tx_out_proof();
+            // Validators may see the transaction in the different bitcoin blocks,
+            // so the votes are counted for the transaction together with its proof.
+            let proof_hash = proof.hash();
+            let mut anchoring_schema = AnchoringSchema::new(view);
+            anchoring_schema
+                .finalized_tx_votes_mut(&tx.id(), &proof_hash)
+                .insert(key);
+
+            let anchoring_cfg = anchoring_schema.actual_anchoring_config();
+            let votes_count = {
+                let votes = anchoring_schema.finalized_tx_votes(&tx.id(), &proof_hash);
+                anchoring_cfg
+                    .anchoring_keys
+                    .iter()
+                    .filter(|&key| votes.contains(key))
+                    .count()
+            };
+            if votes_count >= anchoring_cfg.majority_count() as usize {
+                if !anchoring_schema.tx_out_proofs().contains(&tx.id()) {
+                    anchoring_schema.tx_out_proofs_mut().put(&tx.id(), proof);
+                }
+                anchoring_schema.add_finalized_tx(tx);
+            }
+        }
+    }
+}
+
 fn verify_anchoring_tx_prev_hash<T>(tx: &AnchoringTx, anchoring_schema: &AnchoringSchema<T>) -> bool
 where
     T: AsRef<Snapshot>,
@@ -240,11 +387,37 @@ where
     prev_lects_count >= anchoring_cfg.majority_count()
 }
 
-fn verify_anchoring_tx_payload<T>(tx: &AnchoringTx, schema: &Schema<T>) -> bool
+fn verify_anchoring_tx_payload<T>(
+    tx: &AnchoringTx,
+    schema: &Schema<T>,
+    anchoring_schema: &AnchoringSchema<T>,
+) -> bool
 where
     T: AsRef<Snapshot>,
 {
     let payload = tx.payload();
+    if let Some(blocks_root) = payload.blocks_root {
+        match anchoring_schema.anchored_blocks_start(tx) {
+            Some(start) if start <= payload.block_height => {
+                if anchoring_schema.blocks_root(start, payload.block_height) != blocks_root {
+                    return false;
+                }
+            }
+            _ => return false,
+        }
+    }
+    if let Some(network_id) = payload.network_id {
+        let genesis_hash = schema.block_hashes_by_height().get(0);
+        if genesis_hash.map(|hash| payload::network_id(&hash)) != Some(network_id) {
+            return false;
+        }
+    }
+    if let Some(state_hash) = payload.state_hash {
+        let block = schema.blocks().get(&payload.block_hash);
+        if block.map(|block| *block.state_hash()) != Some(state_hash) {
+            return false;
+        }
+    }
     schema.block_hashes_by_height().get(payload.block_height.0) == Some(payload.block_hash)
 }
 
@@ -18,11 +18,16 @@
 //!
 use std::error::Error;
 use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
 use std::str::FromStr;
 
+use clap::{App, Arg, ArgMatches, SubCommand};
+use serde_json;
 use toml::Value;
 use bitcoin::util::base58::ToBase58;
-use bitcoin::network::constants::Network;
 
 use exonum::helpers::fabric::{AbstractConfig, Argument, CommandExtension, CommandName,
                               CommonConfigTemplate, Context, NodePublicConfig, ServiceFactory};
@@ -33,9 +38,11 @@ use exonum::encoding::serialize::FromHex;
 use service::AnchoringService;
 use super::{AnchoringConfig, AnchoringNodeConfig, AnchoringRpcConfig, gen_btc_keypair};
 use details::btc::{self, PrivateKey, PublicKey};
-use details::rpc::{RpcClient, BitcoinRelay};
+use details::rpc::{BitcoinRelay, RelayBackend, RpcCredentials, RpcTlsConfig, UtxoDiscovery};
 use bitcoin::util::base58::FromBase58;
 use observer::AnchoringObserverConfig;
+use api::AnchoringProof;
+use verifier::{self, ProofVerifier, Verdict};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 /// Anchoring configuration that should be saved into the file
@@ -75,6 +82,66 @@ impl CommandExtension for GenerateNodeConfig {
                 "anchoring-password",
                 false
             ),
+            Argument::new_named(
+                "ANCHORING_RPC_BACKEND",
+                false,
+                "Bitcoin relay backend which serves the host, `bitcoind` (default), `electrum` \
+                 or `esplora`.",
+                None,
+                "anchoring-backend",
+                false
+            ),
+            Argument::new_named(
+                "ANCHORING_UTXO_DISCOVERY",
+                false,
+                "The way bitcoind discovers the anchoring outputs, `importaddress` (default), \
+                 `importdescriptors` or `scantxoutset`.",
+                None,
+                "anchoring-utxo-discovery",
+                false
+            ),
+            Argument::new_named(
+                "ANCHORING_RPC_COOKIE_FILE",
+                false,
+                "Path to the bitcoind `.cookie` file, which is used instead of the user \
+                 and the password.",
+                None,
+                "anchoring-cookie-file",
+                false
+            ),
+            Argument::new_named(
+                "ANCHORING_RPC_PASSWD_FILE",
+                false,
+                "Path to the file with the password to login into bitcoind.",
+                None,
+                "anchoring-password-file",
+                false
+            ),
+            Argument::new_named(
+                "ANCHORING_RPC_PASSWD_ENV",
+                false,
+                "Environment variable with the password to login into bitcoind.",
+                None,
+                "anchoring-password-env",
+                false
+            ),
+            Argument::new_named(
+                "ANCHORING_RPC_TLS_CA_FILE",
+                false,
+                "PEM file with the additional trusted certificates of the https host.",
+                None,
+                "anchoring-tls-ca-file",
+                false
+            ),
+            Argument::new_named(
+                "ANCHORING_RPC_TLS_PIN",
+                false,
+                "Hex encoded SHA-256 fingerprint of the https host certificate, \
+                 which is trusted instead of the certificate chain.",
+                None,
+                "anchoring-tls-pin",
+                false
+            ),
             Argument::new_named(
                 "ANCHORING_OBSERVER_CHECK_INTERVAL",
                 false,
@@ -93,6 +160,29 @@ impl CommandExtension for GenerateNodeConfig {
         );
         let user = context.arg("ANCHORING_RPC_USER").ok();
         let passwd = context.arg("ANCHORING_RPC_PASSWD").ok();
+        let backend: RelayBackend = match context.arg::<String>("ANCHORING_RPC_BACKEND") {
+            Ok(backend) => backend.parse()?,
+            Err(_) => RelayBackend::default(),
+        };
+        let utxo_discovery = match context.arg::<String>("ANCHORING_UTXO_DISCOVERY") {
+            Ok(utxo_discovery) => utxo_discovery.parse::<UtxoDiscovery>()?,
+            Err(_) => UtxoDiscovery::default(),
+        };
+        let credentials = RpcCredentials {
+            cookie_file: context.arg::<String>("ANCHORING_RPC_COOKIE_FILE").ok().map(
+                PathBuf::from,
+            ),
+            password_file: context.arg::<String>("ANCHORING_RPC_PASSWD_FILE").ok().map(
+                PathBuf::from,
+            ),
+            password_env: context.arg("ANCHORING_RPC_PASSWD_ENV").ok(),
+        };
+        let tls = RpcTlsConfig {
+            ca_file: context.arg::<String>("ANCHORING_RPC_TLS_CA_FILE").ok().map(
+                PathBuf::from,
+            ),
+            pinned_cert_sha256: context.arg("ANCHORING_RPC_TLS_PIN").ok(),
+        };
         let observer_check_interval = context.arg("ANCHORING_OBSERVER_CHECK_INTERVAL").ok();
 
         let config: CommonConfigTemplate = context.get("common_config").unwrap();
@@ -104,11 +194,7 @@ impl CommandExtension for GenerateNodeConfig {
             .clone()
             .try_into()
             .unwrap();
-        let network = match network.as_str() {
-            "testnet" => Network::Testnet,
-            "bitcoin" => Network::Bitcoin,
-            _ => panic!("Wrong network type"),
-        };
+        let network: btc::Network = network.parse()?;
 
         let (p, s) = gen_btc_keypair(network);
         let mut services_public_configs: BTreeMap<String, Value> =
@@ -126,6 +212,14 @@ impl CommandExtension for GenerateNodeConfig {
             host: host,
             username: user,
             password: passwd,
+            backend,
+            utxo_discovery,
+            credentials,
+            tls: if tls == RpcTlsConfig::default() {
+                None
+            } else {
+                Some(tls)
+            },
         };
         let observer_config = {
             let mut observer_config = AnchoringObserverConfig::default();
@@ -194,7 +288,19 @@ impl CommandExtension for GenerateCommonConfig {
                 "anchoring-fee",
                 false
             ),
-            Argument::new_positional("NETWORK", true, "Anchoring network name."),
+            Argument::new_named(
+                "ANCHORING_ADDRESS_TYPE",
+                false,
+                "Type of the anchoring address: `p2sh` (default), `p2wsh` or `p2sh-p2wsh`.",
+                None,
+                "anchoring-address-type",
+                false
+            ),
+            Argument::new_positional(
+                "NETWORK",
+                true,
+                "Anchoring network name (bitcoin, testnet, regtest or signet).",
+            ),
         ]
     }
 
@@ -207,9 +313,13 @@ impl CommandExtension for GenerateCommonConfig {
             "Expected `ANCHORING_FEE` \
              in cmd.",
         );
-        let network = context.arg::<String>("NETWORK").expect(
+        let network = context.arg::<btc::Network>("NETWORK").expect(
             "No network name found.",
         );
+        let address_type: btc::AddressType = context
+            .arg::<String>("ANCHORING_ADDRESS_TYPE")
+            .map(|address_type| Value::String(address_type).try_into())
+            .unwrap_or_else(|_| Ok(btc::AddressType::default()))?;
 
         let mut values: BTreeMap<String, Value> = context.get("services_config").expect(
             "Expected services_config \
@@ -229,7 +339,11 @@ impl CommandExtension for GenerateCommonConfig {
                 ("anchoring_fee".to_owned(), Value::try_from(fee).unwrap()),
                 (
                     "anchoring_network".to_owned(),
-                    Value::try_from(network).unwrap()
+                    Value::try_from(network.to_string()).unwrap()
+                ),
+                (
+                    "anchoring_address_type".to_owned(),
+                    Value::try_from(address_type).unwrap()
                 ),
             ].into_iter(),
         );
@@ -317,13 +431,15 @@ impl CommandExtension for Finalize {
             .expect("Anchoring fee not fount")
             .clone()
             .try_into()?;
-
-        let network = match network.as_str() {
-            "testnet" => Network::Testnet,
-            "bitcoin" => Network::Bitcoin,
-            _ => panic!("Wrong network type"),
+        let address_type: btc::AddressType = match common_config
+            .services_config
+            .get("anchoring_address_type") {
+            Some(address_type) => address_type.clone().try_into()?,
+            None => btc::AddressType::default(),
         };
 
+        let network: btc::Network = network.parse()?;
+
         let priv_key: PrivateKey = PrivateKey::from_base58check(&sec_key).unwrap();
         //TODO: validate config keys
         let _pub_key = PublicKey::from_hex(&pub_key).unwrap();
@@ -339,14 +455,13 @@ impl CommandExtension for Finalize {
                 PublicKey::from_hex(&key).unwrap()
             })
             .collect();
-        let client = RpcClient::from(rpc.clone());
+        let client: Box<BitcoinRelay> = rpc.clone().into();
         let mut anchoring_config = AnchoringNodeConfig::new(Some(rpc));
         anchoring_config.observer = observer;
 
         let majority_count = ::majority_count(public_config_list.len() as u8);
-        let address = btc::RedeemScript::from_pubkeys(&pub_keys, majority_count)
-            .compressed(network)
-            .to_address(network);
+        let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, majority_count);
+        let address = btc::Address::from_redeem_script(&redeem_script, address_type, network);
 
         let mut genesis_cfg = if let Some(total_funds) = create_funding_tx_with_amount {
             client.watch_address(&address, false).unwrap();
@@ -364,13 +479,14 @@ impl CommandExtension for Finalize {
         };
 
         anchoring_config.private_keys.insert(
-            address.to_base58check(),
+            address.to_string(),
             priv_key.clone(),
         );
 
         genesis_cfg.fee = fee;
         genesis_cfg.frequency = frequency;
         genesis_cfg.utxo_confirmations = utxo_confirmations;
+        genesis_cfg.address_type = address_type;
 
         node_config.services_configs.insert(
             "anchoring_service".to_owned(),
@@ -408,3 +524,129 @@ impl ServiceFactory for AnchoringServiceFactory {
         Box::new(AnchoringService::new(anch_cfg.genesis, anch_cfg.node))
     }
 }
+
+/// Offline verifier of the anchoring proofs returned by the `anchoring_proof` endpoint.
+///
+/// Unlike the other commands it is not a part of the node bootstrapping process,
+/// so it needs neither the node config nor the running `bitcoind`.
+#[derive(Debug)]
+pub struct VerifyAnchoringProof;
+
+impl VerifyAnchoringProof {
+    /// Returns the name of the subcommand.
+    pub fn name() -> &'static str {
+        "verify-anchoring-proof"
+    }
+
+    /// Returns the subcommand description.
+    pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+        SubCommand::with_name(Self::name())
+            .about("Verifies the anchoring proof against the given bitcoin block headers")
+            .arg(
+                Arg::with_name("ANCHORING_PROOF")
+                    .long("proof")
+                    .help("Path to the json file with the anchoring proof")
+                    .takes_value(true)
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("BITCOIN_HEADERS")
+                    .long("headers")
+                    .help(
+                        "Path to the file with the hex-encoded bitcoin block headers, \
+                         one per line",
+                    )
+                    .takes_value(true)
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("ANCHORING_REDEEM_SCRIPT")
+                    .long("redeem-script")
+                    .help("Hex-encoded redeem script of the anchoring multisig")
+                    .takes_value(true)
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("ANCHORING_NETWORK_ID")
+                    .long("network-id")
+                    .help("Network identifier which the payload v.2 has to commit to")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("ANCHORING_INPUT_VALUES")
+                    .long("input-values")
+                    .help(
+                        "Comma separated values in satoshis of the outputs spent by \
+                         the anchoring transaction, required for the segwit inputs",
+                    )
+                    .takes_value(true)
+                    .use_delimiter(true),
+            )
+    }
+
+    /// Verifies the anchoring proof with the given subcommand arguments.
+    pub fn execute(matches: &ArgMatches) -> Result<Verdict, Box<Error>> {
+        let proof: AnchoringProof = {
+            let path = matches.value_of("ANCHORING_PROOF").unwrap();
+            serde_json::from_str(&read_file(path)?)?
+        };
+        let headers = {
+            let path = matches.value_of("BITCOIN_HEADERS").unwrap();
+            read_file(path)?
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(verifier::parse_block_header)
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        let redeem_script =
+            btc::RedeemScript::from_hex(matches.value_of("ANCHORING_REDEEM_SCRIPT").unwrap())?;
+        let network_id = match matches.value_of("ANCHORING_NETWORK_ID") {
+            Some(network_id) => Some(network_id.parse()?),
+            None => None,
+        };
+        let input_values = match matches.values_of("ANCHORING_INPUT_VALUES") {
+            Some(values) => values.map(u64::from_str).collect::<Result<Vec<_>, _>>()?,
+            None => Vec::new(),
+        };
+
+        let verifier = ProofVerifier::new(redeem_script, headers)
+            .network_id(network_id)
+            .input_values(input_values);
+        Ok(verifier.verify(&proof))
+    }
+
+    /// Runs the verifier if the given command line invokes this subcommand and prints
+    /// the verdict. Returns `None` if the command line is addressed to another command,
+    /// otherwise returns whether the proof is valid.
+    pub fn run<I, T>(cmd_line: I) -> Option<bool>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let args: Vec<OsString> = cmd_line.into_iter().map(Into::into).collect();
+        if args.get(1).map_or(true, |arg| arg != Self::name()) {
+            return None;
+        }
+
+        let matches = App::new("anchoring")
+            .subcommand(Self::subcommand())
+            .get_matches_from(args);
+        let matches = matches.subcommand_matches(Self::name()).unwrap();
+        match Self::execute(matches) {
+            Ok(verdict) => {
+                println!("{}", serde_json::to_string_pretty(&verdict).unwrap());
+                Some(verdict.is_valid())
+            }
+            Err(e) => {
+                eprintln!("Unable to verify the anchoring proof: {}", e);
+                Some(false)
+            }
+        }
+    }
+}
+
+fn read_file(path: &str) -> Result<String, Box<Error>> {
+    let mut content = String::new();
+    File::open(path)?.read_to_string(&mut content)?;
+    Ok(content)
+}
@@ -0,0 +1,400 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `BitcoinRelay` over bitcoind with the legacy or descriptor wallet or without the wallet at all,
+//! with the credentials from the files or the environment and over TLS.
+
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use bitcoinrpc;
+use jsonrpc;
+use serde_json::Value;
+
+use details::btc;
+use details::btc::transactions::{BitcoinTx, FundingTx};
+use blockchain::dto::TxOutProof;
+use super::rpc::{batch_transactions, batch_tx_outs_confirmations, parse_transaction, parse_txid,
+                 unspent_tx_infos, AnchoringRpcConfig, BitcoinRelay, Error, FeeEstimation,
+                 MempoolEntry, Result, RpcTransport, TransactionBlock, TxInfo, TxOut,
+                 UtxoDiscovery, SATOSHI_DIVISOR};
+
+/// Characters of the descriptors in the order of their checksum values.
+const DESCRIPTOR_CHARSET: &str = "0123456789()[],'/*abcdefgh@:$%{}\
+                                  IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~\
+                                  ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+/// Characters of the descriptor checksum.
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+/// Generator of the descriptor checksum code.
+const CHECKSUM_GENERATOR: [u64; 5] = [
+    0xf5_dee5_1989,
+    0xa9_fdca_3312,
+    0x1b_ab10_e32d,
+    0x37_06b1_677a,
+    0x64_4d62_6ffd,
+];
+
+/// Result of the single request from the `importdescriptors` response.
+#[derive(Deserialize, Debug)]
+struct ImportDescriptorsResult {
+    success: bool,
+    error: Option<Value>,
+}
+
+/// Response of the `scantxoutset` RPC endpoint.
+#[derive(Deserialize, Debug)]
+struct ScanTxOutSet {
+    /// Height of the best block at the moment of the scan.
+    height: u64,
+    unspents: Vec<ScannedOutput>,
+}
+
+/// Unspent output from the `scantxoutset` response.
+#[derive(Deserialize, Debug)]
+struct ScannedOutput {
+    txid: String,
+    vout: u32,
+    /// Height of the block with the transaction.
+    height: u64,
+}
+
+/// Client for the `bitcoind` rpc api, for more information visit
+/// this [site](https://en.bitcoin.it/wiki/Original_Bitcoin_client/API_calls_list).
+///
+/// The client discovers the anchoring outputs by any of the
+/// [`UtxoDiscovery`](enum.UtxoDiscovery.html) ways and sends the requests by the
+/// [`RpcTransport`](struct.RpcTransport.html), so it supports the cookie authentication,
+/// the password files and the `https` hosts.
+///
+/// The UTXO set scan does not see the mempool, so in this mode the client remembers the sent
+/// transactions until they leave the mempool. Their outputs are listed as unconfirmed ones
+/// and the outputs spent by them are not listed at all. Funds are sent by the wallet
+/// in any mode, so the `send_to_address` needs the node with the wallet.
+#[derive(Debug)]
+pub struct RpcClient {
+    transport: RpcTransport,
+    sent: Mutex<Vec<BitcoinTx>>,
+}
+
+impl RpcClient {
+    /// Creates a client for the `bitcoind` of the given configuration.
+    pub fn new(cfg: AnchoringRpcConfig) -> RpcClient {
+        RpcClient {
+            transport: RpcTransport::new(cfg),
+            sent: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn utxo_discovery(&self) -> UtxoDiscovery {
+        self.transport.config().utxo_discovery
+    }
+
+    /// Sends the request and returns `None` if bitcoind has no information
+    /// about the requested object.
+    fn request_known<T>(&self, method: &str, params: Vec<Value>) -> Result<Option<T>>
+    where
+        for<'de> T: ::serde::Deserialize<'de>,
+    {
+        match self.transport.request(method, params) {
+            Ok(value) => Ok(Some(value)),
+            Err(Error::Rpc(bitcoinrpc::Error::NoInformation(_))) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn import_address(&self, addr: &btc::Address, rescan: bool) -> Result<()> {
+        let params = vec![addr.to_string().into(), "multisig".into(), false.into(), rescan.into()];
+        let response = self.transport.response("importaddress", params)?;
+        // Bitcoind returns the `null` result on success.
+        if response.error.is_none() && response.result.is_none() {
+            return Ok(());
+        }
+        response.into_result::<Value>()?;
+        Ok(())
+    }
+
+    fn list_unspent_transactions(&self, addr: &btc::Address) -> Result<Vec<TxInfo>> {
+        let params = vec![0.into(), 9_999_999.into(), json!([addr.to_string()])];
+        let unspent_txs: Vec<bitcoinrpc::UnspentTransactionInfo> =
+            self.transport.request("listunspent", params)?;
+        unspent_tx_infos(self, unspent_txs)
+    }
+
+    fn remember_sent(&self, tx: BitcoinTx) {
+        if self.utxo_discovery() == UtxoDiscovery::ScanTxOutSet {
+            let mut sent = self.sent.lock().unwrap();
+            if sent.iter().all(|sent_tx| sent_tx.id() != tx.id()) {
+                sent.push(tx);
+            }
+        }
+    }
+
+    /// Returns the sent transactions which are still in the mempool and forgets the other ones.
+    fn unconfirmed_sent(&self) -> Result<Vec<BitcoinTx>> {
+        let sent = self.sent.lock().unwrap().clone();
+        let mut unconfirmed = Vec::new();
+        for tx in sent {
+            if self.get_mempool_entry_height(tx.id())?.is_some() {
+                unconfirmed.push(tx);
+            } else {
+                let txid = tx.id();
+                self.sent.lock().unwrap().retain(|sent_tx| sent_tx.id() != txid);
+            }
+        }
+        Ok(unconfirmed)
+    }
+
+    fn import_descriptor(&self, addr: &btc::Address, rescan: bool) -> Result<()> {
+        let timestamp = if rescan { Value::from(0) } else { Value::from("now") };
+        let request = json!([{
+            "desc": address_descriptor(addr),
+            "timestamp": timestamp,
+            "label": "multisig",
+        }]);
+        let results: Vec<ImportDescriptorsResult> =
+            self.transport.request("importdescriptors", vec![request])?;
+        for result in results {
+            if !result.success {
+                return Err(match result.error {
+                    Some(error) => jsonrpc::Error::Rpc(error).into(),
+                    None => Error::Protocol("Descriptor is not imported".to_owned()),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn scan_unspent_transactions(&self, addr: &btc::Address) -> Result<Vec<TxInfo>> {
+        let params = vec![
+            "start".into(),
+            json!([{ "desc": address_descriptor(addr) }]),
+        ];
+        let scan: ScanTxOutSet = self.transport.request("scantxoutset", params)?;
+        let unconfirmed = self.unconfirmed_sent()?;
+        let spent = unconfirmed
+            .iter()
+            .flat_map(|tx| {
+                tx.input.iter().map(|input| (btc::TxId::from(input.prev_hash), input.prev_index))
+            })
+            .collect::<HashSet<_>>();
+
+        let mut outputs = Vec::new();
+        for output in scan.unspents {
+            let txid = parse_txid(&output.txid)?;
+            if !spent.contains(&(txid, output.vout)) {
+                outputs.push((txid, output));
+            }
+        }
+        let txids = outputs.iter().map(|&(txid, _)| txid).collect::<Vec<_>>();
+        let raw_txs = self.get_transactions(&txids)?;
+
+        let mut txs = Vec::new();
+        for ((_, output), raw_tx) in outputs.into_iter().zip(raw_txs) {
+            let confirmations = (scan.height + 1).saturating_sub(output.height);
+            if let Some(tx) = raw_tx {
+                txs.extend(TxInfo::unspent(tx, Some(confirmations), output.vout));
+            }
+        }
+        let script_pubkey = addr.script_pubkey();
+        for tx in unconfirmed {
+            let txid = tx.id();
+            for (vout, output) in tx.output.iter().enumerate() {
+                let vout = vout as u32;
+                if output.script_pubkey == script_pubkey && !spent.contains(&(txid, vout)) {
+                    txs.extend(TxInfo::unspent(tx.clone(), Some(0), vout));
+                }
+            }
+        }
+        Ok(txs)
+    }
+}
+
+impl From<AnchoringRpcConfig> for RpcClient {
+    fn from(cfg: AnchoringRpcConfig) -> Self {
+        RpcClient::new(cfg)
+    }
+}
+
+impl BitcoinRelay for RpcClient {
+    fn get_transaction(&self, txid: btc::TxId) -> Result<Option<BitcoinTx>> {
+        let params = vec![txid.to_string().into(), 0.into()];
+        match self.request_known::<String>("getrawtransaction", params)? {
+            Some(hex) => Ok(Some(parse_transaction(hex)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn get_transactions(&self, txids: &[btc::TxId]) -> Result<Vec<Option<BitcoinTx>>> {
+        batch_transactions(&self.transport, txids)
+    }
+
+    fn get_transaction_info(&self, txid: btc::TxId) -> Result<Option<TxInfo>> {
+        let params = vec![txid.to_string().into(), 1.into()];
+        match self.request_known("getrawtransaction", params)? {
+            Some(info) => Ok(Some(TxInfo::from_raw(info)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn watch_address(&self, addr: &btc::Address, rescan: bool) -> Result<()> {
+        match self.utxo_discovery() {
+            UtxoDiscovery::ImportAddress => self.import_address(addr, rescan),
+            UtxoDiscovery::ImportDescriptors => self.import_descriptor(addr, rescan),
+            // The whole UTXO set is scanned each time.
+            UtxoDiscovery::ScanTxOutSet => Ok(()),
+        }
+    }
+
+    fn send_transaction(&self, tx: BitcoinTx) -> Result<()> {
+        self.transport.request::<String>("sendrawtransaction", vec![tx.to_hex().into()])?;
+        self.remember_sent(tx);
+        Ok(())
+    }
+
+    fn send_to_address(&self, addr: &btc::Address, satoshis: u64) -> Result<FundingTx> {
+        let funds_str = (satoshis as f64 / SATOSHI_DIVISOR).to_string();
+        let params = vec![addr.to_string().into(), funds_str.into()];
+        let txid = parse_txid(&self.transport.request::<String>("sendtoaddress", params)?)?;
+        let tx = match self.get_transaction(txid)? {
+            Some(tx) => tx,
+            None => {
+                let msg = format!("Sent transaction is not found, txid={}", txid.to_string());
+                return Err(Error::Protocol(msg));
+            }
+        };
+        self.remember_sent(tx.clone());
+        Ok(FundingTx::from(tx))
+    }
+
+    fn unspent_transactions(&self, addr: &btc::Address) -> Result<Vec<TxInfo>> {
+        match self.utxo_discovery() {
+            UtxoDiscovery::ImportAddress |
+            UtxoDiscovery::ImportDescriptors => self.list_unspent_transactions(addr),
+            UtxoDiscovery::ScanTxOutSet => self.scan_unspent_transactions(addr),
+        }
+    }
+
+    fn get_block_count(&self) -> Result<u64> {
+        self.transport.request("getblockcount", vec![])
+    }
+
+    fn get_best_block_hash(&self) -> Result<String> {
+        self.transport.request("getbestblockhash", vec![])
+    }
+
+    fn get_mempool_entry_height(&self, txid: btc::TxId) -> Result<Option<u64>> {
+        let entry = self.request_known::<MempoolEntry>(
+            "getmempoolentry",
+            vec![txid.to_string().into()],
+        )?;
+        Ok(entry.map(|entry| entry.height))
+    }
+
+    fn get_tx_out_confirmations(&self, txid: btc::TxId, vout: u32) -> Result<Option<u64>> {
+        let params = vec![txid.to_string().into(), vout.into(), true.into()];
+        let response = self.transport.response("gettxout", params)?;
+        // Bitcoind returns the `null` result for the spent and unknown outputs.
+        if response.error.is_none() && response.result.is_none() {
+            return Ok(None);
+        }
+        let out: TxOut = response.into_result()?;
+        Ok(Some(out.confirmations))
+    }
+
+    fn get_tx_outs_confirmations(
+        &self,
+        outpoints: &[(btc::TxId, u32)],
+    ) -> Result<Vec<Option<u64>>> {
+        batch_tx_outs_confirmations(&self.transport, outpoints)
+    }
+
+    fn estimate_fee_rate(&self, conf_target: u16) -> Result<Option<u64>> {
+        let estimation: FeeEstimation =
+            self.transport.request("estimatesmartfee", vec![conf_target.into()])?;
+        Ok(estimation.sat_per_vbyte())
+    }
+
+    fn get_tx_out_proof(&self, txid: btc::TxId) -> Result<Option<TxOutProof>> {
+        let params = vec![txid.to_string().into(), 1.into()];
+        let block = self.request_known::<TransactionBlock>("getrawtransaction", params)?;
+        let block_hash = match block {
+            Some(TransactionBlock { blockhash: Some(block_hash) }) => block_hash,
+            _ => return Ok(None),
+        };
+        let params = vec![json!([txid.to_string()]), block_hash.clone().into()];
+        let proof: String = self.transport.request("gettxoutproof", params)?;
+        Ok(Some(TxOutProof::new(&block_hash, &proof)))
+    }
+
+    fn config(&self) -> AnchoringRpcConfig {
+        self.transport.config().clone()
+    }
+}
+
+/// Returns the `addr()` output descriptor of the address with its checksum.
+pub fn address_descriptor(addr: &btc::Address) -> String {
+    let descriptor = format!("addr({})", addr);
+    let checksum = descriptor_checksum(&descriptor).expect(
+        "Address contains only the descriptor characters",
+    );
+    format!("{}#{}", descriptor, checksum)
+}
+
+/// Computes the checksum of the output descriptor as specified by BIP-380.
+/// Returns `None` if the descriptor contains characters which are not allowed in descriptors.
+pub fn descriptor_checksum(descriptor: &str) -> Option<String> {
+    let mut symbols = Vec::new();
+    let mut groups = Vec::new();
+    for c in descriptor.chars() {
+        let value = match DESCRIPTOR_CHARSET.find(c) {
+            Some(value) => value as u64,
+            None => return None,
+        };
+        symbols.push(value & 31);
+        groups.push(value >> 5);
+        if groups.len() == 3 {
+            symbols.push(groups[0] * 9 + groups[1] * 3 + groups[2]);
+            groups.clear();
+        }
+    }
+    match groups.len() {
+        1 => symbols.push(groups[0]),
+        2 => symbols.push(groups[0] * 3 + groups[1]),
+        _ => {}
+    }
+    symbols.extend_from_slice(&[0; 8]);
+
+    let checksum = checksum_polymod(&symbols) ^ 1;
+    let checksum = (0..8)
+        .map(|i| {
+            let value = (checksum >> (5 * (7 - i))) & 31;
+            CHECKSUM_CHARSET[value as usize] as char
+        })
+        .collect();
+    Some(checksum)
+}
+
+fn checksum_polymod(symbols: &[u64]) -> u64 {
+    let mut checksum = 1;
+    for symbol in symbols {
+        let top = checksum >> 35;
+        checksum = ((checksum & 0x7_ffff_ffff) << 5) ^ symbol;
+        for (i, generator) in CHECKSUM_GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
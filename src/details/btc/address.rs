@@ -12,12 +12,182 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use bitcoin::network::constants::Network;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
 
-use super::types::{Address, RawAddress, RawScript};
+use bitcoin::blockdata::script::Builder;
+use bitcoin::util::address::Type as AddressKind;
+use bitcoin::util::hash::Hash160;
+use bitcoin::util::base58::{Error as FromBase58Error, FromBase58, ToBase58};
+use bech32::{self, FromBase32, ToBase32, u5};
+
+use super::Network;
+use super::types::{Address, AddressType, RawAddress, RawScript};
+
+const WITNESS_V0_SCRIPT_HASH_LEN: usize = 32;
 
 impl Address {
+    /// Creates legacy `p2sh` address for the given redeem script.
     pub fn from_script(script: &RawScript, network: Network) -> Address {
-        RawAddress::from_script(network, script).into()
+        Address::Base58(network, RawAddress::from_script(network.into(), script))
+    }
+
+    /// Creates address of the given type for the given redeem script.
+    pub fn from_redeem_script(
+        script: &RawScript,
+        address_type: AddressType,
+        network: Network,
+    ) -> Address {
+        match address_type {
+            AddressType::P2sh => Address::from_script(script, network),
+            AddressType::P2wsh => {
+                let mut hash = [0; WITNESS_V0_SCRIPT_HASH_LEN];
+                hash.copy_from_slice(&script.to_v0_p2wsh()[2..]);
+                Address::WitnessScriptHash(network, hash)
+            }
+            AddressType::P2shP2wsh => Address::from_script(&script.to_v0_p2wsh(), network),
+        }
+    }
+
+    /// Recovers address from the output script if it is a `p2pkh`, `p2sh` or `p2wsh` one.
+    pub fn from_script_pubkey(script_pubkey: &RawScript, network: Network) -> Option<Address> {
+        let bytes = &script_pubkey[..];
+        if script_pubkey.is_v0_p2wsh() {
+            let mut hash = [0; WITNESS_V0_SCRIPT_HASH_LEN];
+            hash.copy_from_slice(&bytes[2..]);
+            Some(Address::WitnessScriptHash(network, hash))
+        } else if script_pubkey.is_p2sh() {
+            Some(Address::Base58(
+                network,
+                RawAddress {
+                    ty: AddressKind::ScriptHash,
+                    network: network.into(),
+                    hash: Hash160::from(&bytes[2..22]),
+                },
+            ))
+        } else if script_pubkey.is_p2pkh() {
+            Some(Address::Base58(
+                network,
+                RawAddress {
+                    ty: AddressKind::PubkeyHash,
+                    network: network.into(),
+                    hash: Hash160::from(&bytes[3..23]),
+                },
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the network of the address.
+    ///
+    /// Regtest and signet addresses are indistinguishable from the testnet ones, so the parsed
+    /// addresses of these networks report `Testnet`.
+    pub fn network(&self) -> Network {
+        match *self {
+            Address::Base58(network, _) | Address::WitnessScriptHash(network, _) => network,
+        }
+    }
+
+    pub fn script_pubkey(&self) -> RawScript {
+        match *self {
+            Address::Base58(_, ref addr) => addr.script_pubkey(),
+            Address::WitnessScriptHash(_, ref hash) => Builder::new()
+                .push_int(0)
+                .push_slice(hash)
+                .into_script(),
+        }
+    }
+}
+
+impl From<RawAddress> for Address {
+    fn from(addr: RawAddress) -> Address {
+        Address::Base58(addr.network.into(), addr)
+    }
+}
+
+fn decode_bech32(s: &str) -> Result<Address, FromBase58Error> {
+    let wrong_address = |e: bech32::Error| FromBase58Error::Other(e.to_string());
+
+    let (hrp, data) = bech32::decode(s).map_err(wrong_address)?;
+    let network = match Network::from_bech32_hrp(&hrp) {
+        Some(network) => network,
+        None => return Err(FromBase58Error::Other(format!("Unknown bech32 prefix: {}", hrp))),
+    };
+    if data.first().map(|v| v.to_u8()) != Some(0) {
+        return Err(FromBase58Error::Other(
+            "Unsupported witness version".to_owned(),
+        ));
+    }
+
+    let program = Vec::<u8>::from_base32(&data[1..]).map_err(wrong_address)?;
+    if program.len() != WITNESS_V0_SCRIPT_HASH_LEN {
+        return Err(FromBase58Error::Other(
+            "Wrong witness script hash length".to_owned(),
+        ));
+    }
+    let mut hash = [0; WITNESS_V0_SCRIPT_HASH_LEN];
+    hash.copy_from_slice(&program);
+    Ok(Address::WitnessScriptHash(network, hash))
+}
+
+impl FromStr for Address {
+    type Err = FromBase58Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match RawAddress::from_base58check(s) {
+            Ok(addr) => Ok(addr.into()),
+            Err(e) => decode_bech32(s).map_err(|_| e),
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Address::Base58(_, ref addr) => f.write_str(&addr.to_base58check()),
+            Address::WitnessScriptHash(network, ref hash) => {
+                let mut data = vec![u5::try_from_u8(0).unwrap()];
+                data.extend(hash.to_base32());
+                let addr = bech32::encode(network.bech32_hrp(), data).map_err(|_| fmt::Error)?;
+                f.write_str(&addr)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\"Address({})\"", self)
+    }
+}
+
+// Addresses of the test networks are equal if they have the same encoding.
+impl PartialEq for Address {
+    fn eq(&self, other: &Address) -> bool {
+        self.to_string() == other.to_string()
+    }
+}
+
+impl Eq for Address {}
+
+impl Hash for Address {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Equal addresses have the same string representation.
+        self.to_string().hash(state)
+    }
+}
+
+impl Default for AddressType {
+    fn default() -> AddressType {
+        AddressType::P2sh
+    }
+}
+
+impl AddressType {
+    /// Returns `true` if inputs spending from this address type are signed using `BIP143`.
+    pub fn is_witness(&self) -> bool {
+        *self != AddressType::P2sh
     }
 }
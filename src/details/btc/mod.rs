@@ -17,6 +17,7 @@
 mod types;
 mod redeem_script;
 mod address;
+mod network;
 mod private_key;
 mod public_key;
 pub mod payload;
@@ -34,7 +35,10 @@ use exonum::encoding::serialize::FromHexError;
 /// For test purpose only
 pub use self::types::{Address, PrivateKey, PublicKey, RawTransaction, RedeemScript, Signature,
                       TxId};
-pub use bitcoin::network::constants::Network;
+pub use self::types::AddressType;
+pub use self::payload::PayloadVersion;
+pub use self::network::{Network, UnknownNetworkError};
+pub use self::redeem_script::RedeemScriptError;
 
 #[doc(hidden)]
 /// For test purpose only
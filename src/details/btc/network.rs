@@ -0,0 +1,119 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use bitcoin::network::constants::Network as RawNetwork;
+
+/// The bitcoin network type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Network {
+    /// The main bitcoin network.
+    Bitcoin,
+    /// The bitcoin test network.
+    Testnet,
+    /// The local regression test network.
+    Regtest,
+    /// The signet test network.
+    Signet,
+}
+
+/// The error returned when parsing an unknown network name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnknownNetworkError(String);
+
+impl Network {
+    /// Network names accepted by [`FromStr`](#impl-FromStr) implementation.
+    pub const NAMES: &'static [&'static str] = &["bitcoin", "testnet", "regtest", "signet"];
+
+    /// Returns human readable part of the bech32 addresses in this network.
+    pub fn bech32_hrp(&self) -> &'static str {
+        match *self {
+            Network::Bitcoin => "bc",
+            Network::Testnet | Network::Signet => "tb",
+            Network::Regtest => "bcrt",
+        }
+    }
+
+    /// Returns network for the given human readable part of the bech32 address.
+    ///
+    /// Signet and testnet share the same prefix, so `Testnet` is returned for both.
+    pub fn from_bech32_hrp(hrp: &str) -> Option<Network> {
+        match hrp {
+            "bc" => Some(Network::Bitcoin),
+            "tb" => Some(Network::Testnet),
+            "bcrt" => Some(Network::Regtest),
+            _ => None,
+        }
+    }
+}
+
+impl From<Network> for RawNetwork {
+    // All test networks use the testnet base58 prefixes.
+    fn from(network: Network) -> RawNetwork {
+        match network {
+            Network::Bitcoin => RawNetwork::Bitcoin,
+            Network::Testnet | Network::Regtest | Network::Signet => RawNetwork::Testnet,
+        }
+    }
+}
+
+impl From<RawNetwork> for Network {
+    fn from(network: RawNetwork) -> Network {
+        match network {
+            RawNetwork::Bitcoin => Network::Bitcoin,
+            RawNetwork::Testnet => Network::Testnet,
+        }
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Network::Bitcoin => "bitcoin",
+            Network::Testnet => "testnet",
+            Network::Regtest => "regtest",
+            Network::Signet => "signet",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Network {
+    type Err = UnknownNetworkError;
+
+    fn from_str(s: &str) -> Result<Network, UnknownNetworkError> {
+        match s {
+            "bitcoin" => Ok(Network::Bitcoin),
+            "testnet" => Ok(Network::Testnet),
+            "regtest" => Ok(Network::Regtest),
+            "signet" => Ok(Network::Signet),
+            other => Err(UnknownNetworkError(other.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for UnknownNetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Wrong network type: {}", self.0)
+    }
+}
+
+impl Error for UnknownNetworkError {
+    fn description(&self) -> &str {
+        "Wrong network type"
+    }
+}
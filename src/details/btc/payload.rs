@@ -27,6 +27,36 @@ const PAYLOAD_HEADER_LEN: usize = 8;
 const PAYLOAD_V1: u8 = 1;
 const PAYLOAD_V1_KIND_REGULAR: u8 = 0;
 const PAYLOAD_V1_KIND_RECOVER: u8 = 1;
+const PAYLOAD_V1_KIND_BLOCKS: u8 = 2;
+const PAYLOAD_V2: u8 = 2;
+const PAYLOAD_V2_KIND_REGULAR: u8 = 0;
+const PAYLOAD_V2_KIND_RECOVER: u8 = 1;
+const PAYLOAD_V2_KIND_BLOCKS: u8 = 2;
+
+/// Version of the anchoring transaction payload layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PayloadVersion {
+    /// Payload with the anchored block height and hash.
+    #[serde(rename = "v1")]
+    V1,
+    /// Payload additionally committing to the Exonum network identifier and state hash.
+    #[serde(rename = "v2")]
+    V2,
+}
+
+impl Default for PayloadVersion {
+    fn default() -> PayloadVersion {
+        PayloadVersion::V1
+    }
+}
+
+/// Returns the short identifier of the Exonum network with the given genesis block hash.
+///
+/// The identifier consists of the first four bytes of the hash and is used by the
+/// payload v.2 to distinguish anchoring transactions of the different networks.
+pub fn network_id(genesis_block_hash: &Hash) -> u32 {
+    LittleEndian::read_u32(&genesis_block_hash.as_ref()[0..4])
+}
 
 /// Anchoring transaction payload.
 ///
@@ -36,12 +66,37 @@ const PAYLOAD_V1_KIND_RECOVER: u8 = 1;
 /// |-------------------	|----------------------------------------------     |
 /// | 0..6               	| ASCII-encoded prefix `EXONUM` 	                |
 /// | 6                 	| Version byte, currently is 1             	        |
-/// | 7                     | Payload kind: (0 is regular, 1 is recover)        |
+/// | 7                     | Payload kind: (0 is regular, 1 is recover, 2 is blocks) |
 /// | 8..16                 | Block height                                      |
 /// | 16..48                | Block hash                                        |
 /// | 48..80 (Optionally)   | Txid of previous tx chain (only for recover kind) |
+/// | 48..80 (Optionally)   | Root of the anchored blocks (only for blocks kind) |
+///
+/// In this way the length of `regular` payload is 48, and for `recover` and `blocks` is 80.
+///
+/// Data layout in `OP_RETURN` script for `Payload` v.2:
+///
+/// | Position in bytes 	| Description                   	                |
+/// |-------------------	|----------------------------------------------     |
+/// | 0..6               	| ASCII-encoded prefix `EXONUM` 	                |
+/// | 6                 	| Version byte, is 2                     	        |
+/// | 7                     | Payload kind: (0 is regular, 1 is recover, 2 is blocks) |
+/// | 8..12                 | Exonum network identifier                         |
+/// | 12..16                | Block height                                      |
+/// | 16..48                | Block hash                                        |
+/// | 48..80                | Exonum state hash (only for regular kind)         |
+/// | 48..80 (Optionally)   | Txid of previous tx chain (only for recover kind) |
+/// | 48..80 (Optionally)   | Root of the anchored blocks (only for blocks kind) |
 ///
-/// In this way the length of `regular` payload is 48, and for `recover` is 80.
+/// The network identifier is the first four bytes of the Exonum genesis block hash.
+/// The block height is shortened to four bytes to keep the payloads within
+/// the 80 bytes allowed for the standard `OP_RETURN` output.
+/// The `recover` and `blocks` payloads have no room for the state hash, they commit to it
+/// only by the anchored block hash.
+///
+/// The root of the anchored blocks is the root hash of the Merkle tree over the hashes
+/// of all blocks since the block anchored by the previous transaction in the chain.
+/// The `recover` payload has no room for it, so it can not commit to the blocks root.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Payload {
     /// Anchored block height
@@ -50,23 +105,41 @@ pub struct Payload {
     pub block_hash: Hash,
     /// `Txid` of previous transactions chain if it has been lost.
     pub prev_tx_chain: Option<btc::TxId>,
+    /// Identifier of the anchored Exonum network, only for the payload v.2.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network_id: Option<u32>,
+    /// Root hash of the Merkle tree over the hashes of the anchored blocks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blocks_root: Option<Hash>,
+    /// Exonum state hash at the anchored block, only for the regular payload v.2.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state_hash: Option<Hash>,
 }
 
 #[derive(Debug)]
 enum PayloadV1 {
     Regular(Height, Hash),
     Recover(Height, Hash, btc::TxId),
+    Blocks(Height, Hash, Hash),
+}
+
+#[derive(Debug)]
+enum PayloadV2 {
+    Regular(u32, Height, Hash, Hash),
+    Recover(u32, Height, Hash, btc::TxId),
+    Blocks(u32, Height, Hash, Hash),
 }
 
 #[derive(Debug, Default)]
-pub struct PayloadV1Builder {
+pub struct PayloadBuilder {
     block_hash: Option<Hash>,
     block_height: Option<Height>,
     prev_tx_chain: Option<btc::TxId>,
+    network_id: Option<u32>,
+    blocks_root: Option<Hash>,
+    state_hash: Option<Hash>,
 }
 
-pub type PayloadBuilder = PayloadV1Builder;
-
 #[cfg_attr(feature = "cargo-clippy", allow(len_without_is_empty))]
 impl PayloadV1 {
     fn read(bytes: &[u8]) -> Option<PayloadV1> {
@@ -92,6 +165,16 @@ impl PayloadV1 {
                 let txid = btc::TxId::from_slice(&data[40..72]).unwrap();
                 Some(PayloadV1::Recover(Height(block_height), block_hash, txid))
             }
+            PAYLOAD_V1_KIND_BLOCKS => {
+                if data.len() != 72 {
+                    return None;
+                }
+
+                let block_height = LittleEndian::read_u64(&data[0..8]);
+                let block_hash = Hash::from_slice(&data[8..40]).unwrap();
+                let blocks_root = Hash::from_slice(&data[40..72]).unwrap();
+                Some(PayloadV1::Blocks(Height(block_height), block_hash, blocks_root))
+            }
             _ => None,
         }
     }
@@ -113,13 +196,18 @@ impl PayloadV1 {
                 buf[8..40].copy_from_slice(hash.as_ref());
                 buf[40..72].copy_from_slice(txid.as_bytes());
             }
+            PayloadV1::Blocks(height, hash, blocks_root) => {
+                LittleEndian::write_u64(&mut buf[0..8], height.0);
+                buf[8..40].copy_from_slice(hash.as_ref());
+                buf[40..72].copy_from_slice(blocks_root.as_ref());
+            }
         };
     }
 
     fn len(&self) -> usize {
         match *self {
             PayloadV1::Regular(..) => 40,
-            PayloadV1::Recover(..) => 72,
+            PayloadV1::Recover(..) | PayloadV1::Blocks(..) => 72,
         }
     }
 
@@ -127,6 +215,7 @@ impl PayloadV1 {
         match *self {
             PayloadV1::Regular(..) => PAYLOAD_V1_KIND_REGULAR,
             PayloadV1::Recover(..) => PAYLOAD_V1_KIND_RECOVER,
+            PayloadV1::Blocks(..) => PAYLOAD_V1_KIND_BLOCKS,
         }
     }
 
@@ -145,37 +234,194 @@ impl PayloadV1 {
     }
 }
 
-impl PayloadV1Builder {
-    pub fn new() -> PayloadV1Builder {
-        PayloadV1Builder {
+#[cfg_attr(feature = "cargo-clippy", allow(len_without_is_empty))]
+impl PayloadV2 {
+    fn read(bytes: &[u8]) -> Option<PayloadV2> {
+        let kind = bytes[0];
+        let data = &bytes[1..];
+        match kind {
+            PAYLOAD_V2_KIND_REGULAR => {
+                if data.len() != 72 {
+                    return None;
+                }
+
+                let network_id = LittleEndian::read_u32(&data[0..4]);
+                let block_height = LittleEndian::read_u32(&data[4..8]);
+                let block_hash = Hash::from_slice(&data[8..40]).unwrap();
+                let state_hash = Hash::from_slice(&data[40..72]).unwrap();
+                Some(PayloadV2::Regular(
+                    network_id,
+                    Height(u64::from(block_height)),
+                    block_hash,
+                    state_hash,
+                ))
+            }
+            PAYLOAD_V2_KIND_RECOVER => {
+                if data.len() != 72 {
+                    return None;
+                }
+
+                let network_id = LittleEndian::read_u32(&data[0..4]);
+                let block_height = LittleEndian::read_u32(&data[4..8]);
+                let block_hash = Hash::from_slice(&data[8..40]).unwrap();
+                let txid = btc::TxId::from_slice(&data[40..72]).unwrap();
+                Some(PayloadV2::Recover(
+                    network_id,
+                    Height(u64::from(block_height)),
+                    block_hash,
+                    txid,
+                ))
+            }
+            PAYLOAD_V2_KIND_BLOCKS => {
+                if data.len() != 72 {
+                    return None;
+                }
+
+                let network_id = LittleEndian::read_u32(&data[0..4]);
+                let block_height = LittleEndian::read_u32(&data[4..8]);
+                let block_hash = Hash::from_slice(&data[8..40]).unwrap();
+                let blocks_root = Hash::from_slice(&data[40..72]).unwrap();
+                Some(PayloadV2::Blocks(
+                    network_id,
+                    Height(u64::from(block_height)),
+                    block_hash,
+                    blocks_root,
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    fn write(&self, buf: &mut [u8]) {
+        let kind = self.kind();
+        buf[0] = kind as u8;
+
+        let buf = &mut buf[1..];
+        debug_assert_eq!(buf.len(), self.len());
+        // Serialize data
+        match *self {
+            PayloadV2::Regular(network_id, height, hash, state_hash) => {
+                LittleEndian::write_u32(&mut buf[0..4], network_id);
+                LittleEndian::write_u32(&mut buf[4..8], height.0 as u32);
+                buf[8..40].copy_from_slice(hash.as_ref());
+                buf[40..72].copy_from_slice(state_hash.as_ref());
+            }
+            PayloadV2::Recover(network_id, height, hash, txid) => {
+                LittleEndian::write_u32(&mut buf[0..4], network_id);
+                LittleEndian::write_u32(&mut buf[4..8], height.0 as u32);
+                buf[8..40].copy_from_slice(hash.as_ref());
+                buf[40..72].copy_from_slice(txid.as_bytes());
+            }
+            PayloadV2::Blocks(network_id, height, hash, blocks_root) => {
+                LittleEndian::write_u32(&mut buf[0..4], network_id);
+                LittleEndian::write_u32(&mut buf[4..8], height.0 as u32);
+                buf[8..40].copy_from_slice(hash.as_ref());
+                buf[40..72].copy_from_slice(blocks_root.as_ref());
+            }
+        };
+    }
+
+    fn len(&self) -> usize {
+        72
+    }
+
+    fn kind(&self) -> u8 {
+        match *self {
+            PayloadV2::Regular(..) => PAYLOAD_V2_KIND_REGULAR,
+            PayloadV2::Recover(..) => PAYLOAD_V2_KIND_RECOVER,
+            PayloadV2::Blocks(..) => PAYLOAD_V2_KIND_BLOCKS,
+        }
+    }
+
+    fn into_script(self) -> Script {
+        let len = self.len() + PAYLOAD_HEADER_LEN;
+        let mut buf = vec![0; len];
+        // Serialize header
+        buf[0..6].copy_from_slice(PAYLOAD_PREFIX);
+        buf[6] = PAYLOAD_V2;
+        self.write(&mut buf[7..]);
+        // Build script
+        Builder::new()
+            .push_opcode(All::OP_RETURN)
+            .push_slice(buf.as_ref())
+            .into_script()
+    }
+}
+
+impl PayloadBuilder {
+    pub fn new() -> PayloadBuilder {
+        PayloadBuilder {
             block_hash: None,
             block_height: None,
             prev_tx_chain: None,
+            network_id: None,
+            blocks_root: None,
+            state_hash: None,
         }
     }
 
-    pub fn block_height(mut self, height: Height) -> PayloadV1Builder {
+    pub fn block_height(mut self, height: Height) -> PayloadBuilder {
         self.block_height = Some(height);
         self
     }
 
-    pub fn block_hash(mut self, hash: Hash) -> PayloadV1Builder {
+    pub fn block_hash(mut self, hash: Hash) -> PayloadBuilder {
         self.block_hash = Some(hash);
         self
     }
 
-    pub fn prev_tx_chain(mut self, txid: Option<btc::TxId>) -> PayloadV1Builder {
+    pub fn prev_tx_chain(mut self, txid: Option<btc::TxId>) -> PayloadBuilder {
         self.prev_tx_chain = txid;
         self
     }
 
+    /// Sets the Exonum network identifier, the payload v.2 is built if it is specified.
+    pub fn network_id(mut self, network_id: Option<u32>) -> PayloadBuilder {
+        self.network_id = network_id;
+        self
+    }
+
+    /// Sets the root hash of the Merkle tree over the hashes of the anchored blocks.
+    pub fn blocks_root(mut self, blocks_root: Option<Hash>) -> PayloadBuilder {
+        self.blocks_root = blocks_root;
+        self
+    }
+
+    /// Sets the Exonum state hash at the anchored block, which is required by the regular
+    /// payload v.2. The other payloads do not commit to it.
+    pub fn state_hash(mut self, state_hash: Option<Hash>) -> PayloadBuilder {
+        self.state_hash = state_hash;
+        self
+    }
+
     pub fn into_script(self) -> Script {
         let block_height = self.block_height.expect("Block height is not set");
         let block_hash = self.block_hash.expect("Block hash is not set");
+        assert!(
+            self.prev_tx_chain.is_none() || self.blocks_root.is_none(),
+            "Recover payload can not commit to the blocks root"
+        );
+
+        if let Some(network_id) = self.network_id {
+            assert!(
+                block_height.0 <= u64::from(u32::max_value()),
+                "Block height does not fit into the payload v.2"
+            );
+            let payload = match (self.prev_tx_chain, self.blocks_root) {
+                (Some(txid), _) => PayloadV2::Recover(network_id, block_height, block_hash, txid),
+                (None, Some(root)) => PayloadV2::Blocks(network_id, block_height, block_hash, root),
+                (None, None) => {
+                    let state_hash = self.state_hash.expect("State hash is not set");
+                    PayloadV2::Regular(network_id, block_height, block_hash, state_hash)
+                }
+            };
+            return payload.into_script();
+        }
 
-        let payload = match self.prev_tx_chain {
-            Some(txid) => PayloadV1::Recover(block_height, block_hash, txid),
-            None => PayloadV1::Regular(block_height, block_hash),
+        let payload = match (self.prev_tx_chain, self.blocks_root) {
+            (Some(txid), _) => PayloadV1::Recover(block_height, block_hash, txid),
+            (None, Some(root)) => PayloadV1::Blocks(block_height, block_hash, root),
+            (None, None) => PayloadV1::Regular(block_height, block_hash),
         };
         payload.into_script()
     }
@@ -204,6 +450,7 @@ impl Payload {
                     let version = bytes[6];
                     match version {
                         PAYLOAD_V1 => PayloadV1::read(&bytes[7..]).map(Payload::from),
+                        PAYLOAD_V2 => PayloadV2::read(&bytes[7..]).map(Payload::from),
                         _ => None,
                     }
                 } else {
@@ -221,6 +468,9 @@ impl From<PayloadV1> for Payload {
                     block_height: height,
                     block_hash: hash,
                     prev_tx_chain: None,
+                    network_id: None,
+                    blocks_root: None,
+                    state_hash: None,
                 }
             }
             PayloadV1::Recover(height, hash, txid) => {
@@ -228,6 +478,56 @@ impl From<PayloadV1> for Payload {
                     block_height: height,
                     block_hash: hash,
                     prev_tx_chain: Some(txid),
+                    network_id: None,
+                    blocks_root: None,
+                    state_hash: None,
+                }
+            }
+            PayloadV1::Blocks(height, hash, blocks_root) => {
+                Payload {
+                    block_height: height,
+                    block_hash: hash,
+                    prev_tx_chain: None,
+                    network_id: None,
+                    blocks_root: Some(blocks_root),
+                    state_hash: None,
+                }
+            }
+        }
+    }
+}
+
+impl From<PayloadV2> for Payload {
+    fn from(v2: PayloadV2) -> Payload {
+        match v2 {
+            PayloadV2::Regular(network_id, height, hash, state_hash) => {
+                Payload {
+                    block_height: height,
+                    block_hash: hash,
+                    prev_tx_chain: None,
+                    network_id: Some(network_id),
+                    blocks_root: None,
+                    state_hash: Some(state_hash),
+                }
+            }
+            PayloadV2::Recover(network_id, height, hash, txid) => {
+                Payload {
+                    block_height: height,
+                    block_hash: hash,
+                    prev_tx_chain: Some(txid),
+                    network_id: Some(network_id),
+                    blocks_root: None,
+                    state_hash: None,
+                }
+            }
+            PayloadV2::Blocks(network_id, height, hash, blocks_root) => {
+                Payload {
+                    block_height: height,
+                    block_hash: hash,
+                    prev_tx_chain: None,
+                    network_id: Some(network_id),
+                    blocks_root: Some(blocks_root),
+                    state_hash: None,
                 }
             }
         }
@@ -244,7 +544,7 @@ mod tests {
     use details::btc;
     use details::btc::HexValueEx;
 
-    use super::{Payload, PayloadBuilder};
+    use super::{network_id, Payload, PayloadBuilder};
 
     #[test]
     fn test_payload_regular_serialize() {
@@ -310,6 +610,144 @@ mod tests {
         assert_eq!(payload.prev_tx_chain, Some(prev_txid));
     }
 
+    #[test]
+    fn test_payload_v2_regular_serialize() {
+        let block_hash = hash(&[]);
+        let payload_script = PayloadBuilder::new()
+            .block_hash(block_hash)
+            .block_height(Height(1234))
+            .network_id(Some(network_id(&block_hash)))
+            .state_hash(Some(hash(&[1])))
+            .into_script();
+
+        assert_eq!(
+            payload_script.to_hex(),
+            "6a4c5045584f4e554d0200e3b0c442d2040000e3b0c44298fc1c149afbf4c8996fb92427ae41e46\
+                   49b934ca495991b7852b8554bf5122f344554c53bde2ebb8cd2b7e3d1600ad631c385a5d7cce23c7\
+                   785459a"
+        );
+    }
+
+    #[test]
+    fn test_payload_v2_regular_deserialize() {
+        let payload_script = Script::from_hex(
+            "6a4c5045584f4e554d0200e3b0c442d2040000e3b0c44298fc1c\
+                                               149afbf4c8996fb92427ae41e4649b934ca495991b7852b8554b\
+                                               f5122f344554c53bde2ebb8cd2b7e3d1600ad631c385a5d7cce2\
+                                               3c7785459a",
+        ).unwrap();
+
+        let block_hash = hash(&[]);
+        let payload = Payload::from_script(&payload_script).unwrap();
+        assert_eq!(payload.block_hash, block_hash);
+        assert_eq!(payload.block_height, Height(1234));
+        assert_eq!(payload.prev_tx_chain, None);
+        assert_eq!(payload.network_id, Some(network_id(&block_hash)));
+        assert_eq!(payload.state_hash, Some(hash(&[1])));
+    }
+
+    #[test]
+    fn test_payload_v2_recover_serialize() {
+        let block_hash = hash(&[]);
+        let prev_txid = btc::TxId::from_slice(block_hash.as_ref()).unwrap();
+        let payload_script = PayloadBuilder::new()
+            .block_hash(block_hash)
+            .block_height(Height(1234))
+            .prev_tx_chain(Some(prev_txid))
+            .network_id(Some(network_id(&block_hash)))
+            .into_script();
+
+        assert_eq!(
+            payload_script.to_hex(),
+            "6a4c5045584f4e554d0201e3b0c442d2040000e3b0c44298fc1c149afbf4c8996fb92427ae41e46\
+                   49b934ca495991b7852b855e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7\
+                   852b855"
+        );
+    }
+
+    #[test]
+    fn test_payload_v2_recover_deserialize() {
+        let payload_script = Script::from_hex(
+            "6a4c5045584f4e554d0201e3b0c442d2040000e3b0c44298fc1c\
+                                               149afbf4c8996fb92427ae41e4649b934ca495991b7852b855e3\
+                                               b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca49599\
+                                               1b7852b855",
+        ).unwrap();
+
+        let block_hash = hash(&[]);
+        let prev_txid = btc::TxId::from_slice(block_hash.as_ref()).unwrap();
+        let payload = Payload::from_script(&payload_script).unwrap();
+        assert_eq!(payload.block_hash, block_hash);
+        assert_eq!(payload.block_height, Height(1234));
+        assert_eq!(payload.prev_tx_chain, Some(prev_txid));
+        assert_eq!(payload.network_id, Some(network_id(&block_hash)));
+    }
+
+    #[test]
+    #[should_panic(expected = "State hash is not set")]
+    fn test_payload_v2_regular_without_state_hash() {
+        PayloadBuilder::new()
+            .block_hash(hash(&[]))
+            .block_height(Height(1234))
+            .network_id(Some(0))
+            .into_script();
+    }
+
+    #[test]
+    #[should_panic(expected = "Block height does not fit into the payload v.2")]
+    fn test_payload_v2_height_overflow() {
+        PayloadBuilder::new()
+            .block_hash(hash(&[]))
+            .block_height(Height(u64::from(u32::max_value()) + 1))
+            .network_id(Some(0))
+            .into_script();
+    }
+
+    #[test]
+    fn test_payload_blocks_serialize() {
+        let block_hash = hash(&[]);
+        let blocks_root = hash(&[1]);
+        let payload_script = PayloadBuilder::new()
+            .block_hash(block_hash)
+            .block_height(Height(1234))
+            .blocks_root(Some(blocks_root))
+            .into_script();
+
+        let payload = Payload::from_script(&payload_script).unwrap();
+        assert_eq!(payload_script.len(), 83);
+        assert_eq!(payload.block_hash, block_hash);
+        assert_eq!(payload.block_height, Height(1234));
+        assert_eq!(payload.prev_tx_chain, None);
+        assert_eq!(payload.network_id, None);
+        assert_eq!(payload.blocks_root, Some(blocks_root));
+
+        let payload_script = PayloadBuilder::new()
+            .block_hash(block_hash)
+            .block_height(Height(1234))
+            .network_id(Some(network_id(&block_hash)))
+            .blocks_root(Some(blocks_root))
+            .into_script();
+
+        let payload = Payload::from_script(&payload_script).unwrap();
+        assert_eq!(payload_script.len(), 83);
+        assert_eq!(payload.block_height, Height(1234));
+        assert_eq!(payload.network_id, Some(network_id(&block_hash)));
+        assert_eq!(payload.blocks_root, Some(blocks_root));
+    }
+
+    #[test]
+    #[should_panic(expected = "Recover payload can not commit to the blocks root")]
+    fn test_payload_recover_with_blocks_root() {
+        let block_hash = hash(&[]);
+        let prev_txid = btc::TxId::from_slice(block_hash.as_ref()).unwrap();
+        PayloadBuilder::new()
+            .block_hash(block_hash)
+            .block_height(Height(1234))
+            .prev_tx_chain(Some(prev_txid))
+            .blocks_root(Some(block_hash))
+            .into_script();
+    }
+
     #[test]
     fn test_payload_incorrect_deserialize() {
         // Payload from old anchoring transaction
@@ -12,14 +12,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use bitcoin::network::constants::Network;
 use secp256k1::key;
 
+use super::Network;
 use super::types::{PrivateKey, RawPrivkey};
 
 impl PrivateKey {
     pub fn from_key(network: Network, sk: key::SecretKey, compressed: bool) -> PrivateKey {
-        let raw = RawPrivkey::from_key(network, sk, compressed);
+        let raw = RawPrivkey::from_key(network.into(), sk, compressed);
         PrivateKey::from(raw)
     }
 }
@@ -12,20 +12,32 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use bitcoin::blockdata::script::{Builder, Script};
+use std::error::Error;
+use std::fmt;
+
+use bitcoin::blockdata::script::{Builder, Error as ScriptError, Script};
 use bitcoin::blockdata::opcodes::All;
 use bitcoin::blockdata::script::Instruction;
 use bitcoin::util::base58::FromBase58;
 use bitcoin::util::address::Address as RawAddress;
-use bitcoin::network::constants::Network;
 use secp256k1::key::PublicKey as RawPublicKey;
-use secp256k1::Secp256k1;
+use secp256k1::{Error as Secp256k1Error, Secp256k1};
 
-use super::{PublicKey, RedeemScript, Address};
+use super::{Address, Network, PublicKey, RedeemScript};
 
 // TODO implement errors
 
+/// Error of the redeem script parsing.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RedeemScriptError {
+    /// The script instructions are malformed.
+    Script(ScriptError),
+    /// The pushed bytes are not a public key.
+    PublicKey(Secp256k1Error),
+}
+
 impl RedeemScript {
+    /// Creates the multisig script of the given public keys in the compressed form.
     pub fn from_pubkeys<'a, I>(pubkeys: I, majority_count: u8) -> RedeemScript
     where
         I: IntoIterator<Item = &'a PublicKey>,
@@ -67,10 +79,26 @@ impl RedeemScript {
     }
 
     pub fn to_address(&self, network: Network) -> Address {
-        RawAddress::from_script(network, self).into()
+        Address::from_script(self, network)
     }
 
-    pub fn compressed(&self, network: Network) -> RedeemScript {
+    /// Returns the number of signatures required by this multisig script.
+    pub fn quorum(&self) -> usize {
+        match self.0.into_iter().next() {
+            Some(Instruction::Op(opcode)) => {
+                let code = opcode as u8;
+                if code >= All::OP_PUSHNUM_1 as u8 && code <= All::OP_PUSHNUM_16 as u8 {
+                    (code - All::OP_PUSHNUM_1 as u8 + 1) as usize
+                } else {
+                    0
+                }
+            }
+            _ => 0,
+        }
+    }
+
+    /// Returns the script with the public keys in the compressed form.
+    pub fn compressed(&self, network: Network) -> Result<RedeemScript, RedeemScriptError> {
         let mut builder = Builder::new();
         let context = Secp256k1::without_caps();
 
@@ -80,20 +108,41 @@ impl RedeemScript {
                     if bytes.len() == 33 {
                         builder = builder.push_slice(bytes);
                     } else {
-                        let pubkey = RawPublicKey::from_slice(&context, bytes).unwrap();
-                        let addr = RawAddress::from_key(network, &pubkey, true);
+                        let pubkey = RawPublicKey::from_slice(&context, bytes)
+                            .map_err(RedeemScriptError::PublicKey)?;
+                        let addr = RawAddress::from_key(network.into(), &pubkey, true);
                         builder = builder.push_slice(addr.hash[..].as_ref());
                     }
                 }
                 Instruction::Op(opcode) => builder = builder.push_opcode(opcode),
-                Instruction::Error(_) => unimplemented!(),
+                Instruction::Error(e) => return Err(RedeemScriptError::Script(e)),
             }
         }
-        RedeemScript(builder.into_script())
+        Ok(RedeemScript(builder.into_script()))
     }
 
     pub fn script_pubkey(&self, network: Network) -> Script {
-        let addr = RawAddress::from_script(network, self);
+        let addr = RawAddress::from_script(network.into(), self);
         addr.script_pubkey()
     }
 }
+
+impl fmt::Display for RedeemScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RedeemScriptError::Script(ref e) => write!(f, "Malformed redeem script: {}", e),
+            RedeemScriptError::PublicKey(ref e) => {
+                write!(f, "Wrong public key in redeem script: {}", e)
+            }
+        }
+    }
+}
+
+impl Error for RedeemScriptError {
+    fn description(&self) -> &str {
+        match *self {
+            RedeemScriptError::Script(_) => "Malformed redeem script",
+            RedeemScriptError::PublicKey(_) => "Wrong public key in redeem script",
+        }
+    }
+}
@@ -16,28 +16,24 @@ use std::fmt;
 use std::collections::HashMap;
 use std::ops::Deref;
 
-use bitcoin::blockdata::script::Instruction;
 use bitcoin::blockdata::opcodes::All;
-use bitcoin::util::hash::Hash160;
-use bitcoin::network::serialize::{BitcoinHash, deserialize, serialize, serialize_hex};
+use bitcoin::network::serialize::{deserialize, serialize, serialize_hex};
 use bitcoin::blockdata::transaction::{TxIn, TxOut};
 use bitcoin::blockdata::script::{Builder, Script};
-use bitcoin::util::base58::ToBase58;
-use bitcoin::util::address::{Address, Privkey, Type};
-use bitcoin::network::constants::Network;
+use bitcoin::util::address::Privkey;
+use bitcoin::util::bip143::SighashComponents;
 use bitcoin::blockdata::transaction::SigHashType;
 use secp256k1::key::{PublicKey, SecretKey};
 use secp256k1::{Message, Secp256k1, Signature};
-use bitcoinrpc;
 
 use exonum::crypto::{Hash, hash};
 use exonum::encoding::serialize::{FromHexError, FromHex};
 use exonum::helpers::Height;
 use exonum::storage::StorageValue;
 
-use details::rpc::{Error as RpcError, RpcClient};
+use details::rpc::{BitcoinRelay, Error as RpcError, TxInfo};
 use details::btc;
-use details::btc::{HexValueEx, RedeemScript, TxId};
+use details::btc::{AddressType, HexValueEx, Network, RedeemScript, TxId};
 use details::error::Error as InternalError;
 use details::btc::payload::{Payload, PayloadBuilder};
 
@@ -45,6 +41,15 @@ pub type RawBitcoinTx = ::bitcoin::blockdata::transaction::Transaction;
 
 const ANCHORING_TX_FUNDS_OUTPUT: u32 = 0;
 const ANCHORING_TX_DATA_OUTPUT: u32 = 1;
+/// Maximum length of the DER encoded signature with the sighash type byte.
+const MAX_SIGNATURE_LEN: usize = 73;
+/// Length of the `p2sh-p2wsh` scriptSig which pushes the witness program.
+const P2SH_P2WSH_SCRIPT_SIG_LEN: usize = 35;
+/// Input sequence number which signals the opt-in Replace-By-Fee (BIP125).
+pub const RBF_SEQUENCE: u32 = 0xFFFF_FFFD;
+/// Smallest standard value of the anchoring output, which is the bitcoind dust limit
+/// of the `p2sh` output.
+pub const DUST_LIMIT: u64 = 546;
 
 /// Anchoring transaction struct is strict:
 /// - Zero input is previous anchoring tx or initial funding tx
@@ -52,7 +57,7 @@ const ANCHORING_TX_DATA_OUTPUT: u32 = 1;
 /// - First output is anchored metadata
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub struct AnchoringTx(pub RawBitcoinTx);
-/// Funding transaction always has an output to `p2sh` address
+/// Funding transaction always has an output to `p2sh` or `p2wsh` address
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub struct FundingTx(pub RawBitcoinTx);
 /// Other unspecified Bitcoin transaction
@@ -70,13 +75,26 @@ pub trait TxFromRaw: Sized {
     fn from_raw(raw: RawBitcoinTx) -> Option<Self>;
 }
 
+#[derive(Debug)]
+enum Fee {
+    Flat(u64),
+    Rate {
+        sat_per_vbyte: u64,
+        redeem_script: RedeemScript,
+        address_type: AddressType,
+    },
+}
+
 #[derive(Debug)]
 pub struct TransactionBuilder {
     inputs: Vec<(RawBitcoinTx, u32)>,
     output: Option<btc::Address>,
-    fee: Option<u64>,
+    fee: Option<Fee>,
     payload: Option<(Height, Hash)>,
     prev_tx_chain: Option<TxId>,
+    network_id: Option<u32>,
+    blocks_root: Option<Hash>,
+    state_hash: Option<Hash>,
 }
 
 impl HexValueEx for RawBitcoinTx {
@@ -106,32 +124,34 @@ implement_serde_hex! {BitcoinTx}
 
 impl FundingTx {
     pub fn find_out(&self, addr: &btc::Address) -> Option<u32> {
-        let redeem_script_hash = addr.hash;
+        let script_pubkey = addr.script_pubkey();
         self.0
             .output
             .iter()
-            .position(|output| if let Some(Instruction::PushBytes(bytes)) =
-                output.script_pubkey.into_iter().nth(1)
-            {
-                Hash160::from(bytes) == redeem_script_hash
-            } else {
-                false
-            })
+            .position(|output| output.script_pubkey == script_pubkey)
             .map(|x| x as u32)
     }
 
+    /// Returns indexes of all outputs to the given address.
+    pub fn find_outs(&self, addr: &btc::Address) -> Vec<u32> {
+        let script_pubkey = addr.script_pubkey();
+        self.0
+            .output
+            .iter()
+            .enumerate()
+            .filter(|&(_, output)| output.script_pubkey == script_pubkey)
+            .map(|(out, _)| out as u32)
+            .collect()
+    }
+
     pub fn has_unspent_info(
         &self,
-        client: &RpcClient,
+        client: &BitcoinRelay,
         addr: &btc::Address,
-    ) -> Result<Option<bitcoinrpc::UnspentTransactionInfo>, RpcError> {
-        let txid = self.txid();
-        let txs = client.listunspent(
-            0,
-            9_999_999,
-            [addr.to_base58check().as_ref()],
-        )?;
-        Ok(txs.into_iter().find(|txinfo| txinfo.txid == txid))
+    ) -> Result<Option<TxInfo>, RpcError> {
+        let txid = self.id();
+        let txs = client.unspent_transactions(addr)?;
+        Ok(txs.into_iter().find(|txinfo| txinfo.body.id() == txid))
     }
 }
 
@@ -142,23 +162,8 @@ impl AnchoringTx {
 
     pub fn output_address(&self, network: Network) -> btc::Address {
         let script = &self.0.output[ANCHORING_TX_FUNDS_OUTPUT as usize].script_pubkey;
-        let bytes = script
-            .into_iter()
-            .filter_map(|instruction| if let Instruction::PushBytes(bytes) =
-                instruction
-            {
-                Some(bytes)
-            } else {
-                None
-            })
-            .next()
-            .unwrap();
-
-        Address {
-            ty: Type::ScriptHash,
-            network: network,
-            hash: Hash160::from(bytes),
-        }.into()
+        btc::Address::from_script_pubkey(script, network)
+            .expect("Unsupported anchoring tx output script")
     }
 
     pub fn inputs(&self) -> ::std::ops::Range<u32> {
@@ -197,6 +202,81 @@ impl AnchoringTx {
         verify_tx_input(self, input as usize, redeem_script, pub_key, signature)
     }
 
+    pub fn sign_witness_input(
+        &self,
+        redeem_script: &btc::RedeemScript,
+        input: u32,
+        value: u64,
+        priv_key: &Privkey,
+    ) -> btc::Signature {
+        let mut sign_data = sign_witness_tx_input(
+            self,
+            input as usize,
+            redeem_script,
+            value,
+            priv_key.secret_key(),
+        );
+        sign_data.push(SigHashType::All.as_u32() as u8);
+        sign_data
+    }
+
+    pub fn verify_witness_input(
+        &self,
+        redeem_script: &RedeemScript,
+        input: u32,
+        value: u64,
+        pub_key: &PublicKey,
+        signature: &[u8],
+    ) -> bool {
+        // Cuts off btc related sighash type byte
+        let signature = &signature[0..signature.len() - 1];
+        verify_witness_tx_input(
+            self,
+            input as usize,
+            redeem_script,
+            value,
+            pub_key,
+            signature,
+        )
+    }
+
+    /// Estimates the virtual size of this transaction after all its inputs are signed
+    /// by the given multisig `redeem_script`.
+    pub fn estimate_vsize(&self, redeem_script: &RedeemScript, address_type: AddressType) -> u64 {
+        let unsigned_size = serialize(&RawBitcoinTx {
+            witness: vec![],
+            ..self.0.clone()
+        }).unwrap()
+            .len();
+        let inputs_count = self.0.input.len();
+
+        let redeem_script_len = redeem_script.0.len();
+        let signatures_len = redeem_script.quorum() * (1 + MAX_SIGNATURE_LEN);
+        let size = match address_type {
+            AddressType::P2sh => {
+                let script_sig_len = 1 + signatures_len + push_len(redeem_script_len) +
+                    redeem_script_len;
+                // Unsigned input already contains one byte of the empty scriptSig length.
+                let input_len = varint_len(script_sig_len) - 1 + script_sig_len;
+                unsigned_size + inputs_count * input_len
+            }
+            AddressType::P2wsh | AddressType::P2shP2wsh => {
+                let script_sig_len = if address_type == AddressType::P2shP2wsh {
+                    P2SH_P2WSH_SCRIPT_SIG_LEN
+                } else {
+                    0
+                };
+                let witness_len = varint_len(redeem_script.quorum() + 2) + 1 + signatures_len +
+                    varint_len(redeem_script_len) + redeem_script_len;
+                let base_size = unsigned_size + inputs_count * script_sig_len;
+                // Witness data costs a quarter of the base data, plus marker and flag bytes.
+                let weight = base_size * 4 + 2 + inputs_count * witness_len;
+                (weight + 3) / 4
+            }
+        };
+        size as u64
+    }
+
     pub fn finalize(
         self,
         redeem_script: &btc::RedeemScript,
@@ -204,6 +284,19 @@ impl AnchoringTx {
     ) -> AnchoringTx {
         finalize_anchoring_transaction(self, redeem_script, signatures)
     }
+
+    pub fn finalize_with_type(
+        self,
+        address_type: AddressType,
+        redeem_script: &btc::RedeemScript,
+        signatures: HashMap<u32, Vec<btc::Signature>>,
+    ) -> AnchoringTx {
+        if address_type.is_witness() {
+            finalize_witness_anchoring_transaction(self, address_type, redeem_script, signatures)
+        } else {
+            finalize_anchoring_transaction(self, redeem_script, signatures)
+        }
+    }
 }
 
 impl fmt::Debug for AnchoringTx {
@@ -233,9 +326,10 @@ impl From<RawBitcoinTx> for TxKind {
         if find_payload(&tx).is_some() {
             TxKind::Anchoring(AnchoringTx::from(tx))
         } else {
-            // Find output with funds and p2sh script_pubkey
+            // Find output with funds and p2sh or p2wsh script_pubkey
             for out in &tx.output {
-                if out.value > 0 && out.script_pubkey.is_p2sh() {
+                let script_pubkey = &out.script_pubkey;
+                if out.value > 0 && (script_pubkey.is_p2sh() || script_pubkey.is_v0_p2wsh()) {
                     return TxKind::FundingTx(FundingTx::from(tx.clone()));
                 }
             }
@@ -284,11 +378,30 @@ impl TransactionBuilder {
             payload: None,
             fee: None,
             prev_tx_chain: None,
+            network_id: None,
+            blocks_root: None,
+            state_hash: None,
         }
     }
 
     pub fn fee(mut self, fee: u64) -> TransactionBuilder {
-        self.fee = Some(fee);
+        self.fee = Some(Fee::Flat(fee));
+        self
+    }
+
+    /// Sets the fee rate in satoshis per virtual byte, the fee is computed from the estimated
+    /// size of the transaction with all inputs signed by the given multisig `redeem_script`.
+    pub fn fee_rate(
+        mut self,
+        sat_per_vbyte: u64,
+        redeem_script: &RedeemScript,
+        address_type: AddressType,
+    ) -> TransactionBuilder {
+        self.fee = Some(Fee::Rate {
+            sat_per_vbyte,
+            redeem_script: redeem_script.clone(),
+            address_type,
+        });
         self
     }
 
@@ -312,6 +425,24 @@ impl TransactionBuilder {
         self
     }
 
+    /// Sets the Exonum network identifier to build the payload v.2 with.
+    pub fn network_id(mut self, network_id: Option<u32>) -> TransactionBuilder {
+        self.network_id = network_id;
+        self
+    }
+
+    /// Sets the root hash of the anchored blocks to commit to by the payload.
+    pub fn blocks_root(mut self, blocks_root: Option<Hash>) -> TransactionBuilder {
+        self.blocks_root = blocks_root;
+        self
+    }
+
+    /// Sets the Exonum state hash at the anchored block to commit to by the payload v.2.
+    pub fn state_hash(mut self, state_hash: Option<Hash>) -> TransactionBuilder {
+        self.state_hash = state_hash;
+        self
+    }
+
     pub fn into_transaction(mut self) -> Result<AnchoringTx, InternalError> {
         let available_funds: u64 = self.inputs
             .iter()
@@ -319,32 +450,43 @@ impl TransactionBuilder {
             .sum();
 
         let addr = self.output.take().expect("Output address is not set");
-        let fee = self.fee.expect("Fee is not set");
         let (height, block_hash) = self.payload.take().expect("Payload is not set");
+        let metadata_script = PayloadBuilder::new()
+            .block_hash(block_hash)
+            .block_height(height)
+            .prev_tx_chain(self.prev_tx_chain)
+            .network_id(self.network_id)
+            .blocks_root(self.blocks_root)
+            .state_hash(self.state_hash)
+            .into_script();
+        let build_tx = |out_funds| {
+            create_anchoring_transaction(&addr, self.inputs.iter(), out_funds, &metadata_script)
+        };
+
+        let fee = match *self.fee.as_ref().expect("Fee is not set") {
+            Fee::Flat(fee) => fee,
+            Fee::Rate {
+                sat_per_vbyte,
+                ref redeem_script,
+                address_type,
+            } => {
+                // Output value has the fixed size, so the fee does not affect the tx size.
+                let tx = build_tx(available_funds);
+                tx.estimate_vsize(redeem_script, address_type) * sat_per_vbyte
+            }
+        };
         if available_funds < fee {
             return Err(InternalError::InsufficientFunds);
         }
-        let total_funds = available_funds - fee;
-
-        let tx = create_anchoring_transaction(
-            &addr,
-            height,
-            block_hash,
-            self.inputs.iter(),
-            total_funds,
-            self.prev_tx_chain,
-        );
-        Ok(tx)
+        Ok(build_tx(available_funds - fee))
     }
 }
 
 fn create_anchoring_transaction<'a, I>(
     addr: &btc::Address,
-    block_height: Height,
-    block_hash: Hash,
     inputs: I,
     out_funds: u64,
-    prev_chain_txid: Option<TxId>,
+    metadata_script: &Script,
 ) -> AnchoringTx
 where
     I: Iterator<Item = &'a (RawBitcoinTx, u32)>,
@@ -352,19 +494,14 @@ where
     let inputs = inputs
         .map(|&(ref unspent_tx, utxo_vout)| {
             TxIn {
-                prev_hash: unspent_tx.bitcoin_hash(),
+                prev_hash: unspent_tx.txid(),
                 prev_index: utxo_vout,
                 script_sig: Script::new(),
-                sequence: 0xFFFF_FFFF,
+                sequence: RBF_SEQUENCE,
             }
         })
         .collect::<Vec<_>>();
 
-    let metadata_script = PayloadBuilder::new()
-        .block_hash(block_hash)
-        .block_height(block_height)
-        .prev_tx_chain(prev_chain_txid)
-        .into_script();
     let outputs = vec![
         TxOut {
             value: out_funds,
@@ -372,7 +509,7 @@ where
         },
         TxOut {
             value: 0,
-            script_pubkey: metadata_script,
+            script_pubkey: metadata_script.clone(),
         },
     ];
 
@@ -419,6 +556,41 @@ pub fn verify_tx_input(
     }
 }
 
+pub fn sign_witness_tx_input(
+    tx: &RawBitcoinTx,
+    input: usize,
+    witness_script: &Script,
+    value: u64,
+    sec_key: &SecretKey,
+) -> Vec<u8> {
+    let sighash = SighashComponents::new(tx).sighash_all(tx, input, witness_script, value);
+    // Make signature
+    let context = Secp256k1::new();
+    let msg = Message::from_slice(&sighash[..]).unwrap();
+    let sign = context.sign(&msg, sec_key).unwrap();
+    // Serialize signature
+    sign.serialize_der(&context)
+}
+
+pub fn verify_witness_tx_input(
+    tx: &RawBitcoinTx,
+    input: usize,
+    witness_script: &Script,
+    value: u64,
+    pub_key: &PublicKey,
+    signature: &[u8],
+) -> bool {
+    let sighash = SighashComponents::new(tx).sighash_all(tx, input, witness_script, value);
+    let msg = Message::from_slice(&sighash[..]).unwrap();
+
+    let context = Secp256k1::new();
+    if let Ok(sign) = Signature::from_der(&context, signature) {
+        context.verify(&msg, &sign, pub_key).is_ok()
+    } else {
+        false
+    }
+}
+
 fn finalize_anchoring_transaction(
     mut anchoring_tx: AnchoringTx,
     redeem_script: &btc::RedeemScript,
@@ -441,6 +613,61 @@ fn finalize_anchoring_transaction(
     anchoring_tx
 }
 
+fn finalize_witness_anchoring_transaction(
+    mut anchoring_tx: AnchoringTx,
+    address_type: AddressType,
+    redeem_script: &btc::RedeemScript,
+    signatures: HashMap<u32, Vec<btc::Signature>>,
+) -> AnchoringTx {
+    let redeem_script_bytes = redeem_script.0.clone().into_vec();
+    // Nested p2wsh inputs must reveal the witness program in scriptSig
+    let script_sig = if address_type == AddressType::P2shP2wsh {
+        let witness_program = redeem_script.to_v0_p2wsh().into_vec();
+        Builder::new()
+            .push_slice(witness_program.as_ref())
+            .into_script()
+    } else {
+        Script::new()
+    };
+
+    let inputs_count = anchoring_tx.0.input.len();
+    anchoring_tx.0.witness.resize(inputs_count, Vec::new());
+    // build witness
+    for (out, signatures) in signatures {
+        anchoring_tx.0.input[out as usize].script_sig = script_sig.clone();
+        anchoring_tx.0.witness[out as usize] = {
+            let mut witness = vec![Vec::new()];
+            witness.extend(signatures);
+            witness.push(redeem_script_bytes.clone());
+            witness
+        };
+    }
+    anchoring_tx
+}
+
+fn varint_len(n: usize) -> usize {
+    if n < 0xFD {
+        1
+    } else if n <= 0xFFFF {
+        3
+    } else if n <= 0xFFFF_FFFF {
+        5
+    } else {
+        9
+    }
+}
+
+fn push_len(n: usize) -> usize {
+    if n <= 0x4B {
+        1
+    } else if n <= 0xFF {
+        2
+    } else if n <= 0xFFFF {
+        3
+    } else {
+        5
+    }
+}
 
 fn find_payload(tx: &RawBitcoinTx) -> Option<Payload> {
     tx.output.get(ANCHORING_TX_DATA_OUTPUT as usize).and_then(
@@ -31,7 +31,7 @@ use exonum::encoding::serialize::{encode_hex, FromHex, FromHexError, ToHex};
 use exonum::encoding::Field;
 use exonum::storage::{StorageKey, StorageValue};
 
-use super::HexValueEx;
+use super::{HexValueEx, Network};
 
 #[derive(Debug, Clone, Copy, PartialEq, Hash, Eq)]
 pub struct TxId(Sha256dHash);
@@ -39,8 +39,29 @@ pub struct TxId(Sha256dHash);
 pub struct PrivateKey(pub RawPrivkey);
 #[derive(Debug, Clone, Copy, PartialEq, Hash, Eq)]
 pub struct PublicKey(pub RawPublicKey);
-#[derive(Clone, PartialEq, Eq)]
-pub struct Address(pub RawAddress);
+#[derive(Clone)]
+pub enum Address {
+    /// Base58check encoded `p2pkh` or `p2sh` address (including `p2sh` wrapped `p2wsh`).
+    /// The network is kept alongside since all test networks share the testnet prefixes.
+    Base58(Network, RawAddress),
+    /// Bech32 encoded native `p2wsh` address with the `sha256` hash of the witness script.
+    WitnessScriptHash(Network, [u8; 32]),
+}
+
+/// Type of the anchoring multisig address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AddressType {
+    /// Legacy `p2sh` address.
+    #[serde(rename = "p2sh")]
+    P2sh,
+    /// Native segwit `p2wsh` address.
+    #[serde(rename = "p2wsh")]
+    P2wsh,
+    /// Segwit `p2wsh` address nested into the `p2sh` one.
+    #[serde(rename = "p2sh-p2wsh")]
+    P2shP2wsh,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct RedeemScript(pub RawScript);
 
@@ -48,17 +69,15 @@ pub type Signature = Vec<u8>;
 
 implement_wrapper! {Sha256dHash, TxId}
 implement_wrapper! {RawPublicKey, PublicKey}
-implement_wrapper! {RawAddress, Address}
 implement_wrapper! {RawPrivkey, PrivateKey}
 implement_wrapper! {RawScript, RedeemScript}
 
-implement_base58_wrapper! {RawAddress, Address}
 implement_base58_wrapper! {RawPrivkey, PrivateKey}
 
 implement_serde_hex! {PublicKey}
 implement_serde_hex! {RedeemScript}
 implement_serde_hex! {TxId}
-implement_serde_base58check! {Address}
+implement_serde_str! {Address}
 implement_serde_base58check! {PrivateKey}
 
 implement_pod_as_ref_field! { TxId }
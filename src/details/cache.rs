@@ -0,0 +1,250 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `BitcoinRelay` wrapper which caches the responses until the bitcoin tip changes.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use details::btc;
+use details::btc::transactions::{BitcoinTx, FundingTx};
+use blockchain::dto::TxOutProof;
+use super::rpc::{AnchoringRpcConfig, BitcoinRelay, RelayDisagreement, Result, TxInfo};
+
+/// Responses which are valid only for the particular bitcoin tip.
+#[derive(Debug, Default)]
+struct TipCache {
+    /// Hash of the best block, the responses are not cached while it is unknown.
+    best_block_hash: Option<String>,
+    block_count: Option<u64>,
+    transaction_infos: HashMap<btc::TxId, TxInfo>,
+    unspent_transactions: HashMap<String, Vec<TxInfo>>,
+    fee_rates: HashMap<u16, Option<u64>>,
+    tx_out_proofs: HashMap<btc::TxId, TxOutProof>,
+}
+
+/// `BitcoinRelay` which caches the responses of the inner relay.
+///
+/// The raw transactions are immutable, so they are cached indefinitely. Other responses
+/// are cached until the best block hash changes, which is checked once per `begin_check`.
+/// Nothing but the raw transactions is cached until the first successful check.
+/// The mempool entry heights and the transaction outputs, which may be spent in the mempool,
+/// are never cached.
+#[derive(Debug)]
+pub struct CachingRelay {
+    relay: Box<BitcoinRelay>,
+    transactions: Mutex<HashMap<btc::TxId, BitcoinTx>>,
+    tip: Mutex<TipCache>,
+}
+
+impl CachingRelay {
+    /// Creates a caching relay over the given one.
+    pub fn new(relay: Box<BitcoinRelay>) -> CachingRelay {
+        CachingRelay {
+            relay,
+            transactions: Mutex::new(HashMap::new()),
+            tip: Mutex::new(TipCache::default()),
+        }
+    }
+
+    /// Returns the wrapped relay.
+    pub fn inner(&self) -> &BitcoinRelay {
+        self.relay.as_ref()
+    }
+
+    /// Returns the cached response or requests it from the inner relay and caches it
+    /// if the tip is known and has not changed during the request.
+    fn cached<T, G, R, P>(&self, get: G, request: R, put: P) -> Result<T>
+    where
+        G: FnOnce(&TipCache) -> Option<T>,
+        R: FnOnce(&BitcoinRelay) -> Result<T>,
+        P: FnOnce(&mut TipCache, &T),
+    {
+        let best_block_hash = {
+            let tip = self.tip.lock().unwrap();
+            if let Some(value) = get(&tip) {
+                return Ok(value);
+            }
+            tip.best_block_hash.clone()
+        };
+        let value = request(self.relay.as_ref())?;
+        let mut tip = self.tip.lock().unwrap();
+        if best_block_hash.is_some() && tip.best_block_hash == best_block_hash {
+            put(&mut tip, &value);
+        }
+        Ok(value)
+    }
+
+    fn remember_transaction(&self, tx: &BitcoinTx) {
+        self.transactions.lock().unwrap().insert(
+            tx.id(),
+            tx.clone(),
+        );
+    }
+}
+
+impl BitcoinRelay for CachingRelay {
+    fn get_transaction(&self, txid: btc::TxId) -> Result<Option<BitcoinTx>> {
+        if let Some(tx) = self.transactions.lock().unwrap().get(&txid) {
+            return Ok(Some(tx.clone()));
+        }
+        let tx = self.relay.get_transaction(txid)?;
+        if let Some(ref tx) = tx {
+            self.remember_transaction(tx);
+        }
+        Ok(tx)
+    }
+
+    fn get_transactions(&self, txids: &[btc::TxId]) -> Result<Vec<Option<BitcoinTx>>> {
+        let missing = {
+            let transactions = self.transactions.lock().unwrap();
+            txids
+                .iter()
+                .filter(|txid| !transactions.contains_key(*txid))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        if !missing.is_empty() {
+            for tx in self.relay.get_transactions(&missing)?.iter().filter_map(Option::as_ref) {
+                self.remember_transaction(tx);
+            }
+        }
+        let transactions = self.transactions.lock().unwrap();
+        Ok(txids.iter().map(|txid| transactions.get(txid).cloned()).collect())
+    }
+
+    fn get_transaction_info(&self, txid: btc::TxId) -> Result<Option<TxInfo>> {
+        let info = self.cached(
+            |tip| tip.transaction_infos.get(&txid).cloned().map(Some),
+            |relay| relay.get_transaction_info(txid),
+            |tip, info| if let Some(ref info) = *info {
+                tip.transaction_infos.insert(txid, info.clone());
+            },
+        )?;
+        if let Some(ref info) = info {
+            self.remember_transaction(&info.body);
+        }
+        Ok(info)
+    }
+
+    fn watch_address(&self, addr: &btc::Address, rescan: bool) -> Result<()> {
+        self.relay.watch_address(addr, rescan)?;
+        let mut tip = self.tip.lock().unwrap();
+        tip.unspent_transactions.remove(&addr.to_string());
+        Ok(())
+    }
+
+    fn send_transaction(&self, tx: BitcoinTx) -> Result<()> {
+        self.relay.send_transaction(tx.clone())?;
+        self.remember_transaction(&tx);
+        // The sent transaction changes the unspent outputs without the new block.
+        let mut tip = self.tip.lock().unwrap();
+        tip.transaction_infos.clear();
+        tip.unspent_transactions.clear();
+        Ok(())
+    }
+
+    fn send_to_address(&self, addr: &btc::Address, satoshis: u64) -> Result<FundingTx> {
+        let tx = self.relay.send_to_address(addr, satoshis)?;
+        let mut tip = self.tip.lock().unwrap();
+        tip.transaction_infos.clear();
+        tip.unspent_transactions.clear();
+        Ok(tx)
+    }
+
+    fn unspent_transactions(&self, addr: &btc::Address) -> Result<Vec<TxInfo>> {
+        let key = addr.to_string();
+        self.cached(
+            |tip| tip.unspent_transactions.get(&key).cloned(),
+            |relay| relay.unspent_transactions(addr),
+            |tip, txs| {
+                tip.unspent_transactions.insert(key.clone(), txs.clone());
+            },
+        )
+    }
+
+    fn get_block_count(&self) -> Result<u64> {
+        self.cached(
+            |tip| tip.block_count,
+            |relay| relay.get_block_count(),
+            |tip, count| tip.block_count = Some(*count),
+        )
+    }
+
+    fn get_best_block_hash(&self) -> Result<String> {
+        self.relay.get_best_block_hash()
+    }
+
+    fn get_mempool_entry_height(&self, txid: btc::TxId) -> Result<Option<u64>> {
+        self.relay.get_mempool_entry_height(txid)
+    }
+
+    fn get_tx_out_confirmations(&self, txid: btc::TxId, vout: u32) -> Result<Option<u64>> {
+        self.relay.get_tx_out_confirmations(txid, vout)
+    }
+
+    fn get_tx_outs_confirmations(
+        &self,
+        outpoints: &[(btc::TxId, u32)],
+    ) -> Result<Vec<Option<u64>>> {
+        self.relay.get_tx_outs_confirmations(outpoints)
+    }
+
+    fn estimate_fee_rate(&self, conf_target: u16) -> Result<Option<u64>> {
+        self.cached(
+            |tip| tip.fee_rates.get(&conf_target).cloned(),
+            |relay| relay.estimate_fee_rate(conf_target),
+            |tip, rate| {
+                tip.fee_rates.insert(conf_target, *rate);
+            },
+        )
+    }
+
+    fn get_tx_out_proof(&self, txid: btc::TxId) -> Result<Option<TxOutProof>> {
+        self.cached(
+            |tip| tip.tx_out_proofs.get(&txid).cloned().map(Some),
+            |relay| relay.get_tx_out_proof(txid),
+            |tip, proof| if let Some(ref proof) = *proof {
+                tip.tx_out_proofs.insert(txid, proof.clone());
+            },
+        )
+    }
+
+    fn begin_check(&self) {
+        self.relay.begin_check();
+        let best_block_hash = match self.relay.get_best_block_hash() {
+            Ok(hash) => Some(hash),
+            Err(e) => {
+                warn!("Unable to get the best block hash, the relay cache is cleared: {}", e);
+                None
+            }
+        };
+        let mut tip = self.tip.lock().unwrap();
+        if best_block_hash.is_none() || tip.best_block_hash != best_block_hash {
+            trace!("Bitcoin tip has changed to {:?}", best_block_hash);
+            *tip = TipCache {
+                best_block_hash,
+                ..Default::default()
+            };
+        }
+    }
+
+    fn take_disagreements(&self) -> Vec<RelayDisagreement> {
+        self.relay.take_disagreements()
+    }
+
+    fn config(&self) -> AnchoringRpcConfig {
+        self.relay.config()
+    }
+}
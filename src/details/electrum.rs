@@ -0,0 +1,544 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `BitcoinRelay` implementation over the Electrum protocol.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use bitcoin::network::serialize::serialize;
+use bitcoin::util::hash::Sha256dHash;
+use bitcoinrpc;
+use hyper;
+use jsonrpc;
+use serde::Deserialize;
+use serde_json::{self, Value};
+
+use exonum::crypto::hash;
+use exonum::encoding::serialize::{encode_hex, FromHex};
+
+use details::btc;
+use details::btc::transactions::{BitcoinTx, FundingTx};
+use blockchain::dto::TxOutProof;
+use super::rpc::{parse_transaction, parse_txid, AnchoringRpcConfig, BitcoinRelay, Error,
+                 FeeEstimation, MempoolHeights, RelayBackend, Result, TxInfo};
+
+/// Timeout for the Electrum server responses.
+const RESPONSE_TIMEOUT: u64 = 30_000;
+/// Prefix of the Electrum server address which is allowed in the `host` field.
+const TCP_SCHEME: &str = "tcp://";
+/// Error code of the Electrum server which wraps an error of the underlying bitcoind.
+const DAEMON_ERROR: i64 = 2;
+
+/// Unspent output from the `blockchain.scripthash.listunspent` response.
+#[derive(Deserialize, Debug)]
+struct UnspentOutput {
+    /// Id of the transaction with the output.
+    tx_hash: String,
+    /// Index of the output.
+    tx_pos: u32,
+    /// Height of the block with the transaction or zero for the unconfirmed ones.
+    height: u64,
+}
+
+/// Script history entry from the `blockchain.scripthash.get_history` response.
+#[derive(Deserialize, Debug)]
+struct HistoryEntry {
+    /// Id of the transaction.
+    tx_hash: String,
+    /// Height of the block with the transaction, zero or `-1` for the unconfirmed ones.
+    height: i64,
+}
+
+/// `blockchain.transaction.get_merkle` response.
+#[derive(Deserialize, Debug)]
+struct MerkleBranch {
+    /// Height of the block with the transaction.
+    block_height: u64,
+    /// Hashes of the transaction siblings from the bottom of the tree.
+    merkle: Vec<String>,
+    /// Index of the transaction in the block.
+    pos: u32,
+}
+
+/// `blockchain.headers.subscribe` response.
+#[derive(Deserialize, Debug)]
+struct HeaderNotification {
+    /// Height of the best known block.
+    height: u64,
+    /// Serialized header of the best known block.
+    hex: String,
+}
+
+/// Unspent outputs of the script for its status from the `blockchain.scripthash.subscribe`.
+#[derive(Debug)]
+struct ScriptUnspent {
+    status: Option<String>,
+    outputs: Vec<(BitcoinTx, u32, u64)>,
+}
+
+#[derive(Debug)]
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+/// Client for the Electrum protocol servers, for more information visit
+/// this [site](https://electrumx.readthedocs.io/en/latest/protocol.html).
+///
+/// Electrum servers neither hold the wallet nor track the mempool entries, so the
+/// `send_to_address` is not supported and the mempool entry height is the best block
+/// height at the moment when the client has seen the unconfirmed transaction first time.
+#[derive(Debug)]
+pub struct ElectrumClient {
+    host: String,
+    connection: Mutex<Option<Connection>>,
+    nonce: AtomicUsize,
+    unspent: Mutex<HashMap<String, ScriptUnspent>>,
+    mempool_heights: MempoolHeights,
+}
+
+impl ElectrumClient {
+    /// Creates a client for the Electrum server with the given `host:port` address.
+    pub fn new<S: Into<String>>(host: S) -> ElectrumClient {
+        ElectrumClient {
+            host: host.into(),
+            connection: Mutex::new(None),
+            nonce: AtomicUsize::new(0),
+            unspent: Mutex::new(HashMap::new()),
+            mempool_heights: MempoolHeights::default(),
+        }
+    }
+
+    /// Returns the address of the Electrum server.
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    fn request<T>(&self, method: &str, params: Vec<Value>) -> Result<T>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        let mut connection = self.connection.lock().unwrap();
+        if connection.is_none() {
+            *connection = Some(self.connect().map_err(io_error)?);
+        }
+        let id = self.nonce.fetch_add(1, Ordering::Relaxed);
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        });
+        trace!("Electrum request: {}", request);
+
+        let response = match exchange(connection.as_mut().unwrap(), &request, id) {
+            Ok(response) => response,
+            Err(e) => {
+                // The connection state is unknown, so the next request has to reconnect.
+                *connection = None;
+                return Err(io_error(e));
+            }
+        };
+        match response.get("error") {
+            Some(error) if !error.is_null() => return Err(electrum_error(error.clone())),
+            _ => {}
+        }
+        let result = response.get("result").cloned().unwrap_or(Value::Null);
+        serde_json::from_value(result).map_err(|e| Error::parse("response", e))
+    }
+
+    fn connect(&self) -> io::Result<Connection> {
+        let address = if self.host.starts_with(TCP_SCHEME) {
+            &self.host[TCP_SCHEME.len()..]
+        } else {
+            &self.host
+        };
+        let writer = TcpStream::connect(address)?;
+        writer.set_read_timeout(
+            Some(Duration::from_millis(RESPONSE_TIMEOUT)),
+        )?;
+        writer.set_write_timeout(
+            Some(Duration::from_millis(RESPONSE_TIMEOUT)),
+        )?;
+        let reader = BufReader::new(writer.try_clone()?);
+        Ok(Connection { reader, writer })
+    }
+
+    /// Returns the transaction with the height of its block, which is zero for the
+    /// unconfirmed transaction, or `None` for the height if the transaction is not
+    /// in the history of its output scripts.
+    ///
+    /// The verbose `blockchain.transaction.get` is not supported by all servers,
+    /// so the height is looked up in the script history instead.
+    fn get_transaction_height(
+        &self,
+        txid: btc::TxId,
+    ) -> Result<Option<(BitcoinTx, Option<u64>)>> {
+        let tx = match self.get_transaction(txid)? {
+            Some(tx) => tx,
+            None => return Ok(None),
+        };
+        let txid = txid.to_string();
+        let mut scripts = Vec::new();
+        for output in &tx.output {
+            let script_hash = output_script_hash(&output.script_pubkey[..]);
+            if scripts.contains(&script_hash) {
+                continue;
+            }
+            let history: Vec<HistoryEntry> = self.request(
+                "blockchain.scripthash.get_history",
+                vec![script_hash.clone().into()],
+            )?;
+            if let Some(entry) = history.into_iter().find(|entry| entry.tx_hash == txid) {
+                let height = if entry.height > 0 { entry.height as u64 } else { 0 };
+                return Ok(Some((tx, Some(height))));
+            }
+            scripts.push(script_hash);
+        }
+        Ok(Some((tx, None)))
+    }
+}
+
+impl From<AnchoringRpcConfig> for ElectrumClient {
+    fn from(cfg: AnchoringRpcConfig) -> Self {
+        ElectrumClient::new(cfg.host)
+    }
+}
+
+impl BitcoinRelay for ElectrumClient {
+    fn get_transaction(&self, txid: btc::TxId) -> Result<Option<BitcoinTx>> {
+        let params = vec![txid.to_string().into(), false.into()];
+        match self.request::<String>("blockchain.transaction.get", params) {
+            Ok(tx) => Ok(Some(parse_transaction(tx)?)),
+            Err(Error::Rpc(bitcoinrpc::Error::NoInformation(_))) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn get_transaction_info(&self, txid: btc::TxId) -> Result<Option<TxInfo>> {
+        let (body, height) = match self.get_transaction_height(txid)? {
+            Some(tx) => tx,
+            None => return Ok(None),
+        };
+        let confirmations = match height {
+            Some(height) => Some(confirmations(height, self.get_block_count()?)),
+            None => None,
+        };
+        Ok(Some(TxInfo {
+            body,
+            confirmations,
+            vout: None,
+        }))
+    }
+
+    fn watch_address(&self, addr: &btc::Address, _rescan: bool) -> Result<()> {
+        // Electrum servers index all scripts, so the subscription is enough to watch
+        // the address and the rescan is never needed.
+        let _status: Option<String> =
+            self.request("blockchain.scripthash.subscribe", vec![script_hash(addr).into()])?;
+        Ok(())
+    }
+
+    fn send_transaction(&self, tx: BitcoinTx) -> Result<()> {
+        let _txid: String =
+            self.request("blockchain.transaction.broadcast", vec![tx.to_hex().into()])?;
+        Ok(())
+    }
+
+    fn send_to_address(&self, _addr: &btc::Address, _satoshis: u64) -> Result<FundingTx> {
+        Err(Error::rpc(
+            -32601,
+            "Electrum server does not hold the wallet to send funds",
+        ))
+    }
+
+    fn unspent_transactions(&self, addr: &btc::Address) -> Result<Vec<TxInfo>> {
+        let script_hash = script_hash(addr);
+        // The status changes only with the script history, so the outputs are requested
+        // only for the new statuses.
+        let status: Option<String> =
+            self.request("blockchain.scripthash.subscribe", vec![script_hash.clone().into()])?;
+        let is_actual = self.unspent
+            .lock()
+            .unwrap()
+            .get(&script_hash)
+            .map_or(false, |unspent| unspent.status == status);
+        if !is_actual {
+            let outputs: Vec<UnspentOutput> = self.request(
+                "blockchain.scripthash.listunspent",
+                vec![script_hash.clone().into()],
+            )?;
+            let mut unspent = ScriptUnspent {
+                status,
+                outputs: Vec::new(),
+            };
+            for output in outputs {
+                let txid = parse_txid(&output.tx_hash)?;
+                if let Some(tx) = self.get_transaction(txid)? {
+                    unspent.outputs.push((tx, output.tx_pos, output.height));
+                }
+            }
+            self.unspent.lock().unwrap().insert(
+                script_hash.clone(),
+                unspent,
+            );
+        }
+
+        let block_count = self.get_block_count()?;
+        let unspent = self.unspent.lock().unwrap();
+        let txs = unspent[&script_hash]
+            .outputs
+            .iter()
+            .filter_map(|&(ref tx, vout, height)| {
+                TxInfo::unspent(tx.clone(), Some(confirmations(height, block_count)), vout)
+            })
+            .collect();
+        Ok(txs)
+    }
+
+    fn get_block_count(&self) -> Result<u64> {
+        let header: HeaderNotification = self.request("blockchain.headers.subscribe", vec![])?;
+        Ok(header.height)
+    }
+
+    fn get_best_block_hash(&self) -> Result<String> {
+        let header: HeaderNotification = self.request("blockchain.headers.subscribe", vec![])?;
+        let header = Vec::<u8>::from_hex(&header.hex).map_err(|e| Error::parse("header", e))?;
+        Ok(Sha256dHash::from_data(&header).be_hex_string())
+    }
+
+    fn get_mempool_entry_height(&self, txid: btc::TxId) -> Result<Option<u64>> {
+        let is_unconfirmed = match self.get_transaction_height(txid)? {
+            Some((_, height)) => height.unwrap_or(0) == 0,
+            None => false,
+        };
+        self.mempool_heights.entry_height(
+            txid,
+            is_unconfirmed,
+            || self.get_block_count(),
+        )
+    }
+
+    fn get_tx_out_confirmations(&self, txid: btc::TxId, vout: u32) -> Result<Option<u64>> {
+        let script_pubkey = match self.get_transaction(txid)? {
+            Some(tx) => {
+                match tx.output.get(vout as usize) {
+                    Some(output) => output.script_pubkey.clone(),
+                    None => return Ok(None),
+                }
+            }
+            None => return Ok(None),
+        };
+        // Electrum servers do not look up the outputs, so the unspent outputs of the script
+        // are listed instead. The outputs spent in the mempool are not listed.
+        let outputs: Vec<UnspentOutput> = self.request(
+            "blockchain.scripthash.listunspent",
+            vec![output_script_hash(&script_pubkey[..]).into()],
+        )?;
+        let txid = txid.to_string();
+        let height = match outputs.into_iter().find(|output| {
+            output.tx_hash == txid && output.tx_pos == vout
+        }) {
+            Some(output) => output.height,
+            None => return Ok(None),
+        };
+        let block_count = self.get_block_count()?;
+        Ok(Some(confirmations(height, block_count)))
+    }
+
+    fn estimate_fee_rate(&self, conf_target: u16) -> Result<Option<u64>> {
+        let feerate: f64 = self.request("blockchain.estimatefee", vec![conf_target.into()])?;
+        let estimation = FeeEstimation {
+            // Electrum servers return `-1` if the fee rate could not be estimated.
+            feerate: if feerate > 0.0 { Some(feerate) } else { None },
+        };
+        Ok(estimation.sat_per_vbyte())
+    }
+
+    fn get_tx_out_proof(&self, txid: btc::TxId) -> Result<Option<TxOutProof>> {
+        let height = match self.get_transaction_height(txid)? {
+            Some((_, Some(height))) if height > 0 => height,
+            _ => return Ok(None),
+        };
+        let branch: MerkleBranch = self.request(
+            "blockchain.transaction.get_merkle",
+            vec![txid.to_string().into(), height.into()],
+        )?;
+        let header: String =
+            self.request("blockchain.block.header", vec![branch.block_height.into()])?;
+
+        let header = Vec::<u8>::from_hex(&header).map_err(|e| Error::parse("header", e))?;
+        let siblings = branch
+            .merkle
+            .iter()
+            .map(|hash| Sha256dHash::from_hex(hash))
+            .collect::<::std::result::Result<Vec<_>, _>>()
+            .map_err(|e| Error::parse("merkle branch", e))?;
+        let block_hash = Sha256dHash::from_data(&header).be_hex_string();
+        let merkle_block = merkle_block(&header, &txid, &siblings, branch.pos);
+        Ok(Some(TxOutProof::new(&block_hash, &encode_hex(&merkle_block))))
+    }
+
+    fn config(&self) -> AnchoringRpcConfig {
+        AnchoringRpcConfig {
+            host: self.host.clone(),
+            username: None,
+            password: None,
+            backend: RelayBackend::Electrum,
+            utxo_discovery: Default::default(),
+            credentials: Default::default(),
+            tls: None,
+        }
+    }
+}
+
+/// Sends the request and reads the response with the given `id` skipping the notifications.
+fn exchange(connection: &mut Connection, request: &Value, id: usize) -> io::Result<Value> {
+    let mut line = serde_json::to_string(request).unwrap();
+    line.push('\n');
+    connection.writer.write_all(line.as_bytes())?;
+    connection.writer.flush()?;
+
+    loop {
+        let mut line = String::new();
+        if connection.reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Electrum server closed the connection",
+            ));
+        }
+        let response: Value = serde_json::from_str(&line).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, e)
+        })?;
+        trace!("Electrum response: {}", response);
+        if response.get("id").and_then(Value::as_u64) == Some(id as u64) {
+            return Ok(response);
+        }
+    }
+}
+
+/// Returns the number of confirmations of the transaction in the block at the given height,
+/// the zero height means the unconfirmed transaction.
+fn confirmations(height: u64, block_count: u64) -> u64 {
+    if height > 0 && height <= block_count {
+        block_count - height + 1
+    } else {
+        0
+    }
+}
+
+fn io_error(e: io::Error) -> Error {
+    Error::Transport(hyper::Error::Io(e))
+}
+
+/// Converts the Electrum error to the bitcoind one if the server wraps it.
+fn electrum_error(error: Value) -> Error {
+    let code = error.get("code").and_then(Value::as_i64);
+    let message = error.get("message").and_then(Value::as_str);
+    if let (Some(DAEMON_ERROR), Some(message)) = (code, message) {
+        if let Ok(daemon_error) = serde_json::from_str::<Value>(message) {
+            if daemon_error.get("code").is_some() {
+                return Error::from(jsonrpc::Error::Rpc(daemon_error));
+            }
+        }
+    }
+    Error::from(jsonrpc::Error::Rpc(error))
+}
+
+/// Returns the Electrum script hash, which is the reversed `sha256` hash of the address
+/// `script_pubkey`.
+fn script_hash(addr: &btc::Address) -> String {
+    output_script_hash(&addr.script_pubkey()[..])
+}
+
+/// Returns the Electrum script hash of the given output script.
+fn output_script_hash(script_pubkey: &[u8]) -> String {
+    let hash = hash(script_pubkey);
+    let bytes = hash.as_ref().iter().rev().cloned().collect::<Vec<u8>>();
+    encode_hex(&bytes)
+}
+
+/// Builds the `merkleblock` message with the single matched transaction from its
+/// merkle branch, so the result can be verified like the `gettxoutproof` one.
+///
+/// Electrum servers do not report the total number of the block transactions,
+/// so the smallest number consistent with the branch is used.
+pub(crate) fn merkle_block(
+    header: &[u8],
+    txid: &Sha256dHash,
+    siblings: &[Sha256dHash],
+    pos: u32,
+) -> Vec<u8> {
+    // The transaction is the last one at every level where its sibling is a duplicate,
+    // otherwise at least one more node follows it.
+    let mut last = pos;
+    let mut node = *txid;
+    for (level, sibling) in siblings.iter().enumerate() {
+        if (pos >> level) & 1 == 0 {
+            if *sibling != node {
+                last |= (1 << (level + 1)) - 1;
+            }
+            node = merkle_parent(&node, sibling);
+        } else {
+            node = merkle_parent(sibling, &node);
+        }
+    }
+    let total = last + 1;
+    let width = |level: usize| (total + (1 << level) - 1) >> level;
+
+    // Depth-first traversal: the left siblings precede the path and the right ones
+    // follow it in the bottom-up order.
+    let mut bits = Vec::new();
+    let mut hashes = Vec::new();
+    let mut right_siblings = Vec::new();
+    for level in (0..siblings.len()).rev() {
+        bits.push(true);
+        let index = pos >> level;
+        if index & 1 == 1 {
+            bits.push(false);
+            hashes.push(siblings[level]);
+        } else if index + 1 < width(level) {
+            right_siblings.push(siblings[level]);
+        }
+    }
+    bits.push(true);
+    hashes.push(*txid);
+    for sibling in right_siblings.into_iter().rev() {
+        bits.push(false);
+        hashes.push(sibling);
+    }
+
+    let mut flags = vec![0u8; (bits.len() + 7) / 8];
+    for (i, _) in bits.iter().enumerate().filter(|&(_, bit)| *bit) {
+        flags[i / 8] |= 1 << (i % 8);
+    }
+
+    let mut merkle_block = header.to_vec();
+    merkle_block.extend(serialize(&total).unwrap());
+    merkle_block.extend(serialize(&hashes).unwrap());
+    merkle_block.extend(serialize(&flags).unwrap());
+    merkle_block
+}
+
+pub(crate) fn merkle_parent(left: &Sha256dHash, right: &Sha256dHash) -> Sha256dHash {
+    let mut data = left[..].to_vec();
+    data.extend_from_slice(&right[..]);
+    Sha256dHash::from_data(&data)
+}
+
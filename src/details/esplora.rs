@@ -0,0 +1,353 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `BitcoinRelay` implementation over the Esplora REST api.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Read;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use hyper;
+use hyper::client::Response;
+use hyper::status::StatusCode;
+use jsonrpc;
+use serde::Deserialize;
+use serde_json::{self, Value};
+
+use details::btc;
+use details::btc::transactions::{BitcoinTx, FundingTx};
+use blockchain::dto::TxOutProof;
+use super::rpc::{parse_transaction, parse_txid, AnchoringRpcConfig, BitcoinRelay, Error,
+                 MempoolHeights, RelayBackend, Result, TxInfo};
+
+/// Timeout for the Esplora server responses.
+const RESPONSE_TIMEOUT: u64 = 30_000;
+
+/// Status of the transaction from the `GET /tx/:txid/status` response.
+#[derive(Deserialize, Debug)]
+struct TxStatus {
+    /// Whether the transaction is included in the best chain.
+    confirmed: bool,
+    /// Height of the block with the transaction.
+    block_height: Option<u64>,
+    /// Hash of the block with the transaction.
+    block_hash: Option<String>,
+}
+
+/// Spending status of the output from the `GET /tx/:txid/outspend/:vout` response.
+#[derive(Deserialize, Debug)]
+struct OutSpend {
+    /// Whether the output is spent, including the spends in the mempool.
+    spent: bool,
+}
+
+/// Unspent output from the `GET /address/:address/utxo` response.
+#[derive(Deserialize, Debug)]
+struct UnspentOutput {
+    /// Id of the transaction with the output.
+    txid: String,
+    /// Index of the output.
+    vout: u32,
+    /// Status of the transaction with the output.
+    status: TxStatus,
+}
+
+/// Client for the Esplora REST api servers, for more information visit
+/// this [site](https://github.com/Blockstream/esplora/blob/master/API.md).
+///
+/// Esplora servers neither hold the wallet nor report the mempool entries, so the
+/// `send_to_address` is not supported and the mempool entry height is the best block
+/// height at the moment when the client has seen the unconfirmed transaction first time.
+#[derive(Debug)]
+pub struct EsploraClient {
+    host: String,
+    client: hyper::Client,
+    transactions: Mutex<HashMap<btc::TxId, BitcoinTx>>,
+    mempool_heights: MempoolHeights,
+}
+
+impl EsploraClient {
+    /// Creates a client for the Esplora api with the given base url,
+    /// e.g. `http://localhost:3002/api`.
+    pub fn new<S: Into<String>>(host: S) -> EsploraClient {
+        let mut client = hyper::Client::new();
+        client.set_read_timeout(Some(Duration::from_millis(RESPONSE_TIMEOUT)));
+        client.set_write_timeout(Some(Duration::from_millis(RESPONSE_TIMEOUT)));
+        EsploraClient {
+            host: host.into(),
+            client,
+            transactions: Mutex::new(HashMap::new()),
+            mempool_heights: MempoolHeights::default(),
+        }
+    }
+
+    /// Returns the base url of the Esplora api.
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    fn url(&self, path: &str) -> String {
+        let host = if self.host.ends_with('/') {
+            &self.host[..self.host.len() - 1]
+        } else {
+            &self.host
+        };
+        format!("{}{}", host, path)
+    }
+
+    /// Performs the `GET` request and returns the response body or `None`
+    /// if the server does not know the requested object.
+    fn get(&self, path: &str) -> Result<Option<String>> {
+        trace!("Esplora request: GET {}", path);
+        let response = self.client.get(&self.url(path)).send().map_err(http_error)?;
+        match read_response(response)? {
+            (StatusCode::NotFound, _) => Ok(None),
+            (status, body) => {
+                if status.is_success() {
+                    Ok(Some(body))
+                } else {
+                    Err(status_error(status, body))
+                }
+            }
+        }
+    }
+
+    fn get_json<T>(&self, path: &str) -> Result<Option<T>>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        match self.get(path)? {
+            Some(body) => {
+                let value = serde_json::from_str(&body).map_err(|e| Error::parse("response", e))?;
+                Ok(Some(value))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn get_status(&self, txid: btc::TxId) -> Result<Option<TxStatus>> {
+        self.get_json(&format!("/tx/{}/status", txid.to_string()))
+    }
+
+    fn confirmations(&self, status: &TxStatus, block_count: u64) -> u64 {
+        match status.block_height {
+            Some(height) if status.confirmed && height <= block_count => block_count - height + 1,
+            _ => 0,
+        }
+    }
+}
+
+impl From<AnchoringRpcConfig> for EsploraClient {
+    fn from(cfg: AnchoringRpcConfig) -> Self {
+        EsploraClient::new(cfg.host)
+    }
+}
+
+impl BitcoinRelay for EsploraClient {
+    fn get_transaction(&self, txid: btc::TxId) -> Result<Option<BitcoinTx>> {
+        let tx = self.get(&format!("/tx/{}/hex", txid.to_string()))?;
+        match tx {
+            Some(tx) => Ok(Some(parse_transaction(tx.trim())?)),
+            None => Ok(None),
+        }
+    }
+
+    fn get_transaction_info(&self, txid: btc::TxId) -> Result<Option<TxInfo>> {
+        let status = match self.get_status(txid)? {
+            Some(status) => status,
+            None => return Ok(None),
+        };
+        let body = match self.get_transaction(txid)? {
+            Some(body) => body,
+            None => return Ok(None),
+        };
+        let confirmations = self.confirmations(&status, self.get_block_count()?);
+        Ok(Some(TxInfo {
+            body,
+            confirmations: Some(confirmations),
+            vout: None,
+        }))
+    }
+
+    fn watch_address(&self, _addr: &btc::Address, _rescan: bool) -> Result<()> {
+        // Esplora servers index all addresses, so there is nothing to import.
+        Ok(())
+    }
+
+    fn send_transaction(&self, tx: BitcoinTx) -> Result<()> {
+        trace!("Esplora request: POST /tx");
+        let response = self.client
+            .post(&self.url("/tx"))
+            .body(tx.to_hex().as_str())
+            .send()
+            .map_err(http_error)?;
+        match read_response(response)? {
+            (status, _) if status.is_success() => Ok(()),
+            (status, body) => Err(status_error(status, body)),
+        }
+    }
+
+    fn send_to_address(&self, _addr: &btc::Address, _satoshis: u64) -> Result<FundingTx> {
+        Err(Error::rpc(
+            -32601,
+            "Esplora server does not hold the wallet to send funds",
+        ))
+    }
+
+    fn unspent_transactions(&self, addr: &btc::Address) -> Result<Vec<TxInfo>> {
+        let outputs: Vec<UnspentOutput> = self.get_json(&format!("/address/{}/utxo", addr))?
+            .unwrap_or_default();
+        let block_count = self.get_block_count()?;
+
+        let mut txs = Vec::new();
+        let mut actual = HashSet::new();
+        for output in outputs {
+            let txid = parse_txid(&output.txid)?;
+            // Transactions are immutable, so their bodies are requested only once.
+            let cached = self.transactions.lock().unwrap().get(&txid).cloned();
+            let tx = match cached {
+                Some(tx) => tx,
+                None => {
+                    match self.get_transaction(txid)? {
+                        Some(tx) => tx,
+                        None => continue,
+                    }
+                }
+            };
+            self.transactions.lock().unwrap().insert(txid, tx.clone());
+            actual.insert(txid);
+
+            let confirmations = self.confirmations(&output.status, block_count);
+            if let Some(info) = TxInfo::unspent(tx, Some(confirmations), output.vout) {
+                txs.push(info);
+            }
+        }
+        // Forget the spent transactions.
+        self.transactions.lock().unwrap().retain(
+            |txid, _| actual.contains(txid),
+        );
+        Ok(txs)
+    }
+
+    fn get_block_count(&self) -> Result<u64> {
+        let height = self.get("/blocks/tip/height")?.unwrap_or_default();
+        height.trim().parse().map_err(|e| Error::parse("block height", e))
+    }
+
+    fn get_best_block_hash(&self) -> Result<String> {
+        let hash = self.get("/blocks/tip/hash")?.unwrap_or_default();
+        Ok(hash.trim().to_owned())
+    }
+
+    fn get_mempool_entry_height(&self, txid: btc::TxId) -> Result<Option<u64>> {
+        let is_unconfirmed = match self.get_status(txid)? {
+            Some(status) => !status.confirmed,
+            None => false,
+        };
+        self.mempool_heights.entry_height(
+            txid,
+            is_unconfirmed,
+            || self.get_block_count(),
+        )
+    }
+
+    fn get_tx_out_confirmations(&self, txid: btc::TxId, vout: u32) -> Result<Option<u64>> {
+        let path = format!("/tx/{}/outspend/{}", txid.to_string(), vout);
+        match self.get_json::<OutSpend>(&path)? {
+            Some(OutSpend { spent: false }) => {}
+            _ => return Ok(None),
+        }
+        // Esplora reports the unknown outputs as the unspent ones.
+        let tx = match self.get_transaction(txid)? {
+            Some(tx) => tx,
+            None => return Ok(None),
+        };
+        if tx.output.len() <= vout as usize {
+            return Ok(None);
+        }
+        let status = match self.get_status(txid)? {
+            Some(status) => status,
+            None => return Ok(None),
+        };
+        Ok(Some(self.confirmations(&status, self.get_block_count()?)))
+    }
+
+    fn estimate_fee_rate(&self, conf_target: u16) -> Result<Option<u64>> {
+        // Esplora estimates the fee rate in satoshis per virtual byte only for the fixed set
+        // of the confirmation targets, so the closest faster one is used.
+        let estimates: BTreeMap<String, f64> = self.get_json("/fee-estimates")?
+            .unwrap_or_default();
+        let feerate = estimates
+            .iter()
+            .filter_map(|(target, feerate)| {
+                target.parse::<u16>().ok().map(|target| (target, *feerate))
+            })
+            .filter(|&(target, _)| target <= conf_target)
+            .max_by_key(|&(target, _)| target)
+            .map(|(_, feerate)| feerate.ceil() as u64);
+        Ok(feerate)
+    }
+
+    fn get_tx_out_proof(&self, txid: btc::TxId) -> Result<Option<TxOutProof>> {
+        let block_hash = match self.get_status(txid)? {
+            Some(TxStatus {
+                     confirmed: true,
+                     block_hash: Some(block_hash),
+                     ..
+                 }) => block_hash,
+            _ => return Ok(None),
+        };
+        let proof = self.get(&format!("/tx/{}/merkleblock-proof", txid.to_string()))?;
+        Ok(proof.map(|proof| TxOutProof::new(&block_hash, proof.trim())))
+    }
+
+    fn config(&self) -> AnchoringRpcConfig {
+        AnchoringRpcConfig {
+            host: self.host.clone(),
+            username: None,
+            password: None,
+            backend: RelayBackend::Esplora,
+            utxo_discovery: Default::default(),
+            credentials: Default::default(),
+            tls: None,
+        }
+    }
+}
+
+fn read_response(mut response: Response) -> Result<(StatusCode, String)> {
+    let mut body = String::new();
+    response.read_to_string(&mut body).map_err(|e| {
+        http_error(hyper::Error::Io(e))
+    })?;
+    trace!("Esplora response: {} {}", response.status, body);
+    Ok((response.status, body))
+}
+
+fn http_error(e: hyper::Error) -> Error {
+    Error::Transport(e)
+}
+
+/// Converts the unsuccessful response to the bitcoind error if the server forwards it,
+/// e.g. `sendrawtransaction RPC error: {"code":-26,"message":"..."}`.
+fn status_error(status: StatusCode, body: String) -> Error {
+    if let Some(start) = body.find('{') {
+        if let Ok(daemon_error) = serde_json::from_str::<Value>(&body[start..]) {
+            if daemon_error.get("code").is_some() {
+                return Error::from(jsonrpc::Error::Rpc(daemon_error));
+            }
+        }
+    }
+    Error::rpc(i64::from(status.to_u16()), body)
+}
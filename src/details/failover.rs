@@ -0,0 +1,470 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `BitcoinRelay` wrapper with retries, timeouts and fallback endpoints.
+
+use std::cmp;
+use std::io;
+use std::mem;
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use hyper;
+
+use details::btc;
+use details::btc::transactions::{BitcoinTx, FundingTx};
+use blockchain::dto::TxOutProof;
+use observer::Milliseconds;
+use super::rpc::{AnchoringRpcConfig, BitcoinRelay, Error, RelayDisagreement, Result, TxInfo};
+
+/// Error code of bitcoind which is still loading the blocks after the restart.
+const RPC_IN_WARMUP: i64 = -28;
+/// Error code of bitcoind which rejects the transaction, e.g. since it is already in the mempool.
+const RPC_VERIFY_REJECTED: i64 = -26;
+/// Error code of bitcoind which already has the transaction in the blockchain.
+const RPC_VERIFY_ALREADY_IN_CHAIN: i64 = -27;
+
+/// Retry policy of the `FailoverRelay`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct RelayRetryConfig {
+    /// Timeout of the single request.
+    pub timeout: Milliseconds,
+    /// Number of the attempts to send the request to the same endpoint.
+    pub attempts: u32,
+    /// Delay before the second attempt, which is doubled for each next one.
+    pub initial_backoff: Milliseconds,
+    /// Upper bound of the delay between the attempts.
+    pub max_backoff: Milliseconds,
+    /// Number of the consecutive failed requests after which the endpoint is not used.
+    pub failure_threshold: u32,
+    /// Time during which the failed endpoint is not used.
+    pub cooldown: Milliseconds,
+}
+
+impl Default for RelayRetryConfig {
+    fn default() -> RelayRetryConfig {
+        RelayRetryConfig {
+            timeout: 10_000,
+            attempts: 3,
+            initial_backoff: 200,
+            max_backoff: 2_000,
+            failure_threshold: 3,
+            cooldown: 30_000,
+        }
+    }
+}
+
+/// Circuit breaker state of the endpoint.
+#[derive(Debug, Default)]
+struct Breaker {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+/// Address which the endpoint has failed to watch.
+#[derive(Debug, PartialEq)]
+struct PendingWatch {
+    index: usize,
+    addr: btc::Address,
+    rescan: bool,
+}
+
+/// `BitcoinRelay` which retries the failed requests with the exponential backoff and
+/// falls back to the next endpoints in order.
+///
+/// Only the transport errors, timeouts and the errors of the warming up bitcoind are retried,
+/// other errors are the answers of the endpoint. The endpoint is not used for the `cooldown`
+/// after `failure_threshold` failed requests in a row. The timed out request keeps running
+/// in the background until the underlying client returns. Funds are sent by the first
+/// available endpoint without retries, since the failed request might have sent them.
+///
+/// The addresses are watched by all reachable endpoints, the others watch them
+/// on the next `begin_check`.
+#[derive(Debug)]
+pub struct FailoverRelay {
+    relays: Vec<Arc<BitcoinRelay>>,
+    config: RelayRetryConfig,
+    breakers: Mutex<Vec<Breaker>>,
+    pending_watches: Mutex<Vec<PendingWatch>>,
+}
+
+impl FailoverRelay {
+    /// Creates a relay over the given endpoints in the order of preference.
+    ///
+    /// # Panics
+    ///
+    /// If there are no endpoints or the number of attempts is zero.
+    pub fn new(relays: Vec<Box<BitcoinRelay>>, config: RelayRetryConfig) -> FailoverRelay {
+        assert!(!relays.is_empty(), "Failover relay needs at least one endpoint");
+        assert!(config.attempts > 0, "Number of attempts must be positive");
+        let breakers = relays.iter().map(|_| Breaker::default()).collect();
+        FailoverRelay {
+            relays: relays.into_iter().map(Arc::from).collect(),
+            config,
+            breakers: Mutex::new(breakers),
+            pending_watches: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns the retry policy.
+    pub fn retry_config(&self) -> &RelayRetryConfig {
+        &self.config
+    }
+
+    /// Returns the indices of the endpoints which are not cooling down.
+    fn available(&self) -> Vec<usize> {
+        let now = Instant::now();
+        self.breakers
+            .lock()
+            .unwrap()
+            .iter()
+            .enumerate()
+            .filter(|&(_, breaker)| breaker.open_until.map_or(true, |until| until <= now))
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    fn record_success(&self, index: usize) {
+        let mut breakers = self.breakers.lock().unwrap();
+        if breakers[index].open_until.is_some() {
+            info!("Relay endpoint {} is available again", self.host(index));
+        }
+        breakers[index] = Breaker::default();
+    }
+
+    fn record_failure(&self, index: usize, method: &str, e: &Error) {
+        let mut breakers = self.breakers.lock().unwrap();
+        let breaker = &mut breakers[index];
+        breaker.failures += 1;
+        if breaker.failures >= self.config.failure_threshold {
+            let cooldown = Duration::from_millis(self.config.cooldown);
+            breaker.open_until = Some(Instant::now() + cooldown);
+            error!(
+                "Relay endpoint {} failed {} times in a row on {} with {}, \
+                 it is not used for {} ms",
+                self.host(index),
+                breaker.failures,
+                method,
+                e,
+                self.config.cooldown
+            );
+        }
+    }
+
+    fn host(&self, index: usize) -> String {
+        self.relays[index].config().host
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 1u64.checked_shl(attempt - 1).unwrap_or(u64::max_value());
+        let backoff = self.config.initial_backoff.saturating_mul(factor);
+        Duration::from_millis(cmp::min(backoff, self.config.max_backoff))
+    }
+
+    /// Sends the request to the endpoint and waits for the response at most `timeout`.
+    fn send<T, F>(&self, index: usize, request: &Arc<F>) -> Result<T>
+    where
+        T: Send + 'static,
+        F: Fn(&BitcoinRelay) -> Result<T> + Send + Sync + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        let relay = Arc::clone(&self.relays[index]);
+        let request = Arc::clone(request);
+        thread::spawn(move || {
+            // The receiver is gone if the request has timed out.
+            let _ = sender.send(request(relay.as_ref()));
+        });
+        let e = match receiver.recv_timeout(Duration::from_millis(self.config.timeout)) {
+            Ok(response) => return response,
+            Err(mpsc::RecvTimeoutError::Timeout) => io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("Request timed out after {} ms", self.config.timeout),
+            ),
+            Err(mpsc::RecvTimeoutError::Disconnected) => io::Error::new(
+                io::ErrorKind::Other,
+                "Request has been aborted",
+            ),
+        };
+        Err(Error::Transport(hyper::Error::Io(e)))
+    }
+
+    /// Sends the watch request to the endpoint once.
+    fn watch(&self, index: usize, addr: &btc::Address, rescan: bool) -> Result<()> {
+        let addr = addr.clone();
+        let request = Arc::new(move |relay: &BitcoinRelay| relay.watch_address(&addr, rescan));
+        match self.send(index, &request) {
+            Err(e) => {
+                if is_transient(&e) {
+                    self.record_failure(index, "watch_address", &e);
+                }
+                Err(e)
+            }
+            Ok(()) => {
+                self.record_success(index);
+                Ok(())
+            }
+        }
+    }
+
+    fn defer_watch(&self, watch: PendingWatch) {
+        let mut pending = self.pending_watches.lock().unwrap();
+        if !pending.contains(&watch) {
+            pending.push(watch);
+        }
+    }
+
+    /// Sends the pending watch requests to the available endpoints.
+    fn retry_pending_watches(&self) {
+        let pending = mem::replace(&mut *self.pending_watches.lock().unwrap(), Vec::new());
+        if pending.is_empty() {
+            return;
+        }
+        let available = self.available();
+        for watch in pending {
+            if available.contains(&watch.index) {
+                match self.watch(watch.index, &watch.addr, watch.rescan) {
+                    Ok(()) => {
+                        info!(
+                            "Relay endpoint {} watches the address {}",
+                            self.host(watch.index),
+                            watch.addr
+                        );
+                        continue;
+                    }
+                    Err(e) => warn!(
+                        "Relay endpoint {} failed to watch the address {} with {}",
+                        self.host(watch.index),
+                        watch.addr,
+                        e
+                    ),
+                }
+            }
+            self.defer_watch(watch);
+        }
+    }
+
+    fn call<T, F>(&self, method: &str, request: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: Fn(&BitcoinRelay) -> Result<T> + Send + Sync + 'static,
+    {
+        let request = Arc::new(request);
+        let mut last_error = None;
+        for index in self.available() {
+            for attempt in 0..self.config.attempts {
+                if attempt > 0 {
+                    thread::sleep(self.backoff(attempt));
+                }
+                match self.send(index, &request) {
+                    Err(e) => {
+                        if !is_transient(&e) {
+                            self.record_success(index);
+                            return Err(e);
+                        }
+                        warn!(
+                            "Attempt {} to call {} on {} failed with {}",
+                            attempt + 1,
+                            method,
+                            self.host(index),
+                            e
+                        );
+                        last_error = Some(e);
+                    }
+                    Ok(response) => {
+                        self.record_success(index);
+                        return Ok(response);
+                    }
+                }
+            }
+            if let Some(ref e) = last_error {
+                self.record_failure(index, method, e);
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            Error::rpc(
+                -32603,
+                format!("All relay endpoints are cooling down, {} is skipped", method),
+            )
+        }))
+    }
+}
+
+impl BitcoinRelay for FailoverRelay {
+    fn get_transaction(&self, txid: btc::TxId) -> Result<Option<BitcoinTx>> {
+        self.call("get_transaction", move |relay| relay.get_transaction(txid))
+    }
+
+    fn get_transactions(&self, txids: &[btc::TxId]) -> Result<Vec<Option<BitcoinTx>>> {
+        let txids = txids.to_vec();
+        self.call("get_transactions", move |relay| relay.get_transactions(&txids))
+    }
+
+    fn get_transaction_info(&self, txid: btc::TxId) -> Result<Option<TxInfo>> {
+        self.call("get_transaction_info", move |relay| {
+            relay.get_transaction_info(txid)
+        })
+    }
+
+    fn watch_address(&self, addr: &btc::Address, rescan: bool) -> Result<()> {
+        // Every endpoint has to watch the address to be usable as the fallback one,
+        // so the failed ones are retried later, unless no endpoint watches it at all.
+        let available = self.available();
+        let mut failed = Vec::new();
+        let mut last_error = None;
+        for index in 0..self.relays.len() {
+            if available.contains(&index) {
+                match self.watch(index, addr, rescan) {
+                    Ok(()) => continue,
+                    Err(e) => {
+                        warn!(
+                            "Relay endpoint {} failed to watch the address {} with {}",
+                            self.host(index),
+                            addr,
+                            e
+                        );
+                        last_error = Some(e);
+                    }
+                }
+            }
+            failed.push(PendingWatch {
+                index,
+                addr: addr.clone(),
+                rescan,
+            });
+        }
+        if failed.len() == self.relays.len() {
+            return Err(last_error.unwrap_or_else(|| {
+                Error::rpc(
+                    -32603,
+                    "All relay endpoints are cooling down, watch_address is skipped",
+                )
+            }));
+        }
+        for watch in failed {
+            self.defer_watch(watch);
+        }
+        Ok(())
+    }
+
+    fn send_transaction(&self, tx: BitcoinTx) -> Result<()> {
+        // The failed attempt might have sent the transaction anyway,
+        // so the retries accept the transaction which is already known to the endpoint.
+        let retry = AtomicBool::new(false);
+        self.call("send_transaction", move |relay| {
+            let is_retry = retry.swap(true, Ordering::SeqCst);
+            match relay.send_transaction(tx.clone()) {
+                Err(ref e) if is_retry && is_already_known(e) => {
+                    info!(
+                        "Transaction {} is already known to {}",
+                        tx.txid(),
+                        relay.config().host
+                    );
+                    Ok(())
+                }
+                result => result,
+            }
+        })
+    }
+
+    fn send_to_address(&self, addr: &btc::Address, satoshis: u64) -> Result<FundingTx> {
+        let index = self.available().first().cloned().unwrap_or(0);
+        let addr = addr.clone();
+        let request = Arc::new(move |relay: &BitcoinRelay| relay.send_to_address(&addr, satoshis));
+        self.send(index, &request)
+    }
+
+    fn unspent_transactions(&self, addr: &btc::Address) -> Result<Vec<TxInfo>> {
+        let addr = addr.clone();
+        self.call("unspent_transactions", move |relay| {
+            relay.unspent_transactions(&addr)
+        })
+    }
+
+    fn get_block_count(&self) -> Result<u64> {
+        self.call("get_block_count", |relay| relay.get_block_count())
+    }
+
+    fn get_best_block_hash(&self) -> Result<String> {
+        self.call("get_best_block_hash", |relay| relay.get_best_block_hash())
+    }
+
+    fn get_mempool_entry_height(&self, txid: btc::TxId) -> Result<Option<u64>> {
+        self.call("get_mempool_entry_height", move |relay| {
+            relay.get_mempool_entry_height(txid)
+        })
+    }
+
+    fn get_tx_out_confirmations(&self, txid: btc::TxId, vout: u32) -> Result<Option<u64>> {
+        self.call("get_tx_out_confirmations", move |relay| {
+            relay.get_tx_out_confirmations(txid, vout)
+        })
+    }
+
+    fn get_tx_outs_confirmations(
+        &self,
+        outpoints: &[(btc::TxId, u32)],
+    ) -> Result<Vec<Option<u64>>> {
+        let outpoints = outpoints.to_vec();
+        self.call("get_tx_outs_confirmations", move |relay| {
+            relay.get_tx_outs_confirmations(&outpoints)
+        })
+    }
+
+    fn estimate_fee_rate(&self, conf_target: u16) -> Result<Option<u64>> {
+        self.call("estimate_fee_rate", move |relay| {
+            relay.estimate_fee_rate(conf_target)
+        })
+    }
+
+    fn get_tx_out_proof(&self, txid: btc::TxId) -> Result<Option<TxOutProof>> {
+        self.call("get_tx_out_proof", move |relay| relay.get_tx_out_proof(txid))
+    }
+
+    fn take_disagreements(&self) -> Vec<RelayDisagreement> {
+        self.relays
+            .iter()
+            .flat_map(|relay| relay.take_disagreements())
+            .collect()
+    }
+
+    fn begin_check(&self) {
+        self.retry_pending_watches();
+        for relay in &self.relays {
+            relay.begin_check();
+        }
+    }
+
+    fn config(&self) -> AnchoringRpcConfig {
+        self.relays[0].config()
+    }
+}
+
+/// Returns `true` if the request may succeed if it is sent again.
+fn is_transient(e: &Error) -> bool {
+    match *e {
+        Error::Rpc(_) => e.rpc_code() == Some(RPC_IN_WARMUP),
+        Error::Transport(_) | Error::Parse(_) | Error::Protocol(_) => true,
+    }
+}
+
+/// Returns `true` if the endpoint has rejected the transaction since it already has it.
+fn is_already_known(e: &Error) -> bool {
+    match e.rpc_code() {
+        Some(RPC_VERIFY_REJECTED) | Some(RPC_VERIFY_ALREADY_IN_CHAIN) => true,
+        _ => false,
+    }
+}
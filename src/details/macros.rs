@@ -174,13 +174,54 @@ macro_rules! implement_serde_base58check {
 )
 }
 
+macro_rules! implement_serde_str {
+($name:ident) => (
+    impl ::serde::Serialize for $name {
+        fn serialize<S>(&self, ser: S) -> ::std::result::Result<S::Ok, S::Error>
+            where S: ::serde::Serializer
+        {
+            ser.serialize_str(&self.to_string())
+        }
+    }
+
+    impl<'de> ::serde::Deserialize<'de> for $name {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where D: ::serde::Deserializer<'de>
+        {
+            struct StrVisitor;
+
+            impl<'v> ::serde::de::Visitor<'v> for StrVisitor {
+                type Value = $name;
+
+                fn expecting(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+                    write!(fmt, "Expected string representation of {}", stringify!($name))
+                }
+
+                fn visit_str<E>(self, s: &str) -> Result<$name, E>
+                    where E: ::serde::de::Error
+                {
+                    match s.parse::<$name>() {
+                        Ok(value) => Ok(value),
+                        Err(_) => Err(::serde::de::Error::custom(
+                            concat!("Wrong ", stringify!($name)),
+                        )),
+                    }
+                }
+            }
+
+            deserializer.deserialize_str(StrVisitor)
+        }
+    }
+)
+}
+
 macro_rules! implement_tx_wrapper {
 ($name:ident) => (
     implement_wrapper! {RawBitcoinTx, $name}
 
     impl $name {
         pub fn id(&self) -> TxId {
-            TxId::from(self.0.bitcoin_hash())
+            TxId::from(self.0.txid())
         }
 
         pub fn nid(&self) -> TxId {
@@ -188,7 +229,7 @@ macro_rules! implement_tx_wrapper {
         }
 
         pub fn txid(&self) -> String {
-            self.0.bitcoin_hash().be_hex_string()
+            self.0.txid().be_hex_string()
         }
 
         pub fn ntxid(&self) -> String {
@@ -17,6 +17,19 @@ mod macros;
 
 pub mod btc;
 pub mod rpc;
+pub mod bitcoind;
+pub mod transport;
+pub mod electrum;
+pub mod esplora;
+pub mod failover;
+pub mod cache;
+pub mod worker;
+pub mod quorum;
+pub mod notifications;
+#[cfg(feature = "simulated_bitcoin")]
+pub mod simulated;
+#[cfg(feature = "simulated_bitcoin")]
+pub mod simulated_bitcoind;
 pub mod error;
 
 #[cfg(test)]
@@ -0,0 +1,152 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Bitcoin network notifications, which trigger the anchoring checks without waiting
+//! for the next polling.
+
+use std::io;
+use std::sync::mpsc;
+#[cfg(feature = "zmq")]
+use std::thread;
+
+use bitcoin::network::serialize::deserialize;
+#[cfg(feature = "zmq")]
+use zmq;
+
+use exonum::encoding::serialize::encode_hex;
+
+use details::btc::transactions::{BitcoinTx, RawBitcoinTx};
+use super::rpc::{Error, Result};
+
+/// Topic of the bitcoind notifications about the new best blocks.
+pub const HASHBLOCK_TOPIC: &str = "hashblock";
+/// Topic of the bitcoind notifications about the new transactions.
+pub const RAWTX_TOPIC: &str = "rawtx";
+
+/// Event of the bitcoin network.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BitcoinEvent {
+    /// New best block with the given hash.
+    Block(String),
+    /// Transaction which has been accepted to the mempool or included into the block.
+    Transaction(BitcoinTx),
+}
+
+impl BitcoinEvent {
+    /// Parses the body of the bitcoind ZMQ notification with the given topic.
+    /// Returns `None` for the unknown topics.
+    pub fn from_zmq(topic: &[u8], body: &[u8]) -> Result<Option<BitcoinEvent>> {
+        if topic == HASHBLOCK_TOPIC.as_bytes() {
+            if body.len() != 32 {
+                return Err(Error::Parse(
+                    format!("Unexpected block hash length {}", body.len()),
+                ));
+            }
+            // Bitcoind sends the hash in the same byte order as it is displayed.
+            Ok(Some(BitcoinEvent::Block(encode_hex(body))))
+        } else if topic == RAWTX_TOPIC.as_bytes() {
+            let tx = deserialize::<RawBitcoinTx>(body).map_err(
+                |e| Error::parse("transaction", e),
+            )?;
+            Ok(Some(BitcoinEvent::Transaction(BitcoinTx::from(tx))))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Endpoints of the bitcoind ZMQ notifications, which are set by its `zmqpubhashblock`
+/// and `zmqpubrawtx` options.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ZmqNotificationsConfig {
+    /// Endpoint of the new block notifications, e.g. `tcp://127.0.0.1:28332`.
+    pub hashblock: String,
+    /// Endpoint of the new transaction notifications. Only the blocks are subscribed to
+    /// if it is not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rawtx: Option<String>,
+}
+
+impl ZmqNotificationsConfig {
+    /// Subscribes to the notifications, which are received by the dedicated thread.
+    /// The thread stops after the next notification once the receiver is dropped.
+    #[cfg(feature = "zmq")]
+    pub fn subscribe(&self) -> io::Result<mpsc::Receiver<BitcoinEvent>> {
+        let context = zmq::Context::new();
+        let socket = context.socket(zmq::SUB).map_err(zmq_error)?;
+        socket.connect(&self.hashblock).map_err(zmq_error)?;
+        socket.set_subscribe(HASHBLOCK_TOPIC.as_bytes()).map_err(
+            zmq_error,
+        )?;
+        if let Some(ref rawtx) = self.rawtx {
+            if *rawtx != self.hashblock {
+                socket.connect(rawtx).map_err(zmq_error)?;
+            }
+            socket.set_subscribe(RAWTX_TOPIC.as_bytes()).map_err(
+                zmq_error,
+            )?;
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        thread::Builder::new()
+            .name("anchoring-zmq".to_owned())
+            .spawn(move || {
+                // The context must outlive the socket.
+                let _context = context;
+                run_subscriber(&socket, &sender)
+            })?;
+        Ok(receiver)
+    }
+
+    /// Subscribes to the notifications, which is impossible without the `zmq` feature.
+    #[cfg(not(feature = "zmq"))]
+    pub fn subscribe(&self) -> io::Result<mpsc::Receiver<BitcoinEvent>> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "ZMQ notifications require the `zmq` feature",
+        ))
+    }
+}
+
+#[cfg(feature = "zmq")]
+fn run_subscriber(socket: &zmq::Socket, sender: &mpsc::Sender<BitcoinEvent>) {
+    loop {
+        let parts = match socket.recv_multipart(0) {
+            Ok(parts) => parts,
+            Err(e) => {
+                error!("Unable to receive the bitcoind notification: {}", e);
+                return;
+            }
+        };
+        // Bitcoind sends the topic, the body and the sequence number.
+        if parts.len() < 2 {
+            warn!("Received notification without body, parts={}", parts.len());
+            continue;
+        }
+        match BitcoinEvent::from_zmq(&parts[0], &parts[1]) {
+            Ok(Some(event)) => {
+                if sender.send(event).is_err() {
+                    return;
+                }
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Received malformed bitcoind notification: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "zmq")]
+fn zmq_error(e: zmq::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
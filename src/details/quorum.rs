@@ -0,0 +1,423 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Composite `BitcoinRelay` which requires the agreement of several backends.
+
+use std::fmt;
+use std::sync::Mutex;
+
+use details::btc;
+use details::btc::transactions::{BitcoinTx, FundingTx};
+use blockchain::dto::TxOutProof;
+use super::rpc::{AnchoringRpcConfig, BitcoinRelay, Error, Result, TxInfo};
+
+/// Disagreement between the backends of the `QuorumRelay`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelayDisagreement {
+    /// Name of the `BitcoinRelay` method.
+    pub method: String,
+    /// Responses of the backends which differ from the agreed one.
+    pub responses: Vec<String>,
+}
+
+impl fmt::Display for RelayDisagreement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.method, self.responses.join("; "))
+    }
+}
+
+/// `BitcoinRelay` which queries all the wrapped backends and returns the read results
+/// only if at least `quorum` of them agree on them, so a minority of the compromised or
+/// lagging backends can not mislead the validator.
+///
+/// The block counts, confirmations and fee rates are expected to differ slightly, so the
+/// `quorum`-th highest value is used, i.e. at least `quorum` backends report the value or
+/// the higher one. Transactions are broadcast to all backends, and the funds are sent
+/// only by the first one. The backends which differ from the agreed response are
+/// reported as `RelayDisagreement`s.
+#[derive(Debug)]
+pub struct QuorumRelay {
+    relays: Vec<Box<BitcoinRelay>>,
+    quorum: usize,
+    disagreements: Mutex<Vec<RelayDisagreement>>,
+}
+
+impl QuorumRelay {
+    /// Creates a relay over the given backends with the given quorum.
+    ///
+    /// # Panics
+    ///
+    /// If the quorum is zero or exceeds the number of backends.
+    pub fn new(relays: Vec<Box<BitcoinRelay>>, quorum: usize) -> QuorumRelay {
+        assert!(
+            quorum > 0 && quorum <= relays.len(),
+            "Relay quorum {} is out of range for {} relays",
+            quorum,
+            relays.len()
+        );
+        QuorumRelay {
+            relays,
+            quorum,
+            disagreements: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Creates a relay over the given backends with the majority of them as the quorum.
+    pub fn with_majority(relays: Vec<Box<BitcoinRelay>>) -> QuorumRelay {
+        let quorum = relays.len() / 2 + 1;
+        QuorumRelay::new(relays, quorum)
+    }
+
+    /// Returns the number of backends which must agree on the read results.
+    pub fn quorum(&self) -> usize {
+        self.quorum
+    }
+
+    /// Returns the wrapped backends.
+    pub fn relays(&self) -> &[Box<BitcoinRelay>] {
+        &self.relays
+    }
+
+    fn responses<T, F>(&self, request: F) -> Vec<Result<T>>
+    where
+        F: Fn(&BitcoinRelay) -> Result<T>,
+    {
+        self.relays
+            .iter()
+            .map(|relay| request(relay.as_ref()))
+            .collect()
+    }
+
+    fn describe<D: fmt::Debug>(
+        &self,
+        index: usize,
+        response: ::std::result::Result<D, &Error>,
+    ) -> String {
+        let host = self.relays[index].config().host;
+        match response {
+            Ok(value) => format!("{} returned {:?}", host, value),
+            Err(e) => format!("{} failed with {}", host, e),
+        }
+    }
+
+    fn report(&self, method: &str, responses: Vec<String>) {
+        if responses.is_empty() {
+            return;
+        }
+        let disagreement = RelayDisagreement {
+            method: method.to_owned(),
+            responses,
+        };
+        warn!("Relay backends disagree on {}", disagreement);
+        self.disagreements.lock().unwrap().push(disagreement);
+    }
+
+    /// Reports the failed backends and returns the successful responses if there are
+    /// at least `quorum` of them, otherwise the first error.
+    fn answered<T: fmt::Debug>(&self, method: &str, responses: Vec<Result<T>>) -> Result<Vec<T>> {
+        let failed = responses
+            .iter()
+            .enumerate()
+            .filter(|&(_, response)| response.is_err())
+            .map(|(index, response)| self.describe(index, response.as_ref()))
+            .collect();
+        self.report(method, failed);
+
+        let mut values = Vec::new();
+        let mut first_error = None;
+        for response in responses {
+            match response {
+                Ok(value) => values.push(value),
+                Err(e) => {
+                    if first_error.is_none() {
+                        first_error = Some(e);
+                    }
+                }
+            }
+        }
+        match first_error {
+            Some(e) if values.len() < self.quorum => Err(e),
+            _ => Ok(values),
+        }
+    }
+
+    /// Returns the responses which have the same key as at least `quorum - 1` other ones
+    /// and reports all the other responses.
+    fn agreed<T, K, F>(&self, method: &str, responses: Vec<Result<T>>, key: F) -> Result<Vec<T>>
+    where
+        K: PartialEq + fmt::Debug,
+        F: Fn(&T) -> K,
+    {
+        let mut groups: Vec<(K, Vec<usize>)> = Vec::new();
+        for (index, response) in responses.iter().enumerate() {
+            if let Ok(ref value) = *response {
+                let value_key = key(value);
+                match groups.iter().position(|group| group.0 == value_key) {
+                    Some(group) => groups[group].1.push(index),
+                    None => groups.push((value_key, vec![index])),
+                }
+            }
+        }
+        let agreed = groups
+            .into_iter()
+            .map(|group| group.1)
+            .find(|indices| indices.len() >= self.quorum)
+            .unwrap_or_default();
+
+        let disagreed = responses
+            .iter()
+            .enumerate()
+            .filter(|&(index, _)| !agreed.contains(&index))
+            .map(|(index, response)| self.describe(index, response.as_ref().map(&key)))
+            .collect();
+        self.report(method, disagreed);
+
+        if agreed.is_empty() {
+            return Err(self.quorum_error(method, responses));
+        }
+        Ok(
+            responses
+                .into_iter()
+                .enumerate()
+                .filter(|&(index, _)| agreed.contains(&index))
+                .filter_map(|(_, response)| response.ok())
+                .collect(),
+        )
+    }
+
+    /// Returns the first error if less than `quorum` backends have answered,
+    /// otherwise the error about the lack of the agreement between them.
+    fn quorum_error<T>(&self, method: &str, responses: Vec<Result<T>>) -> Error {
+        let answered = responses.iter().filter(|r| r.is_ok()).count();
+        match responses.into_iter().find(|r| r.is_err()) {
+            Some(Err(e)) if answered < self.quorum => e,
+            _ => Error::rpc(
+                -32603,
+                format!("Less than {} relay backends agree on {}", self.quorum, method),
+            ),
+        }
+    }
+
+    /// Returns the `quorum`-th highest value, so that at least `quorum` of the backends
+    /// report the value or the higher one.
+    fn nth_highest<T: Ord>(&self, mut values: Vec<T>) -> T {
+        values.sort_by(|a, b| b.cmp(a));
+        values.swap_remove(self.quorum - 1)
+    }
+}
+
+impl BitcoinRelay for QuorumRelay {
+    fn get_transaction(&self, txid: btc::TxId) -> Result<Option<BitcoinTx>> {
+        let responses = self.responses(|relay| relay.get_transaction(txid));
+        let mut agreed = self.agreed("get_transaction", responses, |tx| {
+            tx.as_ref().map(BitcoinTx::to_hex)
+        })?;
+        Ok(agreed.swap_remove(0))
+    }
+
+    fn get_transactions(&self, txids: &[btc::TxId]) -> Result<Vec<Option<BitcoinTx>>> {
+        let responses = self.responses(|relay| relay.get_transactions(txids));
+        let mut agreed = self.agreed("get_transactions", responses, |txs| {
+            txs.iter()
+                .map(|tx| tx.as_ref().map(BitcoinTx::to_hex))
+                .collect::<Vec<_>>()
+        })?;
+        Ok(agreed.swap_remove(0))
+    }
+
+    fn get_transaction_info(&self, txid: btc::TxId) -> Result<Option<TxInfo>> {
+        let responses = self.responses(|relay| relay.get_transaction_info(txid));
+        let agreed = self.agreed("get_transaction_info", responses, |info| {
+            info.as_ref().map(|info| info.body.to_hex())
+        })?;
+        let infos = match agreed.into_iter().collect::<Option<Vec<_>>>() {
+            Some(infos) => infos,
+            None => return Ok(None),
+        };
+        let confirmations = self.nth_highest(infos.iter().map(|info| info.confirmations).collect());
+        Ok(Some(TxInfo {
+            body: infos[0].body.clone(),
+            confirmations,
+            vout: None,
+        }))
+    }
+
+    fn watch_address(&self, addr: &btc::Address, rescan: bool) -> Result<()> {
+        let responses = self.responses(|relay| relay.watch_address(addr, rescan));
+        self.answered("watch_address", responses)?;
+        Ok(())
+    }
+
+    fn send_transaction(&self, tx: BitcoinTx) -> Result<()> {
+        // The transaction is relayed by the bitcoin network, so it is enough that
+        // at least one backend has accepted it.
+        let responses = self.responses(|relay| relay.send_transaction(tx.clone()));
+        let failed = responses
+            .iter()
+            .enumerate()
+            .filter(|&(_, response)| response.is_err())
+            .map(|(index, response)| self.describe(index, response.as_ref()))
+            .collect::<Vec<_>>();
+        let accepted = failed.len() < responses.len();
+        self.report("send_transaction", failed);
+        if accepted {
+            Ok(())
+        } else {
+            responses.into_iter().find(Result::is_err).unwrap()
+        }
+    }
+
+    fn send_to_address(&self, addr: &btc::Address, satoshis: u64) -> Result<FundingTx> {
+        self.relays[0].send_to_address(addr, satoshis)
+    }
+
+    fn unspent_transactions(&self, addr: &btc::Address) -> Result<Vec<TxInfo>> {
+        let responses = self.responses(|relay| relay.unspent_transactions(addr));
+        let keys = |txs: &Vec<TxInfo>| {
+            txs.iter()
+                .map(|info| (info.body.to_hex(), info.vout))
+                .collect::<Vec<_>>()
+        };
+
+        let listed = responses
+            .iter()
+            .map(|response| response.as_ref().map(&keys).unwrap_or_default())
+            .collect::<Vec<_>>();
+        let mut unspent: Vec<TxInfo> = Vec::new();
+        for txs in responses.iter().filter_map(|response| response.as_ref().ok()) {
+            for info in txs {
+                let key = (info.body.to_hex(), info.vout);
+                let is_known = unspent.iter().any(|known| {
+                    (known.body.to_hex(), known.vout) == key
+                });
+                let votes = listed.iter().filter(|keys| keys.contains(&key)).count();
+                if !is_known && votes >= self.quorum {
+                    unspent.push(info.clone());
+                }
+            }
+        }
+
+        // The backends agree if they list exactly the agreed outputs.
+        let agreed_keys = keys(&unspent);
+        let disagreed = responses
+            .iter()
+            .enumerate()
+            .filter(|&(index, response)| {
+                response.is_err() || listed[index].len() != agreed_keys.len() ||
+                    listed[index].iter().any(|key| !agreed_keys.contains(key))
+            })
+            .map(|(index, response)| {
+                let outputs = response.as_ref().map(|txs| {
+                    txs.iter()
+                        .map(|info| (info.body.id().to_string(), info.vout))
+                        .collect::<Vec<_>>()
+                });
+                self.describe(index, outputs)
+            })
+            .collect::<Vec<_>>();
+        let is_agreed = responses.len() - disagreed.len() >= self.quorum;
+        self.report("unspent_transactions", disagreed);
+        if !is_agreed {
+            return Err(self.quorum_error("unspent_transactions", responses));
+        }
+
+        for info in &mut unspent {
+            let key = (info.body.to_hex(), info.vout);
+            let confirmations = responses
+                .iter()
+                .filter_map(|response| response.as_ref().ok())
+                .filter_map(|txs| {
+                    txs.iter().find(|other| (other.body.to_hex(), other.vout) == key)
+                })
+                .map(|other| other.confirmations)
+                .collect();
+            info.confirmations = self.nth_highest(confirmations);
+        }
+        Ok(unspent)
+    }
+
+    fn get_block_count(&self) -> Result<u64> {
+        let responses = self.responses(|relay| relay.get_block_count());
+        let counts = self.answered("get_block_count", responses)?;
+        Ok(self.nth_highest(counts))
+    }
+
+    fn get_best_block_hash(&self) -> Result<String> {
+        let responses = self.responses(|relay| relay.get_best_block_hash());
+        let mut agreed = self.agreed("get_best_block_hash", responses, String::clone)?;
+        Ok(agreed.swap_remove(0))
+    }
+
+    fn get_mempool_entry_height(&self, txid: btc::TxId) -> Result<Option<u64>> {
+        let responses = self.responses(|relay| relay.get_mempool_entry_height(txid));
+        let heights = self.answered("get_mempool_entry_height", responses)?;
+        Ok(self.nth_highest(heights))
+    }
+
+    fn get_tx_out_confirmations(&self, txid: btc::TxId, vout: u32) -> Result<Option<u64>> {
+        let responses = self.responses(|relay| relay.get_tx_out_confirmations(txid, vout));
+        let confirmations = self.answered("get_tx_out_confirmations", responses)?;
+        Ok(self.nth_highest(confirmations))
+    }
+
+    fn get_tx_outs_confirmations(
+        &self,
+        outpoints: &[(btc::TxId, u32)],
+    ) -> Result<Vec<Option<u64>>> {
+        let responses = self.responses(|relay| relay.get_tx_outs_confirmations(outpoints));
+        let answered = self.answered("get_tx_outs_confirmations", responses)?;
+        // The outputs missing from the malformed responses are treated as the spent ones.
+        Ok(
+            (0..outpoints.len())
+                .map(|index| {
+                    let confirmations = answered
+                        .iter()
+                        .map(|outs| outs.get(index).cloned().unwrap_or_default())
+                        .collect();
+                    self.nth_highest(confirmations)
+                })
+                .collect(),
+        )
+    }
+
+    fn estimate_fee_rate(&self, conf_target: u16) -> Result<Option<u64>> {
+        let responses = self.responses(|relay| relay.estimate_fee_rate(conf_target));
+        let fee_rates = self.answered("estimate_fee_rate", responses)?;
+        Ok(self.nth_highest(fee_rates))
+    }
+
+    fn get_tx_out_proof(&self, txid: btc::TxId) -> Result<Option<TxOutProof>> {
+        // The proofs of the different backends may encode the same block differently,
+        // so only the block hashes have to agree.
+        let responses = self.responses(|relay| relay.get_tx_out_proof(txid));
+        let mut agreed = self.agreed("get_tx_out_proof", responses, |proof| {
+            proof.as_ref().map(|proof| proof.block_hash().to_owned())
+        })?;
+        Ok(agreed.swap_remove(0))
+    }
+
+    fn take_disagreements(&self) -> Vec<RelayDisagreement> {
+        ::std::mem::replace(&mut *self.disagreements.lock().unwrap(), Vec::new())
+    }
+
+    fn begin_check(&self) {
+        for relay in &self.relays {
+            relay.begin_check();
+        }
+    }
+
+    fn config(&self) -> AnchoringRpcConfig {
+        self.relays[0].config()
+    }
+}
@@ -12,19 +12,50 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::fmt;
+use std::str::FromStr;
 use std::string::ToString;
+use std::sync::Mutex;
 
 use bitcoinrpc;
+use hyper;
+use jsonrpc;
+use serde_json::Value;
 
 use exonum::encoding::serialize::FromHex;
 
 use details::btc;
 use details::btc::transactions::{BitcoinTx, FundingTx, TxKind};
+use blockchain::dto::TxOutProof;
 
-pub use bitcoinrpc::Client as RpcClient;
+pub use details::bitcoind::RpcClient;
+pub use details::electrum::ElectrumClient;
+pub use details::esplora::EsploraClient;
+pub use details::quorum::{QuorumRelay, RelayDisagreement};
+pub use details::failover::{FailoverRelay, RelayRetryConfig};
+pub use details::transport::{RpcCredentials, RpcTlsConfig, RpcTransport};
+pub use details::cache::CachingRelay;
+#[cfg(feature = "simulated_bitcoin")]
+pub use details::simulated::SimulatedBitcoin;
+#[cfg(feature = "simulated_bitcoin")]
+pub use details::simulated_bitcoind::SimulatedBitcoind;
 
-pub type Result<T> = bitcoinrpc::Result<T>;
-pub type Error = bitcoinrpc::Error;
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+/// Error of the bitcoin relay.
+#[derive(Debug)]
+pub enum Error {
+    /// Error returned by the relay backend.
+    Rpc(bitcoinrpc::Error),
+    /// Relay backend is unreachable or has not responded in time.
+    Transport(hyper::Error),
+    /// Response of the relay backend cannot be parsed.
+    Parse(String),
+    /// Response of the relay backend does not follow its protocol.
+    Protocol(String),
+}
 
 /// Number of satoshis in a bitcoin.
 ///
@@ -32,15 +63,289 @@ pub type Error = bitcoinrpc::Error;
 /// which measures amounts in bitcoins (rather than satoshis).
 pub const SATOSHI_DIVISOR: f64 = 100_000_000.0;
 
+/// Response of the bitcoind's `estimatesmartfee` RPC endpoint.
+#[derive(Deserialize, Debug)]
+pub struct FeeEstimation {
+    /// Estimated fee rate in bitcoins per kilo virtual byte.
+    pub feerate: Option<f64>,
+}
+
+impl FeeEstimation {
+    /// Returns the estimated fee rate in satoshis per virtual byte.
+    pub fn sat_per_vbyte(&self) -> Option<u64> {
+        self.feerate
+            .map(|feerate| (feerate * SATOSHI_DIVISOR / 1000.0).ceil() as u64)
+    }
+}
+
+/// Response of the bitcoind's `getmempoolentry` RPC endpoint.
+#[derive(Deserialize, Debug)]
+pub(crate) struct MempoolEntry {
+    /// Block height when the transaction entered the mempool.
+    pub(crate) height: u64,
+}
+
+/// Response of the bitcoind's `gettxout` RPC endpoint.
+#[derive(Deserialize, Debug)]
+pub(crate) struct TxOut {
+    /// Number of confirmations of the transaction with the output.
+    pub(crate) confirmations: u64,
+}
+
+/// Response of the bitcoind's verbose `getrawtransaction` RPC endpoint.
+#[derive(Deserialize, Debug)]
+pub(crate) struct TransactionBlock {
+    /// Hash of the block with the transaction if it is confirmed.
+    pub(crate) blockhash: Option<String>,
+}
+
+/// Kind of the bitcoin relay backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RelayBackend {
+    /// `Bitcoind` json rpc with the transactions index and the wallet.
+    #[serde(rename = "bitcoind")]
+    Bitcoind,
+    /// Electrum protocol server over the plain tcp connection.
+    #[serde(rename = "electrum")]
+    Electrum,
+    /// Esplora REST api over the plain http connection.
+    #[serde(rename = "esplora")]
+    Esplora,
+}
+
+/// The error returned when parsing an unknown relay backend name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnknownRelayBackendError(String);
+
+impl RelayBackend {
+    /// Backend names accepted by [`FromStr`](#impl-FromStr) implementation.
+    pub const NAMES: &'static [&'static str] = &["bitcoind", "electrum", "esplora"];
+}
+
+impl Default for RelayBackend {
+    fn default() -> RelayBackend {
+        RelayBackend::Bitcoind
+    }
+}
+
+impl fmt::Display for RelayBackend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            RelayBackend::Bitcoind => "bitcoind",
+            RelayBackend::Electrum => "electrum",
+            RelayBackend::Esplora => "esplora",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for RelayBackend {
+    type Err = UnknownRelayBackendError;
+
+    fn from_str(s: &str) -> ::std::result::Result<RelayBackend, UnknownRelayBackendError> {
+        match s {
+            "bitcoind" => Ok(RelayBackend::Bitcoind),
+            "electrum" => Ok(RelayBackend::Electrum),
+            "esplora" => Ok(RelayBackend::Esplora),
+            other => Err(UnknownRelayBackendError(other.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for UnknownRelayBackendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Wrong relay backend: {}", self.0)
+    }
+}
+
+impl StdError for UnknownRelayBackendError {
+    fn description(&self) -> &str {
+        "Wrong relay backend"
+    }
+}
+
+/// The way `bitcoind` discovers the unspent outputs to the anchoring address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UtxoDiscovery {
+    /// Legacy wallet, the address is imported by `importaddress`
+    /// and its outputs are listed by `listunspent`.
+    #[serde(rename = "importaddress")]
+    ImportAddress,
+    /// Descriptor wallet without private keys, the address is imported by `importdescriptors`
+    /// and its outputs are listed by `listunspent`.
+    #[serde(rename = "importdescriptors")]
+    ImportDescriptors,
+    /// No wallet, the confirmed outputs are found by `scantxoutset`. The unconfirmed ones
+    /// are known only for the transactions which have been sent by the relay.
+    #[serde(rename = "scantxoutset")]
+    ScanTxOutSet,
+}
+
+/// The error returned when parsing an unknown utxo discovery name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnknownUtxoDiscoveryError(String);
+
+impl UtxoDiscovery {
+    /// Names accepted by [`FromStr`](#impl-FromStr) implementation.
+    pub const NAMES: &'static [&'static str] =
+        &["importaddress", "importdescriptors", "scantxoutset"];
+}
+
+impl Default for UtxoDiscovery {
+    fn default() -> UtxoDiscovery {
+        UtxoDiscovery::ImportAddress
+    }
+}
+
+impl fmt::Display for UtxoDiscovery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            UtxoDiscovery::ImportAddress => "importaddress",
+            UtxoDiscovery::ImportDescriptors => "importdescriptors",
+            UtxoDiscovery::ScanTxOutSet => "scantxoutset",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for UtxoDiscovery {
+    type Err = UnknownUtxoDiscoveryError;
+
+    fn from_str(s: &str) -> ::std::result::Result<UtxoDiscovery, UnknownUtxoDiscoveryError> {
+        match s {
+            "importaddress" => Ok(UtxoDiscovery::ImportAddress),
+            "importdescriptors" => Ok(UtxoDiscovery::ImportDescriptors),
+            "scantxoutset" => Ok(UtxoDiscovery::ScanTxOutSet),
+            other => Err(UnknownUtxoDiscoveryError(other.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for UnknownUtxoDiscoveryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Wrong utxo discovery: {}", self.0)
+    }
+}
+
+impl StdError for UnknownUtxoDiscoveryError {
+    fn description(&self) -> &str {
+        "Wrong utxo discovery"
+    }
+}
+
+impl Error {
+    /// Creates the error returned by the relay backend with the given json rpc error code.
+    pub fn rpc<S: Into<String>>(code: i64, message: S) -> Error {
+        Error::Rpc(bitcoinrpc::Error::Other(jsonrpc::Error::Rpc(json!({
+            "code": code,
+            "message": message.into(),
+        }))))
+    }
+
+    /// Creates the error about the malformed `what` part of the response.
+    pub fn parse<E: fmt::Display>(what: &str, e: E) -> Error {
+        Error::Parse(format!("Unable to parse {}: {}", what, e))
+    }
+
+    /// Returns the json rpc error code if the relay backend has returned
+    /// the error which is not recognized by `bitcoinrpc`.
+    pub fn rpc_code(&self) -> Option<i64> {
+        match *self {
+            Error::Rpc(bitcoinrpc::Error::Other(jsonrpc::Error::Rpc(ref value))) => {
+                value.get("code").and_then(Value::as_i64)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl From<bitcoinrpc::Error> for Error {
+    fn from(e: bitcoinrpc::Error) -> Error {
+        match e {
+            bitcoinrpc::Error::Other(jsonrpc::Error::Hyper(e)) => Error::Transport(e),
+            bitcoinrpc::Error::Other(jsonrpc::Error::Json(e)) => Error::parse("response", e),
+            bitcoinrpc::Error::Other(jsonrpc::Error::NoErrorOrResult) => {
+                Error::Protocol("Response has neither result nor error".to_owned())
+            }
+            bitcoinrpc::Error::Other(jsonrpc::Error::NonceMismatch) => {
+                Error::Protocol("Response id does not match the request".to_owned())
+            }
+            e => Error::Rpc(e),
+        }
+    }
+}
+
+impl From<jsonrpc::Error> for Error {
+    fn from(e: jsonrpc::Error) -> Error {
+        Error::from(bitcoinrpc::Error::from(e))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Rpc(ref e) => write!(f, "{}", e),
+            Error::Transport(ref e) => write!(f, "Relay is unreachable: {}", e),
+            Error::Parse(ref msg) => f.write_str(msg),
+            Error::Protocol(ref msg) => write!(f, "Unexpected relay response: {}", msg),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Rpc(_) => "Relay backend error",
+            Error::Transport(_) => "Relay is unreachable",
+            Error::Parse(_) => "Malformed relay response",
+            Error::Protocol(_) => "Unexpected relay response",
+        }
+    }
+
+    fn cause(&self) -> Option<&StdError> {
+        match *self {
+            Error::Rpc(ref e) => Some(e),
+            Error::Transport(ref e) => Some(e),
+            Error::Parse(_) | Error::Protocol(_) => None,
+        }
+    }
+}
+
+fn is_default_backend(backend: &RelayBackend) -> bool {
+    *backend == RelayBackend::default()
+}
+
+fn is_default_utxo_discovery(utxo_discovery: &UtxoDiscovery) -> bool {
+    *utxo_discovery == UtxoDiscovery::default()
+}
+
+fn is_inline_credentials(credentials: &RpcCredentials) -> bool {
+    credentials.is_inline()
+}
+
 /// `Bitcoind` rpc configuration.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct AnchoringRpcConfig {
-    /// Rpc url.
+    /// Rpc url, the `host:port` address of the Electrum server or the Esplora api url.
     pub host: String,
     /// Rpc username.
     pub username: Option<String>,
     /// Rpc password.
     pub password: Option<String>,
+    /// Relay backend which serves the `host`.
+    #[serde(default, skip_serializing_if = "is_default_backend")]
+    pub backend: RelayBackend,
+    /// The way the `bitcoind` backend discovers the anchoring outputs,
+    /// other backends ignore it.
+    #[serde(default, skip_serializing_if = "is_default_utxo_discovery")]
+    pub utxo_discovery: UtxoDiscovery,
+    /// Sources of the `bitcoind` credentials besides the inline `password`,
+    /// other backends ignore them.
+    #[serde(default, skip_serializing_if = "is_inline_credentials")]
+    pub credentials: RpcCredentials,
+    /// TLS settings of the `https` host of the `bitcoind` backend.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<RpcTlsConfig>,
 }
 
 /// Client for the `Bitcoind` rpc api, for more information visit
@@ -48,9 +353,43 @@ pub struct AnchoringRpcConfig {
 #[derive(Debug)]
 pub struct AnchoringRpc(pub RpcClient);
 
-impl From<AnchoringRpcConfig> for RpcClient {
+impl From<AnchoringRpcConfig> for Box<BitcoinRelay> {
     fn from(cfg: AnchoringRpcConfig) -> Self {
-        RpcClient::new(cfg.host, cfg.username, cfg.password)
+        match cfg.backend {
+            RelayBackend::Bitcoind => Box::new(RpcClient::from(cfg)),
+            RelayBackend::Electrum => Box::new(ElectrumClient::from(cfg)),
+            RelayBackend::Esplora => Box::new(EsploraClient::from(cfg)),
+        }
+    }
+}
+
+/// Best block heights at the moment when the unconfirmed transactions have been seen
+/// first time, for the backends which do not track the mempool entries.
+#[derive(Debug, Default)]
+pub struct MempoolHeights(Mutex<HashMap<btc::TxId, u64>>);
+
+impl MempoolHeights {
+    /// Returns the remembered height of the unconfirmed transaction or remembers the current
+    /// `block_count` for the new one. Forgets the transaction once it is not unconfirmed.
+    pub fn entry_height<F>(
+        &self,
+        txid: btc::TxId,
+        is_unconfirmed: bool,
+        block_count: F,
+    ) -> Result<Option<u64>>
+    where
+        F: FnOnce() -> Result<u64>,
+    {
+        if !is_unconfirmed {
+            self.0.lock().unwrap().remove(&txid);
+            return Ok(None);
+        }
+        if let Some(height) = self.0.lock().unwrap().get(&txid) {
+            return Ok(Some(*height));
+        }
+        let height = block_count()?;
+        self.0.lock().unwrap().insert(txid, height);
+        Ok(Some(height))
     }
 }
 
@@ -61,22 +400,85 @@ pub struct TxInfo {
     pub body: BitcoinTx,
     /// Number of confirmations.
     pub confirmations: Option<u64>,
+    /// Index of the unspent output if the information is obtained by `listunspent`.
+    pub vout: Option<u32>,
 }
 
-impl From<bitcoinrpc::RawTransactionInfo> for TxInfo {
-    fn from(info: bitcoinrpc::RawTransactionInfo) -> Self {
-        TxInfo {
-            body: BitcoinTx::from_hex(info.hex.expect("Transaction hex is absent in response."))
-                .unwrap(),
+impl TxInfo {
+    /// Creates an information about the unspent output of the anchoring or funding
+    /// transaction. Returns `None` for the other transactions.
+    pub fn unspent(tx: BitcoinTx, confirmations: Option<u64>, vout: u32) -> Option<TxInfo> {
+        let body = match TxKind::from(tx) {
+            TxKind::Anchoring(tx) => tx.into(),
+            TxKind::FundingTx(tx) => tx.into(),
+            TxKind::Other(_) => return None,
+        };
+        Some(TxInfo {
+            body,
+            confirmations,
+            vout: Some(vout),
+        })
+    }
+
+    /// Creates an information from the verbose `getrawtransaction` response.
+    pub fn from_raw(info: bitcoinrpc::RawTransactionInfo) -> Result<TxInfo> {
+        let body = match info.hex {
+            Some(ref hex) => parse_transaction(hex)?,
+            None => {
+                let msg = format!("Transaction hex is absent, txid={}", info.txid);
+                return Err(Error::Protocol(msg));
+            }
+        };
+        Ok(TxInfo {
+            body,
             confirmations: info.confirmations,
-        }
+            vout: None,
+        })
+    }
+}
+
+/// Parses the hex encoded transaction from the relay response.
+pub fn parse_transaction<S: AsRef<str>>(hex: S) -> Result<BitcoinTx> {
+    BitcoinTx::from_hex(hex.as_ref()).map_err(|e| Error::parse("transaction", e))
+}
+
+/// Parses the transaction id from the relay response.
+pub fn parse_txid(hex: &str) -> Result<btc::TxId> {
+    btc::TxId::from_hex(hex).map_err(|e| Error::parse("txid", e))
+}
+
+/// Checks the outputs of the transaction to the given address by the `relay`.
+/// Returns `None` if there are no such outputs.
+pub fn has_unspent_output(
+    relay: &BitcoinRelay,
+    tx: &BitcoinTx,
+    addr: &btc::Address,
+) -> Result<Option<bool>> {
+    let script_pubkey = addr.script_pubkey();
+    let txid = tx.id();
+    let outpoints = tx.output
+        .iter()
+        .enumerate()
+        .filter(|&(_, output)| output.script_pubkey == script_pubkey)
+        .map(|(vout, _)| (txid, vout as u32))
+        .collect::<Vec<_>>();
+    if outpoints.is_empty() {
+        return Ok(None);
     }
+    let confirmations = relay.get_tx_outs_confirmations(&outpoints)?;
+    Ok(Some(confirmations.iter().any(Option::is_some)))
 }
 
 pub trait BitcoinRelay: 'static + ::std::fmt::Debug + Send + Sync {
     /// Retrieves transaction from the bitcoin blockchain.
     fn get_transaction(&self, txid: btc::TxId) -> Result<Option<BitcoinTx>>;
 
+    /// Retrieves the transactions with the given ids in the same order. The relays which
+    /// support the batch requests send them at once, the others request them one by one.
+    fn get_transactions(&self, txids: &[btc::TxId]) -> Result<Vec<Option<BitcoinTx>>> {
+        txids.iter().map(|txid| self.get_transaction(*txid)).collect()
+    }
+
     /// Retrieves information about transaction with the given id.
     fn get_transaction_info(&self, txid: btc::TxId) -> Result<Option<TxInfo>>;
 
@@ -98,82 +500,121 @@ pub trait BitcoinRelay: 'static + ::std::fmt::Debug + Send + Sync {
         Ok(info.and_then(|x| x.confirmations))
     }
 
+    /// Returns the height of the most-work fully-validated bitcoin chain.
+    fn get_block_count(&self) -> Result<u64>;
+
+    /// Returns the hash of the best block in the most-work fully-validated bitcoin chain.
+    fn get_best_block_hash(&self) -> Result<String>;
+
+    /// Returns the bitcoin block height at which the given transaction entered the mempool
+    /// or `None` if the transaction is not in the mempool.
+    fn get_mempool_entry_height(&self, txid: btc::TxId) -> Result<Option<u64>>;
+
+    /// Returns the number of confirmations of the given transaction output or `None`
+    /// if the output does not exist or is spent, including the spends in the mempool.
+    fn get_tx_out_confirmations(&self, txid: btc::TxId, vout: u32) -> Result<Option<u64>>;
+
+    /// Returns the numbers of confirmations of the given transaction outputs in the same order,
+    /// see `get_tx_out_confirmations`. The relays which support the batch requests send them
+    /// at once, the others request them one by one.
+    fn get_tx_outs_confirmations(
+        &self,
+        outpoints: &[(btc::TxId, u32)],
+    ) -> Result<Vec<Option<u64>>> {
+        outpoints
+            .iter()
+            .map(|&(txid, vout)| self.get_tx_out_confirmations(txid, vout))
+            .collect()
+    }
+
+    /// Estimates the fee rate in satoshis per virtual byte for the transaction
+    /// to be confirmed within `conf_target` blocks. Returns `None` if bitcoind
+    /// does not have enough data for the estimation.
+    fn estimate_fee_rate(&self, conf_target: u16) -> Result<Option<u64>>;
+
+    /// Returns the proof of the transaction inclusion into the bitcoin block
+    /// or `None` if the transaction is not confirmed yet.
+    fn get_tx_out_proof(&self, txid: btc::TxId) -> Result<Option<TxOutProof>>;
+
+    /// Returns and forgets the disagreements between the backends observed since
+    /// the previous call. Only the composite relays, like `QuorumRelay`, can observe them.
+    fn take_disagreements(&self) -> Vec<RelayDisagreement> {
+        Vec::new()
+    }
+
+    /// Notifies the relay that the anchoring handler starts the new check of the bitcoin state.
+    /// Only the caching relays, like `CachingRelay`, use it to check the bitcoin tip.
+    fn begin_check(&self) {}
+
     /// Returns an actual relay configuration.
     fn config(&self) -> AnchoringRpcConfig;
 }
 
-impl BitcoinRelay for RpcClient {
-    fn get_transaction(&self, txid: btc::TxId) -> Result<Option<BitcoinTx>> {
-        let r = self.getrawtransaction(&txid.to_string());
-        match r {
-            Ok(tx) => Ok(Some(BitcoinTx::from_hex(tx).unwrap())),
-            Err(bitcoinrpc::Error::NoInformation(_)) => Ok(None),
-            Err(e) => Err(e),
-        }
-    }
+/// Sends the `getrawtransaction` requests as one batch.
+pub(crate) fn batch_transactions(
+    transport: &RpcTransport,
+    txids: &[btc::TxId],
+) -> Result<Vec<Option<BitcoinTx>>> {
+    let requests = txids
+        .iter()
+        .map(|txid| ("getrawtransaction", vec![txid.to_string().into(), 0.into()]))
+        .collect();
+    transport
+        .batch(requests)?
+        .into_iter()
+        .map(|response| match response.into_result::<String>() {
+            Ok(hex) => Ok(Some(parse_transaction(hex)?)),
+            Err(e) => match Error::from(e) {
+                Error::Rpc(bitcoinrpc::Error::NoInformation(_)) => Ok(None),
+                e => Err(e),
+            },
+        })
+        .collect()
+}
 
-    fn get_transaction_info(&self, txid: btc::TxId) -> Result<Option<TxInfo>> {
-        let info = match self.getrawtransaction_verbose(&txid.to_string()) {
-            Ok(info) => Ok(info),
-            Err(bitcoinrpc::Error::NoInformation(_)) => return Ok(None),
-            Err(e) => Err(e),
-        }?;
-        Ok(Some(info.into()))
-    }
-
-    fn watch_address(&self, addr: &btc::Address, rescan: bool) -> Result<()> {
-        self.importaddress(&addr.to_string(), "multisig", false, rescan)
-    }
-
-    fn send_transaction(&self, tx: BitcoinTx) -> Result<()> {
-        let tx_hex = tx.to_hex();
-        self.sendrawtransaction(&tx_hex)?;
-        Ok(())
-    }
-
-    fn send_to_address(&self, addr: &btc::Address, satoshis: u64) -> Result<FundingTx> {
-        let addr = addr.to_string();
-        let funds_str = (satoshis as f64 / SATOSHI_DIVISOR).to_string();
-        let utxo_txid = self.sendtoaddress(&addr, &funds_str)?;
-        // TODO rewrite Error types to avoid unwraps.
-        let utxo_txid = btc::TxId::from_hex(&utxo_txid).unwrap();
-        Ok(FundingTx::from(self.get_transaction(utxo_txid)?.unwrap()))
-    }
-
-    fn unspent_transactions(&self, addr: &btc::Address) -> Result<Vec<TxInfo>> {
-        let unspent_txs = self.listunspent(0, 9_999_999, [addr.to_string().as_ref()])?;
-        let mut txs = Vec::new();
-        for info in unspent_txs {
-            let txid = btc::TxId::from_hex(&info.txid).unwrap();
-            let confirmations = Some(info.confirmations);
-            if let Some(raw_tx) = self.get_transaction(txid)? {
-                match TxKind::from(raw_tx) {
-                    TxKind::Anchoring(tx) => {
-                        txs.push(TxInfo {
-                            body: tx.into(),
-                            confirmations,
-                        })
-                    }
-                    TxKind::FundingTx(tx) => {
-                        txs.push(TxInfo {
-                            body: tx.into(),
-                            confirmations,
-                        })
-                    }
-                    TxKind::Other(_) => {}
-                }
+/// Sends the `gettxout` requests as one batch.
+pub(crate) fn batch_tx_outs_confirmations(
+    transport: &RpcTransport,
+    outpoints: &[(btc::TxId, u32)],
+) -> Result<Vec<Option<u64>>> {
+    let requests = outpoints
+        .iter()
+        .map(|&(txid, vout)| {
+            ("gettxout", vec![txid.to_string().into(), vout.into(), true.into()])
+        })
+        .collect();
+    transport
+        .batch(requests)?
+        .into_iter()
+        .map(|response| {
+            // Bitcoind returns the `null` result for the spent and unknown outputs.
+            if response.error.is_none() && response.result.is_none() {
+                return Ok(None);
             }
-        }
-        Ok(txs)
-    }
+            let out: TxOut = response.into_result()?;
+            Ok(Some(out.confirmations))
+        })
+        .collect()
+}
 
-    fn config(&self) -> AnchoringRpcConfig {
-        AnchoringRpcConfig {
-            host: self.url().to_string(),
-            username: self.username().clone(),
-            password: self.password().clone(),
+/// Converts the `listunspent` response to the unspent outputs of the anchoring
+/// and funding transactions, which are retrieved by one batch.
+pub(crate) fn unspent_tx_infos<R: BitcoinRelay + ?Sized>(
+    relay: &R,
+    unspent_txs: Vec<bitcoinrpc::UnspentTransactionInfo>,
+) -> Result<Vec<TxInfo>> {
+    let txids = unspent_txs
+        .iter()
+        .map(|info| parse_txid(&info.txid))
+        .collect::<Result<Vec<_>>>()?;
+    let raw_txs = relay.get_transactions(&txids)?;
+    let mut txs = Vec::new();
+    for (info, raw_tx) in unspent_txs.into_iter().zip(raw_txs) {
+        if let Some(raw_tx) = raw_tx {
+            txs.extend(TxInfo::unspent(raw_tx, Some(info.confirmations), info.vout));
         }
     }
+    Ok(txs)
 }
 
 impl<'a, T: BitcoinRelay + 'a> From<T> for Box<BitcoinRelay> {
@@ -0,0 +1,665 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! In-memory simulated bitcoin network which implements `BitcoinRelay`.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+
+use bitcoin::blockdata::block::BlockHeader;
+use bitcoin::blockdata::script::{Instruction, Script};
+use bitcoin::blockdata::transaction::{TxIn, TxOut};
+use bitcoin::network::serialize::{serialize, BitcoinHash};
+use bitcoin::util::hash::Sha256dHash;
+use jsonrpc;
+use secp256k1::Secp256k1;
+use secp256k1::key::PublicKey as RawPublicKey;
+
+use exonum::encoding::serialize::encode_hex;
+
+use details::btc;
+use details::btc::RedeemScript;
+use details::btc::transactions::{verify_tx_input, verify_witness_tx_input, BitcoinTx, FundingTx,
+                                 RawBitcoinTx, RBF_SEQUENCE};
+use blockchain::dto::TxOutProof;
+use super::electrum::{merkle_block, merkle_parent};
+use super::notifications::BitcoinEvent;
+use super::rpc::{AnchoringRpcConfig, BitcoinRelay, Error, RelayBackend, Result, TxInfo};
+
+/// Compact target of the simulated blocks, the same as the regtest one.
+const BLOCK_BITS: u32 = 0x207f_ffff;
+/// Time of the simulated genesis block.
+const GENESIS_TIME: u32 = 1_500_000_000;
+/// Interval between the simulated blocks in seconds.
+const BLOCK_INTERVAL: u32 = 600;
+/// Sighash type byte of the signatures which are accepted by the multisig inputs.
+const SIGHASH_ALL: u8 = 1;
+
+/// Output of the transaction identified by its id and index.
+type OutPoint = (btc::TxId, u32);
+
+#[derive(Debug)]
+struct Block {
+    header: BlockHeader,
+    /// Ids of the block transactions, the first one is the coinbase.
+    txids: Vec<Sha256dHash>,
+}
+
+#[derive(Debug)]
+struct MempoolEntry {
+    txid: btc::TxId,
+    /// Block count at the moment the transaction has entered the mempool.
+    height: u64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    blocks: Vec<Block>,
+    /// Confirmed and mempool transactions.
+    transactions: HashMap<btc::TxId, BitcoinTx>,
+    /// Fees of the known transactions, the wallet ones pay nothing.
+    fees: HashMap<btc::TxId, u64>,
+    /// Heights of the blocks which contain the confirmed transactions.
+    heights: HashMap<btc::TxId, u64>,
+    /// Mempool transactions in the order they have been accepted.
+    mempool: Vec<MempoolEntry>,
+    /// Outputs which are not spent by the confirmed or the mempool transactions.
+    utxos: HashMap<OutPoint, TxOut>,
+    watched: HashSet<String>,
+    fee_rate: Option<u64>,
+    /// Counter which makes the coinbase and wallet transactions unique.
+    nonce: u64,
+    subscribers: Vec<mpsc::Sender<BitcoinEvent>>,
+}
+
+/// `BitcoinRelay` over the in-memory bitcoin network, which is intended for tests.
+///
+/// The network keeps the UTXO set and the mempool, and mines blocks on demand.
+/// Transactions are accepted by the same rules as bitcoind does for the anchoring ones:
+/// the inputs must be unspent, signatures of the spent multisig inputs must be valid
+/// and the mempool conflicts are replaced only if they signal the Replace-By-Fee and pay
+/// less. Funds are sent from the unlimited wallet. Clones share the same network, so
+/// the test can keep one to mine blocks or inject reorganizations while the service
+/// uses another. Subscribers receive the same events as the bitcoind ZMQ notifications
+/// provide.
+#[derive(Debug, Clone)]
+pub struct SimulatedBitcoin {
+    state: Arc<Mutex<State>>,
+}
+
+impl SimulatedBitcoin {
+    /// Creates a network which has only the genesis block.
+    pub fn new() -> SimulatedBitcoin {
+        let mut state = State::default();
+        state.mine_block(false);
+        SimulatedBitcoin { state: Arc::new(Mutex::new(state)) }
+    }
+
+    /// Mines the given number of blocks, the first one confirms the whole mempool.
+    /// Returns hashes of the mined blocks.
+    pub fn mine_blocks(&self, count: u64) -> Vec<String> {
+        let mut state = self.state();
+        (0..count).map(|i| state.mine_block(i == 0)).collect()
+    }
+
+    /// Replaces the given number of the latest blocks with one more empty blocks,
+    /// so that their transactions lose the confirmations and return to the mempool.
+    pub fn reorg(&self, depth: u64) -> Vec<String> {
+        let mut state = self.state();
+        assert!(
+            depth < state.blocks.len() as u64,
+            "Genesis block cannot be disconnected"
+        );
+        let mut disconnected = Vec::new();
+        for _ in 0..depth {
+            let block = state.blocks.pop().unwrap();
+            disconnected.splice(0..0, block.txids.into_iter().skip(1));
+        }
+        let height = state.block_count();
+        let mut mempool = Vec::new();
+        for hash in disconnected {
+            let txid = btc::TxId::from(hash);
+            state.heights.remove(&txid);
+            mempool.push(MempoolEntry { txid, height });
+        }
+        mempool.append(&mut state.mempool);
+        state.mempool = mempool;
+        (0..depth + 1).map(|_| state.mine_block(false)).collect()
+    }
+
+    /// Drops the mempool transaction and its descendants, as if the network has lost them.
+    /// Returns `false` if the transaction is not in the mempool.
+    pub fn evict_transaction(&self, txid: btc::TxId) -> bool {
+        let mut state = self.state();
+        if !state.is_in_mempool(txid) {
+            return false;
+        }
+        state.remove_with_descendants(&[txid]);
+        true
+    }
+
+    /// Returns the outputs to the given address which are not spent by the confirmed
+    /// transactions, as the `scantxoutset` finds them. Each output is identified
+    /// by the transaction id and the index and comes with the height of its block.
+    pub fn confirmed_unspent_outputs(&self, addr: &btc::Address) -> Vec<(btc::TxId, u32, u64)> {
+        let state = self.state();
+        let spent = state
+            .heights
+            .keys()
+            .flat_map(|txid| state.transactions[txid].0.input.iter().map(outpoint))
+            .collect::<HashSet<_>>();
+        let script_pubkey = addr.script_pubkey();
+        let mut outputs = Vec::new();
+        for (txid, height) in &state.heights {
+            for (vout, output) in state.transactions[txid].0.output.iter().enumerate() {
+                let vout = vout as u32;
+                if output.script_pubkey == script_pubkey && !spent.contains(&(*txid, vout)) {
+                    outputs.push((*txid, vout, *height));
+                }
+            }
+        }
+        outputs.sort_by_key(|&(txid, vout, _)| (txid.to_string(), vout));
+        outputs
+    }
+
+    /// Returns ids of the mempool transactions in the order they have been accepted.
+    pub fn mempool(&self) -> Vec<btc::TxId> {
+        self.state().mempool.iter().map(|entry| entry.txid).collect()
+    }
+
+    /// Subscribes to the new blocks and the transactions accepted to the mempool.
+    pub fn subscribe(&self) -> mpsc::Receiver<BitcoinEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.state().subscribers.push(sender);
+        receiver
+    }
+
+    /// Sets the fee rate in satoshis per virtual byte which is returned by the fee estimation.
+    pub fn set_fee_rate(&self, fee_rate: Option<u64>) {
+        self.state().fee_rate = fee_rate;
+    }
+
+    /// Returns the header of the block with the given hash if the block is in the main chain.
+    pub fn block_header(&self, block_hash: &str) -> Option<BlockHeader> {
+        let state = self.state();
+        let header = state.blocks.iter().map(|block| block.header).find(|header| {
+            header.bitcoin_hash().be_hex_string() == block_hash
+        });
+        header
+    }
+
+    fn state<'a>(&'a self) -> MutexGuard<'a, State> {
+        self.state.lock().unwrap()
+    }
+}
+
+impl Default for SimulatedBitcoin {
+    fn default() -> SimulatedBitcoin {
+        SimulatedBitcoin::new()
+    }
+}
+
+impl State {
+    fn block_count(&self) -> u64 {
+        self.blocks.len() as u64 - 1
+    }
+
+    fn confirmations(&self, txid: btc::TxId) -> Option<u64> {
+        self.heights.get(&txid).map(
+            |height| self.block_count() + 1 - height,
+        )
+    }
+
+    fn is_in_mempool(&self, txid: btc::TxId) -> bool {
+        self.mempool.iter().any(|entry| entry.txid == txid)
+    }
+
+    fn mine_block(&mut self, include_mempool: bool) -> String {
+        self.nonce += 1;
+        let coinbase = Sha256dHash::from_data(format!("coinbase {}", self.nonce).as_bytes());
+        let mut txids = vec![coinbase];
+        let height = self.blocks.len() as u64;
+        if include_mempool {
+            for entry in self.mempool.drain(..) {
+                self.heights.insert(entry.txid, height);
+                txids.push(*entry.txid);
+            }
+        }
+
+        let mut header = BlockHeader {
+            version: 0x2000_0000,
+            prev_blockhash: self.blocks.last().map_or_else(
+                Sha256dHash::default,
+                |block| block.header.bitcoin_hash(),
+            ),
+            merkle_root: merkle_root(txids.clone()),
+            time: GENESIS_TIME + BLOCK_INTERVAL * height as u32,
+            bits: BLOCK_BITS,
+            nonce: 0,
+        };
+        while header.spv_validate(&header.target()).is_err() {
+            header.nonce += 1;
+        }
+        self.blocks.push(Block { header, txids });
+        let hash = header.bitcoin_hash().be_hex_string();
+        self.notify(&BitcoinEvent::Block(hash.clone()));
+        hash
+    }
+
+    fn add_to_mempool(&mut self, tx: BitcoinTx, fee: u64) {
+        let txid = tx.id();
+        for input in &tx.0.input {
+            self.utxos.remove(&outpoint(input));
+        }
+        for (index, output) in tx.0.output.iter().enumerate() {
+            self.utxos.insert((txid, index as u32), output.clone());
+        }
+        let height = self.block_count();
+        self.mempool.push(MempoolEntry { txid, height });
+        self.fees.insert(txid, fee);
+        self.notify(&BitcoinEvent::Transaction(tx.clone()));
+        self.transactions.insert(txid, tx);
+    }
+
+    fn notify(&mut self, event: &BitcoinEvent) {
+        self.subscribers.retain(
+            |subscriber| subscriber.send(event.clone()).is_ok(),
+        );
+    }
+
+    /// Returns the given mempool transactions with all their mempool descendants.
+    fn with_descendants(&self, txids: &[btc::TxId]) -> HashSet<btc::TxId> {
+        let mut result = txids.iter().cloned().collect::<HashSet<_>>();
+        // The descendants always follow their ancestors in the mempool.
+        for entry in &self.mempool {
+            let tx = &self.transactions[&entry.txid];
+            if tx.0.input.iter().any(|input| result.contains(&outpoint(input).0)) {
+                result.insert(entry.txid);
+            }
+        }
+        result
+    }
+
+    fn remove_with_descendants(&mut self, txids: &[btc::TxId]) {
+        let removed = self.with_descendants(txids);
+        self.mempool.retain(|entry| !removed.contains(&entry.txid));
+        for txid in &removed {
+            let tx = self.transactions.remove(txid).unwrap();
+            self.fees.remove(txid);
+            for index in 0..tx.0.output.len() {
+                self.utxos.remove(&(*txid, index as u32));
+            }
+            for input in &tx.0.input {
+                let (prev_txid, index) = outpoint(input);
+                if let Some(prev_tx) = self.transactions.get(&prev_txid) {
+                    let output = prev_tx.0.output[index as usize].clone();
+                    self.utxos.insert((prev_txid, index), output);
+                }
+            }
+        }
+    }
+
+    /// Returns the mempool transaction which spends the given output.
+    fn find_spender(&self, spent: &OutPoint) -> Option<btc::TxId> {
+        self.mempool
+            .iter()
+            .find(|entry| {
+                let tx = &self.transactions[&entry.txid];
+                tx.0.input.iter().any(|input| outpoint(input) == *spent)
+            })
+            .map(|entry| entry.txid)
+    }
+
+    fn accept(&mut self, tx: BitcoinTx) -> Result<()> {
+        let txid = tx.id();
+        if self.heights.contains_key(&txid) {
+            return Err(rpc_error(-27, "transaction already in block chain"));
+        }
+        // Bitcoind accepts the mempool transactions again and just relays them.
+        if self.is_in_mempool(txid) {
+            return Ok(());
+        }
+
+        let mut conflicts = Vec::new();
+        let mut input_value = 0;
+        for (index, input) in tx.0.input.iter().enumerate() {
+            let spent = outpoint(input);
+            let output = match self.utxos.get(&spent) {
+                Some(output) => output.clone(),
+                None => {
+                    match self.find_spender(&spent) {
+                        Some(spender) => {
+                            conflicts.push(spender);
+                            self.transactions[&spent.0].0.output[spent.1 as usize].clone()
+                        }
+                        None => return Err(rpc_error(-25, "bad-txns-inputs-missingorspent")),
+                    }
+                }
+            };
+            verify_input(&tx.0, index, &output)?;
+            input_value += output.value;
+        }
+        let output_value = tx.0.output.iter().map(|output| output.value).sum::<u64>();
+        if output_value > input_value {
+            return Err(rpc_error(-26, "bad-txns-in-belowout"));
+        }
+        let fee = input_value - output_value;
+
+        if !conflicts.is_empty() {
+            let replaced = self.with_descendants(&conflicts);
+            let signals_rbf = conflicts.iter().all(|txid| {
+                self.transactions[txid].0.input.iter().any(|input| {
+                    input.sequence <= RBF_SEQUENCE
+                })
+            });
+            if !signals_rbf {
+                return Err(rpc_error(-26, "txn-mempool-conflict"));
+            }
+            let replaced_fee = replaced.iter().map(|txid| self.fees[txid]).sum::<u64>();
+            if fee <= replaced_fee {
+                return Err(rpc_error(-26, "insufficient fee"));
+            }
+            self.remove_with_descendants(&conflicts);
+        }
+        self.add_to_mempool(tx, fee);
+        Ok(())
+    }
+}
+
+impl BitcoinRelay for SimulatedBitcoin {
+    fn get_transaction(&self, txid: btc::TxId) -> Result<Option<BitcoinTx>> {
+        Ok(self.state().transactions.get(&txid).cloned())
+    }
+
+    fn get_transaction_info(&self, txid: btc::TxId) -> Result<Option<TxInfo>> {
+        let state = self.state();
+        Ok(state.transactions.get(&txid).map(|tx| {
+            TxInfo {
+                body: tx.clone(),
+                confirmations: state.confirmations(txid),
+                vout: None,
+            }
+        }))
+    }
+
+    fn watch_address(&self, addr: &btc::Address, _rescan: bool) -> Result<()> {
+        self.state().watched.insert(addr.to_string());
+        Ok(())
+    }
+
+    fn send_transaction(&self, tx: BitcoinTx) -> Result<()> {
+        self.state().accept(tx)
+    }
+
+    fn send_to_address(&self, addr: &btc::Address, satoshis: u64) -> Result<FundingTx> {
+        let mut state = self.state();
+        state.nonce += 1;
+        let tx = RawBitcoinTx {
+            version: 2,
+            lock_time: 0,
+            input: vec![
+                TxIn {
+                    prev_hash: Sha256dHash::from_data(format!("wallet {}", state.nonce).as_bytes()),
+                    prev_index: 0,
+                    script_sig: Script::new(),
+                    sequence: 0xFFFF_FFFF,
+                },
+            ],
+            output: vec![
+                TxOut {
+                    value: satoshis,
+                    script_pubkey: addr.script_pubkey(),
+                },
+            ],
+            witness: vec![],
+        };
+        state.add_to_mempool(BitcoinTx::from(tx.clone()), 0);
+        Ok(FundingTx::from(tx))
+    }
+
+    fn unspent_transactions(&self, addr: &btc::Address) -> Result<Vec<TxInfo>> {
+        let state = self.state();
+        if !state.watched.contains(&addr.to_string()) {
+            return Ok(Vec::new());
+        }
+        let script_pubkey = addr.script_pubkey();
+        let mut outputs = state
+            .utxos
+            .iter()
+            .filter(|&(_, output)| output.script_pubkey == script_pubkey)
+            .map(|(&(txid, vout), _)| (txid, vout))
+            .collect::<Vec<_>>();
+        outputs.sort_by_key(|&(txid, vout)| (txid.to_string(), vout));
+
+        Ok(
+            outputs
+                .into_iter()
+                .filter_map(|(txid, vout)| {
+                    let confirmations = state.confirmations(txid).unwrap_or(0);
+                    let tx = state.transactions[&txid].clone();
+                    TxInfo::unspent(tx, Some(confirmations), vout)
+                })
+                .collect(),
+        )
+    }
+
+    fn get_block_count(&self) -> Result<u64> {
+        Ok(self.state().block_count())
+    }
+
+    fn get_best_block_hash(&self) -> Result<String> {
+        let state = self.state();
+        let tip = state.blocks.last().unwrap();
+        Ok(tip.header.bitcoin_hash().be_hex_string())
+    }
+
+    fn get_mempool_entry_height(&self, txid: btc::TxId) -> Result<Option<u64>> {
+        let state = self.state();
+        let entry = state.mempool.iter().find(|entry| entry.txid == txid);
+        Ok(entry.map(|entry| entry.height))
+    }
+
+    fn get_tx_out_confirmations(&self, txid: btc::TxId, vout: u32) -> Result<Option<u64>> {
+        let state = self.state();
+        if !state.utxos.contains_key(&(txid, vout)) {
+            return Ok(None);
+        }
+        Ok(Some(state.confirmations(txid).unwrap_or(0)))
+    }
+
+    fn estimate_fee_rate(&self, _conf_target: u16) -> Result<Option<u64>> {
+        Ok(self.state().fee_rate)
+    }
+
+    fn get_tx_out_proof(&self, txid: btc::TxId) -> Result<Option<TxOutProof>> {
+        let state = self.state();
+        let block = match state.heights.get(&txid) {
+            Some(&height) => &state.blocks[height as usize],
+            None => return Ok(None),
+        };
+        let pos = block.txids.iter().position(|hash| *hash == *txid).unwrap();
+        let siblings = merkle_branch(block.txids.clone(), pos);
+        let header = serialize(&block.header).unwrap();
+        let proof = merkle_block(&header, &txid, &siblings, pos as u32);
+        let block_hash = block.header.bitcoin_hash().be_hex_string();
+        Ok(Some(TxOutProof::new(&block_hash, &encode_hex(&proof))))
+    }
+
+    fn config(&self) -> AnchoringRpcConfig {
+        AnchoringRpcConfig {
+            host: "simulated".to_owned(),
+            username: None,
+            password: None,
+            backend: RelayBackend::Bitcoind,
+            utxo_discovery: Default::default(),
+            credentials: Default::default(),
+            tls: None,
+        }
+    }
+}
+
+fn outpoint(input: &TxIn) -> OutPoint {
+    (btc::TxId::from(input.prev_hash), input.prev_index)
+}
+
+/// Creates the error which bitcoind returns for the rejected transactions.
+fn rpc_error(code: i64, message: &str) -> Error {
+    Error::from(jsonrpc::Error::Rpc(json!({
+        "code": code,
+        "message": message,
+    })))
+}
+
+fn script_error(index: usize, reason: &str) -> Error {
+    let message = format!(
+        "mandatory-script-verify-flag-failed (input {}: {})",
+        index,
+        reason
+    );
+    rpc_error(-26, &message)
+}
+
+/// Checks the signatures of the input which spends the multisig `p2sh`, `p2wsh`
+/// or `p2sh-p2wsh` output. The other outputs belong to the wallet and are not checked.
+fn verify_input(tx: &RawBitcoinTx, index: usize, spent: &TxOut) -> Result<()> {
+    let script_sig = &tx.input[index].script_sig;
+    let witness = tx.witness.get(index).map_or(&[][..], |witness| witness.as_slice());
+    if spent.script_pubkey.is_p2sh() {
+        let pushes = script_pushes(script_sig).ok_or_else(|| {
+            script_error(index, "scriptSig is not push only")
+        })?;
+        let redeem_script = match pushes.last() {
+            Some(script) => Script::from(script.clone()),
+            None => return Err(script_error(index, "redeem script is absent")),
+        };
+        if redeem_script.to_p2sh() != spent.script_pubkey {
+            return Err(script_error(index, "redeem script hash mismatch"));
+        }
+        if redeem_script.is_v0_p2wsh() {
+            return verify_witness(tx, index, &redeem_script, witness, spent.value);
+        }
+        if pushes.len() < 2 || !pushes[0].is_empty() {
+            return Err(script_error(index, "multisig dummy element is absent"));
+        }
+        let signatures = &pushes[1..pushes.len() - 1];
+        verify_multisig(index, &redeem_script, signatures, |pub_key, signature| {
+            verify_tx_input(tx, index, &redeem_script, pub_key, signature)
+        })
+    } else if spent.script_pubkey.is_v0_p2wsh() {
+        if !script_sig.is_empty() {
+            return Err(script_error(index, "witness input has non-empty scriptSig"));
+        }
+        verify_witness(tx, index, &spent.script_pubkey, witness, spent.value)
+    } else {
+        Ok(())
+    }
+}
+
+fn verify_witness(
+    tx: &RawBitcoinTx,
+    index: usize,
+    witness_program: &Script,
+    witness: &[Vec<u8>],
+    value: u64,
+) -> Result<()> {
+    let witness_script = match witness.last() {
+        Some(script) => Script::from(script.clone()),
+        None => return Err(script_error(index, "witness is absent")),
+    };
+    if witness_script.to_v0_p2wsh() != *witness_program {
+        return Err(script_error(index, "witness program hash mismatch"));
+    }
+    if witness.len() < 2 || !witness[0].is_empty() {
+        return Err(script_error(index, "multisig dummy element is absent"));
+    }
+    let signatures = &witness[1..witness.len() - 1];
+    verify_multisig(index, &witness_script, signatures, |pub_key, signature| {
+        verify_witness_tx_input(tx, index, &witness_script, value, pub_key, signature)
+    })
+}
+
+/// Checks the signatures against the public keys of the multisig script in the same order
+/// as `OP_CHECKMULTISIG` does.
+fn verify_multisig<F>(
+    index: usize,
+    script: &Script,
+    signatures: &[Vec<u8>],
+    verify: F,
+) -> Result<()>
+where
+    F: Fn(&RawPublicKey, &[u8]) -> bool,
+{
+    let quorum = RedeemScript(script.clone()).quorum();
+    if quorum == 0 || signatures.len() != quorum {
+        return Err(script_error(index, "wrong number of signatures"));
+    }
+    let context = Secp256k1::without_caps();
+    let pub_keys = script_pushes(script)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|bytes| RawPublicKey::from_slice(&context, &bytes).ok())
+        .collect::<Vec<_>>();
+
+    let mut pub_keys = pub_keys.iter();
+    for signature in signatures {
+        let signature = match signature.split_last() {
+            Some((&SIGHASH_ALL, signature)) => signature,
+            _ => return Err(script_error(index, "unsupported sighash type")),
+        };
+        if !pub_keys.any(|pub_key| verify(pub_key, signature)) {
+            return Err(script_error(index, "signature must be valid"));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the data pushed by the script or `None` if it contains other opcodes
+/// except for the multisig ones.
+fn script_pushes(script: &Script) -> Option<Vec<Vec<u8>>> {
+    let mut pushes = Vec::new();
+    for instruction in script {
+        match instruction {
+            Instruction::PushBytes(bytes) => pushes.push(bytes.to_vec()),
+            Instruction::Op(_) if script.is_p2sh() || script.is_v0_p2wsh() => return None,
+            Instruction::Op(_) => {}
+            Instruction::Error(_) => return None,
+        }
+    }
+    Some(pushes)
+}
+
+fn merkle_root(mut hashes: Vec<Sha256dHash>) -> Sha256dHash {
+    while hashes.len() > 1 {
+        hashes = merkle_level(&hashes);
+    }
+    hashes[0]
+}
+
+/// Returns the siblings of the transaction with the given position from the bottom up.
+fn merkle_branch(mut hashes: Vec<Sha256dHash>, mut pos: usize) -> Vec<Sha256dHash> {
+    let mut branch = Vec::new();
+    while hashes.len() > 1 {
+        branch.push(*hashes.get(pos ^ 1).unwrap_or(&hashes[pos]));
+        hashes = merkle_level(&hashes);
+        pos /= 2;
+    }
+    branch
+}
+
+fn merkle_level(hashes: &[Sha256dHash]) -> Vec<Sha256dHash> {
+    hashes
+        .chunks(2)
+        .map(|pair| merkle_parent(&pair[0], pair.last().unwrap()))
+        .collect()
+}
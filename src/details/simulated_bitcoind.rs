@@ -0,0 +1,589 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Bitcoind compatible json rpc server over the `SimulatedBitcoin` network.
+
+use std::io::Read;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bitcoin::blockdata::script::Script;
+use bitcoin::network::serialize::serialize;
+use hyper;
+use hyper::header::{Authorization, Basic, ContentType};
+use hyper::server::{Handler, Listening, Request, Response, Server};
+use hyper::status::StatusCode;
+use jsonrpc;
+use serde::Deserialize;
+use serde_json::{self, Value};
+
+use exonum::encoding::serialize::{encode_hex, FromHex};
+
+use details::btc;
+use details::btc::transactions::{BitcoinTx, RawBitcoinTx};
+use super::bitcoind::descriptor_checksum;
+use super::rpc::{AnchoringRpcConfig, BitcoinRelay, Error, RelayBackend, SATOSHI_DIVISOR};
+use super::simulated::SimulatedBitcoin;
+
+/// Number of the threads which serve the requests.
+const SERVER_THREADS: usize = 4;
+
+/// Error object of the json rpc response.
+type RpcResult = ::std::result::Result<Value, Value>;
+
+/// Json rpc server which serves the `bitcoind` requests used by `RpcClient` over
+/// the `SimulatedBitcoin` network, so that the `RpcClient` can be tested offline.
+///
+/// The server supports the `getrawtransaction`, `importaddress`, `importdescriptors`,
+/// `listunspent`, `scantxoutset`, `sendrawtransaction`, `sendtoaddress`, `getblockcount`,
+/// `getbestblockhash`, `getmempoolentry`, `gettxout`, `estimatesmartfee`, `gettxoutproof`
+/// and `generate` methods, which may be sent by the json rpc batches. Only the `addr()`
+/// descriptors are supported.
+/// Credentials are checked only once they are set by `set_credentials`.
+///
+/// The server is listening until the process exits, since `hyper` is unable to stop it.
+#[derive(Debug)]
+pub struct SimulatedBitcoind {
+    bitcoin: SimulatedBitcoin,
+    credentials: Credentials,
+    requests: Arc<AtomicUsize>,
+    listening: Listening,
+}
+
+/// Username and password required by the server.
+type Credentials = Arc<Mutex<Option<(String, String)>>>;
+
+impl SimulatedBitcoind {
+    /// Starts the server on the random local port.
+    pub fn start(bitcoin: SimulatedBitcoin) -> hyper::Result<SimulatedBitcoind> {
+        SimulatedBitcoind::start_on("127.0.0.1:0", bitcoin)
+    }
+
+    /// Starts the server on the given address.
+    pub fn start_on(addr: &str, bitcoin: SimulatedBitcoin) -> hyper::Result<SimulatedBitcoind> {
+        let mut server = Server::http(addr)?;
+        // Idle connections of the clients' pools must not occupy the server threads.
+        server.keep_alive(None);
+        let credentials = Credentials::default();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let handler = RpcHandler {
+            bitcoin: bitcoin.clone(),
+            credentials: credentials.clone(),
+            requests: Arc::clone(&requests),
+        };
+        let listening = server.handle_threads(handler, SERVER_THREADS)?;
+        Ok(SimulatedBitcoind {
+            bitcoin,
+            credentials,
+            requests,
+            listening,
+        })
+    }
+
+    /// Requires the given credentials from the subsequent requests, like bitcoind does
+    /// after the restart with the new cookie.
+    pub fn set_credentials(&self, username: &str, password: &str) {
+        let credentials = (username.to_owned(), password.to_owned());
+        *self.credentials.lock().unwrap() = Some(credentials);
+    }
+
+    /// Returns the number of the http requests received so far, each json rpc batch
+    /// is counted once.
+    pub fn requests(&self) -> usize {
+        self.requests.load(Ordering::SeqCst)
+    }
+
+    /// Returns the address the server is listening on.
+    pub fn addr(&self) -> SocketAddr {
+        self.listening.socket
+    }
+
+    /// Returns the simulated network served by the server.
+    pub fn bitcoin(&self) -> &SimulatedBitcoin {
+        &self.bitcoin
+    }
+
+    /// Returns the rpc configuration of the server.
+    pub fn config(&self) -> AnchoringRpcConfig {
+        AnchoringRpcConfig {
+            host: format!("http://{}", self.addr()),
+            username: None,
+            password: None,
+            backend: RelayBackend::Bitcoind,
+            utxo_discovery: Default::default(),
+            credentials: Default::default(),
+            tls: None,
+        }
+    }
+}
+
+impl Drop for SimulatedBitcoind {
+    fn drop(&mut self) {
+        // Dropping the `Listening` waits for the listener thread forever.
+        let _ = self.listening.close();
+    }
+}
+
+#[derive(Debug)]
+struct RpcHandler {
+    bitcoin: SimulatedBitcoin,
+    credentials: Credentials,
+    requests: Arc<AtomicUsize>,
+}
+
+impl Handler for RpcHandler {
+    fn handle(&self, mut request: Request, mut response: Response) {
+        self.requests.fetch_add(1, Ordering::SeqCst);
+        if !self.is_authorized(&request) {
+            *response.status_mut() = StatusCode::Unauthorized;
+            return;
+        }
+        let mut body = String::new();
+        let (status, reply) = match request.read_to_string(&mut body) {
+            Ok(_) => self.reply(&body),
+            Err(e) => single_reply(error_reply(Value::Null, rpc_error(-32700, &e.to_string()))),
+        };
+        *response.status_mut() = status;
+        response.headers_mut().set(ContentType::json());
+        if let Err(e) = response.send(&reply) {
+            warn!("Unable to send the simulated bitcoind response: {}", e);
+        }
+    }
+}
+
+impl RpcHandler {
+    fn is_authorized(&self, request: &Request) -> bool {
+        let credentials = self.credentials.lock().unwrap();
+        let (username, password) = match credentials.as_ref() {
+            Some(credentials) => (&credentials.0, &credentials.1),
+            None => return true,
+        };
+        match request.headers.get::<Authorization<Basic>>() {
+            Some(auth) => auth.username == *username && auth.password.as_ref() == Some(password),
+            None => false,
+        }
+    }
+
+    /// Replies to the single request or to the batch of them.
+    fn reply(&self, body: &str) -> (StatusCode, Vec<u8>) {
+        match serde_json::from_str(body) {
+            // Bitcoind answers the batch with the `OK` status even if some requests fail.
+            Ok(Value::Array(requests)) => {
+                let replies = requests
+                    .into_iter()
+                    .map(|request| self.reply_request(request))
+                    .collect::<Vec<_>>();
+                (StatusCode::Ok, serde_json::to_vec(&replies).unwrap())
+            }
+            Ok(request) => single_reply(self.reply_request(request)),
+            Err(e) => single_reply(error_reply(Value::Null, rpc_error(-32700, &e.to_string()))),
+        }
+    }
+
+    fn reply_request(&self, request: Value) -> jsonrpc::Response {
+        let request: jsonrpc::Request = match serde_json::from_value(request) {
+            Ok(request) => request,
+            Err(e) => return error_reply(Value::Null, rpc_error(-32600, &e.to_string())),
+        };
+        trace!("Simulated bitcoind request {:?}", request);
+        match self.call(&request.method, &request.params) {
+            Ok(result) => jsonrpc::Response {
+                result: Some(result),
+                error: None,
+                id: request.id,
+            },
+            Err(error) => error_reply(request.id, error),
+        }
+    }
+
+    fn call(&self, method: &str, params: &[Value]) -> RpcResult {
+        let bitcoin = &self.bitcoin;
+        match method {
+            "getrawtransaction" => {
+                let txid = txid_param(params, 0)?;
+                let tx = match bitcoin.get_transaction(txid).map_err(relay_error)? {
+                    Some(tx) => tx,
+                    None => {
+                        let msg = "No such mempool or blockchain transaction";
+                        return Err(rpc_error(-5, msg));
+                    }
+                };
+                let verbose = match params.get(1) {
+                    Some(Value::Bool(verbose)) => *verbose,
+                    Some(Value::Number(verbose)) => verbose.as_u64() != Some(0),
+                    _ => false,
+                };
+                if verbose {
+                    self.transaction_info(&tx)
+                } else {
+                    Ok(tx.to_hex().into())
+                }
+            }
+            "importaddress" => {
+                let addr = address_param(params, 0)?;
+                bitcoin.watch_address(&addr, false).map_err(relay_error)?;
+                Ok(Value::Null)
+            }
+            "importdescriptors" => {
+                let requests = param::<Vec<Value>>(params, 0)?;
+                let mut results = Vec::new();
+                for request in &requests {
+                    let descriptor = request.get("desc").and_then(Value::as_str).unwrap_or("");
+                    match descriptor_address(descriptor, true) {
+                        Ok(addr) => {
+                            bitcoin.watch_address(&addr, false).map_err(relay_error)?;
+                            results.push(json!({ "success": true }));
+                        }
+                        Err(error) => results.push(json!({ "success": false, "error": error })),
+                    }
+                }
+                Ok(Value::Array(results))
+            }
+            "scantxoutset" => {
+                if param::<String>(params, 0)? != "start" {
+                    return Err(rpc_error(-8, "Only the start action is supported"));
+                }
+                let objects = param::<Vec<Value>>(params, 1)?;
+                let mut unspents = Vec::new();
+                let mut total_amount = 0;
+                for object in &objects {
+                    let descriptor = match object {
+                        Value::String(descriptor) => descriptor.as_str(),
+                        object => object.get("desc").and_then(Value::as_str).unwrap_or(""),
+                    };
+                    let addr = descriptor_address(descriptor, false)?;
+                    for (txid, vout, height) in bitcoin.confirmed_unspent_outputs(&addr) {
+                        let tx = bitcoin.get_transaction(txid).map_err(relay_error)?.unwrap();
+                        let output = &tx.0.output[vout as usize];
+                        total_amount += output.value;
+                        unspents.push(json!({
+                            "txid": txid.to_string(),
+                            "vout": vout,
+                            "scriptPubKey": format!("{:x}", output.script_pubkey),
+                            "desc": descriptor,
+                            "amount": output.value as f64 / SATOSHI_DIVISOR,
+                            "height": height,
+                        }));
+                    }
+                }
+                Ok(json!({
+                    "success": true,
+                    "txouts": unspents.len(),
+                    "height": bitcoin.get_block_count().map_err(relay_error)?,
+                    "bestblock": bitcoin.get_best_block_hash().map_err(relay_error)?,
+                    "unspents": unspents,
+                    "total_amount": total_amount as f64 / SATOSHI_DIVISOR,
+                }))
+            }
+            "listunspent" => {
+                let min_confirmations = param::<u64>(params, 0).unwrap_or(1);
+                let max_confirmations = param::<u64>(params, 1).unwrap_or(9_999_999);
+                let addrs = param::<Vec<String>>(params, 2)?;
+                let mut entries = Vec::new();
+                for addr in addrs {
+                    let addr = parse_address(&addr)?;
+                    let script_pubkey = addr.script_pubkey();
+                    for info in bitcoin.unspent_transactions(&addr).map_err(relay_error)? {
+                        let confirmations = info.confirmations.unwrap_or(0);
+                        if confirmations < min_confirmations || confirmations > max_confirmations {
+                            continue;
+                        }
+                        let vout = info.vout.unwrap();
+                        let output = &info.body.0.output[vout as usize];
+                        entries.push(json!({
+                            "txid": info.body.id().to_string(),
+                            "vout": vout,
+                            "address": addr.to_string(),
+                            "scriptPubKey": format!("{:x}", script_pubkey),
+                            "amount": output.value as f64 / SATOSHI_DIVISOR,
+                            "confirmations": confirmations,
+                            "spendable": false,
+                            "solvable": false,
+                        }));
+                    }
+                }
+                Ok(Value::Array(entries))
+            }
+            "sendrawtransaction" => {
+                let hex = param::<String>(params, 0)?;
+                let tx = BitcoinTx::from_hex(&hex).map_err(|_| rpc_error(-22, "TX decode failed"))?;
+                let txid = tx.id();
+                bitcoin.send_transaction(tx).map_err(relay_error)?;
+                Ok(txid.to_string().into())
+            }
+            "sendtoaddress" => {
+                let addr = address_param(params, 0)?;
+                let amount = match params.get(1) {
+                    Some(Value::String(amount)) => f64::from_str(amount).ok(),
+                    Some(Value::Number(amount)) => amount.as_f64(),
+                    _ => None,
+                };
+                let satoshis = match amount {
+                    Some(amount) if amount > 0.0 => (amount * SATOSHI_DIVISOR).round() as u64,
+                    _ => return Err(rpc_error(-3, "Invalid amount")),
+                };
+                let tx = bitcoin.send_to_address(&addr, satoshis).map_err(relay_error)?;
+                Ok(tx.id().to_string().into())
+            }
+            "getblockcount" => Ok(bitcoin.get_block_count().map_err(relay_error)?.into()),
+            "getbestblockhash" => Ok(bitcoin.get_best_block_hash().map_err(relay_error)?.into()),
+            "getmempoolentry" => {
+                let txid = txid_param(params, 0)?;
+                match bitcoin.get_mempool_entry_height(txid).map_err(relay_error)? {
+                    Some(height) => Ok(json!({ "height": height })),
+                    None => Err(rpc_error(-5, "Transaction not in mempool")),
+                }
+            }
+            "gettxout" => {
+                let txid = txid_param(params, 0)?;
+                let vout = param::<u32>(params, 1)?;
+                let confirmations = bitcoin.get_tx_out_confirmations(txid, vout).map_err(
+                    relay_error,
+                )?;
+                Ok(match confirmations {
+                    Some(confirmations) => {
+                        let tx = bitcoin.get_transaction(txid).map_err(relay_error)?.unwrap();
+                        let output = &tx.output[vout as usize];
+                        json!({
+                            "bestblock": bitcoin.get_best_block_hash().map_err(relay_error)?,
+                            "confirmations": confirmations,
+                            "value": output.value as f64 / SATOSHI_DIVISOR,
+                            "scriptPubKey": { "hex": encode_hex(&output.script_pubkey[..]) },
+                            "coinbase": false,
+                        })
+                    }
+                    None => Value::Null,
+                })
+            }
+            "estimatesmartfee" => {
+                let conf_target = param::<u16>(params, 0)?;
+                let fee_rate = bitcoin.estimate_fee_rate(conf_target).map_err(relay_error)?;
+                Ok(match fee_rate {
+                    Some(fee_rate) => json!({
+                        "feerate": fee_rate as f64 * 1000.0 / SATOSHI_DIVISOR,
+                        "blocks": conf_target,
+                    }),
+                    None => json!({
+                        "errors": ["Insufficient data or no feerate found"],
+                        "blocks": 0,
+                    }),
+                })
+            }
+            "gettxoutproof" => {
+                let txids = param::<Vec<String>>(params, 0)?;
+                let block_hash = param::<String>(params, 1).ok();
+                if txids.len() != 1 {
+                    return Err(rpc_error(-8, "Only the single transaction is supported"));
+                }
+                let txid = parse_txid(&txids[0])?;
+                let proof = bitcoin.get_tx_out_proof(txid).map_err(relay_error)?;
+                match proof {
+                    Some(ref proof) if block_hash.map_or(true, |h| h == proof.block_hash()) => {
+                        Ok(proof.proof().into())
+                    }
+                    _ => Err(rpc_error(-5, "Transaction not yet in block")),
+                }
+            }
+            "generate" => {
+                let count = param::<u64>(params, 0)?;
+                Ok(json!(bitcoin.mine_blocks(count)))
+            }
+            _ => Err(rpc_error(-32601, "Method not found")),
+        }
+    }
+
+    /// Returns the verbose `getrawtransaction` response.
+    fn transaction_info(&self, tx: &BitcoinTx) -> RpcResult {
+        let txid = tx.id();
+        let raw = &tx.0;
+        let base_size = serialize(&RawBitcoinTx {
+            witness: vec![],
+            ..raw.clone()
+        }).unwrap()
+            .len();
+        let size = serialize(raw).unwrap().len();
+        let inputs = raw.input
+            .iter()
+            .enumerate()
+            .map(|(index, input)| {
+                let witness = match raw.witness.get(index) {
+                    Some(witness) if !witness.is_empty() => {
+                        Some(witness.iter().map(encode_hex).collect::<Vec<_>>())
+                    }
+                    _ => None,
+                };
+                json!({
+                    "txid": input.prev_hash.be_hex_string(),
+                    "vout": input.prev_index,
+                    "scriptSig": {
+                        "asm": script_asm(&input.script_sig),
+                        "hex": format!("{:x}", input.script_sig),
+                    },
+                    "txinwitness": witness,
+                    "sequence": input.sequence,
+                })
+            })
+            .collect::<Vec<_>>();
+        let outputs = raw.output
+            .iter()
+            .enumerate()
+            .map(|(n, output)| {
+                json!({
+                    "value": output.value as f64 / SATOSHI_DIVISOR,
+                    "n": n,
+                    "scriptPubKey": {
+                        "asm": script_asm(&output.script_pubkey),
+                        "hex": format!("{:x}", output.script_pubkey),
+                        "type": script_type(&output.script_pubkey),
+                    },
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut info = json!({
+            "hex": tx.to_hex(),
+            "txid": txid.to_string(),
+            "hash": txid.to_string(),
+            "size": size,
+            "vsize": (base_size * 3 + size + 3) / 4,
+            "version": raw.version,
+            "locktime": raw.lock_time,
+            "vin": inputs,
+            "vout": outputs,
+        });
+        let proof = self.bitcoin.get_tx_out_proof(txid).map_err(relay_error)?;
+        if let Some(proof) = proof {
+            let confirmations = self.bitcoin
+                .get_transaction_confirmations(txid)
+                .map_err(relay_error)?;
+            info["blockhash"] = proof.block_hash().into();
+            info["confirmations"] = confirmations.into();
+        }
+        Ok(info)
+    }
+}
+
+fn rpc_error(code: i64, message: &str) -> Value {
+    json!({
+        "code": code,
+        "message": message,
+    })
+}
+
+/// Returns the status and the body of the reply to the single request.
+fn single_reply(reply: jsonrpc::Response) -> (StatusCode, Vec<u8>) {
+    let status = if reply.error.is_some() {
+        StatusCode::InternalServerError
+    } else {
+        StatusCode::Ok
+    };
+    (status, serde_json::to_vec(&reply).unwrap())
+}
+
+fn error_reply(id: Value, error: Value) -> jsonrpc::Response {
+    jsonrpc::Response {
+        result: None,
+        error: Some(error),
+        id,
+    }
+}
+
+/// Converts the relay error back to the bitcoind error object.
+fn relay_error(e: Error) -> Value {
+    use bitcoinrpc::Error::*;
+
+    match e {
+        Error::Rpc(NoInformation(msg)) => rpc_error(-5, &msg),
+        Error::Rpc(InsufficientFunds) => rpc_error(-6, "Insufficient funds"),
+        Error::Rpc(Memory(msg)) => rpc_error(-7, &msg),
+        Error::Rpc(TransactionIncorrect(msg)) => rpc_error(-25, &msg),
+        Error::Rpc(TransactionRejected(msg)) => rpc_error(-26, &msg),
+        Error::Rpc(TransactionAlreadyInChain) => {
+            rpc_error(-27, "transaction already in block chain")
+        }
+        Error::Rpc(Other(jsonrpc::Error::Rpc(error))) => error,
+        e => rpc_error(-32603, &e.to_string()),
+    }
+}
+
+fn param<T>(params: &[Value], index: usize) -> ::std::result::Result<T, Value>
+where
+    for<'de> T: Deserialize<'de>,
+{
+    let value = params.get(index).cloned().unwrap_or(Value::Null);
+    serde_json::from_value(value).map_err(|e| {
+        rpc_error(-3, &format!("Invalid parameter {}: {}", index, e))
+    })
+}
+
+fn parse_txid(txid: &str) -> ::std::result::Result<btc::TxId, Value> {
+    btc::TxId::from_hex(txid).map_err(|_| rpc_error(-8, "txid must be hexadecimal string"))
+}
+
+fn parse_address(addr: &str) -> ::std::result::Result<btc::Address, Value> {
+    btc::Address::from_str(addr).map_err(|_| rpc_error(-5, "Invalid Bitcoin address"))
+}
+
+fn txid_param(params: &[Value], index: usize) -> ::std::result::Result<btc::TxId, Value> {
+    parse_txid(&param::<String>(params, index)?)
+}
+
+fn address_param(params: &[Value], index: usize) -> ::std::result::Result<btc::Address, Value> {
+    parse_address(&param::<String>(params, index)?)
+}
+
+/// Parses the address from the `addr()` descriptor and verifies its checksum if it is given.
+fn descriptor_address(
+    descriptor: &str,
+    require_checksum: bool,
+) -> ::std::result::Result<btc::Address, Value> {
+    let (descriptor, checksum) = match descriptor.find('#') {
+        Some(pos) => (&descriptor[..pos], Some(&descriptor[pos + 1..])),
+        None => (descriptor, None),
+    };
+    match checksum {
+        Some(checksum) if descriptor_checksum(descriptor) != Some(checksum.to_owned()) => {
+            return Err(rpc_error(-5, "Provided checksum does not match"))
+        }
+        Some(_) => {}
+        None if require_checksum => return Err(rpc_error(-5, "Missing checksum")),
+        None => {}
+    }
+    if !descriptor.starts_with("addr(") || !descriptor.ends_with(')') {
+        return Err(rpc_error(-5, "Only addr() descriptors are supported"));
+    }
+    parse_address(&descriptor[5..descriptor.len() - 1])
+}
+
+fn script_asm(script: &Script) -> String {
+    // `Script` is displayed as `Script(<asm>)`.
+    let script = script.to_string();
+    script["Script(".len()..script.len() - 1].to_owned()
+}
+
+fn script_type(script: &Script) -> &'static str {
+    if script.is_p2sh() {
+        "scripthash"
+    } else if script.is_v0_p2wsh() {
+        "witness_v0_scripthash"
+    } else if script.is_p2pkh() {
+        "pubkeyhash"
+    } else if script.is_provably_unspendable() {
+        "nulldata"
+    } else {
+        "nonstandard"
+    }
+}
@@ -18,10 +18,12 @@ use std::collections::HashMap;
 
 use serde_json;
 use rand::Rng;
-use bitcoin::network::constants::Network;
+use details::btc::Network;
 use bitcoin::util::base58::{FromBase58, ToBase58};
 use bitcoin::util::address::Privkey as RawPrivateKey;
+use bitcoin::blockdata::script::Error as ScriptError;
 use bitcoin::blockdata::transaction::SigHashType;
+use bitcoin::network::serialize::serialize;
 use secp256k1::key::PublicKey as RawPublicKey;
 use secp256k1::Secp256k1;
 
@@ -32,7 +34,8 @@ use exonum::encoding::serialize::{FromHex, encode_hex};
 use exonum::encoding::Field;
 
 use details::btc::transactions::{sign_tx_input, verify_tx_input, AnchoringTx, BitcoinTx,
-                                 FundingTx, TransactionBuilder, TxKind};
+                                 FundingTx, RawBitcoinTx, TransactionBuilder, TxKind,
+                                 RBF_SEQUENCE};
 use details::btc;
 use details::btc::HexValueEx;
 
@@ -109,7 +112,7 @@ fn test_privkey_serde_wif() {
     let privkey = btc::PrivateKey::from_base58check(privkey_str).unwrap();
 
     assert!(privkey.compressed);
-    assert_eq!(privkey.network, Network::Testnet);
+    assert_eq!(Network::from(privkey.network), Network::Testnet);
     assert_eq!(privkey.to_base58check(), privkey_str);
 }
 
@@ -337,7 +340,7 @@ fn test_redeem_script_from_pubkeys() {
         redeem_script
     );
 
-    let compressed_redeem_script = redeem_script.compressed(Network::Testnet);
+    let compressed_redeem_script = redeem_script.compressed(Network::Testnet).unwrap();
     assert_eq!(
         compressed_redeem_script.to_hex(),
         "5321027db7837e51888e94c094703030d162c682c8dba312210f44ff440fbd5e5c24732102bdd27289\
@@ -346,9 +349,16 @@ fn test_redeem_script_from_pubkeys() {
          aed84f502c0e4171976e19deb054ae"
     );
     assert_eq!(
-        compressed_redeem_script.compressed(Network::Testnet),
+        compressed_redeem_script.compressed(Network::Testnet).unwrap(),
         compressed_redeem_script
     );
+
+    // The public key is truncated.
+    let malformed_redeem_script = btc::RedeemScript::from_hex("5321027db7837e").unwrap();
+    assert_eq!(
+        malformed_redeem_script.compressed(Network::Testnet),
+        Err(btc::RedeemScriptError::Script(ScriptError::EarlyEndOfScript))
+    );
 }
 
 #[test]
@@ -431,7 +441,8 @@ fn test_anchoring_tx_sign() {
         .map(|x| btc::PublicKey::from_hex(x).unwrap())
         .collect::<Vec<_>>();
     let redeem_script = btc::RedeemScript::from_pubkeys(pub_keys.iter(), 3)
-        .compressed(Network::Testnet);
+        .compressed(Network::Testnet)
+        .unwrap();
 
     let prev_tx = AnchoringTx::from_hex(
         "01000000014970bd8d76edf52886f62e3073714bddc6c33bccebb6b1d\
@@ -516,7 +527,9 @@ fn test_anchoring_tx_output_address() {
     ].iter()
         .map(|x| btc::PublicKey::from_hex(x).unwrap())
         .collect::<Vec<_>>();
-    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3).compressed(Network::Testnet);
+    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3)
+        .compressed(Network::Testnet)
+        .unwrap();
 
     assert_eq!(
         tx.output_address(Network::Testnet),
@@ -545,7 +558,7 @@ fn test_anchoring_tx_prev_chain() {
         .payload(Height::zero(), Hash::default())
         .prev_tx_chain(Some(prev_tx.id()))
         .send_to(
-            btc::Address::from_base58check("2N1mHzwKTmjnC7JjqeGFBRKYE4WDTjTfop1").unwrap(),
+            "2N1mHzwKTmjnC7JjqeGFBRKYE4WDTjTfop1".parse().unwrap(),
         )
         .into_transaction()
         .unwrap();
@@ -604,7 +617,9 @@ fn test_tx_kind_other() {
 #[test]
 fn test_tx_verify_sighash_type_correct() {
     let (pub_keys, priv_keys) = gen_anchoring_keys(4);
-    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3).compressed(Network::Testnet);
+    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3)
+        .compressed(Network::Testnet)
+        .unwrap();
 
     let tx = dummy_anchoring_tx(&redeem_script);
     let pub_key = &pub_keys[0];
@@ -620,7 +635,9 @@ fn test_tx_verify_sighash_type_correct() {
 #[test]
 fn test_tx_verify_incorrect_signature() {
     let (pub_keys, priv_keys) = gen_anchoring_keys(4);
-    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3).compressed(Network::Testnet);
+    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3)
+        .compressed(Network::Testnet)
+        .unwrap();
 
     let tx = dummy_anchoring_tx(&redeem_script);
     let pub_key = &pub_keys[0];
@@ -640,7 +657,9 @@ fn test_tx_verify_incorrect_signature() {
 #[test]
 fn test_tx_verify_non_strict_der_signature() {
     let (pub_keys, priv_keys) = gen_anchoring_keys(4);
-    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3).compressed(Network::Testnet);
+    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3)
+        .compressed(Network::Testnet)
+        .unwrap();
 
     let tx = dummy_anchoring_tx(&redeem_script);
     let pub_key = &pub_keys[0];
@@ -668,7 +687,9 @@ fn test_tx_verify_non_strict_der_signature() {
 #[test]
 fn test_tx_verify_sighash_type_wrong() {
     let (pub_keys, priv_keys) = gen_anchoring_keys(4);
-    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3).compressed(Network::Testnet);
+    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3)
+        .compressed(Network::Testnet)
+        .unwrap();
 
     let tx = dummy_anchoring_tx(&redeem_script);
     let pub_key = &pub_keys[0];
@@ -678,47 +699,587 @@ fn test_tx_verify_sighash_type_wrong() {
     assert!(tx.verify_input(&redeem_script, 0, &pub_key, &btc_signature));
 }
 
-// rpc tests. Works through `rpc` by given env variables.
-// See the `anchoring_client` method on top of this file.
+#[test]
+fn test_p2wsh_address_bech32() {
+    // Test vector from BIP-173
+    let addr_str = "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7";
+    let addr: btc::Address = addr_str.parse().unwrap();
+
+    assert_eq!(addr.network(), Network::Testnet);
+    assert_eq!(
+        addr.script_pubkey().to_hex(),
+        "00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262"
+    );
+    assert_eq!(addr.to_string(), addr_str);
+    assert_eq!(
+        btc::Address::from_script_pubkey(&addr.script_pubkey(), Network::Testnet),
+        Some(addr)
+    );
+}
+
+#[test]
+fn test_redeem_script_address_types() {
+    let (pub_keys, _) = gen_anchoring_keys(4);
+    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3)
+        .compressed(Network::Testnet)
+        .unwrap();
+
+    let p2sh = btc::Address::from_redeem_script(
+        &redeem_script,
+        btc::AddressType::P2sh,
+        Network::Testnet,
+    );
+    assert_eq!(p2sh, redeem_script.to_address(Network::Testnet));
+
+    let p2wsh = btc::Address::from_redeem_script(
+        &redeem_script,
+        btc::AddressType::P2wsh,
+        Network::Testnet,
+    );
+    assert!(p2wsh.to_string().starts_with("tb1q"));
+    assert!(p2wsh.script_pubkey().is_v0_p2wsh());
+    assert_eq!(p2wsh.script_pubkey(), redeem_script.to_v0_p2wsh());
+
+    let p2sh_p2wsh = btc::Address::from_redeem_script(
+        &redeem_script,
+        btc::AddressType::P2shP2wsh,
+        Network::Testnet,
+    );
+    assert!(p2sh_p2wsh.script_pubkey().is_p2sh());
+    assert_ne!(p2sh_p2wsh, p2sh);
+
+    for addr in &[p2sh, p2wsh, p2sh_p2wsh] {
+        let json = serde_json::to_string(addr).unwrap();
+        let addr2: btc::Address = serde_json::from_str(&json).unwrap();
+        assert_eq!(&addr2, addr);
+    }
+}
+
+#[test]
+fn test_network_from_str() {
+    for name in Network::NAMES {
+        let network: Network = name.parse().unwrap();
+        assert_eq!(&network.to_string(), name);
+    }
+    assert!("mainnet".parse::<Network>().is_err());
+}
+
+#[test]
+fn test_regtest_and_signet_addresses() {
+    let (pub_key, priv_key) = btc::gen_btc_keypair(Network::Regtest);
+    assert!(priv_key.to_base58check().starts_with('c'));
+
+    let redeem_script = btc::RedeemScript::from_pubkeys(&[pub_key], 1)
+        .compressed(Network::Regtest)
+        .unwrap();
+    let p2sh = redeem_script.to_address(Network::Regtest);
+    assert!(p2sh.to_string().starts_with('2'));
+    assert_eq!(p2sh.network(), Network::Regtest);
+
+    let regtest_p2wsh = btc::Address::from_redeem_script(
+        &redeem_script,
+        btc::AddressType::P2wsh,
+        Network::Regtest,
+    );
+    assert!(regtest_p2wsh.to_string().starts_with("bcrt1q"));
+    assert_eq!(regtest_p2wsh.network(), Network::Regtest);
+    assert_eq!(
+        regtest_p2wsh.to_string().parse::<btc::Address>().unwrap(),
+        regtest_p2wsh
+    );
+
+    let signet_p2wsh = btc::Address::from_redeem_script(
+        &redeem_script,
+        btc::AddressType::P2wsh,
+        Network::Signet,
+    );
+    assert!(signet_p2wsh.to_string().starts_with("tb1q"));
+    assert_eq!(signet_p2wsh.network(), Network::Signet);
+    assert_eq!(
+        signet_p2wsh.to_string().parse::<btc::Address>().unwrap(),
+        signet_p2wsh
+    );
+}
+
+#[test]
+fn test_tx_verify_witness_signature() {
+    let (pub_keys, priv_keys) = gen_anchoring_keys(4);
+    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3)
+        .compressed(Network::Testnet)
+        .unwrap();
+
+    let tx = dummy_anchoring_tx(&redeem_script);
+    let pub_key = &pub_keys[0];
+    let btc_signature = tx.sign_witness_input(&redeem_script, 0, 3000, &priv_keys[0]);
+
+    assert_eq!(
+        *btc_signature.last().unwrap(),
+        SigHashType::All.as_u32() as u8
+    );
+    assert!(tx.verify_witness_input(
+        &redeem_script,
+        0,
+        3000,
+        pub_key,
+        &btc_signature,
+    ));
+    // Witness signature commits to the spent output value
+    assert!(!tx.verify_witness_input(
+        &redeem_script,
+        0,
+        3001,
+        pub_key,
+        &btc_signature,
+    ));
+    // Legacy and witness signatures are not interchangeable
+    assert!(!tx.verify_input(&redeem_script, 0, pub_key, &btc_signature));
+}
+
+#[test]
+fn test_anchoring_tx_finalize_p2wsh() {
+    let (pub_keys, priv_keys) = gen_anchoring_keys(4);
+    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3)
+        .compressed(Network::Testnet)
+        .unwrap();
+
+    let tx = dummy_anchoring_tx(&redeem_script);
+    let signatures = (0..3)
+        .map(|id| {
+            tx.sign_witness_input(&redeem_script, 0, 3000, &priv_keys[id])
+        })
+        .collect::<Vec<_>>();
+    let mut signatures_map = HashMap::new();
+    signatures_map.insert(0, signatures.clone());
+
+    let signed_tx =
+        tx.clone()
+            .finalize_with_type(btc::AddressType::P2wsh, &redeem_script, signatures_map);
+
+    assert!(signed_tx.input[0].script_sig.is_empty());
+    assert_eq!(signed_tx.witness.len(), 1);
+    let witness = &signed_tx.witness[0];
+    assert_eq!(witness.len(), 5);
+    assert!(witness[0].is_empty());
+    assert_eq!(&witness[1..4], signatures.as_slice());
+    assert_eq!(witness[4], redeem_script.0.clone().into_vec());
+    // Native segwit transaction id does not depend on the signatures
+    assert_eq!(signed_tx.id(), tx.id());
+    assert_eq!(signed_tx.nid(), tx.nid());
+
+    let signed_tx2 = AnchoringTx::from_hex(signed_tx.to_hex()).unwrap();
+    assert_eq!(signed_tx2, signed_tx);
+}
+
+#[test]
+fn test_anchoring_tx_finalize_p2sh_p2wsh() {
+    let (pub_keys, priv_keys) = gen_anchoring_keys(4);
+    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3)
+        .compressed(Network::Testnet)
+        .unwrap();
+
+    let tx = dummy_anchoring_tx(&redeem_script);
+    let signatures = (0..3)
+        .map(|id| {
+            tx.sign_witness_input(&redeem_script, 0, 3000, &priv_keys[id])
+        })
+        .collect::<Vec<_>>();
+    let mut signatures_map = HashMap::new();
+    signatures_map.insert(0, signatures);
+
+    let signed_tx = tx.clone().finalize_with_type(
+        btc::AddressType::P2shP2wsh,
+        &redeem_script,
+        signatures_map,
+    );
+
+    let witness_program = redeem_script.to_v0_p2wsh().into_vec();
+    let mut expected_script_sig = vec![witness_program.len() as u8];
+    expected_script_sig.extend(witness_program);
+    assert_eq!(
+        signed_tx.input[0].script_sig.clone().into_vec(),
+        expected_script_sig
+    );
+    assert_eq!(signed_tx.witness[0].len(), 5);
+    assert_eq!(signed_tx.nid(), tx.nid());
+}
+
+#[test]
+fn test_anchoring_tx_signals_rbf() {
+    let (pub_keys, _) = gen_anchoring_keys(4);
+    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3)
+        .compressed(Network::Testnet)
+        .unwrap();
+
+    let tx = dummy_anchoring_tx(&redeem_script);
+    assert!(tx.input.iter().all(|input| input.sequence == RBF_SEQUENCE));
+}
+
+#[test]
+fn test_redeem_script_quorum() {
+    let (pub_keys, _) = gen_anchoring_keys(4);
+    for quorum in 1..5 {
+        let redeem_script =
+            btc::RedeemScript::from_pubkeys(&pub_keys, quorum)
+                .compressed(Network::Testnet)
+                .unwrap();
+        assert_eq!(redeem_script.quorum(), quorum as usize);
+    }
+}
+
+#[test]
+fn test_anchoring_tx_estimate_vsize() {
+    let (pub_keys, priv_keys) = gen_anchoring_keys(4);
+    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3)
+        .compressed(Network::Testnet)
+        .unwrap();
+    let tx = dummy_anchoring_tx(&redeem_script);
+
+    let address_types = [
+        btc::AddressType::P2sh,
+        btc::AddressType::P2wsh,
+        btc::AddressType::P2shP2wsh,
+    ];
+    for address_type in &address_types {
+        let signatures = (0..3)
+            .map(|id| if address_type.is_witness() {
+                tx.sign_witness_input(&redeem_script, 0, 3000, &priv_keys[id])
+            } else {
+                tx.sign_input(&redeem_script, 0, &priv_keys[id])
+            })
+            .collect::<Vec<_>>();
+        let mut signatures_map = HashMap::new();
+        signatures_map.insert(0, signatures);
+        let signed_tx =
+            tx.clone()
+                .finalize_with_type(*address_type, &redeem_script, signatures_map);
+
+        let stripped_size = serialize(&RawBitcoinTx {
+            witness: vec![],
+            ..signed_tx.0.clone()
+        }).unwrap()
+            .len() as u64;
+        let total_size = serialize(&signed_tx.0).unwrap().len() as u64;
+        let vsize = (stripped_size * 3 + total_size + 3) / 4;
+
+        let estimated_vsize = tx.estimate_vsize(&redeem_script, *address_type);
+        assert!(estimated_vsize >= vsize);
+        // Each signature may be a few bytes shorter than the maximum length.
+        assert!(estimated_vsize - vsize <= 3 * 3);
+    }
+    assert!(
+        tx.estimate_vsize(&redeem_script, btc::AddressType::P2wsh) <
+            tx.estimate_vsize(&redeem_script, btc::AddressType::P2sh)
+    );
+}
+
+#[test]
+fn test_anchoring_tx_builder_fee_rate() {
+    let (pub_keys, _) = gen_anchoring_keys(4);
+    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3)
+        .compressed(Network::Testnet)
+        .unwrap();
+    let addr = btc::Address::from_redeem_script(
+        &redeem_script,
+        btc::AddressType::P2wsh,
+        Network::Testnet,
+    );
+    let input_tx = dummy_anchoring_tx(&redeem_script);
+
+    let tx = TransactionBuilder::with_prev_tx(&input_tx, 0)
+        .fee_rate(2, &redeem_script, btc::AddressType::P2wsh)
+        .payload(Height::zero(), Hash::zero())
+        .send_to(addr)
+        .into_transaction()
+        .unwrap();
+
+    let fee = tx.estimate_vsize(&redeem_script, btc::AddressType::P2wsh) * 2;
+    assert_eq!(tx.amount(), input_tx.amount() - fee);
+}
+
+#[test]
+fn test_relay_error_kinds() {
+    use std::io;
+
+    use bitcoinrpc;
+    use hyper;
+    use jsonrpc;
+
+    use details::rpc::{parse_transaction, parse_txid, Error};
+
+    let e = Error::from(bitcoinrpc::Error::NoInformation("Unknown tx".to_owned()));
+    match e {
+        Error::Rpc(bitcoinrpc::Error::NoInformation(_)) => {}
+        other => panic!("Unexpected error {:?}", other),
+    }
+    let e = Error::from(jsonrpc::Error::Rpc(json!({ "code": -28, "message": "Loading" })));
+    assert_eq!(e.rpc_code(), Some(-28));
+
+    let io_error = io::Error::new(io::ErrorKind::ConnectionRefused, "Connection refused");
+    let e = Error::from(jsonrpc::Error::Hyper(hyper::Error::Io(io_error)));
+    assert_eq!(e.rpc_code(), None);
+    match e {
+        Error::Transport(_) => {}
+        other => panic!("Unexpected error {:?}", other),
+    }
+    match Error::from(jsonrpc::Error::NoErrorOrResult) {
+        Error::Protocol(_) => {}
+        other => panic!("Unexpected error {:?}", other),
+    }
+
+    let e = parse_transaction("0100").unwrap_err();
+    assert!(e.to_string().starts_with("Unable to parse transaction"));
+    match parse_txid("1d9a") {
+        Err(Error::Parse(_)) => {}
+        other => panic!("Unexpected response {:?}", other),
+    }
+}
+
+#[test]
+fn test_descriptor_checksum() {
+    use std::str::FromStr;
+
+    use details::bitcoind::{address_descriptor, descriptor_checksum};
+    use details::rpc::{AnchoringRpcConfig, UtxoDiscovery};
+
+    assert_eq!(descriptor_checksum("raw(deadbeef)"), Some("89f8spxm".to_owned()));
+    assert_eq!(descriptor_checksum("raw(deadbeef)\u{e9}"), None);
+    let addr = btc::Address::from_str("mkmZxiEcEd8ZqjQWVZuC6so5dFMKEFpN2j").unwrap();
+    assert_eq!(
+        address_descriptor(&addr),
+        "addr(mkmZxiEcEd8ZqjQWVZuC6so5dFMKEFpN2j)#02wpgw69"
+    );
+
+    for name in UtxoDiscovery::NAMES {
+        let utxo_discovery = UtxoDiscovery::from_str(name).unwrap();
+        assert_eq!(utxo_discovery.to_string(), *name);
+    }
+    assert!(UtxoDiscovery::from_str("listunspent").is_err());
+
+    let cfg: AnchoringRpcConfig = serde_json::from_value(json!({
+        "host": "http://127.0.0.1:18332",
+        "username": null,
+        "password": null,
+    })).unwrap();
+    assert_eq!(cfg.utxo_discovery, UtxoDiscovery::ImportAddress);
+    assert!(serde_json::to_value(&cfg).unwrap().get("utxo_discovery").is_none());
+    let cfg: AnchoringRpcConfig = serde_json::from_value(json!({
+        "host": "http://127.0.0.1:18332",
+        "username": null,
+        "password": null,
+        "utxo_discovery": "scantxoutset",
+    })).unwrap();
+    assert_eq!(cfg.utxo_discovery, UtxoDiscovery::ScanTxOutSet);
+}
+
+#[test]
+fn test_rpc_credentials_config() {
+    use std::path::PathBuf;
+
+    use details::rpc::{AnchoringRpcConfig, RpcCredentials, RpcTlsConfig};
+    use details::transport::parse_fingerprint;
+
+    let cfg: AnchoringRpcConfig = serde_json::from_value(json!({
+        "host": "http://127.0.0.1:18332",
+        "username": null,
+        "password": null,
+    })).unwrap();
+    assert!(cfg.credentials.is_inline());
+    assert_eq!(cfg.tls, None);
+    let value = serde_json::to_value(&cfg).unwrap();
+    assert!(value.get("credentials").is_none());
+    assert!(value.get("tls").is_none());
+
+    let cfg: AnchoringRpcConfig = serde_json::from_value(json!({
+        "host": "https://127.0.0.1:18332",
+        "username": null,
+        "password": null,
+        "credentials": { "cookie_file": "/var/lib/bitcoind/testnet3/.cookie" },
+        "tls": { "ca_file": "/etc/anchoring/ca.pem" },
+    })).unwrap();
+    assert_eq!(
+        cfg.credentials,
+        RpcCredentials {
+            cookie_file: Some(PathBuf::from("/var/lib/bitcoind/testnet3/.cookie")),
+            ..Default::default()
+        }
+    );
+    assert_eq!(
+        cfg.tls,
+        Some(RpcTlsConfig {
+            ca_file: Some(PathBuf::from("/etc/anchoring/ca.pem")),
+            pinned_cert_sha256: None,
+        })
+    );
+
+    let fingerprint = "00:01:02:03:04:05:06:07:08:09:0a:0b:0c:0d:0e:0f:\
+                       10:11:12:13:14:15:16:17:18:19:1A:1B:1C:1D:1E:1F";
+    assert_eq!(parse_fingerprint(fingerprint), Some((0..32).collect()));
+    assert_eq!(parse_fingerprint(&fingerprint.replace(':', "")), Some((0..32).collect()));
+    assert_eq!(parse_fingerprint("00:01:02"), None);
+    assert_eq!(parse_fingerprint(&fingerprint.replace("1F", "1G")), None);
+}
+
+#[test]
+fn test_bitcoin_event_from_zmq() {
+    use details::rpc::Error;
+    use details::notifications::BitcoinEvent;
+    use local_storage::AnchoringNodeConfig;
+
+    let block_hash = "000000000000000000137a2fdf1a4eb2e2fd7e1c26cda3578da4ad2f2a3ee4f8";
+    let body = Vec::<u8>::from_hex(block_hash).unwrap();
+    assert_eq!(
+        BitcoinEvent::from_zmq(b"hashblock", &body).unwrap(),
+        Some(BitcoinEvent::Block(block_hash.to_owned()))
+    );
+    match BitcoinEvent::from_zmq(b"hashblock", &body[1..]) {
+        Err(Error::Parse(_)) => {}
+        other => panic!("Unexpected event {:?}", other),
+    }
+
+    let (pub_keys, _) = gen_anchoring_keys(4);
+    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3)
+        .compressed(Network::Testnet)
+        .unwrap();
+    let tx = dummy_anchoring_tx(&redeem_script);
+    let body = serialize(&tx.0).unwrap();
+    assert_eq!(
+        BitcoinEvent::from_zmq(b"rawtx", &body).unwrap(),
+        Some(BitcoinEvent::Transaction(BitcoinTx::from(tx.0.clone())))
+    );
+    match BitcoinEvent::from_zmq(b"rawtx", &body[1..]) {
+        Err(Error::Parse(_)) => {}
+        other => panic!("Unexpected event {:?}", other),
+    }
+    assert_eq!(BitcoinEvent::from_zmq(b"hashtx", &body).unwrap(), None);
+
+    let node: AnchoringNodeConfig = serde_json::from_value(json!({
+        "rpc": null,
+        "private_keys": {},
+        "check_lect_frequency": 30,
+        "observer": { "check_interval": 10000, "enabled": false },
+        "zmq_notifications": { "hashblock": "tcp://127.0.0.1:28332" },
+    })).unwrap();
+    let zmq = node.zmq_notifications.unwrap();
+    assert_eq!(zmq.hashblock, "tcp://127.0.0.1:28332");
+    assert_eq!(zmq.rawtx, None);
+}
+
+#[cfg(feature = "zmq")]
+#[test]
+fn test_zmq_notifications_subscribe() {
+    use std::thread;
+    use std::time::Duration;
+    use std::sync::mpsc;
+
+    use zmq;
+
+    use details::notifications::{BitcoinEvent, ZmqNotificationsConfig};
+
+    let context = zmq::Context::new();
+    let publisher = context.socket(zmq::PUB).unwrap();
+    publisher.bind("tcp://127.0.0.1:*").unwrap();
+    let endpoint = publisher.get_last_endpoint().unwrap().unwrap();
+    let config = ZmqNotificationsConfig {
+        hashblock: endpoint.clone(),
+        rawtx: Some(endpoint),
+    };
+    let events = config.subscribe().unwrap();
+
+    let block_hash = "000000000000000000137a2fdf1a4eb2e2fd7e1c26cda3578da4ad2f2a3ee4f8";
+    let block = Vec::<u8>::from_hex(block_hash).unwrap();
+    let (pub_keys, _) = gen_anchoring_keys(4);
+    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3)
+        .compressed(Network::Testnet)
+        .unwrap();
+    let tx = dummy_anchoring_tx(&redeem_script);
+    let body = serialize(&tx.0).unwrap();
+    // The subscription is established asynchronously, so the first notifications may be lost.
+    let event = loop {
+        publisher.send_multipart([b"hashtx".as_ref(), &block, &[0; 4]], 0).unwrap();
+        publisher.send_multipart([b"hashblock".as_ref(), &block, &[0; 4]], 0).unwrap();
+        match events.recv_timeout(Duration::from_millis(100)) {
+            Ok(event) => break event,
+            Err(mpsc::RecvTimeoutError::Timeout) => thread::sleep(Duration::from_millis(100)),
+            Err(e) => panic!("Subscriber has stopped: {}", e),
+        }
+    };
+    assert_eq!(event, BitcoinEvent::Block(block_hash.to_owned()));
+
+    publisher.send_multipart([b"rawtx".as_ref(), &body, &[1, 0, 0, 0]], 0).unwrap();
+    let event = loop {
+        match events.recv_timeout(Duration::from_secs(5)).unwrap() {
+            BitcoinEvent::Block(_) => {}
+            event => break event,
+        }
+    };
+    assert_eq!(event, BitcoinEvent::Transaction(BitcoinTx::from(tx.0)));
+}
+
+// rpc tests. Works through `rpc` by given env variables or through the simulated
+// bitcoind if they are not set and the `simulated_bitcoin` feature is enabled.
+// See the `anchoring_client` method on top of this module.
 #[cfg(feature = "rpc_tests")]
 mod rpc {
     use super::*;
 
-    use bitcoin::network::constants::Network;
-    use bitcoin::util::base58::ToBase58;
-    use bitcoinrpc;
+    use details::btc::Network;
 
     use exonum::helpers::{self, Height};
     use exonum::crypto::{hash, Hash};
 
-    use details::rpc::{AnchoringRpcConfig, BitcoinRelay, RpcClient};
+    use details::rpc::{AnchoringRpcConfig, BitcoinRelay, Error as RpcError, RpcClient};
     use details::btc::transactions::{AnchoringTx, FundingTx, TransactionBuilder};
     use details::btc;
 
     fn anchoring_client() -> RpcClient {
         use std::env;
-        let rpc = AnchoringRpcConfig {
-            host: env::var("ANCHORING_RELAY_HOST")
-                .expect("Env variable ANCHORING_RELAY_HOST needs to be setted")
-                .parse()
-                .unwrap(),
-            username: env::var("ANCHORING_USER").ok(),
-            password: env::var("ANCHORING_PASSWORD").ok(),
+        let rpc = match env::var("ANCHORING_RELAY_HOST") {
+            Ok(host) => AnchoringRpcConfig {
+                host,
+                username: env::var("ANCHORING_USER").ok(),
+                password: env::var("ANCHORING_PASSWORD").ok(),
+                backend: Default::default(),
+                utxo_discovery: Default::default(),
+                credentials: Default::default(),
+                tls: None,
+            },
+            Err(_) => simulated_rpc_config(),
         };
 
         RpcClient::from(rpc)
     }
 
+    #[cfg(feature = "simulated_bitcoin")]
+    fn simulated_rpc_config() -> AnchoringRpcConfig {
+        use std::mem;
+
+        use details::rpc::{SimulatedBitcoin, SimulatedBitcoind};
+
+        let bitcoind = SimulatedBitcoind::start(SimulatedBitcoin::new()).unwrap();
+        let config = bitcoind.config();
+        // The client outlives this function, so the server has to live until the process exits.
+        // `Listening::close` of hyper 0.10 does not stop the server, so nothing is lost here.
+        mem::forget(bitcoind);
+        config
+    }
+
+    #[cfg(not(feature = "simulated_bitcoin"))]
+    fn simulated_rpc_config() -> AnchoringRpcConfig {
+        panic!("Env variable ANCHORING_RELAY_HOST needs to be setted")
+    }
+
     pub fn create_multisig_address<'a, I>(
         client: &BitcoinRelay,
         network: btc::Network,
         count: u8,
         pub_keys: I,
-    ) -> Result<(btc::RedeemScript, btc::Address), bitcoinrpc::Error>
+    ) -> Result<(btc::RedeemScript, btc::Address), RpcError>
     where
         I: IntoIterator<Item = &'a btc::PublicKey>,
     {
-        let redeem_script = btc::RedeemScript::from_pubkeys(pub_keys, count).compressed(network);
+        let redeem_script = btc::RedeemScript::from_pubkeys(pub_keys, count)
+            .compressed(network)
+            .unwrap();
         let addr = btc::Address::from_script(&redeem_script, network);
 
         client.watch_address(&addr, false)?;
@@ -851,9 +1412,7 @@ mod rpc {
             tx
         };
 
-        let utxos = client
-            .listunspent(0, 9999999, &[addr.to_base58check().as_ref()])
-            .unwrap();
+        let utxos = client.unspent_transactions(&addr).unwrap();
         trace!("utxos={:#?}", utxos);
 
         // Send anchoring txs
@@ -982,9 +1541,7 @@ mod rpc {
             tx
         };
 
-        let utxos = client
-            .listunspent(0, 9999999, &[addr.to_base58check().as_ref()])
-            .unwrap();
+        let utxos = client.unspent_transactions(&addr).unwrap();
         trace!("utxos={:#?}", utxos);
 
         // Send anchoring txs
@@ -0,0 +1,429 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Json rpc transport of the `bitcoind` requests with the credentials, which are read
+//! from the files or the environment, and the optional TLS.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use hyper;
+use hyper::header::{Authorization, Basic, ContentType};
+use hyper::status::StatusCode;
+use jsonrpc;
+use serde::{Deserialize, Serialize};
+use serde_json::{self, Value};
+
+use super::rpc::{AnchoringRpcConfig, Error, Result};
+
+/// Sources of the `bitcoind` rpc credentials which are used instead of the inline
+/// `username` and `password`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RpcCredentials {
+    /// Path to the `.cookie` file of bitcoind with the `username:password` pair.
+    /// The file is read again once it is modified or bitcoind rejects the credentials,
+    /// since bitcoind rotates the cookie on each restart.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cookie_file: Option<PathBuf>,
+    /// Path to the file with the rpc password on its first line, which is read
+    /// on each request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_file: Option<PathBuf>,
+    /// Name of the environment variable with the rpc password.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_env: Option<String>,
+}
+
+/// TLS settings of the `https` rpc endpoint, e.g. bitcoind behind the TLS proxy.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RpcTlsConfig {
+    /// Path to the PEM file with the certificates of the authorities which are trusted
+    /// in addition to the system ones.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_file: Option<PathBuf>,
+    /// Hex encoded SHA-256 fingerprint of the server certificate. If it is set, the server
+    /// is trusted by this certificate only, and neither its chain nor the host name
+    /// are verified.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pinned_cert_sha256: Option<String>,
+}
+
+impl RpcCredentials {
+    /// Returns `true` if the credentials are taken from the inline configuration only.
+    pub fn is_inline(&self) -> bool {
+        *self == RpcCredentials::default()
+    }
+}
+
+/// Content of the bitcoind `.cookie` file.
+#[derive(Debug, Clone)]
+struct Cookie {
+    /// Modification time of the file when it has been read.
+    modified: Option<SystemTime>,
+    username: String,
+    password: String,
+}
+
+/// Transport of the json rpc requests to the `bitcoind` endpoint.
+///
+/// The credentials are resolved for each request in the following order: the cookie file,
+/// the password file, the password environment variable and the inline password.
+/// The `https` hosts require the `tls` feature.
+#[derive(Debug)]
+pub struct RpcTransport {
+    cfg: AnchoringRpcConfig,
+    /// Http client or the reason why it cannot be created.
+    client: ::std::result::Result<hyper::Client, String>,
+    cookie: Mutex<Option<Cookie>>,
+    nonce: Mutex<u64>,
+}
+
+impl RpcTransport {
+    /// Creates the transport to the endpoint of the given configuration.
+    /// The TLS errors are reported by the requests.
+    pub fn new(cfg: AnchoringRpcConfig) -> RpcTransport {
+        let client = if cfg.host.starts_with("https://") {
+            tls::https_client(cfg.tls.clone().unwrap_or_default())
+        } else {
+            Ok(hyper::Client::new())
+        };
+        if let Err(ref e) = client {
+            error!("Unable to set up the rpc transport to {}: {}", cfg.host, e);
+        }
+        RpcTransport {
+            cfg,
+            client,
+            cookie: Mutex::new(None),
+            nonce: Mutex::new(0),
+        }
+    }
+
+    /// Returns the configuration of the transport.
+    pub fn config(&self) -> &AnchoringRpcConfig {
+        &self.cfg
+    }
+
+    /// Sends the request and returns its result.
+    pub fn request<T>(&self, method: &str, params: Vec<Value>) -> Result<T>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        Ok(self.response(method, params)?.into_result()?)
+    }
+
+    /// Sends the request and returns the raw response. If bitcoind rejects the cookie,
+    /// the cookie file is read again and the request is repeated once.
+    pub fn response(&self, method: &str, params: Vec<Value>) -> Result<jsonrpc::Response> {
+        let request = self.build_request(method, params);
+        let response: jsonrpc::Response = self.send(&request)?;
+        if response.id != request.id {
+            return Err(jsonrpc::Error::NonceMismatch.into());
+        }
+        Ok(response)
+    }
+
+    /// Sends the requests as one json rpc batch and returns their raw responses
+    /// in the order of the requests.
+    pub fn batch(&self, requests: Vec<(&str, Vec<Value>)>) -> Result<Vec<jsonrpc::Response>> {
+        if requests.is_empty() {
+            return Ok(Vec::new());
+        }
+        let requests = requests
+            .into_iter()
+            .map(|(method, params)| self.build_request(method, params))
+            .collect::<Vec<_>>();
+        let mut responses: Vec<jsonrpc::Response> = self.send(&requests)?;
+        // The batch responses may come in any order.
+        requests
+            .iter()
+            .map(|request| {
+                match responses.iter().position(|response| response.id == request.id) {
+                    Some(index) => Ok(responses.swap_remove(index)),
+                    None => Err(jsonrpc::Error::NonceMismatch.into()),
+                }
+            })
+            .collect()
+    }
+
+    fn build_request(&self, method: &str, params: Vec<Value>) -> jsonrpc::Request {
+        jsonrpc::Request {
+            method: method.to_owned(),
+            params,
+            id: self.next_nonce().into(),
+        }
+    }
+
+    fn send<R, T>(&self, request: &R) -> Result<T>
+    where
+        R: Serialize,
+        for<'de> T: Deserialize<'de>,
+    {
+        let body = serde_json::to_vec(request).map_err(|e| Error::parse("request", e))?;
+        let mut status = self.post(&body, false)?;
+        if status.0 == StatusCode::Unauthorized && self.cfg.credentials.cookie_file.is_some() {
+            info!("Rpc credentials are rejected, reading the cookie file again");
+            status = self.post(&body, true)?;
+        }
+
+        let (status, body) = status;
+        match serde_json::from_str(&body) {
+            Ok(response) => Ok(response),
+            // Bitcoind reports the rpc errors in the body, so the status matters
+            // only for the other responses.
+            Err(_) if !status.is_success() => {
+                Err(Error::Protocol(format!("Http status {}: {}", status, body)))
+            }
+            Err(e) => Err(Error::parse("response", e)),
+        }
+    }
+
+    fn next_nonce(&self) -> u64 {
+        let mut nonce = self.nonce.lock().unwrap();
+        *nonce += 1;
+        *nonce
+    }
+
+    fn post(&self, body: &[u8], reload_cookie: bool) -> Result<(StatusCode, String)> {
+        let client = match self.client {
+            Ok(ref client) => client,
+            Err(ref e) => {
+                let e = io::Error::new(io::ErrorKind::Other, e.clone());
+                return Err(Error::Transport(hyper::Error::Io(e)));
+            }
+        };
+        let mut request = client.post(&self.cfg.host).header(ContentType::json()).body(body);
+        if let Some(auth) = self.authorization(reload_cookie)? {
+            request = request.header(auth);
+        }
+        let mut response = request.send().map_err(Error::Transport)?;
+        let mut body = String::new();
+        response.read_to_string(&mut body).map_err(|e| {
+            Error::Transport(hyper::Error::Io(e))
+        })?;
+        Ok((response.status, body))
+    }
+
+    fn authorization(&self, reload_cookie: bool) -> Result<Option<Authorization<Basic>>> {
+        let credentials = &self.cfg.credentials;
+        if let Some(ref path) = credentials.cookie_file {
+            let cookie = self.cookie(path, reload_cookie)?;
+            return Ok(Some(Authorization(Basic {
+                username: cookie.username,
+                password: Some(cookie.password),
+            })));
+        }
+
+        let password = if let Some(ref path) = credentials.password_file {
+            let password = read_file(path)?;
+            Some(password.lines().next().unwrap_or_default().to_owned())
+        } else if let Some(ref name) = credentials.password_env {
+            let password = env::var(name).map_err(|e| {
+                Error::Protocol(format!("Unable to read the rpc password from {}: {}", name, e))
+            })?;
+            Some(password)
+        } else {
+            self.cfg.password.clone()
+        };
+        Ok(self.cfg.username.clone().map(|username| {
+            Authorization(Basic { username, password })
+        }))
+    }
+
+    /// Returns the cookie, which is read again if the file has been modified.
+    fn cookie(&self, path: &Path, reload: bool) -> Result<Cookie> {
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+        let mut cookie = self.cookie.lock().unwrap();
+        match *cookie {
+            Some(ref cookie) if !reload && modified.is_some() && cookie.modified == modified => {
+                return Ok(cookie.clone())
+            }
+            _ => {}
+        }
+
+        let content = read_file(path)?;
+        let mut parts = content.trim().splitn(2, ':');
+        let (username, password) = match (parts.next(), parts.next()) {
+            (Some(username), Some(password)) => (username.to_owned(), password.to_owned()),
+            _ => {
+                let msg = format!("Cookie file {} is malformed", path.display());
+                return Err(Error::Protocol(msg));
+            }
+        };
+        let new_cookie = Cookie {
+            modified,
+            username,
+            password,
+        };
+        *cookie = Some(new_cookie.clone());
+        Ok(new_cookie)
+    }
+}
+
+fn read_file(path: &Path) -> Result<String> {
+    let mut content = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut content))
+        .map_err(|e| {
+            Error::Protocol(format!("Unable to read {}: {}", path.display(), e))
+        })?;
+    Ok(content)
+}
+
+/// Parses the hex encoded SHA-256 fingerprint, the bytes may be separated by colons.
+pub fn parse_fingerprint(fingerprint: &str) -> Option<Vec<u8>> {
+    let hex = fingerprint.replace(':', "");
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    (0..32)
+        .map(|i| u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(feature = "tls")]
+mod tls {
+    use std::fmt;
+    use std::io::{self, Read, Write};
+    use std::net::{Shutdown, SocketAddr};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use hyper;
+    use hyper::net::{HttpStream, HttpsConnector, NetworkStream, SslClient};
+    use openssl::hash::MessageDigest;
+    use openssl::ssl::{SslConnector, SslMethod, SslStream, SslVerifyMode};
+
+    use super::{parse_fingerprint, RpcTlsConfig};
+
+    /// Creates the client of the `https` endpoints with the given settings.
+    pub fn https_client(cfg: RpcTlsConfig) -> ::std::result::Result<hyper::Client, String> {
+        let mut builder = SslConnector::builder(SslMethod::tls()).map_err(|e| e.to_string())?;
+        if let Some(ref path) = cfg.ca_file {
+            builder.set_ca_file(path).map_err(|e| {
+                format!("Unable to load {}: {}", path.display(), e)
+            })?;
+        }
+        let pinned = match cfg.pinned_cert_sha256 {
+            Some(ref fingerprint) => {
+                let pin = parse_fingerprint(fingerprint).ok_or_else(|| {
+                    format!("Malformed certificate fingerprint {}", fingerprint)
+                })?;
+                builder.set_verify_callback(SslVerifyMode::PEER, move |_, ctx| {
+                    // The chain is not verified, only the server certificate is compared.
+                    if ctx.error_depth() > 0 {
+                        return true;
+                    }
+                    ctx.current_cert()
+                        .and_then(|cert| cert.digest(MessageDigest::sha256()).ok())
+                        .map_or(false, |digest| *digest == *pin)
+                });
+                true
+            }
+            None => false,
+        };
+        let ssl = OpensslClient {
+            connector: builder.build(),
+            pinned,
+        };
+        Ok(hyper::Client::with_connector(HttpsConnector::new(ssl)))
+    }
+
+    /// `hyper` adapter of the `openssl` connector.
+    struct OpensslClient {
+        connector: SslConnector,
+        /// Whether the server certificate is pinned, so the host name is not verified.
+        pinned: bool,
+    }
+
+    impl SslClient for OpensslClient {
+        type Stream = TlsStream;
+
+        fn wrap_client(&self, stream: HttpStream, host: &str) -> hyper::Result<TlsStream> {
+            let mut config = self.connector.configure().map_err(ssl_error)?;
+            config.set_verify_hostname(!self.pinned);
+            let stream = config.connect(host, stream).map_err(|e| {
+                hyper::Error::Ssl(Box::new(io::Error::new(
+                    io::ErrorKind::Other,
+                    e.to_string(),
+                )))
+            })?;
+            Ok(TlsStream(Arc::new(Mutex::new(stream))))
+        }
+    }
+
+    /// TLS stream which is shared between the clones as `hyper` requires.
+    #[derive(Clone)]
+    struct TlsStream(Arc<Mutex<SslStream<HttpStream>>>);
+
+    impl fmt::Debug for TlsStream {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("TlsStream")
+        }
+    }
+
+    impl Read for TlsStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().read(buf)
+        }
+    }
+
+    impl Write for TlsStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.0.lock().unwrap().flush()
+        }
+    }
+
+    impl NetworkStream for TlsStream {
+        fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+            self.0.lock().unwrap().get_mut().peer_addr()
+        }
+
+        fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+            self.0.lock().unwrap().get_ref().set_read_timeout(dur)
+        }
+
+        fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+            self.0.lock().unwrap().get_ref().set_write_timeout(dur)
+        }
+
+        fn close(&mut self, how: Shutdown) -> io::Result<()> {
+            self.0.lock().unwrap().get_mut().close(how)
+        }
+    }
+
+    fn ssl_error<E: ::std::error::Error + Send + Sync + 'static>(e: E) -> hyper::Error {
+        hyper::Error::Ssl(Box::new(e))
+    }
+}
+
+#[cfg(not(feature = "tls"))]
+mod tls {
+    use hyper;
+
+    use super::RpcTlsConfig;
+
+    /// Creates the client of the `https` endpoints, which is impossible
+    /// without the `tls` feature.
+    pub fn https_client(_: RpcTlsConfig) -> ::std::result::Result<hyper::Client, String> {
+        Err("Https rpc endpoints require the `tls` feature".to_owned())
+    }
+}
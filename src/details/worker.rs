@@ -0,0 +1,81 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Worker thread which handles the committed blocks instead of the commit hook of the node,
+//! so that the slow bitcoin relay never delays the processing of the next blocks.
+
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use exonum::blockchain::{ApiContext, Blockchain, ServiceContext};
+use exonum::crypto::{PublicKey, SecretKey};
+use exonum::helpers::Height;
+use exonum::node::ApiSender;
+
+use handler::AnchoringHandler;
+use service::handle_commit;
+
+/// Handles the commits queued by the anchoring service in the dedicated thread.
+///
+/// The worker owns the handler while it handles a commit, so the relay requests are sent
+/// from the worker thread, and the resulting signatures and lects are sent back to the node
+/// through its transaction sender as the other incoming transactions.
+#[derive(Debug)]
+pub struct CommitWorker {
+    handler: Arc<Mutex<AnchoringHandler>>,
+    blockchain: Blockchain,
+    public_key: PublicKey,
+    secret_key: SecretKey,
+    sender: ApiSender,
+}
+
+impl CommitWorker {
+    /// Creates the worker for the given `handler` with the blockchain, service keys
+    /// and the transaction sender of the api `context`.
+    pub fn new(handler: Arc<Mutex<AnchoringHandler>>, context: &ApiContext) -> CommitWorker {
+        CommitWorker {
+            handler,
+            blockchain: context.blockchain().clone(),
+            public_key: *context.public_key(),
+            secret_key: context.secret_key().clone(),
+            sender: context.node_channel().clone(),
+        }
+    }
+
+    /// Spawns the worker thread and returns the sender of the commit events to it.
+    /// The thread stops when the sender is dropped.
+    pub fn spawn(self) -> mpsc::Sender<Height> {
+        let (sender, commits) = mpsc::channel();
+        thread::spawn(move || self.run(&commits));
+        sender
+    }
+
+    fn run(&self, commits: &mpsc::Receiver<Height>) {
+        while let Ok(height) = commits.recv() {
+            // The handler checks the latest state, so the commits queued while the previous
+            // one has been handled are outdated.
+            let height = commits.try_iter().last().unwrap_or(height);
+            trace!("Handle commit of the block at height {}", height);
+            let context = ServiceContext::new(
+                self.public_key,
+                self.secret_key.clone(),
+                self.sender.clone(),
+                self.blockchain.fork(),
+            );
+            let mut handler = self.handler.lock().unwrap();
+            handle_commit(&mut handler, &context);
+        }
+        trace!("Commit worker has stopped");
+    }
+}
@@ -16,7 +16,7 @@ use std::io;
 
 pub use details::error::Error as InternalError;
 pub use handler::error::Error as HandlerError;
-use bitcoinrpc::Error as RpcError;
+use details::rpc::Error as RpcError;
 
 /// Anchoring btc service Error type.
 #[derive(Debug, Error)]
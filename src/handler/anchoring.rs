@@ -12,16 +12,19 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use bitcoin::util::base58::ToBase58;
+
+use std::cmp;
 
 use exonum::blockchain::{Schema, ServiceContext};
+use exonum::crypto::Hash;
 use exonum::helpers::Height;
 use exonum::encoding::serialize::encode_hex;
 
 use error::Error as ServiceError;
 use details::btc;
 use details::btc::HexValueEx;
-use details::btc::transactions::{AnchoringTx, TransactionBuilder};
+use details::btc::payload;
+use details::btc::transactions::{AnchoringTx, TransactionBuilder, TxKind, DUST_LIMIT};
 use blockchain::consensus_storage::AnchoringConfig;
 use blockchain::schema::AnchoringSchema;
 use blockchain::dto::{MsgAnchoringSignature, MsgAnchoringUpdateLatest};
@@ -36,11 +39,12 @@ impl AnchoringHandler {
         context: &ServiceContext,
     ) -> Result<(), ServiceError> {
         let multisig = self.multisig_address(cfg);
-        trace!("Anchoring state, addr={}", multisig.addr.to_base58check());
+        trace!("Anchoring state, addr={}", multisig.addr.to_string());
 
-        if context.height().0 % self.node.check_lect_frequency == 0 {
+        if self.is_lect_check_due(context.height()) {
             // First of all we try to update our lect and actual configuration
             self.update_our_lect(&multisig, context)?;
+            self.update_our_fee_rate(&multisig, context)?;
         }
         // Now if we have anchoring tx proposal we must try to finalize it
         if let Some(proposal) = self.proposal_tx.clone() {
@@ -72,7 +76,8 @@ impl AnchoringHandler {
                 if latest_anchored_height > anchored_height {
                     return self.create_proposal_tx(&tx, multisig, latest_anchored_height, context);
                 }
-                Ok(())
+                // Replace the lect if it got stuck in the bitcoin mempool
+                self.try_bump_lect_fee(multisig, context)
             }
             LectKind::None => {
                 warn!("Unable to reach consensus in the lect");
@@ -89,18 +94,29 @@ impl AnchoringHandler {
         context: &ServiceContext,
     ) -> Result<(), ServiceError> {
         trace!("Create tx chain");
-        if let Some(funding_tx) = self.avaliable_funding_tx(multisig)? {
+        let funds = self.available_funds(multisig)?;
+        if let Some((funding_tx, out)) = funds.first().cloned() {
             // Create anchoring proposal
             let height = multisig.common.latest_anchoring_height(context.height());
             let hash = Schema::new(context.snapshot())
                 .block_hashes_by_height()
                 .get(height.0)
                 .unwrap();
+            // The new chain commits to all blocks since the genesis one
+            let blocks_root = match prev_tx_chain {
+                Some(_) => None,
+                None => payload_blocks_root(multisig.common, Height::zero(), height, context),
+            };
 
-            let out = funding_tx.find_out(&multisig.addr).unwrap();
-            let proposal = TransactionBuilder::with_prev_tx(&funding_tx, out)
-                .fee(multisig.common.fee)
+            let mut builder = TransactionBuilder::with_prev_tx(&funding_tx, out);
+            for &(ref funds, out) in &funds[1..] {
+                builder = builder.add_funds(funds, out);
+            }
+            let proposal = apply_fee_policy(builder, multisig, context)
                 .payload(height, hash)
+                .network_id(payload_network_id(multisig.common, context))
+                .state_hash(payload_state_hash(multisig.common, &hash, context))
+                .blocks_root(blocks_root)
                 .prev_tx_chain(prev_tx_chain)
                 .send_to(multisig.addr.clone())
                 .into_transaction()?;
@@ -131,16 +147,22 @@ impl AnchoringHandler {
             .get(height.0)
             .unwrap();
 
+        let blocks_root = payload_blocks_root(
+            multisig.common,
+            lect.payload().block_height.next(),
+            height,
+            context,
+        );
+
         let proposal = {
-            let mut builder = TransactionBuilder::with_prev_tx(lect, 0)
-                .fee(multisig.common.fee)
+            let builder = TransactionBuilder::with_prev_tx(lect, 0);
+            let mut builder = apply_fee_policy(builder, multisig, context)
                 .payload(height, hash)
+                .network_id(payload_network_id(multisig.common, context))
+                .state_hash(payload_state_hash(multisig.common, &hash, context))
+                .blocks_root(blocks_root)
                 .send_to(multisig.addr.clone());
-            if let Some(funds) = self.avaliable_funding_tx(multisig)? {
-                let out = funds.find_out(&multisig.addr).expect(
-                    "Funding tx has proper \
-                     multisig output",
-                );
+            for (funds, out) in self.available_funds(multisig)? {
                 builder = builder.add_funds(&funds, out);
             }
             builder.into_transaction()?
@@ -156,6 +178,113 @@ impl AnchoringHandler {
         self.sign_proposal_tx(proposal, multisig, context)
     }
 
+    // Create proposal that replaces the stuck lect with the doubled fee.
+    // All validators replace the same agreed lect, and the replacement is deterministic,
+    // so it gets the majority of signatures once the lect is stuck for the majority.
+    pub fn try_bump_lect_fee(
+        &mut self,
+        multisig: &MultisigAddress,
+        context: &ServiceContext,
+    ) -> Result<(), ServiceError> {
+        let timeout = match multisig.common.fee_bump_timeout {
+            Some(timeout) => timeout,
+            None => return Ok(()),
+        };
+
+        let lect = {
+            let anchoring_schema = AnchoringSchema::new(context.snapshot());
+            match anchoring_schema.collect_lects(multisig.common).map(TxKind::from) {
+                Some(TxKind::Anchoring(tx)) => tx,
+                _ => return Ok(()),
+            }
+        };
+        // The confirmed lect cannot be replaced.
+        let entry_height = match self.client().get_mempool_entry_height(lect.id())? {
+            Some(height) => height,
+            None => return Ok(()),
+        };
+        let unconfirmed_blocks = self.client()
+            .get_block_count()?
+            .saturating_sub(entry_height);
+        if unconfirmed_blocks < timeout {
+            return Ok(());
+        }
+
+        trace!(
+            "Bump fee for lect={:#?}, unconfirmed_blocks={}",
+            lect,
+            unconfirmed_blocks
+        );
+        self.create_replacement_tx(&lect, multisig, context)
+    }
+
+    pub fn create_replacement_tx(
+        &mut self,
+        lect: &AnchoringTx,
+        multisig: &MultisigAddress,
+        context: &ServiceContext,
+    ) -> Result<(), ServiceError> {
+        let mut spent_txs = Vec::new();
+        {
+            let anchoring_schema = AnchoringSchema::new(context.snapshot());
+            for input in lect.inputs() {
+                if let Some(tx) = anchoring_schema.spent_tx(lect, input) {
+                    spent_txs.push((tx, lect.input[input as usize].prev_index));
+                } else {
+                    warn!(
+                        "Unable to find spent tx for input={} of lect={:#?}",
+                        input,
+                        lect
+                    );
+                    return Ok(());
+                }
+            }
+        }
+        let available_funds: u64 = spent_txs
+            .iter()
+            .map(|&(ref tx, out)| tx.output[out as usize].value)
+            .sum();
+        let fee = available_funds - lect.amount();
+        // The anchoring output below the dust limit is non-standard.
+        let max_fee = available_funds.saturating_sub(DUST_LIMIT);
+        let bumped_fee = cmp::min(fee.saturating_mul(2), max_fee);
+        if bumped_fee <= fee {
+            warn!(
+                "Unable to bump fee for lect={:#?}, fee={}, available_funds={}",
+                lect,
+                fee,
+                available_funds
+            );
+            return Ok(());
+        }
+
+        // The replacement anchors the same block, so it depends only on the lect.
+        let payload = lect.payload();
+        let proposal = {
+            let mut builder = TransactionBuilder::with_prev_tx(&spent_txs[0].0, spent_txs[0].1);
+            for &(ref tx, out) in &spent_txs[1..] {
+                builder = builder.add_funds(tx, out);
+            }
+            builder
+                .fee(bumped_fee)
+                .payload(payload.block_height, payload.block_hash)
+                .network_id(payload.network_id)
+                .state_hash(payload.state_hash)
+                .blocks_root(payload.blocks_root)
+                .prev_tx_chain(payload.prev_tx_chain)
+                .send_to(multisig.addr.clone())
+                .into_transaction()?
+        };
+
+        info!(
+            "BUMP FEE ====== txid={}, replaced_txid={}, fee={}",
+            proposal.txid(),
+            lect.txid(),
+            bumped_fee
+        );
+        self.sign_proposal_tx(proposal, multisig, context)
+    }
+
     pub fn sign_proposal_tx(
         &mut self,
         proposal: AnchoringTx,
@@ -163,7 +292,28 @@ impl AnchoringHandler {
         context: &ServiceContext,
     ) -> Result<(), ServiceError> {
         for input in proposal.inputs() {
-            let signature = proposal.sign_input(&multisig.redeem_script, input, &multisig.priv_key);
+            let signature = if multisig.common.address_type.is_witness() {
+                let anchoring_schema = AnchoringSchema::new(context.snapshot());
+                let value = match anchoring_schema.spent_output_value(&proposal, input) {
+                    Some(value) => value,
+                    None => {
+                        warn!(
+                            "Unable to find spent output for input={} of proposal={:#?}",
+                            input,
+                            proposal
+                        );
+                        return Ok(());
+                    }
+                };
+                proposal.sign_witness_input(
+                    &multisig.redeem_script,
+                    input,
+                    value,
+                    &multisig.priv_key,
+                )
+            } else {
+                proposal.sign_input(&multisig.redeem_script, input, &multisig.priv_key)
+            };
 
             let sign_msg = MsgAnchoringSignature::new(
                 context.public_key(),
@@ -212,7 +362,11 @@ impl AnchoringHandler {
             collect_signatures(&proposal, multisig.common, &signatures)
         };
         if let Some(signatures) = collected_signatures {
-            let new_lect = proposal.finalize(&multisig.redeem_script, signatures);
+            let new_lect = proposal.finalize_with_type(
+                multisig.common.address_type,
+                &multisig.redeem_script,
+                signatures,
+            );
             // Send transaction if it needs
             if self.client().get_transaction(new_lect.id())?.is_none() {
                 self.client().send_transaction(new_lect.clone().into())?;
@@ -221,7 +375,7 @@ impl AnchoringHandler {
                     new_lect,
                     new_lect
                         .output_address(multisig.common.network)
-                        .to_base58check()
+                        .to_string()
                 );
             }
 
@@ -259,3 +413,69 @@ impl AnchoringHandler {
         Ok(())
     }
 }
+
+/// Sets the fee of the anchoring transaction according to the actual fee policy.
+fn apply_fee_policy(
+    builder: TransactionBuilder,
+    multisig: &MultisigAddress,
+    context: &ServiceContext,
+) -> TransactionBuilder {
+    let fee_rate = AnchoringSchema::new(context.snapshot()).fee_rate(multisig.common);
+    if let Some(sat_per_vbyte) = fee_rate {
+        builder.fee_rate(
+            sat_per_vbyte,
+            &multisig.redeem_script,
+            multisig.common.address_type,
+        )
+    } else {
+        builder.fee(multisig.common.fee)
+    }
+}
+
+// Returns the network identifier to be committed by the payload if the config requires it.
+fn payload_network_id(cfg: &AnchoringConfig, context: &ServiceContext) -> Option<u32> {
+    match cfg.payload_version {
+        btc::PayloadVersion::V1 => None,
+        btc::PayloadVersion::V2 => {
+            let genesis_hash = Schema::new(context.snapshot())
+                .block_hashes_by_height()
+                .get(0)
+                .expect("Genesis block is always committed before the service handles commits");
+            Some(payload::network_id(&genesis_hash))
+        }
+    }
+}
+
+// Returns the state hash at the anchored block to be committed by the payload
+// if the config requires it.
+fn payload_state_hash(
+    cfg: &AnchoringConfig,
+    block_hash: &Hash,
+    context: &ServiceContext,
+) -> Option<Hash> {
+    match cfg.payload_version {
+        btc::PayloadVersion::V1 => None,
+        btc::PayloadVersion::V2 => {
+            let block = Schema::new(context.snapshot())
+                .blocks()
+                .get(block_hash)
+                .expect("Anchored block is committed to the blockchain");
+            Some(*block.state_hash())
+        }
+    }
+}
+
+// Returns the root of the blocks in the range `from..=to` if the config requires
+// the payload to commit to it.
+fn payload_blocks_root(
+    cfg: &AnchoringConfig,
+    from: Height,
+    to: Height,
+    context: &ServiceContext,
+) -> Option<Hash> {
+    if cfg.commit_blocks_root {
+        Some(AnchoringSchema::new(context.snapshot()).blocks_root(from, to))
+    } else {
+        None
+    }
+}
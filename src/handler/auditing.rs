@@ -12,7 +12,6 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use bitcoin::util::base58::ToBase58;
 
 use exonum::blockchain::ServiceContext;
 
@@ -32,7 +31,7 @@ impl AnchoringHandler {
         state: &ServiceContext,
     ) -> Result<(), ServiceError> {
         trace!("Auditing state");
-        if state.height().0 % self.node.check_lect_frequency == 0 {
+        if self.is_lect_check_due(state.height()) {
             let r = match self.collect_lects(state)? {
                 LectKind::Funding(tx) => self.check_funding_lect(tx, state),
                 LectKind::Anchoring(tx) => self.check_anchoring_lect(&tx),
@@ -66,7 +65,7 @@ impl AnchoringHandler {
             let e = HandlerError::IncorrectLect {
                 reason: format!(
                     "Initial funding_tx has no outputs with address={}",
-                    addr.to_base58check()
+                    addr.to_string()
                 ),
                 tx: tx.into(),
             };
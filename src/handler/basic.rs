@@ -13,9 +13,9 @@
 // limitations under the License.
 
 use std::collections::HashSet;
+use std::str::FromStr;
 use std::sync::mpsc;
 
-use bitcoin::util::base58::ToBase58;
 
 use exonum::blockchain::ServiceContext;
 use exonum::storage::Snapshot;
@@ -23,13 +23,14 @@ use exonum::helpers::{Height, ValidatorId};
 
 use error::Error as ServiceError;
 use handler::error::Error as HandlerError;
-use details::rpc::BitcoinRelay;
+use details::rpc::{has_unspent_output, BitcoinRelay};
+use details::notifications::BitcoinEvent;
 use details::btc;
 use details::btc::transactions::{AnchoringTx, BitcoinTx, FundingTx, TxKind};
 use local_storage::AnchoringNodeConfig;
-use blockchain::consensus_storage::AnchoringConfig;
+use blockchain::consensus_storage::{AnchoringConfig, FeePolicy};
 use blockchain::schema::AnchoringSchema;
-use blockchain::dto::MsgAnchoringUpdateLatest;
+use blockchain::dto::{MsgAnchoringFeeRate, MsgAnchoringUpdateLatest};
 
 use super::{AnchoringHandler, AnchoringState, LectKind, MultisigAddress};
 
@@ -42,6 +43,8 @@ impl AnchoringHandler {
             proposal_tx: None,
             known_addresses: HashSet::new(),
             errors_sink: None,
+            bitcoin_events: None,
+            lect_check_requested: false,
         }
     }
 
@@ -76,7 +79,7 @@ impl AnchoringHandler {
     #[doc(hidden)]
     pub fn multisig_address<'a>(&self, common: &'a AnchoringConfig) -> MultisigAddress<'a> {
         let (redeem_script, addr) = common.redeem_script();
-        let addr_str = addr.to_base58check();
+        let addr_str = addr.to_string();
         let priv_key = self.node
             .private_keys
             .get(&addr_str)
@@ -94,7 +97,10 @@ impl AnchoringHandler {
     pub fn import_address(&mut self, addr: &btc::Address) -> Result<(), ServiceError> {
         let addr_str = addr.to_string();
         if !self.known_addresses.contains(&addr_str) {
-            self.client().watch_address(addr, false)?;
+            // The tracked outputs are checked one by one without the wallet.
+            if !self.node.chain_utxo_tracking {
+                self.client().watch_address(addr, false)?;
+            }
 
             trace!("Add address to known, addr={}", addr_str);
             self.known_addresses.insert(addr_str);
@@ -105,7 +111,7 @@ impl AnchoringHandler {
     /// Adds a `private_key` for the corresponding anchoring `address`.
     pub fn add_private_key(&mut self, address: &btc::Address, private_key: btc::PrivateKey) {
         self.node.private_keys.insert(
-            address.to_base58check(),
+            address.to_string(),
             private_key,
         );
     }
@@ -115,6 +121,77 @@ impl AnchoringHandler {
         self.errors_sink = sink;
     }
 
+    #[doc(hidden)]
+    pub fn report_error(&self, e: HandlerError) {
+        error!("An error in handler occured: {}", e);
+        if let Some(sink) = self.errors_sink.as_ref() {
+            let res = sink.send(e);
+            if let Err(err) = res {
+                error!("Can't send error to channel: {}", err);
+            }
+        }
+    }
+
+    /// Sets the receiver of the bitcoin network events, which trigger the lect checks
+    /// in addition to the ones each `check_lect_frequency` blocks.
+    pub fn set_bitcoin_events(&mut self, events: Option<mpsc::Receiver<BitcoinEvent>>) {
+        self.bitcoin_events = events;
+    }
+
+    #[doc(hidden)]
+    pub fn receive_bitcoin_events(&mut self) {
+        let mut known_scripts = None;
+        loop {
+            let event = match self.bitcoin_events.as_ref().map(|events| events.try_recv()) {
+                Some(Ok(event)) => event,
+                Some(Err(mpsc::TryRecvError::Disconnected)) => {
+                    warn!("Bitcoin events have stopped, lect is checked by polling only");
+                    self.bitcoin_events = None;
+                    return;
+                }
+                Some(Err(mpsc::TryRecvError::Empty)) | None => return,
+            };
+            let is_relevant = match event {
+                BitcoinEvent::Block(hash) => {
+                    trace!("Received bitcoin block, hash={}", hash);
+                    true
+                }
+                // Both funding and anchoring transactions pay to the anchoring address.
+                BitcoinEvent::Transaction(tx) => {
+                    let known_scripts = known_scripts.get_or_insert_with(|| {
+                        self.known_addresses
+                            .iter()
+                            .filter_map(|addr| btc::Address::from_str(addr).ok())
+                            .map(|addr| addr.script_pubkey())
+                            .collect::<Vec<_>>()
+                    });
+                    tx.output.iter().any(|output| {
+                        known_scripts.contains(&output.script_pubkey)
+                    })
+                }
+            };
+            if is_relevant {
+                self.lect_check_requested = true;
+            }
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn is_lect_check_due(&self, height: Height) -> bool {
+        self.lect_check_requested || height.0 % self.node.check_lect_frequency == 0
+    }
+
+    #[doc(hidden)]
+    pub fn report_relay_disagreements(&self) {
+        let disagreements = match self.client.as_ref() {
+            Some(client) => client.take_disagreements(),
+            None => return,
+        };
+        for disagreement in disagreements {
+            self.report_error(HandlerError::RelayDisagreement(disagreement));
+        }
+    }
+
     #[doc(hidden)]
     pub fn actual_config(&self, state: &ServiceContext) -> Result<AnchoringConfig, ServiceError> {
         let schema = AnchoringSchema::new(state.snapshot());
@@ -281,7 +358,11 @@ impl AnchoringHandler {
 
     #[doc(hidden)]
     pub fn handle_commit(&mut self, state: &ServiceContext) -> Result<(), ServiceError> {
-        match self.current_state(state)? {
+        if let Some(ref client) = self.client {
+            client.begin_check();
+        }
+        self.receive_bitcoin_events();
+        let result = match self.current_state(state)? {
             AnchoringState::Anchoring { cfg } => self.handle_anchoring_state(&cfg, state),
             AnchoringState::Transition { from, to } => {
                 self.handle_transition_state(&from, &to, state)
@@ -296,7 +377,12 @@ impl AnchoringHandler {
             } => self.handle_waiting_state(lect, confirmations),
             AnchoringState::Auditing { cfg } => self.handle_auditing_state(&cfg, state),
             AnchoringState::Broken => panic!("Broken anchoring state detected!"),
+        };
+        // The requested check is repeated on the next commit if it has failed.
+        if result.is_ok() {
+            self.lect_check_requested = false;
         }
+        result
     }
 
     #[doc(hidden)]
@@ -366,10 +452,15 @@ impl AnchoringHandler {
         multisig: &MultisigAddress,
         state: &ServiceContext,
     ) -> Result<Option<BitcoinTx>, ServiceError> {
-        let lects = self.client()
-            .unspent_transactions(&multisig.addr)?
-            .into_iter()
-            .map(|tx| tx.body);
+        let lects = if self.node.chain_utxo_tracking {
+            self.tracked_unspent_lects(multisig, state)?
+        } else {
+            self.client()
+                .unspent_transactions(&multisig.addr)?
+                .into_iter()
+                .map(|tx| tx.body)
+                .collect()
+        };
         for lect in lects {
             if self.transaction_is_lect(&lect, multisig, state)? {
                 return Ok(Some(lect));
@@ -406,34 +497,138 @@ impl AnchoringHandler {
     }
 
     #[doc(hidden)]
-    pub fn avaliable_funding_tx(
+    pub fn update_our_fee_rate(
+        &mut self,
+        multisig: &MultisigAddress,
+        state: &ServiceContext,
+    ) -> Result<Option<u64>, ServiceError> {
+        let (conf_target, min_fee_rate, max_fee_rate) = match multisig.common.fee_policy {
+            FeePolicy::Estimate {
+                conf_target,
+                min_sat_per_vbyte,
+                max_sat_per_vbyte,
+            } => (conf_target, min_sat_per_vbyte, max_sat_per_vbyte),
+            _ => return Ok(None),
+        };
+
+        trace!("Update our fee rate");
+        // Estimations beyond the policy limits are rejected by the other validators.
+        let fee_rate = self.client()
+            .estimate_fee_rate(conf_target)?
+            .map(|fee_rate| fee_rate.max(min_fee_rate).min(max_fee_rate));
+        if let Some(fee_rate) = fee_rate {
+            let key = self.anchoring_key(multisig.common, state);
+            let our_fee_rate = AnchoringSchema::new(state.snapshot()).fee_rates().get(key);
+            if Some(fee_rate) != our_fee_rate {
+                info!("FEE RATE ====== sat_per_vbyte={}", fee_rate);
+                let fee_rate_msg = MsgAnchoringFeeRate::new(
+                    state.public_key(),
+                    self.validator_id(state),
+                    fee_rate,
+                    state.secret_key(),
+                );
+                state.transaction_sender().send(Box::new(fee_rate_msg))?;
+            }
+        } else {
+            warn!("Unable to estimate fee rate for conf_target={}", conf_target);
+        }
+        Ok(fee_rate)
+    }
+
+    /// Lists the known lects with the unspent outputs to the anchoring address without
+    /// the relay's wallet. These are the actual lects of the validators and the latest of
+    /// our lects which has not disappeared from the bitcoin network.
+    #[doc(hidden)]
+    pub fn tracked_unspent_lects(
         &self,
         multisig: &MultisigAddress,
-    ) -> Result<Option<FundingTx>, ServiceError> {
-        let funding_tx = multisig.common.funding_tx();
-        // Do not need to check funding_tx to the different address.
-        if funding_tx.find_out(&multisig.addr).is_none() {
-            return Ok(None);
+        state: &ServiceContext,
+    ) -> Result<Vec<BitcoinTx>, ServiceError> {
+        let schema = AnchoringSchema::new(state.snapshot());
+        let mut unspent: Vec<BitcoinTx> = Vec::new();
+        for key in &multisig.common.anchoring_keys {
+            if let Some(lect) = schema.lect(key) {
+                if unspent.iter().all(|tx| tx.id() != lect.id()) &&
+                    self.has_unspent_output(&lect, &multisig.addr)? == Some(true)
+                {
+                    unspent.push(lect);
+                }
+            }
+        }
+
+        // Our lects are checked from the latest one while they are unknown to the bitcoin
+        // network, since the outputs of the disappeared lect become unspent again.
+        let lects = schema.lects(self.anchoring_key(multisig.common, state));
+        for idx in (0..lects.len()).rev() {
+            let lect = lects.get(idx).unwrap().tx();
+            match self.has_unspent_output(&lect, &multisig.addr)? {
+                Some(true) => {
+                    if unspent.iter().all(|tx| tx.id() != lect.id()) {
+                        unspent.push(lect);
+                    }
+                    break;
+                }
+                Some(false) if self.client().get_transaction(lect.id())?.is_none() => {}
+                _ => break,
+            }
+        }
+        Ok(unspent)
+    }
+
+    /// Checks the outputs of the transaction to the given address by the relay.
+    /// Returns `None` if there are no such outputs.
+    #[doc(hidden)]
+    pub fn has_unspent_output(
+        &self,
+        tx: &BitcoinTx,
+        addr: &btc::Address,
+    ) -> Result<Option<bool>, ServiceError> {
+        Ok(has_unspent_output(self.client(), tx, addr)?)
+    }
+
+    /// Returns unspent and confirmed outputs of the funding transactions to the anchoring address.
+    #[doc(hidden)]
+    pub fn available_funds(
+        &self,
+        multisig: &MultisigAddress,
+    ) -> Result<Vec<(FundingTx, u32)>, ServiceError> {
+        let outpoints = multisig.common.funding_outpoints(&multisig.addr);
+        // Do not need to check funding txs to the different address.
+        if outpoints.is_empty() {
+            return Ok(Vec::new());
         }
 
         trace!(
-            "Checking funding_tx={:#?}, addr={} availability",
-            funding_tx,
-            multisig.addr.to_base58check()
+            "Checking funding outpoints={:#?}, addr={} availability",
+            outpoints,
+            multisig.addr.to_string()
         );
-        if let Some(info) = self.client()
-            .unspent_transactions(&multisig.addr)?
-            .iter()
-            .find(|tx| tx.body.0 == funding_tx.0)
-        {
-            trace!(
-                "avaliable_funding_tx={:#?}, confirmations={:?}",
-                funding_tx,
-                info.confirmations
-            );
-            return Ok(Some(funding_tx.clone()));
-        }
-        Ok(None)
+        let funds = if self.node.chain_utxo_tracking {
+            let outs = outpoints
+                .iter()
+                .map(|&(ref funding_tx, out)| (funding_tx.id(), out))
+                .collect::<Vec<_>>();
+            let confirmations = self.client().get_tx_outs_confirmations(&outs)?;
+            outpoints
+                .into_iter()
+                .zip(confirmations)
+                .filter(|&(_, confirmations)| confirmations.unwrap_or(0) > 0)
+                .map(|(outpoint, _)| outpoint)
+                .collect::<Vec<_>>()
+        } else {
+            let unspent_txs = self.client().unspent_transactions(&multisig.addr)?;
+            outpoints
+                .into_iter()
+                .filter(|&(ref funding_tx, out)| {
+                    unspent_txs.iter().any(|info| {
+                        info.body.0 == funding_tx.0 && info.vout == Some(out) &&
+                            info.confirmations.unwrap_or(0) > 0
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+        trace!("available_funds={:#?}", funds);
+        Ok(funds)
     }
 
     #[doc(hidden)]
@@ -18,11 +18,13 @@ use std::error;
 use exonum::helpers::Height;
 
 use details::btc::transactions::BitcoinTx;
+use details::rpc::RelayDisagreement;
 
 #[derive(Debug, PartialEq)]
 pub enum Error {
     IncorrectLect { reason: String, tx: BitcoinTx },
     LectNotFound { height: Height },
+    RelayDisagreement(RelayDisagreement),
 }
 
 impl fmt::Display for Error {
@@ -34,6 +36,9 @@ impl fmt::Display for Error {
             Error::LectNotFound { height } => {
                 write!(f, "Suitable lect not found for height={}", height)
             }
+            Error::RelayDisagreement(ref disagreement) => {
+                write!(f, "Relay backends disagree on {}", disagreement)
+            }
         }
     }
 }
@@ -43,6 +48,7 @@ impl error::Error for Error {
         match *self {
             Error::IncorrectLect { .. } => "Incorrect lect",
             Error::LectNotFound { .. } => "Suitable lect not found",
+            Error::RelayDisagreement(..) => "Relay backends disagree",
         }
     }
 
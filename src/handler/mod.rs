@@ -25,6 +25,7 @@ use std::collections::HashSet;
 use std::sync::mpsc;
 
 use details::rpc::BitcoinRelay;
+use details::notifications::BitcoinEvent;
 use details::btc;
 use details::btc::transactions::{AnchoringTx, BitcoinTx, FundingTx};
 use local_storage::AnchoringNodeConfig;
@@ -44,6 +45,10 @@ pub struct AnchoringHandler {
     pub errors_sink: Option<mpsc::Sender<error::Error>>,
     #[doc(hidden)]
     pub known_addresses: HashSet<String>,
+    #[doc(hidden)]
+    pub bitcoin_events: Option<mpsc::Receiver<BitcoinEvent>>,
+    #[doc(hidden)]
+    pub lect_check_requested: bool,
 }
 
 #[doc(hidden)]
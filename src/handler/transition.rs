@@ -12,7 +12,6 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use bitcoin::util::base58::ToBase58;
 
 use exonum::blockchain::ServiceContext;
 
@@ -38,12 +37,12 @@ impl AnchoringHandler {
         };
         trace!(
             "Transition state, addr={}, following_config={:#?}",
-            multisig.addr.to_base58check(),
+            multisig.addr.to_string(),
             to
         );
 
         // Similar we update lect each n blocks
-        if state.height().0 % self.node.check_lect_frequency == 0 {
+        if self.is_lect_check_due(state.height()) {
             // First of all we try to update our lect and actual configuration
             self.update_our_lect(&multisig, state)?;
         }
@@ -112,14 +111,14 @@ impl AnchoringHandler {
     ) -> Result<(), ServiceError> {
         let multisig: MultisigAddress = self.multisig_address(actual_cfg);
 
-        if state.height().0 % self.node.check_lect_frequency == 0 {
+        if self.is_lect_check_due(state.height()) {
             // First of all we try to update our lect and actual configuration
             self.update_our_lect(&multisig, state)?;
         }
 
         trace!(
             "Starting a new tx chain to addr={} from scratch",
-            multisig.addr.to_base58check()
+            multisig.addr.to_string()
         );
 
         let lect_txid = {
@@ -59,11 +59,15 @@
 
 #![deny(missing_docs, missing_debug_implementations)]
 
+extern crate bech32;
 extern crate bitcoin;
 extern crate byteorder;
+extern crate clap;
 #[macro_use]
 extern crate derive_error;
 extern crate exonum_bitcoinrpc as bitcoinrpc;
+extern crate exonum_jsonrpc as jsonrpc;
+extern crate hyper;
 #[macro_use]
 extern crate log;
 extern crate secp256k1;
@@ -73,6 +77,10 @@ extern crate serde_derive;
 #[macro_use]
 extern crate serde_json;
 extern crate toml;
+#[cfg(feature = "zmq")]
+extern crate zmq;
+#[cfg(feature = "tls")]
+extern crate openssl;
 
 #[macro_use]
 extern crate exonum;
@@ -98,14 +106,22 @@ pub mod error;
 pub mod api;
 pub mod observer;
 pub mod cmd;
+pub mod verifier;
 
-pub use details::btc::{gen_btc_keypair, gen_btc_keypair_with_rng, Network as BitcoinNetwork};
-pub use details::rpc::{RpcClient, AnchoringRpcConfig, BitcoinRelay};
-pub use blockchain::consensus_storage::AnchoringConfig;
+pub use details::btc::{gen_btc_keypair, gen_btc_keypair_with_rng, AddressType, PayloadVersion,
+                       Network as BitcoinNetwork};
+pub use details::rpc::{RpcClient, ElectrumClient, EsploraClient, QuorumRelay, RelayDisagreement,
+                       FailoverRelay, RelayRetryConfig, CachingRelay,
+                       AnchoringRpcConfig, BitcoinRelay, RelayBackend,
+                       UtxoDiscovery, RpcCredentials, RpcTlsConfig};
+#[cfg(feature = "simulated_bitcoin")]
+pub use details::rpc::{SimulatedBitcoin, SimulatedBitcoind};
+pub use details::notifications::{BitcoinEvent, ZmqNotificationsConfig};
+pub use blockchain::consensus_storage::{AnchoringConfig, FeePolicy};
 pub use local_storage::AnchoringNodeConfig;
 pub use service::{gen_anchoring_testnet_config, gen_anchoring_testnet_config_with_rng,
                   AnchoringService, ANCHORING_SERVICE_ID, ANCHORING_SERVICE_NAME};
-pub use cmd::AnchoringServiceFactory;
+pub use cmd::{AnchoringServiceFactory, VerifyAnchoringProof};
 pub use handler::AnchoringHandler;
 pub use error::Error;
 
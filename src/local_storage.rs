@@ -15,8 +15,10 @@
 use std::default::Default;
 use std::collections::BTreeMap;
 
-use details::rpc::AnchoringRpcConfig;
+use details::rpc::{AnchoringRpcConfig, BitcoinRelay, CachingRelay, FailoverRelay, QuorumRelay,
+                   RelayRetryConfig};
 use details::btc;
+use details::notifications::ZmqNotificationsConfig;
 use observer::AnchoringObserverConfig;
 
 /// Private part of anchoring service configuration stored on a local machine.
@@ -25,6 +27,36 @@ pub struct AnchoringNodeConfig {
     /// Rpc configuration. Must exist if node is validator.
     /// Otherwise node can only check `lect` payload without any checks with `bitcoind`.
     pub rpc: Option<AnchoringRpcConfig>,
+    /// Configurations of the fallback relays, which are used in order if the `rpc` one fails.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fallback_rpc: Vec<AnchoringRpcConfig>,
+    /// Retry policy of the requests to the `rpc` and `fallback_rpc` relays.
+    /// The failed requests are not retried if neither the policy nor the fallback relays are set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relay_retry: Option<RelayRetryConfig>,
+    /// Configurations of the additional relays, which are queried together with the `rpc` one
+    /// by the `QuorumRelay`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_rpc: Vec<AnchoringRpcConfig>,
+    /// Number of the relays which must agree on the read results,
+    /// the majority of them by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relay_quorum: Option<usize>,
+    /// Cache the relay responses until the bitcoin tip changes.
+    #[serde(default, skip_serializing_if = "is_disabled")]
+    pub relay_cache: bool,
+    /// Handle the committed blocks right in the commit hook of the node instead of
+    /// the dedicated worker thread, so that the slow relay delays the next blocks.
+    #[serde(default, skip_serializing_if = "is_disabled")]
+    pub sync_commit: bool,
+    /// Bitcoind ZMQ notifications, which trigger the lect checks of the handler and
+    /// the observer on the new blocks and transactions in addition to the polling.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zmq_notifications: Option<ZmqNotificationsConfig>,
+    /// Track the anchoring outputs by the known lects and the relay's spent checks instead
+    /// of the relay's wallet, so that the anchoring addresses are not imported to it.
+    #[serde(default, skip_serializing_if = "is_disabled")]
+    pub chain_utxo_tracking: bool,
     /// Set of private keys for each anchoring address.
     pub private_keys: BTreeMap<String, btc::PrivateKey>,
     /// Frequency of lect check in blocks.
@@ -41,15 +73,68 @@ impl AnchoringNodeConfig {
             ..Default::default()
         }
     }
+
+    /// Creates the bitcoin relay from the `rpc` configuration. The relay is wrapped into
+    /// the `FailoverRelay` together with the `fallback_rpc` ones if they or the retry policy
+    /// are given, then into the `QuorumRelay` if the `extra_rpc` configurations are given
+    /// and then into the `CachingRelay` if it is enabled.
+    pub fn relay(&self) -> Option<Box<BitcoinRelay>> {
+        let rpc = match self.rpc {
+            Some(ref rpc) => rpc.clone(),
+            None => return None,
+        };
+        let primary: Box<BitcoinRelay> = if self.fallback_rpc.is_empty() &&
+            self.relay_retry.is_none()
+        {
+            rpc.into()
+        } else {
+            let relays = Some(rpc)
+                .into_iter()
+                .chain(self.fallback_rpc.iter().cloned())
+                .map(Into::into)
+                .collect();
+            let retry = self.relay_retry.clone().unwrap_or_default();
+            Box::new(FailoverRelay::new(relays, retry))
+        };
+        let relay: Box<BitcoinRelay> = if self.extra_rpc.is_empty() {
+            primary
+        } else {
+            let relays = Some(primary)
+                .into_iter()
+                .chain(self.extra_rpc.iter().cloned().map(Into::into))
+                .collect();
+            match self.relay_quorum {
+                Some(quorum) => Box::new(QuorumRelay::new(relays, quorum)),
+                None => Box::new(QuorumRelay::with_majority(relays)),
+            }
+        };
+        if self.relay_cache {
+            Some(Box::new(CachingRelay::new(relay)))
+        } else {
+            Some(relay)
+        }
+    }
 }
 
 impl Default for AnchoringNodeConfig {
     fn default() -> AnchoringNodeConfig {
         AnchoringNodeConfig {
             rpc: None,
+            fallback_rpc: Vec::new(),
+            relay_retry: None,
+            extra_rpc: Vec::new(),
+            relay_quorum: None,
+            relay_cache: false,
+            sync_commit: false,
+            zmq_notifications: None,
+            chain_utxo_tracking: false,
             observer: AnchoringObserverConfig::default(),
             private_keys: BTreeMap::new(),
             check_lect_frequency: 30,
         }
     }
 }
+
+fn is_disabled(flag: &bool) -> bool {
+    !*flag
+}
@@ -14,19 +14,27 @@
 
 //! Anchoring transactions' chain observer.
 
-use std::time::Duration;
+use std::fmt;
+use std::time::{Duration, Instant};
 use std::thread::sleep;
+use std::sync::mpsc;
 
-use bitcoin::util::base58::ToBase58;
 
 use exonum::blockchain::{Blockchain, Schema};
-use exonum::storage::Fork;
+use exonum::crypto::{PublicKey, SecretKey};
+use exonum::helpers::ValidatorId;
+use exonum::node::TransactionSend;
+use exonum::storage::Snapshot;
 
-use details::rpc::{RpcClient, AnchoringRpcConfig, BitcoinRelay};
+use details::btc;
+use details::rpc::{has_unspent_output, BitcoinRelay};
+use details::notifications::BitcoinEvent;
 use details::btc::transactions::{AnchoringTx, BitcoinTx, TxKind};
 use blockchain::schema::AnchoringSchema;
 use blockchain::consensus_storage::AnchoringConfig;
+use blockchain::dto::{MsgAnchoringFinalizedTx, TxOutProof};
 use error::Error as ServiceError;
+use local_storage::AnchoringNodeConfig;
 
 /// Type alias for milliseconds.
 pub type Milliseconds = u64;
@@ -51,26 +59,56 @@ impl Default for AnchoringObserverConfig {
     }
 }
 
-/// Anchoring chain observer. Periodically checks the state of the anchor chain and keeps
-/// the verified transactions in database.
+/// Anchoring chain observer. Periodically checks the state of the anchor chain.
+///
+/// If the vote sender is set, the observer of the validator node votes for the confirmed
+/// transactions along with their proofs, which are added to the `anchoring_tx_chain`
+/// and `tx_out_proofs` tables by the majority of validators.
+///
+/// If the bitcoin events are set, the anchor chain is also checked on each new bitcoin block.
+///
+/// If the chain UTXO tracking is enabled, the latest anchoring transaction is found among
+/// the lects of the validators instead of the relay's wallet.
 #[derive(Debug)]
 pub struct AnchoringChainObserver {
     blockchain: Blockchain,
     client: Box<BitcoinRelay>,
     check_interval: Milliseconds,
+    chain_utxo_tracking: bool,
+    bitcoin_events: Option<mpsc::Receiver<BitcoinEvent>>,
+    vote_sender: Option<VoteSender>,
+}
+
+/// Service keys and the transaction sender of the node for the votes.
+struct VoteSender {
+    public_key: PublicKey,
+    secret_key: SecretKey,
+    sender: Box<TransactionSend>,
+}
+
+impl fmt::Debug for VoteSender {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("VoteSender")
+            .field("public_key", &self.public_key)
+            .finish()
+    }
 }
 
 impl AnchoringChainObserver {
-    /// Constructs observer for the given `blockchain`.
-    pub fn new(
-        blockchain: Blockchain,
-        rpc: AnchoringRpcConfig,
-        observer: &AnchoringObserverConfig,
-    ) -> AnchoringChainObserver {
+    /// Constructs observer for the given `blockchain` with the relay and the observer
+    /// configuration of the anchoring `node`.
+    ///
+    /// # Panics
+    ///
+    /// If the rpc configuration of the `node` is not set.
+    pub fn new(blockchain: Blockchain, node: &AnchoringNodeConfig) -> AnchoringChainObserver {
         AnchoringChainObserver {
             blockchain,
-            client: Box::new(RpcClient::from(rpc)),
-            check_interval: observer.check_interval,
+            client: node.relay().expect("Rpc config is not setted"),
+            check_interval: node.observer.check_interval,
+            chain_utxo_tracking: node.chain_utxo_tracking,
+            bitcoin_events: None,
+            vote_sender: None,
         }
     }
 
@@ -84,9 +122,38 @@ impl AnchoringChainObserver {
             blockchain,
             client,
             check_interval,
+            chain_utxo_tracking: false,
+            bitcoin_events: None,
+            vote_sender: None,
         }
     }
 
+    /// Sets the receiver of the bitcoin network events, which trigger the anchoring chain
+    /// checks between the polling ones.
+    pub fn set_bitcoin_events(&mut self, events: Option<mpsc::Receiver<BitcoinEvent>>) {
+        self.bitcoin_events = events;
+    }
+
+    /// Enables the search of the latest anchoring transaction among the lects of the validators
+    /// instead of the relay's wallet, which does not watch the anchoring addresses then.
+    pub fn set_chain_utxo_tracking(&mut self, enabled: bool) {
+        self.chain_utxo_tracking = enabled;
+    }
+
+    /// Sets the service keypair and the transaction sender, which are used to vote for
+    /// the confirmed anchoring transactions if the node is a validator.
+    pub fn set_vote_sender(
+        &mut self,
+        service_keypair: (PublicKey, SecretKey),
+        sender: Box<TransactionSend>,
+    ) {
+        self.vote_sender = Some(VoteSender {
+            public_key: service_keypair.0,
+            secret_key: service_keypair.1,
+            sender,
+        });
+    }
+
     /// Runs obesrver in infinity loop.
     pub fn run(&mut self) -> Result<(), ServiceError> {
         info!(
@@ -101,28 +168,66 @@ impl AnchoringChainObserver {
                     e
                 );
             }
-            sleep(duration);
+            self.wait_next_check(duration);
         }
     }
 
-    /// Tries to get `lect` for the current anchoring configuration and retrospectively adds
-    /// all previosly unknown anchoring transactions.
+    /// Waits for the new bitcoin block, but not longer than the given `duration`.
+    fn wait_next_check(&mut self, duration: Duration) {
+        let deadline = Instant::now() + duration;
+        loop {
+            let result = match self.bitcoin_events {
+                Some(ref events) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return;
+                    }
+                    events.recv_timeout(deadline - now)
+                }
+                None => break,
+            };
+            match result {
+                // Only the confirmations of the anchoring transactions matter to the observer.
+                Ok(BitcoinEvent::Block(hash)) => {
+                    trace!("Received bitcoin block, hash={}", hash);
+                    return;
+                }
+                Ok(BitcoinEvent::Transaction(_)) => {}
+                Err(mpsc::RecvTimeoutError::Timeout) => return,
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    warn!("Bitcoin events have stopped, observer continues polling only");
+                    self.bitcoin_events = None;
+                }
+            }
+        }
+        let now = Instant::now();
+        if now < deadline {
+            sleep(deadline - now);
+        }
+    }
+
+    /// Tries to get `lect` for the current anchoring configuration and votes for all
+    /// the confirmed anchoring transactions, which precede it and are not finalized
+    /// yet, along with their proofs.
     pub fn check_anchoring_chain(&mut self) -> Result<(), ServiceError> {
-        let mut fork = self.blockchain.fork();
-        if !self.is_blockchain_inited(&fork) {
+        let snapshot = self.blockchain.snapshot();
+        if !self.is_blockchain_inited(&*snapshot) {
             return Ok(());
         }
 
-        let cfg = AnchoringSchema::new(&fork).actual_anchoring_config();
-        if let Some(lect) = self.find_lect(&fork, &cfg)? {
-            if !self.lect_payload_is_correct(&fork, &lect) {
+        let cfg = AnchoringSchema::new(&snapshot).actual_anchoring_config();
+        if let Some(lect) = self.find_lect(&*snapshot, &cfg)? {
+            if !self.lect_payload_is_correct(&*snapshot, &lect) {
                 error!("Received lect with incorrect payload, content={:#?}", lect);
                 return Ok(());
             }
 
-            self.update_anchoring_chain(&mut fork, &cfg, lect)?;
-            let patch = fork.into_patch();
-            self.blockchain.merge(patch).unwrap(); // FIXME remove unwrap.
+            // The finalized chain is changed by the votes only, so nothing is written here.
+            let confirmed_txs = self.confirmed_txs(&*snapshot, &cfg, lect)?;
+            // Transactions are voted in the order of the anchoring chain.
+            for (tx, proof) in confirmed_txs.into_iter().rev() {
+                self.vote_for_finalized_tx(&*snapshot, &cfg, tx, proof)?;
+            }
         }
         Ok(())
     }
@@ -137,71 +242,127 @@ impl AnchoringChainObserver {
         &self.blockchain
     }
 
-    fn update_anchoring_chain(
+    /// Returns the confirmed anchoring transactions, which precede the given `lect`,
+    /// along with their proofs in the descending order of the anchored heights.
+    /// Transactions are looked up until the finalized one with the known proof.
+    fn confirmed_txs(
         &self,
-        fork: &mut Fork,
+        snapshot: &Snapshot,
         actual_cfg: &AnchoringConfig,
         mut lect: AnchoringTx,
-    ) -> Result<(), ServiceError> {
-        let mut anchoring_schema = AnchoringSchema::new(fork);
+    ) -> Result<Vec<(AnchoringTx, TxOutProof)>, ServiceError> {
+        let anchoring_schema = AnchoringSchema::new(snapshot);
+        let mut confirmed_txs = Vec::new();
 
         loop {
             let payload = lect.payload();
-            let height = payload.block_height.into();
 
-            // We already committed given lect to chain and there is no need to continue
+            // We already finalized given lect in chain and there is no need to continue
             // checking chain.
-            if let Some(other_lect) = anchoring_schema.anchoring_tx_chain().get(&height) {
-                if other_lect == lect {
-                    return Ok(());
-                }
+            let has_proof = anchoring_schema.tx_out_proofs().contains(&lect.id());
+            if anchoring_schema.is_finalized_tx(&lect) && has_proof {
+                return Ok(confirmed_txs);
             }
 
             let confirmations = self.client.get_transaction_confirmations(lect.id())?;
             if confirmations.as_ref() >= Some(&actual_cfg.utxo_confirmations) {
                 trace!(
-                    "Adds transaction to chain, height={}, content={:#?}",
+                    "Found confirmed transaction, height={}, content={:#?}",
                     payload.block_height,
                     lect
                 );
 
-                anchoring_schema.anchoring_tx_chain_mut().put(
-                    &height,
-                    lect.clone(),
-                );
+                if let Some(proof) = self.client.get_tx_out_proof(lect.id())? {
+                    confirmed_txs.push((lect.clone(), proof));
+                }
             }
 
             let prev_txid = payload.prev_tx_chain.unwrap_or_else(|| lect.prev_hash());
             if let Some(prev_tx) = self.client.get_transaction(prev_txid)? {
                 lect = match TxKind::from(prev_tx) {
                     TxKind::Anchoring(lect) => lect,
-                    TxKind::FundingTx(_) => return Ok(()),
+                    TxKind::FundingTx(_) => return Ok(confirmed_txs),
                     TxKind::Other(tx) => {
                         panic!("Found incorrect lect transaction, content={:#?}", tx)
                     }
                 }
             } else {
-                return Ok(());
+                return Ok(confirmed_txs);
             }
         }
     }
 
+    /// Sends the vote for the given confirmed anchoring transaction with its `proof`
+    /// if the node is a validator, which has not voted for it yet.
+    fn vote_for_finalized_tx(
+        &self,
+        snapshot: &Snapshot,
+        actual_cfg: &AnchoringConfig,
+        tx: AnchoringTx,
+        proof: TxOutProof,
+    ) -> Result<(), ServiceError> {
+        let vote_sender = match self.vote_sender {
+            Some(ref vote_sender) => vote_sender,
+            None => return Ok(()),
+        };
+        let validator_keys = Schema::new(snapshot).actual_configuration().validator_keys;
+        let position = validator_keys
+            .iter()
+            .position(|keys| keys.service_key == vote_sender.public_key);
+        let id = match position {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        let anchoring_key = match actual_cfg.anchoring_keys.get(id) {
+            Some(key) => key,
+            None => return Ok(()),
+        };
+        let anchoring_schema = AnchoringSchema::new(snapshot);
+        let votes = anchoring_schema.finalized_tx_votes(&tx.id(), &proof.hash());
+        if votes.contains(anchoring_key) {
+            return Ok(());
+        }
+
+        info!(
+            "FINALIZED ====== txid={}, height={}",
+            tx.txid(),
+            tx.payload().block_height
+        );
+        let vote = MsgAnchoringFinalizedTx::new(
+            &vote_sender.public_key,
+            ValidatorId(id as u16),
+            tx,
+            proof,
+            &vote_sender.secret_key,
+        );
+        vote_sender.sender.send(Box::new(vote))?;
+        Ok(())
+    }
+
     fn find_lect(
         &self,
-        fork: &Fork,
+        snapshot: &Snapshot,
         actual_cfg: &AnchoringConfig,
     ) -> Result<Option<AnchoringTx>, ServiceError> {
         let actual_addr = actual_cfg.redeem_script().1;
 
         trace!(
             "Tries to find lect for the addr: {}",
-            actual_addr.to_base58check()
+            actual_addr.to_string()
         );
 
-        let unspent_txs: Vec<_> = self.client.unspent_transactions(&actual_addr)?;
+        let unspent_txs = if self.chain_utxo_tracking {
+            self.tracked_unspent_lects(snapshot, actual_cfg, &actual_addr)?
+        } else {
+            self.client
+                .unspent_transactions(&actual_addr)?
+                .into_iter()
+                .map(|tx| tx.body)
+                .collect()
+        };
         for tx in unspent_txs {
-            if self.transaction_is_lect(fork, actual_cfg, &tx.body)? {
-                if let TxKind::Anchoring(lect) = TxKind::from(tx.body) {
+            if self.transaction_is_lect(snapshot, actual_cfg, &tx)? {
+                if let TxKind::Anchoring(lect) = TxKind::from(tx) {
                     return Ok(Some(lect));
                 }
             }
@@ -209,14 +370,35 @@ impl AnchoringChainObserver {
         Ok(None)
     }
 
+    /// Lists the lects of the validators with the unspent outputs to the anchoring address.
+    fn tracked_unspent_lects(
+        &self,
+        snapshot: &Snapshot,
+        actual_cfg: &AnchoringConfig,
+        actual_addr: &btc::Address,
+    ) -> Result<Vec<BitcoinTx>, ServiceError> {
+        let anchoring_schema = AnchoringSchema::new(snapshot);
+        let mut unspent: Vec<BitcoinTx> = Vec::new();
+        for key in &actual_cfg.anchoring_keys {
+            if let Some(lect) = anchoring_schema.lect(key) {
+                if unspent.iter().all(|tx| tx.id() != lect.id()) &&
+                    has_unspent_output(self.client.as_ref(), &lect, actual_addr)? == Some(true)
+                {
+                    unspent.push(lect);
+                }
+            }
+        }
+        Ok(unspent)
+    }
+
     fn transaction_is_lect(
         &self,
-        fork: &Fork,
+        snapshot: &Snapshot,
         actual_cfg: &AnchoringConfig,
         tx: &BitcoinTx,
     ) -> Result<bool, ServiceError> {
         let txid = tx.id();
-        let anchoring_schema = AnchoringSchema::new(fork);
+        let anchoring_schema = AnchoringSchema::new(snapshot);
 
         let mut lect_count = 0;
         for key in &actual_cfg.anchoring_keys {
@@ -227,15 +409,15 @@ impl AnchoringChainObserver {
         Ok(lect_count >= actual_cfg.majority_count())
     }
 
-    fn lect_payload_is_correct(&self, fork: &Fork, lect: &AnchoringTx) -> bool {
-        let core_schema = Schema::new(fork);
+    fn lect_payload_is_correct(&self, snapshot: &Snapshot, lect: &AnchoringTx) -> bool {
+        let core_schema = Schema::new(snapshot);
         let payload = lect.payload();
         let block_hash = core_schema.block_hash_by_height(payload.block_height);
         block_hash == Some(payload.block_hash)
     }
 
-    fn is_blockchain_inited(&self, fork: &Fork) -> bool {
-        let schema = Schema::new(fork);
+    fn is_blockchain_inited(&self, snapshot: &Snapshot) -> bool {
+        let schema = Schema::new(snapshot);
         let len = schema.block_hashes_by_height().len();
         len > 0
     }
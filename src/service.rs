@@ -12,11 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::sync::{Arc, Mutex};
+use std::sync::{mpsc, Arc, Mutex, PoisonError};
 use std::thread;
 use std::ops::Drop;
 
-use bitcoin::util::base58::ToBase58;
 use iron::{Handler, Request, Response};
 use iron::prelude::IronResult;
 use serde_json;
@@ -24,8 +23,9 @@ use serde_json::value::Value;
 use rand::{thread_rng, Rng};
 use router::Router;
 
-use exonum::blockchain::{ApiContext, Blockchain, Service, ServiceContext, Transaction};
+use exonum::blockchain::{ApiContext, Service, ServiceContext, Transaction};
 use exonum::crypto::Hash;
+use exonum::helpers::Height;
 use exonum::messages::RawTransaction;
 use exonum::encoding::Error as StreamStructError;
 use exonum::storage::{Fork, Snapshot};
@@ -33,12 +33,16 @@ use exonum::api::Api;
 
 use api::PublicApi;
 use details::btc;
-use details::rpc::{BitcoinRelay, RpcClient};
+use details::rpc::BitcoinRelay;
+use details::notifications::BitcoinEvent;
+use details::worker::CommitWorker;
 use local_storage::AnchoringNodeConfig;
 use handler::AnchoringHandler;
 use blockchain::consensus_storage::AnchoringConfig;
 use blockchain::schema::AnchoringSchema;
-use blockchain::dto::{MsgAnchoringSignature, MsgAnchoringUpdateLatest, ANCHORING_MESSAGE_LATEST,
+use blockchain::dto::{MsgAnchoringFeeRate, MsgAnchoringFinalizedTx, MsgAnchoringSignature,
+                      MsgAnchoringUpdateLatest, ANCHORING_MESSAGE_FEE_RATE,
+                      ANCHORING_MESSAGE_FINALIZED_TX, ANCHORING_MESSAGE_LATEST,
                       ANCHORING_MESSAGE_SIGNATURE};
 use error::Error as ServiceError;
 use handler::error::Error as HandlerError;
@@ -50,19 +54,31 @@ pub const ANCHORING_SERVICE_ID: u16 = 3;
 pub const ANCHORING_SERVICE_NAME: &str = "btc_anchoring";
 
 /// Anchoring service implementation for the Exonum blockchain.
+///
+/// The commits are handled by the `CommitWorker`, unless the `sync_commit` is enabled
+/// in the node configuration. The worker is started on the first commit after any of the api
+/// handlers has provided the blockchain for it. Until then, or if the worker has stopped,
+/// the commits are handled synchronously.
 #[derive(Debug)]
 pub struct AnchoringService {
     genesis: AnchoringConfig,
     handler: Arc<Mutex<AnchoringHandler>>,
+    worker: Mutex<Option<CommitWorker>>,
+    commits: Mutex<Option<mpsc::Sender<Height>>>,
 }
 
 impl AnchoringService {
     /// Creates a new service instance with the given `consensus` and `local` configurations.
     pub fn new(consensus: AnchoringConfig, local: AnchoringNodeConfig) -> AnchoringService {
-        let client = local.rpc.clone().map(RpcClient::from).map(Into::into);
+        let client = local.relay();
+        let events = subscribe_bitcoin_events(&local);
+        let mut handler = AnchoringHandler::new(client, local);
+        handler.set_bitcoin_events(events);
         AnchoringService {
             genesis: consensus,
-            handler: Arc::new(Mutex::new(AnchoringHandler::new(client, local))),
+            handler: Arc::new(Mutex::new(handler)),
+            worker: Mutex::new(None),
+            commits: Mutex::new(None),
         }
     }
 
@@ -75,6 +91,8 @@ impl AnchoringService {
         AnchoringService {
             genesis: genesis,
             handler: Arc::new(Mutex::new(AnchoringHandler::new(Some(client), local_cfg))),
+            worker: Mutex::new(None),
+            commits: Mutex::new(None),
         }
     }
 
@@ -82,6 +100,19 @@ impl AnchoringService {
     pub fn handler(&self) -> Arc<Mutex<AnchoringHandler>> {
         Arc::clone(&self.handler)
     }
+
+    /// Prepares the commit worker with the blockchain and the transaction sender
+    /// of the api `context`, unless the worker is already prepared or started.
+    fn prepare_commit_worker(&self, handler: &AnchoringHandler, context: &ApiContext) {
+        if handler.node.sync_commit {
+            return;
+        }
+        let commits = self.commits.lock().unwrap();
+        let mut worker = self.worker.lock().unwrap();
+        if commits.is_none() && worker.is_none() {
+            *worker = Some(CommitWorker::new(self.handler(), context));
+        }
+    }
 }
 
 impl Service for AnchoringService {
@@ -102,6 +133,10 @@ impl Service for AnchoringService {
         match raw.message_type() {
             ANCHORING_MESSAGE_LATEST => Ok(Box::new(MsgAnchoringUpdateLatest::from_raw(raw)?)),
             ANCHORING_MESSAGE_SIGNATURE => Ok(Box::new(MsgAnchoringSignature::from_raw(raw)?)),
+            ANCHORING_MESSAGE_FEE_RATE => Ok(Box::new(MsgAnchoringFeeRate::from_raw(raw)?)),
+            ANCHORING_MESSAGE_FINALIZED_TX => {
+                Ok(Box::new(MsgAnchoringFinalizedTx::from_raw(raw)?))
+            }
             _ => Err(StreamStructError::IncorrectMessageType {
                 message_type: raw.message_type(),
             }),
@@ -120,36 +155,60 @@ impl Service for AnchoringService {
     }
 
     fn handle_commit(&self, state: &ServiceContext) {
-        let mut handler = self.handler.lock().unwrap();
-        match handler.handle_commit(state) {
-            Err(ServiceError::Handler(e @ HandlerError::IncorrectLect { .. })) => {
-                panic!("A critical error occured: {}", e)
+        {
+            let mut commits = self.commits.lock().unwrap();
+            if commits.is_none() {
+                *commits = self.worker.lock().unwrap().take().map(CommitWorker::spawn);
             }
-            Err(ServiceError::Handler(e)) => {
-                error!("An error in handler occured: {}", e);
-                if let Some(sink) = handler.errors_sink.as_ref() {
-                    let res = sink.send(e);
-                    if let Err(err) = res {
-                        error!("Can't send error to channel: {}", err);
-                    }
+            if let Some(sender) = commits.take() {
+                if sender.send(state.height()).is_ok() {
+                    *commits = Some(sender);
+                    return;
                 }
+                // The worker stops only after the panic in the handler.
+                error!("Commit worker has stopped, the commits are handled synchronously");
             }
-            Err(e) => {
-                error!("An error occured: {:?}", e);
-            }
-            Ok(()) => (),
         }
+        let mut handler = self.handler.lock().unwrap_or_else(PoisonError::into_inner);
+        handle_commit(&mut handler, state);
     }
 
     /// Public api implementation.
     /// See [`PublicApi`](api/struct.PublicApi.html) for details.
+    ///
+    /// Also prepares the commit worker, since the api `context` provides the blockchain
+    /// and the transaction sender for it.
     fn public_api_handler(&self, context: &ApiContext) -> Option<Box<Handler>> {
         let handler = self.handler.lock().unwrap();
-        let router = PublicApiHandler::new(context.blockchain(), &handler.node);
+        self.prepare_commit_worker(&handler, context);
+        let router = PublicApiHandler::new(context, &handler.node);
         Some(Box::new(router))
     }
+
+    /// The service has no private api, but the `context` is used to prepare the commit worker
+    /// on the nodes without the public api.
+    fn private_api_handler(&self, context: &ApiContext) -> Option<Box<Handler>> {
+        let handler = self.handler.lock().unwrap();
+        self.prepare_commit_worker(&handler, context);
+        None
+    }
 }
 
+/// Handles the commit by the anchoring `handler` and reports its errors.
+/// The incorrect lect is the critical error, so it panics.
+pub(crate) fn handle_commit(handler: &mut AnchoringHandler, context: &ServiceContext) {
+    match handler.handle_commit(context) {
+        Err(ServiceError::Handler(e @ HandlerError::IncorrectLect { .. })) => {
+            panic!("A critical error occured: {}", e)
+        }
+        Err(ServiceError::Handler(e)) => handler.report_error(e),
+        Err(e) => {
+            error!("An error occured: {:?}", e);
+        }
+        Ok(()) => (),
+    }
+    handler.report_relay_disagreements();
+}
 
 /// Generates testnet configuration by given rpc for given nodes amount
 /// using given random number generator.
@@ -180,16 +239,14 @@ where
     }
 
     let majority_count = ::majority_count(count);
-    let address = btc::RedeemScript::from_pubkeys(&pub_keys, majority_count)
-        .compressed(network)
-        .to_address(network);
+    let address = btc::RedeemScript::from_pubkeys(&pub_keys, majority_count).to_address(network);
     client.watch_address(&address, false).unwrap();
     let tx = client.send_to_address(&address, total_funds).unwrap();
 
     let genesis_cfg = AnchoringConfig::new_with_funding_tx(network, pub_keys, tx);
     for (idx, node_cfg) in node_cfgs.iter_mut().enumerate() {
         node_cfg.private_keys.insert(
-            address.to_base58check(),
+            address.to_string(),
             priv_keys[idx].clone(),
         );
     }
@@ -209,6 +266,22 @@ pub fn gen_anchoring_testnet_config(
     gen_anchoring_testnet_config_with_rng(client, network, count, total_funds, &mut rng)
 }
 
+/// Subscribes to the bitcoind notifications if they are configured.
+/// The failed subscription is only logged, since the polling still works.
+fn subscribe_bitcoin_events(config: &AnchoringNodeConfig) -> Option<mpsc::Receiver<BitcoinEvent>> {
+    let zmq = match config.zmq_notifications {
+        Some(ref zmq) => zmq,
+        None => return None,
+    };
+    match zmq.subscribe() {
+        Ok(events) => Some(events),
+        Err(e) => {
+            error!("Unable to subscribe to the bitcoind notifications: {}", e);
+            None
+        }
+    }
+}
+
 /// Helper class that combines `Router` for public api with the observer thread.
 struct PublicApiHandler {
     router: Router,
@@ -216,17 +289,22 @@ struct PublicApiHandler {
 }
 
 impl PublicApiHandler {
-    /// Creates public api handler instance for the given `blockchain`
+    /// Creates public api handler instance for the given api `context`
     /// and anchoring node `config`.
-    pub fn new(blockchain: &Blockchain, config: &AnchoringNodeConfig) -> PublicApiHandler {
+    pub fn new(context: &ApiContext, config: &AnchoringNodeConfig) -> PublicApiHandler {
+        let blockchain = context.blockchain();
         let mut router = Router::new();
         let api = PublicApi { blockchain: blockchain.clone() };
         api.wire(&mut router);
 
         let observer = if config.observer.enabled {
-            let rpc_cfg = config.rpc.clone().expect("Rpc config is not setted");
-            let mut observer =
-                AnchoringChainObserver::new(blockchain.clone(), rpc_cfg, &config.observer);
+            let mut observer = AnchoringChainObserver::new(blockchain.clone(), config);
+            observer.set_bitcoin_events(subscribe_bitcoin_events(config));
+            // The votes of the validator node are signed by its service keys.
+            observer.set_vote_sender(
+                (*context.public_key(), context.secret_key().clone()),
+                Box::new(context.node_channel().clone()),
+            );
 
             Some(thread::spawn(move || { observer.run().unwrap(); }))
         } else {
@@ -0,0 +1,466 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Offline verifier of the anchoring proofs.
+//!
+//! The verifier checks the [`AnchoringProof`] returned by the `anchoring_proof` endpoint
+//! against the set of bitcoin block headers obtained from a trusted source. Neither the
+//! Exonum node nor `bitcoind` is required to run it.
+//!
+//! [`AnchoringProof`]: ../api/struct.AnchoringProof.html
+
+use std::collections::HashMap;
+use std::io::Cursor;
+
+use bitcoin::blockdata::script::Instruction;
+use bitcoin::network::encodable::ConsensusDecodable;
+use bitcoin::network::serialize::{BitcoinHash, RawDecoder};
+use bitcoin::util::hash::Sha256dHash;
+use secp256k1::Secp256k1;
+use secp256k1::key::PublicKey as RawPublicKey;
+
+use exonum::encoding::serialize::FromHex;
+
+use api::{AnchoringProof, BlockLink};
+use blockchain::dto::TxOutProof;
+use details::btc::{PublicKey, RedeemScript};
+use details::btc::payload::Payload;
+use details::btc::transactions::AnchoringTx;
+
+pub use bitcoin::blockdata::block::BlockHeader;
+
+/// Outcome of the single verification step.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", content = "reason", rename_all = "snake_case")]
+pub enum Check {
+    /// The check has been passed.
+    Passed,
+    /// The check has been failed for the given reason.
+    Failed(String),
+    /// The check could not be performed for the given reason.
+    Skipped(String),
+}
+
+/// Structured result of the anchoring proof verification.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Verdict {
+    /// The payload of the anchoring transaction matches the one from the proof.
+    pub payload: Check,
+    /// The requested block is linked to the anchored one.
+    pub block_link: Check,
+    /// Every input of the anchoring transaction is signed by the quorum of the multisig.
+    pub multisig: Check,
+    /// The anchoring transaction is included into one of the given bitcoin blocks.
+    pub merkle_inclusion: Check,
+}
+
+impl Verdict {
+    /// Returns `true` if all checks have been passed.
+    pub fn is_valid(&self) -> bool {
+        [
+            &self.payload,
+            &self.block_link,
+            &self.multisig,
+            &self.merkle_inclusion,
+        ].iter()
+            .all(|check| **check == Check::Passed)
+    }
+}
+
+/// Verifier of the anchoring proofs against the given multisig and bitcoin block headers.
+#[derive(Debug)]
+pub struct ProofVerifier {
+    redeem_script: RedeemScript,
+    headers: HashMap<Sha256dHash, BlockHeader>,
+    network_id: Option<u32>,
+    input_values: Vec<u64>,
+}
+
+impl ProofVerifier {
+    /// Creates a verifier for the anchoring transactions signed by the given `redeem_script`
+    /// and included into the blocks with the given trusted `headers`.
+    pub fn new<I>(redeem_script: RedeemScript, headers: I) -> ProofVerifier
+    where
+        I: IntoIterator<Item = BlockHeader>,
+    {
+        ProofVerifier {
+            redeem_script,
+            headers: headers
+                .into_iter()
+                .map(|header| (header.bitcoin_hash(), header))
+                .collect(),
+            network_id: None,
+            input_values: Vec::new(),
+        }
+    }
+
+    /// Sets the network identifier which the payload v.2 has to commit to.
+    pub fn network_id(mut self, network_id: Option<u32>) -> ProofVerifier {
+        self.network_id = network_id;
+        self
+    }
+
+    /// Sets the values of the outputs spent by the anchoring transaction inputs.
+    /// They are required to check the signatures of the segwit inputs.
+    pub fn input_values(mut self, input_values: Vec<u64>) -> ProofVerifier {
+        self.input_values = input_values;
+        self
+    }
+
+    /// Verifies the given anchoring proof.
+    pub fn verify(&self, proof: &AnchoringProof) -> Verdict {
+        Verdict {
+            payload: to_check(self.verify_payload(proof)),
+            block_link: to_check(verify_block_link(proof)),
+            multisig: self.verify_multisig(&proof.tx),
+            merkle_inclusion: to_check(self.verify_merkle_inclusion(proof)),
+        }
+    }
+
+    fn verify_payload(&self, proof: &AnchoringProof) -> Result<(), String> {
+        let payload = proof
+            .tx
+            .0
+            .output
+            .get(1)
+            .and_then(|out| Payload::from_script(&out.script_pubkey))
+            .ok_or_else(|| "Anchoring transaction has no payload".to_owned())?;
+        if payload != proof.payload {
+            return Err(format!(
+                "Payload of the anchoring transaction {:?} differs from the proof one",
+                payload
+            ));
+        }
+        if proof.block_height > payload.block_height {
+            return Err(format!(
+                "Requested block {} is above the anchored block {}",
+                proof.block_height,
+                payload.block_height
+            ));
+        }
+        if let Some(network_id) = self.network_id {
+            if payload.network_id != Some(network_id) {
+                return Err(format!(
+                    "Payload does not commit to the network {:08x}",
+                    network_id
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn verify_multisig(&self, tx: &AnchoringTx) -> Check {
+        let pubkeys = self.redeem_script_pubkeys();
+        let quorum = self.redeem_script.quorum();
+        if quorum == 0 || pubkeys.len() < quorum {
+            return Check::Failed("Redeem script is not a multisig one".to_owned());
+        }
+        let redeem_script_bytes = &self.redeem_script.0[..];
+
+        for input in tx.inputs() {
+            let witness = tx.0
+                .witness
+                .get(input as usize)
+                .cloned()
+                .unwrap_or_default();
+            let is_witness = !witness.is_empty();
+            let (script, signatures) = if !is_witness {
+                let mut pushes: Vec<Vec<u8>> = tx.0.input[input as usize]
+                    .script_sig
+                    .into_iter()
+                    .filter_map(|instruction| match instruction {
+                        Instruction::PushBytes(bytes) => Some(bytes.to_vec()),
+                        _ => None,
+                    })
+                    .collect();
+                (pushes.pop().unwrap_or_default(), pushes)
+            } else {
+                let mut items = witness;
+                let script = items.pop().unwrap_or_default();
+                (script, items)
+            };
+            if script.as_slice() != redeem_script_bytes {
+                return Check::Failed(format!(
+                    "Input {} is not spent by the given redeem script",
+                    input
+                ));
+            }
+
+            let value = if is_witness {
+                match self.input_values.get(input as usize) {
+                    Some(value) => Some(*value),
+                    None => {
+                        return Check::Skipped(
+                            format!("Value of the segwit input {} is unknown", input),
+                        )
+                    }
+                }
+            } else {
+                None
+            };
+
+            // Signatures have to follow in the same order as the public keys.
+            let mut keys = pubkeys.iter();
+            let mut signed = 0;
+            for signature in signatures.iter().filter(|signature| signature.len() > 1) {
+                let is_signed_by = |pubkey: &PublicKey| match value {
+                    Some(value) => {
+                        tx.verify_witness_input(
+                            &self.redeem_script,
+                            input,
+                            value,
+                            pubkey,
+                            signature,
+                        )
+                    }
+                    None => tx.verify_input(&self.redeem_script, input, pubkey, signature),
+                };
+                if keys.any(is_signed_by) {
+                    signed += 1;
+                }
+            }
+            if signed < quorum {
+                return Check::Failed(format!(
+                    "Input {} has only {} of {} required signatures",
+                    input,
+                    signed,
+                    quorum
+                ));
+            }
+        }
+        Check::Passed
+    }
+
+    fn verify_merkle_inclusion(&self, proof: &AnchoringProof) -> Result<(), String> {
+        let tx_out_proof = proof.tx_out_proof.as_ref().ok_or_else(|| {
+            "Proof has no evidence of the transaction inclusion into the bitcoin block".to_owned()
+        })?;
+        let merkle_block = MerkleBlock::from_tx_out_proof(tx_out_proof)?;
+        let block_hash = merkle_block.header.bitcoin_hash();
+        if block_hash.be_hex_string() != tx_out_proof.block_hash().to_lowercase() {
+            return Err(format!(
+                "Merkle block {} differs from the declared block {}",
+                block_hash.be_hex_string(),
+                tx_out_proof.block_hash()
+            ));
+        }
+        match self.headers.get(&block_hash) {
+            Some(header) if *header == merkle_block.header => {}
+            _ => {
+                return Err(format!(
+                    "Bitcoin block {} is not among the given headers",
+                    block_hash.be_hex_string()
+                ))
+            }
+        }
+        let header = &merkle_block.header;
+        if header.spv_validate(&header.target()).is_err() {
+            return Err(format!(
+                "Bitcoin block {} has invalid proof of work",
+                block_hash.be_hex_string()
+            ));
+        }
+
+        let (merkle_root, matches) = merkle_block.extract_matches()?;
+        if merkle_root != header.merkle_root {
+            return Err("Merkle root differs from the block header one".to_owned());
+        }
+        if !matches.contains(&*proof.tx.id()) {
+            return Err(format!(
+                "Transaction {} is not included into the block {}",
+                proof.tx.txid(),
+                block_hash.be_hex_string()
+            ));
+        }
+        Ok(())
+    }
+
+    fn redeem_script_pubkeys(&self) -> Vec<PublicKey> {
+        let context = Secp256k1::without_caps();
+        self.redeem_script
+            .0
+            .into_iter()
+            .filter_map(|instruction| match instruction {
+                Instruction::PushBytes(bytes) => RawPublicKey::from_slice(&context, bytes).ok(),
+                _ => None,
+            })
+            .map(PublicKey::from)
+            .collect()
+    }
+}
+
+/// Parses the hex-encoded bitcoin block header.
+pub fn parse_block_header(hex: &str) -> Result<BlockHeader, String> {
+    let bytes = Vec::<u8>::from_hex(hex.trim()).map_err(|e| e.to_string())?;
+    if bytes.len() != 80 {
+        return Err(format!("Unexpected block header length {}", bytes.len()));
+    }
+    let mut decoder = RawDecoder::new(Cursor::new(bytes.as_slice()));
+    BlockHeader::consensus_decode(&mut decoder).map_err(|e| e.to_string())
+}
+
+fn to_check(result: Result<(), String>) -> Check {
+    match result {
+        Ok(()) => Check::Passed,
+        Err(reason) => Check::Failed(reason),
+    }
+}
+
+fn verify_block_link(proof: &AnchoringProof) -> Result<(), String> {
+    let payload = &proof.payload;
+    match proof.link {
+        BlockLink::BlocksRoot {
+            first_block_height,
+            blocks_count,
+            proof: ref list_proof,
+        } => {
+            let blocks_root = payload.blocks_root.ok_or_else(|| {
+                "Payload does not commit to the blocks root".to_owned()
+            })?;
+            if blocks_count == 0 ||
+                first_block_height.0 + blocks_count - 1 != payload.block_height.0
+            {
+                return Err("Blocks root does not end at the anchored block".to_owned());
+            }
+            if proof.block_height < first_block_height {
+                return Err(format!(
+                    "Requested block {} is not committed by the blocks root",
+                    proof.block_height
+                ));
+            }
+            let values = list_proof.validate(blocks_root, blocks_count).map_err(|e| {
+                format!("Invalid blocks root proof: {:?}", e)
+            })?;
+            let index = proof.block_height.0 - first_block_height.0;
+            if values != vec![(index, &proof.block_hash)] {
+                return Err("Blocks root proof does not contain the requested block".to_owned());
+            }
+        }
+        BlockLink::Headers { ref headers } => {
+            let mut height = proof.block_height;
+            let mut hash = proof.block_hash;
+            for header in headers {
+                height = height.next();
+                if header.height() != height || *header.prev_hash() != hash {
+                    return Err(format!("Header at the height {} is out of chain", height));
+                }
+                hash = header.hash();
+            }
+            if height != payload.block_height || hash != payload.block_hash {
+                return Err("Headers do not lead to the anchored block".to_owned());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The `merkleblock` message returned by the `gettxoutproof` rpc.
+struct MerkleBlock {
+    header: BlockHeader,
+    total_transactions: u32,
+    hashes: Vec<Sha256dHash>,
+    flags: Vec<u8>,
+}
+
+impl MerkleBlock {
+    fn from_tx_out_proof(tx_out_proof: &TxOutProof) -> Result<MerkleBlock, String> {
+        let bytes = Vec::<u8>::from_hex(tx_out_proof.proof()).map_err(|e| {
+            format!("Unable to decode merkle block: {}", e)
+        })?;
+        let mut decoder = RawDecoder::new(Cursor::new(bytes.as_slice()));
+        let decode_error = |e: ::bitcoin::util::Error| format!("Malformed merkle block: {}", e);
+        Ok(MerkleBlock {
+            header: ConsensusDecodable::consensus_decode(&mut decoder).map_err(&decode_error)?,
+            total_transactions: ConsensusDecodable::consensus_decode(&mut decoder)
+                .map_err(&decode_error)?,
+            hashes: ConsensusDecodable::consensus_decode(&mut decoder).map_err(&decode_error)?,
+            flags: ConsensusDecodable::consensus_decode(&mut decoder).map_err(&decode_error)?,
+        })
+    }
+
+    /// Traverses the partial merkle tree as described in BIP 37 and returns its root
+    /// together with the matched transaction hashes.
+    fn extract_matches(&self) -> Result<(Sha256dHash, Vec<Sha256dHash>), String> {
+        if self.total_transactions == 0 || self.hashes.len() > self.total_transactions as usize {
+            return Err("Malformed partial merkle tree".to_owned());
+        }
+        let mut tree_height = 0;
+        while self.tree_width(tree_height) > 1 {
+            tree_height += 1;
+        }
+
+        let mut traversal = Traversal {
+            block: self,
+            bits_used: 0,
+            hashes_used: 0,
+            matches: Vec::new(),
+        };
+        let root = traversal.node(tree_height, 0)?;
+        if traversal.hashes_used != self.hashes.len() ||
+            (traversal.bits_used + 7) / 8 != self.flags.len()
+        {
+            return Err("Partial merkle tree has unused data".to_owned());
+        }
+        Ok((root, traversal.matches))
+    }
+
+    fn tree_width(&self, height: u32) -> u32 {
+        (self.total_transactions + (1 << height) - 1) >> height
+    }
+}
+
+struct Traversal<'a> {
+    block: &'a MerkleBlock,
+    bits_used: usize,
+    hashes_used: usize,
+    matches: Vec<Sha256dHash>,
+}
+
+impl<'a> Traversal<'a> {
+    fn node(&mut self, height: u32, pos: u32) -> Result<Sha256dHash, String> {
+        let flag = {
+            let byte = self.block.flags.get(self.bits_used / 8).ok_or_else(|| {
+                "Partial merkle tree has too few flags".to_owned()
+            })?;
+            byte & (1 << (self.bits_used % 8)) != 0
+        };
+        self.bits_used += 1;
+
+        if height == 0 || !flag {
+            let hash = *self.block.hashes.get(self.hashes_used).ok_or_else(|| {
+                "Partial merkle tree has too few hashes".to_owned()
+            })?;
+            self.hashes_used += 1;
+            if height == 0 && flag {
+                self.matches.push(hash);
+            }
+            return Ok(hash);
+        }
+
+        let left = self.node(height - 1, pos * 2)?;
+        let right = if pos * 2 + 1 < self.block.tree_width(height - 1) {
+            let right = self.node(height - 1, pos * 2 + 1)?;
+            if right == left {
+                return Err("Partial merkle tree has duplicate hashes".to_owned());
+            }
+            right
+        } else {
+            left
+        };
+        let mut data = left[..].to_vec();
+        data.extend_from_slice(&right[..]);
+        Ok(Sha256dHash::from_data(&data))
+    }
+}
+
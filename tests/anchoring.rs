@@ -35,19 +35,24 @@ pub mod testkit_extras;
 
 use std::ops::Deref;
 
-use bitcoin::blockdata::transaction::SigHashType;
-use bitcoin::network::constants::Network;
+use bitcoin::blockdata::transaction::{SigHashType, TxIn, TxOut};
 use bitcoin::blockdata::script::Script;
 
-use exonum::blockchain::Transaction;
+use exonum::blockchain::{Schema, Transaction};
 use exonum::messages::Message;
 use exonum::helpers::{Height, ValidatorId};
 use exonum::encoding::serialize::FromHex;
 use exonum::crypto::Hash;
 
-use exonum_btc_anchoring::blockchain::dto::{MsgAnchoringSignature, MsgAnchoringUpdateLatest};
+use exonum_btc_anchoring::FeePolicy;
+use exonum_btc_anchoring::blockchain::dto::{MsgAnchoringFeeRate, MsgAnchoringSignature,
+                                            MsgAnchoringUpdateLatest};
+use exonum_btc_anchoring::blockchain::schema::AnchoringSchema;
+use exonum_btc_anchoring::details::btc;
+use exonum_btc_anchoring::details::btc::Network;
+use exonum_btc_anchoring::details::btc::payload;
 use exonum_btc_anchoring::details::btc::transactions::{verify_tx_input, AnchoringTx, FundingTx,
-                                                       TransactionBuilder};
+                                                       RawBitcoinTx, TransactionBuilder};
 use testkit_extras::AnchoringTestKit;
 use testkit_extras::helpers::*;
 
@@ -119,6 +124,60 @@ fn test_anchoring_second_block_normal() {
     anchor_second_block_normal(&mut testkit);
 }
 
+// We anchor blocks with the payload v.2
+// problems: none
+// result: success with the network identifier and the state hash in the payloads
+#[test]
+fn test_anchoring_payload_v2() {
+    let mut testkit =
+        AnchoringTestKit::new_with_config(|cfg| cfg.payload_version = btc::PayloadVersion::V2);
+    anchor_first_block(&mut testkit);
+    anchor_first_block_lect_normal(&mut testkit);
+    anchor_second_block_normal(&mut testkit);
+
+    let network_id = payload::network_id(&testkit.block_hash_on_height(Height::zero()));
+    let payload = testkit.latest_anchored_tx().payload();
+    let block = Schema::new(testkit.snapshot())
+        .blocks()
+        .get(&payload.block_hash)
+        .unwrap();
+    assert_eq!(payload.network_id, Some(network_id));
+    assert_eq!(payload.state_hash, Some(*block.state_hash()));
+    assert_eq!(payload.block_height, Height(10));
+}
+
+// We anchor blocks with the payload committing to the blocks root
+// problems: none
+// result: success with the blocks roots saved to the schema
+#[test]
+fn test_anchoring_blocks_root() {
+    let mut testkit = AnchoringTestKit::new_with_config(|cfg| cfg.commit_blocks_root = true);
+    anchor_first_block(&mut testkit);
+    anchor_first_block_lect_normal(&mut testkit);
+    let first_anchored_tx = testkit.latest_anchored_tx();
+    anchor_second_block_normal(&mut testkit);
+    let second_anchored_tx = testkit.latest_anchored_tx();
+
+    let anchoring_schema = AnchoringSchema::new(testkit.snapshot());
+    // The first transaction commits to the genesis block only.
+    assert_eq!(
+        first_anchored_tx.payload().blocks_root,
+        anchoring_schema.blocks_roots(Height(0)).get(&0)
+    );
+    // The second one commits to the blocks since the previous anchored one.
+    let blocks_root = second_anchored_tx.payload().blocks_root.unwrap();
+    assert_eq!(
+        anchoring_schema.blocks_roots(Height(1)).get(&10),
+        Some(blocks_root)
+    );
+    let proof = anchoring_schema.blocks_root_proof(Height(1), Height(10), Height(5));
+    let block_hash = testkit.block_hash_on_height(Height(5));
+    assert_eq!(
+        proof.validate(blocks_root, 10).unwrap(),
+        vec![(4, &block_hash)]
+    );
+}
+
 // We anchor second block after successfuly anchored first with additional funds
 // problems: none
 // result: success
@@ -169,6 +228,86 @@ fn test_anchoring_second_block_additional_funds() {
     assert!(testkit.mempool().contains_key(&lect.hash()));
 }
 
+// We anchor second block after successfuly anchored first with the extra funding tx
+// problems: one of the funding outputs is unconfirmed
+// result: success with only confirmed funding outputs spent
+#[test]
+fn test_anchoring_second_block_extra_funding_txs() {
+    let mut extra_funds = None;
+    let mut testkit = AnchoringTestKit::new_with_config(|cfg| {
+        let output = TxOut {
+            value: 3000,
+            script_pubkey: cfg.redeem_script().1.script_pubkey(),
+        };
+        let tx = FundingTx::from(RawBitcoinTx {
+            version: 1,
+            lock_time: 0,
+            input: vec![
+                TxIn {
+                    prev_hash: cfg.funding_tx().0.txid(),
+                    prev_index: 1,
+                    script_sig: Script::new(),
+                    sequence: 0xFFFF_FFFF,
+                },
+            ],
+            output: vec![output.clone(), output],
+            witness: vec![],
+        });
+        cfg.extra_funding_txs = vec![tx.clone()];
+        extra_funds = Some(tx);
+    });
+    let extra_funds = extra_funds.unwrap();
+    let requests = testkit.requests();
+    let anchoring_addr = testkit.current_addr();
+
+    anchor_first_block(&mut testkit);
+    anchor_first_block_lect_normal(&mut testkit);
+
+    let height = testkit.next_anchoring_height();
+    testkit.create_blocks_until(height);
+
+    let mut unconfirmed_entry = listunspent_entry(&extra_funds, &anchoring_addr, 0);
+    unconfirmed_entry["vout"] = json!(1);
+    requests.expect(vec![
+        request! {
+            method: "listunspent",
+            params: [0, 9_999_999, [&anchoring_addr.to_string()]],
+            response: [
+                listunspent_entry(&testkit.latest_anchored_tx(), &anchoring_addr, 1),
+                listunspent_entry(&extra_funds, &anchoring_addr, 10),
+                unconfirmed_entry
+            ]
+        },
+        get_transaction_request(&testkit.latest_anchored_tx()),
+        get_transaction_request(&extra_funds),
+        get_transaction_request(&extra_funds),
+    ]);
+    testkit.create_block();
+
+    let extra_funds_id = extra_funds.id();
+    let block_hash = testkit.block_hash_on_height(Height(10));
+    let (proposal, signatures) = testkit.gen_anchoring_tx_with_signatures(
+        Height(10),
+        block_hash,
+        &[extra_funds],
+        None,
+        &anchoring_addr,
+    );
+    assert_eq!(proposal.input.len(), 2);
+    assert_eq!(btc::TxId::from(proposal.input[1].prev_hash), extra_funds_id);
+    assert_eq!(proposal.input[1].prev_index, 0);
+
+    assert!(testkit.mempool().contains_key(&signatures[0].hash()));
+    assert!(testkit.mempool().contains_key(&signatures[1].hash()));
+
+    let anchored_tx = &mut testkit.latest_anchored_tx();
+    requests.expect(send_raw_transaction_requests(anchored_tx));
+
+    testkit.create_block_with_transactions(signatures);
+    let lect = gen_service_tx_lect(&testkit, ValidatorId(0), anchored_tx, 2);
+    assert!(testkit.mempool().contains_key(&lect.hash()));
+}
+
 // We anchor second block after successfuly anchored first
 // problems: second anchoring tx is lost
 // result: we have lost anchoring tx
@@ -845,3 +984,152 @@ fn test_anchoring_signature_unknown_output_address() {
     let signs_after = dump_signatures(&testkit, &tx.id());
     assert_eq!(signs_before, signs_after);
 }
+
+// We anchor first block and the anchoring tx gets stuck in the bitcoin mempool
+// problems: anchoring tx is unconfirmed for `fee_bump_timeout` bitcoin blocks
+// result: we replace it by the tx with the doubled fee
+#[test]
+fn test_anchoring_bump_fee_stuck_lect() {
+    let mut testkit = AnchoringTestKit::new_with_config(|cfg| cfg.fee_bump_timeout = Some(3));
+    let requests = testkit.requests();
+    let anchoring_addr = testkit.current_addr();
+
+    anchor_first_block(&mut testkit);
+    let height = testkit.next_check_lect_height();
+    testkit.create_blocks_until(height);
+
+    let stuck_tx = testkit.latest_anchored_tx();
+    requests.expect(vec![
+        request! {
+            method: "listunspent",
+            params: [0, 9_999_999, [&anchoring_addr.to_string()]],
+            response: [
+                listunspent_entry(&stuck_tx, &anchoring_addr, 0)
+            ]
+        },
+        get_transaction_request(&stuck_tx),
+        request! {
+            method: "getmempoolentry",
+            params: [&stuck_tx.txid()],
+            response: {
+                "size": 223,
+                "fee": 0.00001,
+                "time": 1_514_764_800,
+                "height": 1000
+            }
+        },
+        request! {
+            method: "getblockcount",
+            params: [],
+            response: 1003
+        },
+    ]);
+    testkit.create_block();
+
+    let replacement = {
+        let funding_tx = testkit.current_funding_tx();
+        let out = funding_tx.find_out(&anchoring_addr).unwrap();
+        TransactionBuilder::with_prev_tx(&funding_tx, out)
+            .fee(2000)
+            .payload(Height::zero(), testkit.block_hash_on_height(Height::zero()))
+            .send_to(anchoring_addr.clone())
+            .into_transaction()
+            .unwrap()
+    };
+    assert_eq!(replacement.prev_hash(), stuck_tx.prev_hash());
+    assert_eq!(replacement.amount(), stuck_tx.amount() - 1000);
+
+    let signatures = testkit.gen_anchoring_signatures(&replacement);
+    assert!(testkit.mempool().contains_key(&signatures[0].hash()));
+
+    let replacement = testkit.finalize_tx(replacement, signatures.clone());
+    requests.expect(send_raw_transaction_requests(&replacement));
+    testkit.create_block_with_transactions(signatures.into_iter().map(Box::<Transaction>::from));
+
+    let lect = gen_service_tx_lect(&testkit, ValidatorId(0), &replacement, 2);
+    assert!(testkit.mempool().contains_key(&Box::<Transaction>::from(lect).hash()));
+}
+
+// We anchor first block and the anchoring tx waits in the bitcoin mempool
+// problems: anchoring tx is unconfirmed for less than `fee_bump_timeout` bitcoin blocks
+// result: we keep it
+#[test]
+fn test_anchoring_bump_fee_recent_lect() {
+    let mut testkit = AnchoringTestKit::new_with_config(|cfg| cfg.fee_bump_timeout = Some(3));
+    let requests = testkit.requests();
+    let anchoring_addr = testkit.current_addr();
+
+    anchor_first_block(&mut testkit);
+    let height = testkit.next_check_lect_height();
+    testkit.create_blocks_until(height);
+
+    let stuck_tx = testkit.latest_anchored_tx();
+    requests.expect(vec![
+        request! {
+            method: "listunspent",
+            params: [0, 9_999_999, [&anchoring_addr.to_string()]],
+            response: [
+                listunspent_entry(&stuck_tx, &anchoring_addr, 0)
+            ]
+        },
+        get_transaction_request(&stuck_tx),
+        request! {
+            method: "getmempoolentry",
+            params: [&stuck_tx.txid()],
+            response: {
+                "size": 223,
+                "fee": 0.00001,
+                "time": 1_514_764_800,
+                "height": 1000
+            }
+        },
+        request! {
+            method: "getblockcount",
+            params: [],
+            response: 1002
+        },
+    ]);
+    testkit.create_block();
+    assert!(testkit.mempool().is_empty());
+}
+
+// We anchor first block with disabled fee bumping
+// problems: none
+// result: stuck anchoring tx is not checked
+#[test]
+fn test_anchoring_bump_fee_disabled() {
+    let mut testkit = AnchoringTestKit::default();
+    anchor_first_block(&mut testkit);
+    anchor_first_block_lect_normal(&mut testkit);
+    assert!(testkit.handler().proposal_tx.is_none());
+}
+
+fn gen_service_tx_fee_rate(testkit: &AnchoringTestKit, fee_rate: u64) -> MsgAnchoringFeeRate {
+    let keypair = testkit.network().validators()[0].service_keypair();
+    MsgAnchoringFeeRate::new(keypair.0, ValidatorId(0), fee_rate, keypair.1)
+}
+
+// Fee rates of the validators are accepted only within the limits of the estimate fee policy.
+#[test]
+fn test_anchoring_fee_rate_validation() {
+    let mut testkit = AnchoringTestKit::new_with_config(|cfg| {
+        cfg.fee_policy = FeePolicy::Estimate {
+            conf_target: 6,
+            min_sat_per_vbyte: 2,
+            max_sat_per_vbyte: 50,
+        }
+    });
+    let fork = testkit.blockchain_mut().fork();
+    for &(fee_rate, accepted) in &[(1, false), (2, true), (50, true), (51, false)] {
+        let msg = gen_service_tx_fee_rate(&testkit, fee_rate);
+        assert_eq!(msg.validate(&fork).is_some(), accepted);
+    }
+
+    // Fee rates are not used by the other policies.
+    let mut testkit = AnchoringTestKit::new_with_config(|cfg| {
+        cfg.fee_policy = FeePolicy::Rate { sat_per_vbyte: 10 }
+    });
+    let fork = testkit.blockchain_mut().fork();
+    let msg = gen_service_tx_fee_rate(&testkit, 10);
+    assert!(msg.validate(&fork).is_none());
+}
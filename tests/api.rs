@@ -33,19 +33,30 @@ extern crate exonum_testkit;
 pub mod testkit_extras;
 
 use exonum::messages::Message;
+use exonum::blockchain::Schema;
+use exonum::storage::StorageValue;
 use exonum::helpers::{Height, ValidatorId};
 use exonum::encoding::serialize::FromHex;
 use exonum_testkit::{ApiKind, TestKitApi};
 
 use exonum_btc_anchoring::ANCHORING_SERVICE_NAME;
-use exonum_btc_anchoring::api::{AnchoringInfo, LectInfo};
+use exonum_btc_anchoring::api::{AnchoredBlockProof, AnchoringInfo, AnchoringProof,
+                                AnchoringTxInfo, BlockLink, LectInfo};
 use exonum_btc_anchoring::observer::AnchoringChainObserver;
-use exonum_btc_anchoring::blockchain::dto::MsgAnchoringUpdateLatest;
+use exonum_btc_anchoring::blockchain::dto::{AnchoringTxKind, MsgAnchoringFinalizedTx,
+                                            MsgAnchoringUpdateLatest, TxOutProof};
 use exonum_btc_anchoring::details::btc;
 use exonum_btc_anchoring::details::btc::transactions::{AnchoringTx, BitcoinTx};
-use testkit_extras::{AnchoringTestKit, TestClient};
+use testkit_extras::{AnchoringTestKit, TestClient, TestTransactions};
 use testkit_extras::helpers::*;
 
+const FIRST_BTC_BLOCK_HASH: &str =
+    "00000000000000f1a8c2b7e3d4f5a6b7c8d9e0f1a2b3c4d5e6f7a8b9c0d1e2f3";
+const FIRST_TX_OUT_PROOF: &str = "0000002001";
+const SECOND_BTC_BLOCK_HASH: &str =
+    "000000000000002b3c4d5e6f7a8b9c0d1e2f3a4b5c6d7e8f9a0b1c2d3e4f5a6b";
+const SECOND_TX_OUT_PROOF: &str = "0000002002";
+
 trait AnchoringApi {
     fn actual_lect(&self) -> Option<AnchoringInfo>;
 
@@ -56,6 +67,12 @@ trait AnchoringApi {
     fn following_address(&self) -> Option<btc::Address>;
 
     fn nearest_lect(&self, height: u64) -> Option<AnchoringTx>;
+
+    fn anchored_block_proof(&self, height: u64) -> Option<AnchoredBlockProof>;
+
+    fn anchoring_proof(&self, height: u64) -> Option<AnchoringProof>;
+
+    fn anchoring_tx(&self, txid: &btc::TxId) -> Option<AnchoringTxInfo>;
 }
 
 impl AnchoringApi for TestKitApi {
@@ -90,6 +107,27 @@ impl AnchoringApi for TestKitApi {
             &format!("/v1/nearest_lect/{}", height),
         )
     }
+
+    fn anchored_block_proof(&self, height: u64) -> Option<AnchoredBlockProof> {
+        self.get(
+            ApiKind::Service(ANCHORING_SERVICE_NAME),
+            &format!("/v1/block_proof/{}", height),
+        )
+    }
+
+    fn anchoring_proof(&self, height: u64) -> Option<AnchoringProof> {
+        self.get(
+            ApiKind::Service(ANCHORING_SERVICE_NAME),
+            &format!("/v1/anchoring_proof/{}", height),
+        )
+    }
+
+    fn anchoring_tx(&self, txid: &btc::TxId) -> Option<AnchoringTxInfo> {
+        self.get(
+            ApiKind::Service(ANCHORING_SERVICE_NAME),
+            &format!("/v1/anchoring_tx/{}", txid.to_string()),
+        )
+    }
 }
 
 // Test normal api usage
@@ -118,6 +156,30 @@ fn test_api_public_common() {
 }
 
 
+// Test lookup of the anchored block by the anchoring transaction id
+#[test]
+fn test_api_public_anchoring_tx() {
+    let mut testkit = AnchoringTestKit::default();
+    anchor_first_block(&mut testkit);
+    anchor_first_block_lect_normal(&mut testkit);
+
+    let anchored_tx = testkit.latest_anchored_tx();
+    let stored_cfg = Schema::new(testkit.snapshot()).actual_configuration();
+    let expected_info = AnchoringTxInfo {
+        txid: anchored_tx.id(),
+        kind: AnchoringTxKind::Regular,
+        payload: anchored_tx.payload(),
+        address: testkit.current_addr(),
+        amount: anchored_tx.amount(),
+        config_hash: stored_cfg.hash(),
+        config_actual_from: stored_cfg.actual_from,
+    };
+
+    let api = testkit.api();
+    assert_eq!(api.anchoring_tx(&anchored_tx.id()), Some(expected_info));
+    assert_eq!(api.anchoring_tx(&testkit.current_funding_tx().id()), None);
+}
+
 // Try to get lect from nonexistent validator id
 // result: Panic
 #[test]
@@ -230,7 +292,13 @@ fn test_api_anchoring_observer_normal() {
         Box::new(client),
         0,
     );
-    requests.expect(vec![
+    let votes = TestTransactions::default();
+    let keypair = testkit.validator(ValidatorId(0)).service_keypair();
+    observer.set_vote_sender(
+        (*keypair.0, keypair.1.clone()),
+        Box::new(votes.clone()),
+    );
+    let mut expected_requests = vec![
         request! {
             method: "listunspent",
             params: [0, 9_999_999, [&anchoring_addr]],
@@ -240,19 +308,196 @@ fn test_api_anchoring_observer_normal() {
         },
         get_transaction_request(&second_anchored_tx),
         confirmations_request(&second_anchored_tx, 100),
+    ];
+    expected_requests.extend(tx_out_proof_requests(
+        &second_anchored_tx,
+        SECOND_BTC_BLOCK_HASH,
+        SECOND_TX_OUT_PROOF,
+    ));
+    expected_requests.extend(vec![
         get_transaction_request(&first_anchored_tx),
         confirmations_request(&first_anchored_tx, 200),
-        get_transaction_request(&testkit.current_funding_tx()),
     ]);
+    expected_requests.extend(tx_out_proof_requests(
+        &first_anchored_tx,
+        FIRST_BTC_BLOCK_HASH,
+        FIRST_TX_OUT_PROOF,
+    ));
+    expected_requests.push(get_transaction_request(&testkit.current_funding_tx()));
+    requests.expect(expected_requests);
     observer.check_anchoring_chain().unwrap();
 
+    // The observer votes for the confirmed transactions in the order of the anchoring chain.
+    let first_proof = TxOutProof::new(FIRST_BTC_BLOCK_HASH, FIRST_TX_OUT_PROOF);
+    let second_proof = TxOutProof::new(SECOND_BTC_BLOCK_HASH, SECOND_TX_OUT_PROOF);
+    let votes = votes
+        .take()
+        .into_iter()
+        .map(|vote| MsgAnchoringFinalizedTx::from_raw(vote.raw().clone()).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        votes,
+        vec![
+            gen_service_tx_finalized(&testkit, ValidatorId(0), &first_anchored_tx, &first_proof),
+            gen_service_tx_finalized(&testkit, ValidatorId(0), &second_anchored_tx, &second_proof),
+        ]
+    );
+    // Transactions are not finalized until the majority of validators votes for them.
+    assert_eq!(testkit.api().nearest_lect(0), None);
+    force_finalize_anchoring_tx(&mut testkit, &second_anchored_tx, &second_proof);
+
     let api = testkit.api();
 
     // Check that `first_anchored_tx` anchors the block at height 0.
     assert_eq!(api.nearest_lect(0), Some(first_anchored_tx));
     // Check that closest anchoring transaction for height 1 is
     // `second_anchored_tx` that anchors the block at height 10.
-    assert_eq!(api.nearest_lect(1), Some(second_anchored_tx));
+    assert_eq!(api.nearest_lect(1), Some(second_anchored_tx.clone()));
     // Check that there are no anchoring transactions for heights that greater than 10
     assert_eq!(api.nearest_lect(11), None);
+
+    // Check the proof that the block at height 5 is anchored by `second_anchored_tx`.
+    let proof = api.anchoring_proof(5).unwrap();
+    assert_eq!(proof.block_hash, testkit.block_hash_on_height(Height(5)));
+    assert_eq!(proof.payload, second_anchored_tx.payload());
+    assert_eq!(proof.tx, second_anchored_tx);
+    let tx_out_proof = proof.tx_out_proof.unwrap();
+    assert_eq!(tx_out_proof.block_hash(), SECOND_BTC_BLOCK_HASH);
+    assert_eq!(tx_out_proof.proof(), SECOND_TX_OUT_PROOF);
+    // Headers link the requested block to the anchored one.
+    let headers = match proof.link {
+        BlockLink::Headers { headers } => headers,
+        link => panic!("Unexpected block link {:?}", link),
+    };
+    assert_eq!(headers.len(), 5);
+    let anchored_hash = headers.iter().fold(proof.block_hash, |prev_hash, header| {
+        assert_eq!(header.prev_hash(), &prev_hash);
+        header.hash()
+    });
+    assert_eq!(anchored_hash, proof.payload.block_hash);
+    // The anchored block itself needs no headers.
+    let proof = api.anchoring_proof(10).unwrap();
+    assert_eq!(proof.link, BlockLink::Headers { headers: Vec::new() });
+    assert_eq!(api.anchoring_proof(11), None);
+}
+
+// Validators see the anchoring transaction in the different bitcoin blocks, so it is
+// finalized only after the majority of them has voted for the same proof.
+#[test]
+fn test_api_finalized_tx_proof_majority() {
+    let mut testkit = AnchoringTestKit::default();
+    anchor_first_block(&mut testkit);
+    anchor_first_block_lect_normal(&mut testkit);
+    let anchored_tx = testkit.latest_anchored_tx();
+
+    let first_proof = TxOutProof::new(FIRST_BTC_BLOCK_HASH, FIRST_TX_OUT_PROOF);
+    let second_proof = TxOutProof::new(SECOND_BTC_BLOCK_HASH, SECOND_TX_OUT_PROOF);
+    let votes = vec![
+        gen_service_tx_finalized(&testkit, ValidatorId(0), &anchored_tx, &first_proof),
+        gen_service_tx_finalized(&testkit, ValidatorId(1), &anchored_tx, &second_proof),
+        gen_service_tx_finalized(&testkit, ValidatorId(2), &anchored_tx, &first_proof),
+    ];
+    execute_finalized_tx_votes(&mut testkit, votes);
+    assert_eq!(testkit.api().nearest_lect(0), None);
+
+    let vote = gen_service_tx_finalized(&testkit, ValidatorId(3), &anchored_tx, &first_proof);
+    execute_finalized_tx_votes(&mut testkit, vec![vote]);
+    let api = testkit.api();
+    assert_eq!(api.nearest_lect(0), Some(anchored_tx));
+    let tx_out_proof = api.anchoring_proof(0).unwrap().tx_out_proof.unwrap();
+    assert_eq!(tx_out_proof, first_proof);
+}
+
+// Testing the proofs of the blocks inclusion into the anchored blocks root.
+#[test]
+fn test_api_anchored_block_proof() {
+    let mut testkit = AnchoringTestKit::new_with_config(|cfg| cfg.commit_blocks_root = true);
+    let anchoring_addr = testkit.current_addr();
+
+    anchor_first_block(&mut testkit);
+    anchor_first_block_lect_normal(&mut testkit);
+    let first_anchored_tx = testkit.latest_anchored_tx();
+
+    anchor_second_block_normal(&mut testkit);
+    let second_anchored_tx = testkit.latest_anchored_tx();
+
+    let client = TestClient::default();
+    let requests = client.requests();
+    let mut observer = AnchoringChainObserver::new_with_client(
+        testkit.blockchain_mut().clone(),
+        Box::new(client),
+        0,
+    );
+    let mut expected_requests = vec![
+        request! {
+            method: "listunspent",
+            params: [0, 9_999_999, [&anchoring_addr]],
+            response: [
+                listunspent_entry(&second_anchored_tx, &anchoring_addr, 10)
+            ]
+        },
+        get_transaction_request(&second_anchored_tx),
+        confirmations_request(&second_anchored_tx, 100),
+    ];
+    expected_requests.extend(tx_out_proof_requests(
+        &second_anchored_tx,
+        SECOND_BTC_BLOCK_HASH,
+        SECOND_TX_OUT_PROOF,
+    ));
+    expected_requests.extend(vec![
+        get_transaction_request(&first_anchored_tx),
+        confirmations_request(&first_anchored_tx, 200),
+    ]);
+    expected_requests.extend(tx_out_proof_requests(
+        &first_anchored_tx,
+        FIRST_BTC_BLOCK_HASH,
+        FIRST_TX_OUT_PROOF,
+    ));
+    expected_requests.push(get_transaction_request(&testkit.current_funding_tx()));
+    requests.expect(expected_requests);
+    observer.check_anchoring_chain().unwrap();
+    let second_proof = TxOutProof::new(SECOND_BTC_BLOCK_HASH, SECOND_TX_OUT_PROOF);
+    force_finalize_anchoring_tx(&mut testkit, &second_anchored_tx, &second_proof);
+
+    let api = testkit.api();
+
+    // The genesis block is committed by the first anchoring transaction.
+    let proof = api.anchored_block_proof(0).unwrap();
+    assert_eq!(proof.tx, first_anchored_tx);
+    assert_eq!(proof.first_block_height, Height::zero());
+    assert_eq!(proof.blocks_count, 1);
+
+    // The intermediate block is committed by the second anchoring transaction.
+    let proof = api.anchored_block_proof(5).unwrap();
+    assert_eq!(proof.tx, second_anchored_tx);
+    assert_eq!(proof.first_block_height, Height(1));
+    assert_eq!(proof.blocks_count, 10);
+    let blocks_root = proof.tx.payload().blocks_root.unwrap();
+    let values = proof.proof.validate(blocks_root, proof.blocks_count).unwrap();
+    assert_eq!(
+        values,
+        vec![(4, &testkit.block_hash_on_height(Height(5)))]
+    );
+
+    // There are no anchoring transactions for heights that greater than 10
+    assert_eq!(api.anchored_block_proof(11), None);
+
+    // The anchoring proof uses the blocks root to link the block to the anchored one.
+    let proof = api.anchoring_proof(5).unwrap();
+    assert_eq!(proof.tx, second_anchored_tx);
+    match proof.link {
+        BlockLink::BlocksRoot {
+            first_block_height,
+            blocks_count,
+            ..
+        } => {
+            assert_eq!(first_block_height, Height(1));
+            assert_eq!(blocks_count, 10);
+        }
+        link => panic!("Unexpected block link {:?}", link),
+    }
+    assert_eq!(
+        proof.tx_out_proof.unwrap().block_hash(),
+        SECOND_BTC_BLOCK_HASH
+    );
 }
@@ -0,0 +1,221 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate bitcoin;
+extern crate exonum_bitcoinrpc as bitcoinrpc;
+extern crate secp256k1;
+extern crate rand;
+extern crate serde;
+extern crate libc;
+extern crate byteorder;
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate serde_json;
+#[macro_use]
+extern crate pretty_assertions;
+extern crate exonum;
+extern crate exonum_btc_anchoring;
+extern crate exonum_testkit;
+
+#[macro_use]
+pub mod testkit_extras;
+
+use exonum_btc_anchoring::{AnchoringNodeConfig, BitcoinRelay, CachingRelay};
+use exonum_btc_anchoring::details::btc;
+use exonum_btc_anchoring::details::btc::transactions::BitcoinTx;
+use testkit_extras::relay::{gen_address, gen_tx, rpc, MockRelay};
+
+fn caching_relay(relay: &MockRelay) -> CachingRelay {
+    CachingRelay::new(Box::new(relay.clone()))
+}
+
+fn read_all(relay: &CachingRelay, tx: &BitcoinTx, addr: &btc::Address) {
+    relay.get_transaction_confirmations(tx.id()).unwrap();
+    relay.unspent_transactions(addr).unwrap();
+    relay.get_block_count().unwrap();
+    relay.estimate_fee_rate(6).unwrap();
+}
+
+#[test]
+fn test_caching_relay_transactions() {
+    let inner = MockRelay::new("counting");
+    let relay = caching_relay(&inner);
+    let tx = gen_tx(&[1]);
+
+    // Unknown transactions are not cached.
+    assert_eq!(relay.get_transaction(tx.id()).unwrap(), None);
+    inner.state().tx = Some(tx.clone());
+    assert_eq!(relay.get_transaction(tx.id()).unwrap(), Some(tx.clone()));
+    assert_eq!(inner.calls("get_transaction"), 2);
+
+    // Raw transactions are cached regardless of the tip.
+    inner.set_tip(Some("a"), 1);
+    relay.begin_check();
+    inner.set_tip(Some("b"), 2);
+    relay.begin_check();
+    assert_eq!(relay.get_transaction(tx.id()).unwrap(), Some(tx.clone()));
+    assert_eq!(inner.calls("get_transaction"), 2);
+
+    // The bodies from the transaction info are cached too.
+    let other_tx = gen_tx(&[2]);
+    inner.state().tx = Some(other_tx.clone());
+    relay.get_transaction_info(other_tx.id()).unwrap();
+    assert_eq!(
+        relay.get_transaction(other_tx.id()).unwrap(),
+        Some(other_tx.clone())
+    );
+    assert_eq!(inner.calls("get_transaction"), 2);
+
+    // Only the transactions which are not cached yet are requested by the batch.
+    let third_tx = gen_tx(&[3]);
+    inner.state().tx = Some(third_tx.clone());
+    assert_eq!(
+        relay
+            .get_transactions(&[tx.id(), third_tx.id(), other_tx.id()])
+            .unwrap(),
+        vec![Some(tx), Some(third_tx), Some(other_tx)]
+    );
+    assert_eq!(inner.calls("get_transaction"), 3);
+}
+
+#[test]
+fn test_caching_relay_tip() {
+    let inner = MockRelay::new("counting");
+    let relay = caching_relay(&inner);
+    let tx = gen_tx(&[1]);
+    let addr = gen_address();
+    {
+        let mut state = inner.state();
+        state.tx = Some(tx.clone());
+        state.confirmations = Some(1);
+        state.unspent = vec![(tx.clone(), 1)];
+        state.fee_rate = Some(10);
+    }
+    inner.set_tip(Some("a"), 100);
+
+    relay.begin_check();
+    read_all(&relay, &tx, &addr);
+    read_all(&relay, &tx, &addr);
+    // The tip has not changed.
+    relay.begin_check();
+    read_all(&relay, &tx, &addr);
+    for method in &[
+        "get_transaction_info",
+        "unspent_transactions",
+        "get_block_count",
+        "estimate_fee_rate",
+    ]
+    {
+        assert_eq!(inner.calls(method), 1, "{}", method);
+    }
+    assert_eq!(inner.calls("get_best_block_hash"), 2);
+
+    // The changes are not visible until the tip changes.
+    {
+        let mut state = inner.state();
+        state.confirmations = Some(2);
+        state.unspent = vec![(tx.clone(), 2)];
+    }
+    inner.set_tip(Some("a"), 101);
+    assert_eq!(relay.get_block_count().unwrap(), 100);
+    assert_eq!(relay.get_transaction_confirmations(tx.id()).unwrap(), Some(1));
+
+    inner.set_tip(Some("b"), 101);
+    relay.begin_check();
+    assert_eq!(relay.get_block_count().unwrap(), 101);
+    assert_eq!(relay.get_transaction_confirmations(tx.id()).unwrap(), Some(2));
+    assert_eq!(
+        relay.unspent_transactions(&addr).unwrap()[0].confirmations,
+        Some(2)
+    );
+    assert_eq!(inner.calls("get_block_count"), 2);
+    assert_eq!(inner.calls("unspent_transactions"), 2);
+
+    // Mempool heights are never cached.
+    relay.get_mempool_entry_height(tx.id()).unwrap();
+    relay.get_mempool_entry_height(tx.id()).unwrap();
+    assert_eq!(inner.calls("get_mempool_entry_height"), 2);
+}
+
+#[test]
+fn test_caching_relay_unknown_tip() {
+    let inner = MockRelay::new("counting");
+    let relay = caching_relay(&inner);
+    let tx = gen_tx(&[1]);
+    let addr = gen_address();
+    inner.state().tx = Some(tx.clone());
+
+    // Nothing is cached before the first check.
+    read_all(&relay, &tx, &addr);
+    read_all(&relay, &tx, &addr);
+    assert_eq!(inner.calls("get_block_count"), 2);
+
+    inner.set_tip(Some("a"), 100);
+    relay.begin_check();
+    read_all(&relay, &tx, &addr);
+    read_all(&relay, &tx, &addr);
+    assert_eq!(inner.calls("get_block_count"), 3);
+
+    // The cache is cleared if the tip is unavailable.
+    inner.set_tip(None, 101);
+    relay.begin_check();
+    assert_eq!(relay.get_block_count().unwrap(), 101);
+    assert_eq!(relay.get_block_count().unwrap(), 101);
+    assert_eq!(inner.calls("get_block_count"), 5);
+}
+
+#[test]
+fn test_caching_relay_send_transaction() {
+    let inner = MockRelay::new("counting");
+    let relay = caching_relay(&inner);
+    let tx = gen_tx(&[1]);
+    let addr = gen_address();
+    inner.set_tip(Some("a"), 100);
+
+    relay.begin_check();
+    assert!(relay.unspent_transactions(&addr).unwrap().is_empty());
+    relay.send_transaction(tx.clone()).unwrap();
+    assert_eq!(relay.get_transaction(tx.id()).unwrap(), Some(tx.clone()));
+    assert_eq!(inner.calls("get_transaction"), 0);
+
+    // The unspent outputs are requested again, while the block count is still cached.
+    inner.state().unspent = vec![(tx.clone(), 0)];
+    assert_eq!(relay.unspent_transactions(&addr).unwrap()[0].body, tx);
+    relay.get_block_count().unwrap();
+    relay.get_block_count().unwrap();
+    assert_eq!(inner.calls("unspent_transactions"), 2);
+    assert_eq!(inner.calls("get_block_count"), 1);
+}
+
+#[test]
+fn test_caching_relay_node_config() {
+    let mut node = AnchoringNodeConfig::new(Some(rpc("http://127.0.0.1:18332")));
+    assert!(
+        serde_json::to_value(&node)
+            .unwrap()
+            .get("relay_cache")
+            .is_none()
+    );
+
+    node.relay_cache = true;
+    let value = serde_json::to_value(&node).unwrap();
+    assert_eq!(value["relay_cache"], serde_json::Value::Bool(true));
+    let parsed: AnchoringNodeConfig = serde_json::from_value(value).unwrap();
+    assert_eq!(parsed, node);
+    assert_eq!(
+        node.relay().unwrap().config().host,
+        "http://127.0.0.1:18332"
+    );
+}
@@ -0,0 +1,434 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate bitcoin;
+extern crate exonum_bitcoinrpc as bitcoinrpc;
+extern crate secp256k1;
+extern crate rand;
+extern crate serde;
+extern crate libc;
+extern crate byteorder;
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate serde_json;
+#[macro_use]
+extern crate pretty_assertions;
+extern crate exonum;
+extern crate exonum_btc_anchoring;
+extern crate exonum_testkit;
+
+#[macro_use]
+pub mod testkit_extras;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::thread::{self, JoinHandle};
+
+use bitcoin::network::serialize::{serialize, BitcoinHash};
+use bitcoin::util::hash::Sha256dHash;
+use serde_json::Value;
+
+use exonum::crypto::hash;
+use exonum::encoding::serialize::encode_hex;
+use exonum::helpers::Height;
+
+use exonum_btc_anchoring::{BitcoinRelay, ElectrumClient, RelayBackend};
+use exonum_btc_anchoring::api::{AnchoringProof, BlockLink};
+use exonum_btc_anchoring::details::btc;
+use exonum_btc_anchoring::details::btc::transactions::BitcoinTx;
+use exonum_btc_anchoring::verifier::{Check, ProofVerifier};
+use testkit_extras::helpers::{gen_anchoring_tx, gen_funding_tx, gen_multisig_address,
+                              merkle_branch, mine_header};
+
+/// Expected request to the fake Electrum server with the response to it.
+struct Exchange {
+    method: &'static str,
+    params: Value,
+    response: Value,
+}
+
+fn ok(method: &'static str, params: Value, result: Value) -> Exchange {
+    Exchange {
+        method,
+        params,
+        response: json!({ "result": result }),
+    }
+}
+
+fn err(method: &'static str, params: Value, code: i64, message: &str) -> Exchange {
+    Exchange {
+        method,
+        params,
+        response: json!({ "error": { "code": code, "message": message } }),
+    }
+}
+
+/// Runs the fake Electrum server which expects the given requests in order and sends
+/// a notification before every response.
+fn fake_electrum_server(exchanges: Vec<Exchange>) -> (ElectrumClient, JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let handle = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        for exchange in exchanges {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            assert!(
+                !line.is_empty(),
+                "Expected request for method {}",
+                exchange.method
+            );
+            let request: Value = serde_json::from_str(&line).unwrap();
+            assert_eq!(request["method"], exchange.method);
+            assert_eq!(
+                request["params"],
+                exchange.params,
+                "Invalid params for method {}!",
+                exchange.method
+            );
+
+            let notification = json!({
+                "jsonrpc": "2.0",
+                "method": "blockchain.headers.subscribe",
+                "params": [{ "height": 1, "hex": "" }],
+            });
+            let mut response = exchange.response;
+            response["jsonrpc"] = json!("2.0");
+            response["id"] = request["id"].clone();
+            for message in &[notification, response] {
+                writeln!(writer, "{}", message).unwrap();
+            }
+        }
+    });
+    (ElectrumClient::new(format!("tcp://{}", address)), handle)
+}
+
+fn finish(client: ElectrumClient, handle: JoinHandle<()>) {
+    drop(client);
+    handle.join().unwrap();
+}
+
+fn script_hash(addr: &btc::Address) -> String {
+    let hash = hash(&addr.script_pubkey()[..]);
+    encode_hex(hash.as_ref().iter().rev().cloned().collect::<Vec<u8>>())
+}
+
+fn get_raw_tx(tx: &BitcoinTx) -> Exchange {
+    ok(
+        "blockchain.transaction.get",
+        json!([tx.id().to_string(), false]),
+        json!(tx.to_hex()),
+    )
+}
+
+/// History of the address with the given transaction at the given height.
+fn get_history(addr: &btc::Address, tx: &BitcoinTx, height: i64) -> Exchange {
+    ok(
+        "blockchain.scripthash.get_history",
+        json!([script_hash(addr)]),
+        json!([{ "tx_hash": tx.id().to_string(), "height": height }]),
+    )
+}
+
+fn get_block_count(height: u64) -> Exchange {
+    ok(
+        "blockchain.headers.subscribe",
+        json!([]),
+        json!({ "height": height, "hex": "" }),
+    )
+}
+
+#[test]
+fn test_electrum_get_transaction() {
+    let (_, address) = gen_multisig_address();
+    let tx = BitcoinTx::from(gen_funding_tx(&address));
+    let txid = tx.id();
+    let unknown_txid = btc::TxId::from(Sha256dHash::from_data(b"unknown"));
+
+    let (client, handle) = fake_electrum_server(vec![
+        ok(
+            "blockchain.transaction.get",
+            json!([txid.to_string(), false]),
+            json!(tx.to_hex()),
+        ),
+        err(
+            "blockchain.transaction.get",
+            json!([unknown_txid.to_string(), false]),
+            2,
+            r#"{"code": -5, "message": "No such mempool or blockchain transaction"}"#,
+        ),
+        get_raw_tx(&tx),
+        get_history(&address, &tx, 105),
+        get_block_count(109),
+        get_raw_tx(&tx),
+        get_history(&address, &tx, 0),
+        get_block_count(109),
+        // The transaction is not in the script history.
+        get_raw_tx(&tx),
+        ok(
+            "blockchain.scripthash.get_history",
+            json!([script_hash(&address)]),
+            json!([]),
+        ),
+    ]);
+
+    assert_eq!(client.get_transaction(txid).unwrap(), Some(tx.clone()));
+    assert_eq!(client.get_transaction(unknown_txid).unwrap(), None);
+    assert_eq!(client.get_transaction_confirmations(txid).unwrap(), Some(5));
+    assert_eq!(client.get_transaction_confirmations(txid).unwrap(), Some(0));
+    let info = client.get_transaction_info(txid).unwrap().unwrap();
+    assert_eq!(info.body, tx);
+    assert_eq!(info.confirmations, None);
+    finish(client, handle);
+}
+
+#[test]
+fn test_electrum_unspent_transactions() {
+    let (_, address) = gen_multisig_address();
+    let funding_tx = BitcoinTx::from(gen_funding_tx(&address));
+    let script_hash = script_hash(&address);
+
+    let (client, handle) = fake_electrum_server(vec![
+        ok(
+            "blockchain.scripthash.subscribe",
+            json!([script_hash]),
+            Value::Null,
+        ),
+        ok(
+            "blockchain.scripthash.subscribe",
+            json!([script_hash]),
+            json!("status"),
+        ),
+        ok(
+            "blockchain.scripthash.listunspent",
+            json!([script_hash]),
+            json!([
+                { "tx_hash": funding_tx.id().to_string(), "tx_pos": 0, "height": 100, "value": 3000 }
+            ]),
+        ),
+        ok(
+            "blockchain.transaction.get",
+            json!([funding_tx.id().to_string(), false]),
+            json!(funding_tx.to_hex()),
+        ),
+        ok(
+            "blockchain.headers.subscribe",
+            json!([]),
+            json!({ "height": 109, "hex": "" }),
+        ),
+        // The outputs are not requested again for the same status.
+        ok(
+            "blockchain.scripthash.subscribe",
+            json!([script_hash]),
+            json!("status"),
+        ),
+        ok(
+            "blockchain.headers.subscribe",
+            json!([]),
+            json!({ "height": 110, "hex": "" }),
+        ),
+    ]);
+
+    client.watch_address(&address, true).unwrap();
+    let txs = client.unspent_transactions(&address).unwrap();
+    assert_eq!(txs.len(), 1);
+    assert_eq!(txs[0].body, funding_tx);
+    assert_eq!(txs[0].confirmations, Some(10));
+    assert_eq!(txs[0].vout, Some(0));
+
+    let txs = client.unspent_transactions(&address).unwrap();
+    assert_eq!(txs.len(), 1);
+    assert_eq!(txs[0].confirmations, Some(11));
+    finish(client, handle);
+}
+
+#[test]
+fn test_electrum_send_transaction() {
+    let (_, address) = gen_multisig_address();
+    let tx = BitcoinTx::from(gen_funding_tx(&address));
+
+    let (client, handle) = fake_electrum_server(vec![
+        ok(
+            "blockchain.transaction.broadcast",
+            json!([tx.to_hex()]),
+            json!(tx.id().to_string()),
+        ),
+        err(
+            "blockchain.transaction.broadcast",
+            json!([tx.to_hex()]),
+            2,
+            r#"{"code": -26, "message": "mandatory-script-verify-flag-failed"}"#,
+        ),
+    ]);
+
+    client.send_transaction(tx.clone()).unwrap();
+    assert!(client.send_transaction(tx).is_err());
+    // Electrum servers do not hold the wallet.
+    assert!(client.send_to_address(&address, 1000).is_err());
+    finish(client, handle);
+}
+
+#[test]
+fn test_electrum_fee_rate_and_mempool() {
+    let (_, address) = gen_multisig_address();
+    let tx = BitcoinTx::from(gen_funding_tx(&address));
+    let txid = tx.id();
+
+    let (client, handle) = fake_electrum_server(vec![
+        ok("blockchain.estimatefee", json!([6]), json!(0.0002)),
+        ok("blockchain.estimatefee", json!([6]), json!(-1)),
+        get_raw_tx(&tx),
+        get_history(&address, &tx, 0),
+        get_block_count(100),
+        // The first seen height is kept while the transaction is unconfirmed.
+        get_raw_tx(&tx),
+        get_history(&address, &tx, -1),
+        get_raw_tx(&tx),
+        get_history(&address, &tx, 101),
+    ]);
+
+    assert_eq!(client.estimate_fee_rate(6).unwrap(), Some(20));
+    assert_eq!(client.estimate_fee_rate(6).unwrap(), None);
+    assert_eq!(client.get_mempool_entry_height(txid).unwrap(), Some(100));
+    assert_eq!(client.get_mempool_entry_height(txid).unwrap(), Some(100));
+    assert_eq!(client.get_mempool_entry_height(txid).unwrap(), None);
+    finish(client, handle);
+}
+
+#[test]
+fn test_electrum_tx_out_confirmations() {
+    let (_, address) = gen_multisig_address();
+    let tx = BitcoinTx::from(gen_funding_tx(&address));
+    let txid = tx.id();
+    let script_hash = script_hash(&address);
+    let get_tx = || {
+        ok(
+            "blockchain.transaction.get",
+            json!([txid.to_string(), false]),
+            json!(tx.to_hex()),
+        )
+    };
+
+    let (client, handle) = fake_electrum_server(vec![
+        get_tx(),
+        ok(
+            "blockchain.scripthash.listunspent",
+            json!([script_hash]),
+            json!([{ "tx_hash": txid.to_string(), "tx_pos": 0, "height": 100, "value": 3000 }]),
+        ),
+        ok(
+            "blockchain.headers.subscribe",
+            json!([]),
+            json!({ "height": 109, "hex": "" }),
+        ),
+        // The output spent in the mempool is not listed.
+        get_tx(),
+        ok("blockchain.scripthash.listunspent", json!([script_hash]), json!([])),
+        // The output index is out of range.
+        get_tx(),
+    ]);
+
+    assert_eq!(client.get_tx_out_confirmations(txid, 0).unwrap(), Some(10));
+    assert_eq!(client.get_tx_out_confirmations(txid, 0).unwrap(), None);
+    assert_eq!(client.get_tx_out_confirmations(txid, 1).unwrap(), None);
+    finish(client, handle);
+}
+
+// The proof built from the merkle branch is verified as the `gettxoutproof` one.
+#[test]
+fn test_electrum_tx_out_proof() {
+    let (redeem_script, address) = gen_multisig_address();
+    let tx = gen_anchoring_tx(&address);
+    let txid = tx.id();
+
+    // The transaction is not the last one and it is the last one with the duplicates.
+    for &(count, pos) in &[(3, 1), (5, 4), (6, 2), (1, 0)] {
+        let mut txids = (0..count)
+            .map(|i| Sha256dHash::from_data(&[i as u8]))
+            .collect::<Vec<_>>();
+        txids[pos] = *txid;
+        let (merkle_root, branch) = merkle_branch(&txids, pos);
+        let header = mine_header(merkle_root);
+
+        let raw_tx = BitcoinTx::from(tx.0.clone());
+        let (client, handle) = fake_electrum_server(vec![
+            get_raw_tx(&raw_tx),
+            get_history(&address, &raw_tx, 100),
+            ok(
+                "blockchain.transaction.get_merkle",
+                json!([txid.to_string(), 100]),
+                json!({
+                    "block_height": 100,
+                    "merkle": branch.iter().map(|h| h.be_hex_string()).collect::<Vec<_>>(),
+                    "pos": pos,
+                }),
+            ),
+            ok(
+                "blockchain.block.header",
+                json!([100]),
+                json!(encode_hex(serialize(&header).unwrap())),
+            ),
+            ok(
+                "blockchain.headers.subscribe",
+                json!([]),
+                json!({ "height": 100, "hex": encode_hex(serialize(&header).unwrap()) }),
+            ),
+        ]);
+        let tx_out_proof = client.get_tx_out_proof(txid).unwrap().unwrap();
+        assert_eq!(
+            client.get_best_block_hash().unwrap(),
+            header.bitcoin_hash().be_hex_string()
+        );
+        finish(client, handle);
+
+        let proof = AnchoringProof {
+            block_height: Height(10),
+            block_hash: hash(&[1]),
+            payload: tx.payload(),
+            link: BlockLink::Headers { headers: Vec::new() },
+            tx: tx.clone(),
+            tx_out_proof: Some(tx_out_proof),
+        };
+        let verdict = ProofVerifier::new(redeem_script.clone(), vec![header]).verify(&proof);
+        assert_eq!(
+            verdict.merkle_inclusion,
+            Check::Passed,
+            "{} transactions",
+            count
+        );
+    }
+}
+
+#[test]
+fn test_electrum_config() {
+    let client = ElectrumClient::new("tcp://127.0.0.1:50001");
+    let config = client.config();
+    assert_eq!(config.host, "tcp://127.0.0.1:50001");
+    assert_eq!(config.backend, RelayBackend::Electrum);
+
+    let relay: Box<BitcoinRelay> = config.clone().into();
+    assert_eq!(relay.config(), config);
+    assert_eq!(
+        serde_json::to_value(&config).unwrap(),
+        json!({
+            "host": "tcp://127.0.0.1:50001",
+            "username": null,
+            "password": null,
+            "backend": "electrum",
+        })
+    );
+}
@@ -0,0 +1,372 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate bitcoin;
+extern crate exonum_bitcoinrpc as bitcoinrpc;
+extern crate secp256k1;
+extern crate rand;
+extern crate serde;
+extern crate libc;
+extern crate byteorder;
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate serde_json;
+#[macro_use]
+extern crate pretty_assertions;
+extern crate exonum;
+extern crate exonum_btc_anchoring;
+extern crate exonum_testkit;
+
+#[macro_use]
+pub mod testkit_extras;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::thread::{self, JoinHandle};
+
+use bitcoin::network::serialize::{serialize, BitcoinHash};
+use bitcoin::util::hash::Sha256dHash;
+use serde_json::Value;
+
+use exonum::crypto::hash;
+use exonum::encoding::serialize::encode_hex;
+use exonum::helpers::Height;
+
+use exonum_btc_anchoring::{BitcoinRelay, EsploraClient, RelayBackend};
+use exonum_btc_anchoring::api::{AnchoringProof, BlockLink};
+use exonum_btc_anchoring::details::btc;
+use exonum_btc_anchoring::details::btc::transactions::BitcoinTx;
+use exonum_btc_anchoring::verifier::{Check, ProofVerifier};
+use testkit_extras::helpers::{gen_anchoring_tx, gen_funding_tx, gen_multisig_address,
+                              mine_header};
+
+/// Expected request to the fake Esplora server with the response to it.
+struct Exchange {
+    method: &'static str,
+    path: String,
+    body: String,
+    status: u16,
+    response: String,
+}
+
+fn get(path: &str, status: u16, response: &str) -> Exchange {
+    Exchange {
+        method: "GET",
+        path: path.to_owned(),
+        body: String::new(),
+        status,
+        response: response.to_owned(),
+    }
+}
+
+fn get_json(path: &str, response: Value) -> Exchange {
+    get(path, 200, &response.to_string())
+}
+
+fn post(path: &str, body: &str, status: u16, response: &str) -> Exchange {
+    Exchange {
+        method: "POST",
+        path: path.to_owned(),
+        body: body.to_owned(),
+        status,
+        response: response.to_owned(),
+    }
+}
+
+/// Runs the fake Esplora server which expects the given requests in order and closes
+/// the connection after every response.
+fn fake_esplora_server(exchanges: Vec<Exchange>) -> (EsploraClient, JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let handle = thread::spawn(move || for exchange in exchanges {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).unwrap();
+        assert_eq!(
+            request_line.trim(),
+            format!("{} /api{} HTTP/1.1", exchange.method, exchange.path)
+        );
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+            if header.trim().is_empty() {
+                break;
+            }
+            let header = header.to_lowercase();
+            if header.starts_with("content-length:") {
+                content_length = header["content-length:".len()..].trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+        assert_eq!(
+            String::from_utf8(body).unwrap(),
+            exchange.body,
+            "Invalid body for {}",
+            exchange.path
+        );
+
+        write!(
+            writer,
+            "HTTP/1.1 {} Status\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            exchange.status,
+            exchange.response.len(),
+            exchange.response
+        ).unwrap();
+    });
+    (EsploraClient::new(format!("http://{}/api/", address)), handle)
+}
+
+fn finish(client: EsploraClient, handle: JoinHandle<()>) {
+    drop(client);
+    handle.join().unwrap();
+}
+
+#[test]
+fn test_esplora_get_transaction() {
+    let (_, address) = gen_multisig_address();
+    let tx = BitcoinTx::from(gen_funding_tx(&address));
+    let txid = tx.id();
+    let unknown_txid = btc::TxId::from(Sha256dHash::from_data(b"unknown"));
+
+    let (client, handle) = fake_esplora_server(vec![
+        get(&format!("/tx/{}/hex", txid.to_string()), 200, &tx.to_hex()),
+        get(
+            &format!("/tx/{}/hex", unknown_txid.to_string()),
+            404,
+            "Transaction not found",
+        ),
+        get_json(
+            &format!("/tx/{}/status", txid.to_string()),
+            json!({ "confirmed": true, "block_height": 96, "block_hash": "00" }),
+        ),
+        get(&format!("/tx/{}/hex", txid.to_string()), 200, &tx.to_hex()),
+        get("/blocks/tip/height", 200, "100"),
+        get_json(
+            &format!("/tx/{}/status", txid.to_string()),
+            json!({ "confirmed": false }),
+        ),
+        get(&format!("/tx/{}/hex", txid.to_string()), 200, &tx.to_hex()),
+        get("/blocks/tip/height", 200, "100"),
+        get(
+            &format!("/tx/{}/status", unknown_txid.to_string()),
+            404,
+            "Transaction not found",
+        ),
+        get("/blocks/tip/height", 500, "Internal error"),
+    ]);
+
+    assert_eq!(client.get_transaction(txid).unwrap(), Some(tx.clone()));
+    assert_eq!(client.get_transaction(unknown_txid).unwrap(), None);
+    assert_eq!(client.get_transaction_confirmations(txid).unwrap(), Some(5));
+    let info = client.get_transaction_info(txid).unwrap().unwrap();
+    assert_eq!(info.body, tx);
+    assert_eq!(info.confirmations, Some(0));
+    assert!(client.get_transaction_info(unknown_txid).unwrap().is_none());
+    assert!(client.get_block_count().is_err());
+    finish(client, handle);
+}
+
+#[test]
+fn test_esplora_unspent_transactions() {
+    let (_, address) = gen_multisig_address();
+    let funding_tx = BitcoinTx::from(gen_funding_tx(&address));
+    let utxo = json!([
+        {
+            "txid": funding_tx.id().to_string(),
+            "vout": 0,
+            "status": { "confirmed": true, "block_height": 100, "block_hash": "00" },
+            "value": 3000,
+        }
+    ]);
+    let utxo_path = format!("/address/{}/utxo", address);
+
+    let (client, handle) = fake_esplora_server(vec![
+        get_json(&utxo_path, utxo.clone()),
+        get("/blocks/tip/height", 200, "109"),
+        get(
+            &format!("/tx/{}/hex", funding_tx.id().to_string()),
+            200,
+            &funding_tx.to_hex(),
+        ),
+        // The transaction body is not requested again.
+        get_json(&utxo_path, utxo),
+        get("/blocks/tip/height", 200, "110"),
+    ]);
+
+    // Esplora servers index all addresses.
+    client.watch_address(&address, true).unwrap();
+    let txs = client.unspent_transactions(&address).unwrap();
+    assert_eq!(txs.len(), 1);
+    assert_eq!(txs[0].body, funding_tx);
+    assert_eq!(txs[0].confirmations, Some(10));
+    assert_eq!(txs[0].vout, Some(0));
+
+    let txs = client.unspent_transactions(&address).unwrap();
+    assert_eq!(txs.len(), 1);
+    assert_eq!(txs[0].confirmations, Some(11));
+    finish(client, handle);
+}
+
+#[test]
+fn test_esplora_send_transaction() {
+    let (_, address) = gen_multisig_address();
+    let tx = BitcoinTx::from(gen_funding_tx(&address));
+
+    let (client, handle) = fake_esplora_server(vec![
+        post("/tx", &tx.to_hex(), 200, &tx.id().to_string()),
+        post(
+            "/tx",
+            &tx.to_hex(),
+            400,
+            r#"sendrawtransaction RPC error: {"code":-26,"message":"mandatory-script-verify-flag-failed"}"#,
+        ),
+    ]);
+
+    client.send_transaction(tx.clone()).unwrap();
+    assert!(client.send_transaction(tx).is_err());
+    // Esplora servers do not hold the wallet.
+    assert!(client.send_to_address(&address, 1000).is_err());
+    finish(client, handle);
+}
+
+#[test]
+fn test_esplora_fee_rate_and_mempool() {
+    let (_, address) = gen_multisig_address();
+    let tx = BitcoinTx::from(gen_funding_tx(&address));
+    let txid = tx.id();
+    let status_path = format!("/tx/{}/status", txid.to_string());
+    let estimates = json!({ "1": 30.5, "2": 25.0, "3": 19.2, "6": 10.0, "144": 1.0 });
+
+    let (client, handle) = fake_esplora_server(vec![
+        get_json("/fee-estimates", estimates.clone()),
+        get_json("/fee-estimates", estimates),
+        get_json("/fee-estimates", json!({})),
+        get_json(&status_path, json!({ "confirmed": false })),
+        get("/blocks/tip/height", 200, "100"),
+        // The first seen height is kept while the transaction is unconfirmed.
+        get_json(&status_path, json!({ "confirmed": false })),
+        get_json(
+            &status_path,
+            json!({ "confirmed": true, "block_height": 101, "block_hash": "00" }),
+        ),
+    ]);
+
+    assert_eq!(client.estimate_fee_rate(6).unwrap(), Some(10));
+    // The closest faster target is used.
+    assert_eq!(client.estimate_fee_rate(5).unwrap(), Some(20));
+    assert_eq!(client.estimate_fee_rate(6).unwrap(), None);
+    assert_eq!(client.get_mempool_entry_height(txid).unwrap(), Some(100));
+    assert_eq!(client.get_mempool_entry_height(txid).unwrap(), Some(100));
+    assert_eq!(client.get_mempool_entry_height(txid).unwrap(), None);
+    finish(client, handle);
+}
+
+#[test]
+fn test_esplora_tx_out_confirmations() {
+    let (_, address) = gen_multisig_address();
+    let tx = BitcoinTx::from(gen_funding_tx(&address));
+    let txid = tx.id();
+    let outspend_path = format!("/tx/{}/outspend/0", txid.to_string());
+
+    let (client, handle) = fake_esplora_server(vec![
+        get_json(&outspend_path, json!({ "spent": false })),
+        get(&format!("/tx/{}/hex", txid.to_string()), 200, &tx.to_hex()),
+        get_json(
+            &format!("/tx/{}/status", txid.to_string()),
+            json!({ "confirmed": true, "block_height": 100, "block_hash": "00" }),
+        ),
+        get("/blocks/tip/height", 200, "109"),
+        get_json(
+            &outspend_path,
+            json!({ "spent": true, "txid": "00", "vin": 0, "status": { "confirmed": false } }),
+        ),
+    ]);
+
+    assert_eq!(client.get_tx_out_confirmations(txid, 0).unwrap(), Some(10));
+    assert_eq!(client.get_tx_out_confirmations(txid, 0).unwrap(), None);
+    finish(client, handle);
+}
+
+#[test]
+fn test_esplora_tx_out_proof() {
+    let (redeem_script, address) = gen_multisig_address();
+    let tx = gen_anchoring_tx(&address);
+    let txid = tx.id();
+    let status_path = format!("/tx/{}/status", txid.to_string());
+
+    let header = mine_header(*txid);
+    let block_hash = header.bitcoin_hash().be_hex_string();
+    // The block with the single transaction.
+    let mut merkle_block = serialize(&header).unwrap();
+    merkle_block.extend(serialize(&1u32).unwrap());
+    merkle_block.extend(serialize(&vec![*txid]).unwrap());
+    merkle_block.extend(serialize(&vec![1u8]).unwrap());
+
+    let (client, handle) = fake_esplora_server(vec![
+        get_json(&status_path, json!({ "confirmed": false })),
+        get_json(
+            &status_path,
+            json!({ "confirmed": true, "block_height": 100, "block_hash": block_hash }),
+        ),
+        get(
+            &format!("/tx/{}/merkleblock-proof", txid.to_string()),
+            200,
+            &encode_hex(&merkle_block),
+        ),
+        get("/blocks/tip/hash", 200, &block_hash),
+    ]);
+    assert!(client.get_tx_out_proof(txid).unwrap().is_none());
+    let tx_out_proof = client.get_tx_out_proof(txid).unwrap().unwrap();
+    assert_eq!(client.get_best_block_hash().unwrap(), block_hash);
+    finish(client, handle);
+    assert_eq!(tx_out_proof.block_hash(), block_hash);
+
+    let proof = AnchoringProof {
+        block_height: Height(10),
+        block_hash: hash(&[1]),
+        payload: tx.payload(),
+        link: BlockLink::Headers { headers: Vec::new() },
+        tx: tx.clone(),
+        tx_out_proof: Some(tx_out_proof),
+    };
+    let verdict = ProofVerifier::new(redeem_script, vec![header]).verify(&proof);
+    assert_eq!(verdict.merkle_inclusion, Check::Passed);
+}
+
+#[test]
+fn test_esplora_config() {
+    let client = EsploraClient::new("http://127.0.0.1:3002/api");
+    let config = client.config();
+    assert_eq!(config.host, "http://127.0.0.1:3002/api");
+    assert_eq!(config.backend, RelayBackend::Esplora);
+
+    let relay: Box<BitcoinRelay> = config.clone().into();
+    assert_eq!(relay.config(), config);
+    assert_eq!(
+        serde_json::to_value(&config).unwrap(),
+        json!({
+            "host": "http://127.0.0.1:3002/api",
+            "username": null,
+            "password": null,
+            "backend": "esplora",
+        })
+    );
+    assert_eq!("esplora".parse::<RelayBackend>(), Ok(RelayBackend::Esplora));
+}
@@ -0,0 +1,229 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate bitcoin;
+extern crate exonum_bitcoinrpc as bitcoinrpc;
+extern crate secp256k1;
+extern crate rand;
+extern crate serde;
+extern crate libc;
+extern crate byteorder;
+extern crate hyper;
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate serde_json;
+#[macro_use]
+extern crate pretty_assertions;
+extern crate exonum;
+extern crate exonum_btc_anchoring;
+extern crate exonum_testkit;
+
+#[macro_use]
+pub mod testkit_extras;
+
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use exonum_btc_anchoring::{AnchoringNodeConfig, BitcoinRelay, FailoverRelay, RelayRetryConfig};
+use exonum_btc_anchoring::details::rpc::Error;
+use testkit_extras::relay::{gen_address, gen_tx, rpc, MockRelay, RelayResponse};
+
+/// Connection refused.
+fn down() -> RelayResponse {
+    let e = io::Error::new(io::ErrorKind::ConnectionRefused, "Connection refused");
+    RelayResponse::Fail(Error::Transport(hyper::Error::Io(e)))
+}
+
+/// Bitcoind is loading the blocks.
+fn warmup() -> RelayResponse {
+    RelayResponse::Fail(Error::rpc(-28, "Loading block index..."))
+}
+
+/// Bitcoind does not know the requested object.
+fn missing() -> RelayResponse {
+    RelayResponse::Fail(Error::Rpc(bitcoinrpc::Error::NoInformation(
+        "No such mempool or blockchain transaction".to_owned(),
+    )))
+}
+
+fn failover_relay(relays: &[&MockRelay], retry: RelayRetryConfig) -> FailoverRelay {
+    let relays = relays
+        .iter()
+        .map(|&relay| Box::new(relay.clone()) as Box<BitcoinRelay>)
+        .collect();
+    FailoverRelay::new(relays, retry)
+}
+
+/// Returns the number of the requests which have been sent to the relay.
+fn calls(relay: &MockRelay) -> usize {
+    relay.calls("get_block_count") + relay.calls("send_to_address")
+}
+
+fn retry_config(attempts: u32) -> RelayRetryConfig {
+    RelayRetryConfig {
+        timeout: 100,
+        attempts,
+        initial_backoff: 1,
+        max_backoff: 2,
+        failure_threshold: 2,
+        cooldown: 300,
+    }
+}
+
+#[test]
+fn test_failover_relay_retries() {
+    use RelayResponse::*;
+
+    let primary = MockRelay::with_responses("primary", vec![down(), warmup(), Count(5)]);
+    let fallback = MockRelay::new("fallback");
+    let relay = failover_relay(&[&primary, &fallback], retry_config(3));
+    assert_eq!(relay.get_block_count().unwrap(), 5);
+    assert_eq!(calls(&primary), 3);
+    assert_eq!(calls(&fallback), 0);
+}
+
+#[test]
+fn test_failover_relay_fallback() {
+    use RelayResponse::*;
+
+    let primary = MockRelay::with_responses("primary", vec![down(), Hang]);
+    let fallback = MockRelay::with_responses("fallback", vec![Count(7)]);
+    let relay = failover_relay(&[&primary, &fallback], retry_config(2));
+    assert_eq!(relay.get_block_count().unwrap(), 7);
+    assert_eq!(calls(&primary), 2);
+    assert_eq!(calls(&fallback), 1);
+    assert_eq!(relay.config().host, "primary");
+}
+
+// Answers of the endpoint are neither retried nor sent to the fallback endpoints.
+#[test]
+fn test_failover_relay_answers() {
+    let primary = MockRelay::with_responses("primary", vec![missing(), down()]);
+    let fallback = MockRelay::new("fallback");
+    let relay = failover_relay(&[&primary, &fallback], retry_config(3));
+    match relay.get_block_count() {
+        Err(Error::Rpc(bitcoinrpc::Error::NoInformation(_))) => {}
+        other => panic!("Unexpected response {:?}", other),
+    }
+    // Funds are never sent twice.
+    assert!(relay.send_to_address(&gen_address(), 1000).is_err());
+    assert_eq!(calls(&primary), 2);
+    assert_eq!(calls(&fallback), 0);
+}
+
+#[test]
+fn test_failover_relay_circuit_breaker() {
+    use RelayResponse::*;
+
+    let primary = MockRelay::with_responses("primary", vec![down(), down(), Count(4)]);
+    let fallback = MockRelay::with_responses("fallback", vec![Count(1), Count(2), Count(3)]);
+    let relay = failover_relay(&[&primary, &fallback], retry_config(1));
+    assert_eq!(relay.get_block_count().unwrap(), 1);
+    assert_eq!(relay.get_block_count().unwrap(), 2);
+    // The primary endpoint is cooling down after two failed requests.
+    assert_eq!(relay.get_block_count().unwrap(), 3);
+    assert_eq!(calls(&primary), 2);
+
+    thread::sleep(Duration::from_millis(400));
+    assert_eq!(relay.get_block_count().unwrap(), 4);
+    assert_eq!(calls(&primary), 3);
+
+    // All endpoints are cooling down, so the request is not sent at all.
+    let primary = MockRelay::with_responses("primary", vec![down(), down()]);
+    let relay = failover_relay(&[&primary], retry_config(1));
+    assert!(relay.get_block_count().is_err());
+    assert!(relay.get_block_count().is_err());
+    assert!(relay.get_block_count().is_err());
+    assert_eq!(calls(&primary), 2);
+}
+
+#[test]
+fn test_failover_relay_watch_address() {
+    let primary = MockRelay::new("primary");
+    let fallback = MockRelay::new("fallback");
+    let relay = failover_relay(&[&primary, &fallback], retry_config(1));
+    let addr = gen_address();
+
+    fallback.state().fail = true;
+    relay.watch_address(&addr, false).unwrap();
+    assert_eq!(primary.calls("watch_address"), 1);
+    assert_eq!(fallback.calls("watch_address"), 1);
+
+    // The fallback endpoint watches the address on the next checks.
+    relay.begin_check();
+    assert_eq!(fallback.calls("watch_address"), 2);
+    fallback.state().fail = false;
+    relay.begin_check();
+    relay.begin_check();
+    assert_eq!(fallback.calls("watch_address"), 3);
+    assert_eq!(primary.calls("watch_address"), 1);
+
+    // The address is not watched at all.
+    primary.state().fail = true;
+    fallback.state().fail = true;
+    assert!(relay.watch_address(&addr, false).is_err());
+}
+
+#[test]
+fn test_failover_relay_resent_transaction() {
+    use RelayResponse::*;
+
+    // The timed out request has sent the transaction.
+    let rejected = || Fail(Error::rpc(-26, "txn-already-in-mempool"));
+    let primary = MockRelay::with_responses("primary", vec![Hang, rejected()]);
+    let relay = failover_relay(&[&primary], retry_config(2));
+    relay.send_transaction(gen_tx(b"resent")).unwrap();
+    assert_eq!(primary.calls("send_transaction"), 2);
+
+    let in_chain = Fail(Error::rpc(-27, "transaction already in block chain"));
+    let primary = MockRelay::with_responses("primary", vec![down(), in_chain]);
+    let relay = failover_relay(&[&primary], retry_config(2));
+    relay.send_transaction(gen_tx(b"resent")).unwrap();
+
+    // The first attempt is not retried, so the rejection is the answer of the endpoint.
+    let primary = MockRelay::with_responses("primary", vec![rejected()]);
+    let relay = failover_relay(&[&primary], retry_config(2));
+    assert!(relay.send_transaction(gen_tx(b"resent")).is_err());
+    assert_eq!(primary.calls("send_transaction"), 1);
+}
+
+#[test]
+fn test_failover_relay_node_config() {
+    let mut node = AnchoringNodeConfig::new(Some(rpc("http://127.0.0.1:18332")));
+    let value = serde_json::to_value(&node).unwrap();
+    assert!(value.get("fallback_rpc").is_none());
+    assert!(value.get("relay_retry").is_none());
+
+    node.fallback_rpc = vec![rpc("http://127.0.0.2:18332")];
+    node.relay_retry = Some(retry_config(5));
+    let value = serde_json::to_value(&node).unwrap();
+    let parsed: AnchoringNodeConfig = serde_json::from_value(value).unwrap();
+    assert_eq!(parsed, node);
+    assert_eq!(
+        node.relay().unwrap().config().host,
+        "http://127.0.0.1:18332"
+    );
+
+    // Omitted fields of the retry policy have the default values.
+    let retry: RelayRetryConfig = serde_json::from_value(json!({ "attempts": 5 })).unwrap();
+    assert_eq!(
+        retry,
+        RelayRetryConfig {
+            attempts: 5,
+            ..Default::default()
+        }
+    );
+}
@@ -0,0 +1,246 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate bitcoin;
+extern crate exonum_bitcoinrpc as bitcoinrpc;
+extern crate secp256k1;
+extern crate rand;
+extern crate serde;
+extern crate libc;
+extern crate byteorder;
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate serde_json;
+#[macro_use]
+extern crate pretty_assertions;
+extern crate exonum;
+extern crate exonum_btc_anchoring;
+extern crate exonum_testkit;
+
+#[macro_use]
+pub mod testkit_extras;
+
+use std::sync::mpsc;
+
+use exonum_btc_anchoring::{AnchoringHandler, AnchoringNodeConfig, BitcoinRelay, QuorumRelay};
+use exonum_btc_anchoring::details::btc::transactions::BitcoinTx;
+use exonum_btc_anchoring::details::rpc::Error;
+use exonum_btc_anchoring::error::HandlerError;
+use testkit_extras::relay::{gen_address, gen_tx, rpc, MockRelay};
+
+/// Creates the relay which returns the given transaction with its confirmations.
+fn tx_relay(host: &str, tx: BitcoinTx, confirmations: u64) -> MockRelay {
+    let relay = MockRelay::new(host);
+    {
+        let mut state = relay.state();
+        state.tx = Some(tx);
+        state.confirmations = Some(confirmations);
+    }
+    relay
+}
+
+fn down_relay(host: &str) -> MockRelay {
+    let relay = MockRelay::new(host);
+    relay.state().fail = true;
+    relay
+}
+
+fn quorum_relay(relays: &[MockRelay], quorum: usize) -> QuorumRelay {
+    let relays = relays
+        .iter()
+        .cloned()
+        .map(|relay| Box::new(relay) as Box<BitcoinRelay>)
+        .collect();
+    QuorumRelay::new(relays, quorum)
+}
+
+// The lying minority is outvoted and reported.
+#[test]
+fn test_quorum_relay_reads() {
+    let tx = gen_tx(b"tx");
+    let honest = tx_relay("honest", tx.clone(), 5);
+    let lagging = tx_relay("lagging", tx.clone(), 3);
+    let liar = tx_relay("liar", gen_tx(b"fake"), 100);
+    let tips = [(&honest, 100, 10), (&lagging, 98, 12), (&liar, 1000, 1000)];
+    for &(relay, block_count, fee_rate) in &tips {
+        let mut state = relay.state();
+        state.block_count = block_count;
+        state.fee_rate = Some(fee_rate);
+    }
+
+    let relay = quorum_relay(&[honest, lagging, liar], 2);
+    assert_eq!(relay.quorum(), 2);
+    assert_eq!(relay.get_transaction(tx.id()).unwrap(), Some(tx.clone()));
+    assert_eq!(
+        relay.get_transaction_confirmations(tx.id()).unwrap(),
+        Some(3)
+    );
+    assert_eq!(relay.get_block_count().unwrap(), 100);
+    assert_eq!(relay.estimate_fee_rate(6).unwrap(), Some(12));
+    assert_eq!(relay.config().host, "honest");
+
+    let disagreements = relay.take_disagreements();
+    let methods = disagreements
+        .iter()
+        .map(|d| d.method.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(methods, vec!["get_transaction", "get_transaction_info"]);
+    assert_eq!(disagreements[0].responses.len(), 1);
+    assert!(disagreements[0].responses[0].starts_with("liar returned"));
+    assert!(relay.take_disagreements().is_empty());
+}
+
+#[test]
+fn test_quorum_relay_no_quorum() {
+    let first = tx_relay("first", gen_tx(b"first"), 1);
+    let second = tx_relay("second", gen_tx(b"second"), 1);
+    let down = down_relay("down");
+
+    let txid = gen_tx(b"tx").id();
+    // All answered backends disagree.
+    let relay = quorum_relay(&[first.clone(), second, down.clone()], 2);
+    assert!(relay.get_transaction(txid).is_err());
+    assert_eq!(relay.take_disagreements()[0].responses.len(), 3);
+    assert!(relay.get_block_count().is_ok());
+
+    // Not enough backends answered, so the error of the failed one is returned.
+    let relay = quorum_relay(&[first, down.clone(), down], 2);
+    match relay.get_transaction(txid) {
+        Err(Error::Rpc(bitcoinrpc::Error::NoInformation(_))) => {}
+        other => panic!("Unexpected response {:?}", other),
+    }
+    assert!(relay.get_block_count().is_err());
+    assert!(relay.watch_address(&gen_address(), false).is_err());
+}
+
+#[test]
+fn test_quorum_relay_batch_reads() {
+    let tx = gen_tx(b"tx");
+    let honest = tx_relay("honest", tx.clone(), 5);
+    let lagging = tx_relay("lagging", tx.clone(), 3);
+    let liar = tx_relay("liar", gen_tx(b"fake"), 100);
+
+    let txids = vec![tx.id(), gen_tx(b"other").id()];
+    let outpoints = vec![(tx.id(), 0), (tx.id(), 1)];
+    let relay = quorum_relay(&[honest, lagging, liar.clone()], 2);
+    assert_eq!(
+        relay.get_transactions(&txids).unwrap(),
+        vec![Some(tx.clone()), Some(tx.clone())]
+    );
+    assert_eq!(
+        relay.get_tx_outs_confirmations(&outpoints).unwrap(),
+        vec![Some(5), Some(5)]
+    );
+    let disagreements = relay.take_disagreements();
+    assert_eq!(disagreements.len(), 1);
+    assert_eq!(disagreements[0].method, "get_transactions");
+    assert!(disagreements[0].responses[0].starts_with("liar returned"));
+
+    // The whole batch has to be agreed.
+    let relay = quorum_relay(&[MockRelay::new("unaware"), liar], 2);
+    assert!(relay.get_transactions(&txids).is_err());
+}
+
+#[test]
+fn test_quorum_relay_unspent_transactions() {
+    let funding_tx = gen_tx(b"funding");
+    let honest = MockRelay::new("honest");
+    honest.state().unspent = vec![(funding_tx.clone(), 10)];
+    let lagging = MockRelay::new("lagging");
+    lagging.state().unspent = vec![(funding_tx.clone(), 9)];
+    let liar = MockRelay::new("liar");
+    liar.state().unspent = vec![(funding_tx.clone(), 100), (gen_tx(b"fake"), 100)];
+
+    let address = gen_address();
+    let relay = quorum_relay(&[honest.clone(), lagging.clone(), liar.clone()], 2);
+    let txs = relay.unspent_transactions(&address).unwrap();
+    assert_eq!(txs.len(), 1);
+    assert_eq!(txs[0].body, funding_tx);
+    assert_eq!(txs[0].confirmations, Some(10));
+    assert_eq!(txs[0].vout, Some(0));
+    let disagreements = relay.take_disagreements();
+    assert_eq!(disagreements.len(), 1);
+    assert!(disagreements[0].responses[0].starts_with("liar returned"));
+
+    // The output is listed only by a minority.
+    honest.state().unspent.clear();
+    let relay = quorum_relay(&[honest, lagging, liar], 3);
+    assert!(relay.unspent_transactions(&address).is_err());
+}
+
+#[test]
+fn test_quorum_relay_send_transaction() {
+    let tx = gen_tx(b"tx");
+    let first = MockRelay::new("first");
+    let down = down_relay("down");
+
+    let relay = quorum_relay(&[first.clone(), down.clone()], 2);
+    relay.send_transaction(tx.clone()).unwrap();
+    assert_eq!(first.state().sent, vec![tx.clone()]);
+    assert_eq!(relay.take_disagreements()[0].method, "send_transaction");
+
+    let relay = quorum_relay(&[down.clone(), down], 1);
+    assert!(relay.send_transaction(tx).is_err());
+}
+
+#[test]
+fn test_quorum_relay_errors_sink() {
+    let honest = MockRelay::new("honest");
+    honest.state().block_count = 100;
+    let down = down_relay("down");
+    let relay = quorum_relay(&[honest.clone(), honest, down], 2);
+    assert_eq!(relay.get_block_count().unwrap(), 100);
+
+    let mut handler = AnchoringHandler::new(Some(Box::new(relay)), AnchoringNodeConfig::default());
+    let (sender, receiver) = mpsc::channel();
+    handler.set_errors_sink(Some(sender));
+    handler.report_relay_disagreements();
+    match receiver.try_recv() {
+        Ok(HandlerError::RelayDisagreement(disagreement)) => {
+            assert_eq!(disagreement.method, "get_block_count");
+            assert_eq!(
+                disagreement.responses,
+                vec!["down failed with Relay is down".to_owned()]
+            );
+        }
+        other => panic!("Unexpected error {:?}", other),
+    }
+    assert!(receiver.try_recv().is_err());
+}
+
+#[test]
+fn test_quorum_relay_node_config() {
+    let mut node = AnchoringNodeConfig::new(Some(rpc("http://127.0.0.1:18332")));
+    assert!(
+        serde_json::to_value(&node).unwrap().get("extra_rpc").is_none(),
+        "Single relay configuration is unchanged"
+    );
+    assert_eq!(
+        node.relay().unwrap().config().host,
+        "http://127.0.0.1:18332"
+    );
+
+    node.extra_rpc = vec![rpc("http://127.0.0.2:18332"), rpc("http://127.0.0.3:18332")];
+    node.relay_quorum = Some(3);
+    let value = serde_json::to_value(&node).unwrap();
+    assert_eq!(value["relay_quorum"], json!(3));
+    let node: AnchoringNodeConfig = serde_json::from_value(value).unwrap();
+    assert_eq!(node.extra_rpc.len(), 2);
+    assert_eq!(
+        node.relay().unwrap().config().host,
+        "http://127.0.0.1:18332"
+    );
+    assert!(AnchoringNodeConfig::default().relay().is_none());
+}
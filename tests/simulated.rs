@@ -0,0 +1,910 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(feature = "simulated_bitcoin")]
+
+extern crate exonum_bitcoinrpc as bitcoinrpc;
+extern crate rand;
+#[macro_use]
+extern crate pretty_assertions;
+extern crate exonum;
+extern crate exonum_btc_anchoring;
+extern crate exonum_testkit;
+extern crate tempdir;
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use rand::{SeedableRng, StdRng};
+use tempdir::TempDir;
+
+use exonum::blockchain::Transaction;
+use exonum::crypto::hash;
+use exonum::helpers::{Height, ValidatorId};
+use exonum::encoding;
+use exonum::messages::RawMessage;
+use exonum::node::TransactionSend;
+use exonum_testkit::{ApiKind, TestKit, TestKitBuilder};
+
+use exonum_btc_anchoring::{gen_anchoring_testnet_config_with_rng, AnchoringConfig,
+                           AnchoringNodeConfig, AnchoringRpcConfig, AnchoringService,
+                           BitcoinRelay, FeePolicy, RpcClient, RpcCredentials,
+                           SimulatedBitcoin, SimulatedBitcoind, UtxoDiscovery,
+                           ANCHORING_SERVICE_NAME};
+use exonum_btc_anchoring::api::AnchoringProof;
+use exonum_btc_anchoring::blockchain::dto::{MsgAnchoringFeeRate, MsgAnchoringFinalizedTx,
+                                             MsgAnchoringSignature, MsgAnchoringUpdateLatest,
+                                             TxOutProof};
+use exonum_btc_anchoring::blockchain::schema::AnchoringSchema;
+use exonum_btc_anchoring::details::btc;
+use exonum_btc_anchoring::details::btc::transactions::{AnchoringTx, BitcoinTx, FundingTx,
+                                                         TransactionBuilder, TxFromRaw};
+use exonum_btc_anchoring::details::rpc::{Error, Result, RpcTransport, TxInfo};
+use exonum_btc_anchoring::observer::AnchoringChainObserver;
+use exonum_btc_anchoring::verifier::ProofVerifier;
+
+const FUNDS: u64 = 10_000;
+
+/// Multisig address of the 3 out of 4 validators.
+struct Multisig {
+    address_type: btc::AddressType,
+    redeem_script: btc::RedeemScript,
+    addr: btc::Address,
+    priv_keys: Vec<btc::PrivateKey>,
+}
+
+impl Multisig {
+    fn new(address_type: btc::AddressType) -> Multisig {
+        let network = btc::Network::Testnet;
+        let keypairs = (0..4)
+            .map(|_| btc::gen_btc_keypair(network))
+            .collect::<Vec<_>>();
+        let redeem_script =
+            btc::RedeemScript::from_pubkeys(keypairs.iter().map(|keypair| &keypair.0), 3)
+                .compressed(network)
+                .unwrap();
+        let addr = btc::Address::from_redeem_script(&redeem_script, address_type, network);
+        Multisig {
+            address_type,
+            redeem_script,
+            addr,
+            priv_keys: keypairs.into_iter().map(|keypair| keypair.1).collect(),
+        }
+    }
+
+    fn fund(&self, bitcoin: &SimulatedBitcoin) -> FundingTx {
+        bitcoin.watch_address(&self.addr, false).unwrap();
+        bitcoin.send_to_address(&self.addr, FUNDS).unwrap()
+    }
+
+    /// Creates the transaction which spends the given output to the same address.
+    fn spend(&self, prev_tx: &BitcoinTx, fee: u64) -> AnchoringTx {
+        let script_pubkey = self.addr.script_pubkey();
+        let out = prev_tx
+            .0
+            .output
+            .iter()
+            .position(|output| output.script_pubkey == script_pubkey)
+            .unwrap();
+        TransactionBuilder::with_prev_tx(&prev_tx.0, out as u32)
+            .payload(Height::zero(), hash(&[fee as u8]))
+            .send_to(self.addr.clone())
+            .fee(fee)
+            .into_transaction()
+            .unwrap()
+    }
+
+    /// Signs the single input of the transaction with the given keys.
+    fn sign(&self, tx: AnchoringTx, value: u64, keys: &[usize]) -> BitcoinTx {
+        let signatures = keys.iter()
+            .map(|&key| {
+                let priv_key = &self.priv_keys[key];
+                if self.address_type.is_witness() {
+                    tx.sign_witness_input(&self.redeem_script, 0, value, priv_key)
+                } else {
+                    tx.sign_input(&self.redeem_script, 0, priv_key)
+                }
+            })
+            .collect::<Vec<_>>();
+        let mut signatures_map = HashMap::new();
+        signatures_map.insert(0, signatures);
+        let tx = tx.finalize_with_type(self.address_type, &self.redeem_script, signatures_map);
+        BitcoinTx::from(tx.0)
+    }
+}
+
+fn assert_rejected<T: Debug>(response: Result<T>, reason: &str) {
+    match response {
+        Err(Error::Rpc(bitcoinrpc::Error::TransactionRejected(ref msg)))
+            if msg.contains(reason) => {}
+        other => panic!("Unexpected response {:?}", other),
+    }
+}
+
+fn confirmations(bitcoin: &SimulatedBitcoin, tx: &BitcoinTx) -> Option<u64> {
+    bitcoin
+        .get_transaction_info(tx.id())
+        .unwrap()
+        .unwrap()
+        .confirmations
+}
+
+#[test]
+fn test_simulated_mempool_and_mining() {
+    let bitcoin = SimulatedBitcoin::new();
+    let multisig = Multisig::new(btc::AddressType::P2sh);
+    assert_eq!(bitcoin.get_block_count().unwrap(), 0);
+
+    let funding_tx = BitcoinTx::from(multisig.fund(&bitcoin).0);
+    assert_eq!(bitcoin.mempool(), vec![funding_tx.id()]);
+    assert_eq!(bitcoin.get_mempool_entry_height(funding_tx.id()).unwrap(), Some(0));
+    assert_eq!(confirmations(&bitcoin, &funding_tx), None);
+    assert_eq!(bitcoin.get_tx_out_proof(funding_tx.id()).unwrap(), None);
+    let unspent = bitcoin.unspent_transactions(&multisig.addr).unwrap();
+    assert_eq!(unspent.len(), 1);
+    assert_eq!(unspent[0].body, funding_tx);
+    assert_eq!(unspent[0].confirmations, Some(0));
+
+    let hashes = bitcoin.mine_blocks(3);
+    assert_eq!(bitcoin.get_block_count().unwrap(), 3);
+    assert_eq!(bitcoin.get_best_block_hash().unwrap(), hashes[2]);
+    assert!(bitcoin.mempool().is_empty());
+    assert_eq!(bitcoin.get_mempool_entry_height(funding_tx.id()).unwrap(), None);
+    assert_eq!(confirmations(&bitcoin, &funding_tx), Some(3));
+
+    let proof = bitcoin.get_tx_out_proof(funding_tx.id()).unwrap().unwrap();
+    assert_eq!(proof.block_hash(), hashes[0]);
+    assert!(bitcoin.block_header(&hashes[0]).is_some());
+
+    // Only the watched addresses are reported.
+    let other = Multisig::new(btc::AddressType::P2sh);
+    bitcoin.send_to_address(&other.addr, FUNDS).unwrap();
+    assert!(bitcoin.unspent_transactions(&other.addr).unwrap().is_empty());
+}
+
+#[test]
+fn test_simulated_multisig_signatures() {
+    let address_types = [
+        btc::AddressType::P2sh,
+        btc::AddressType::P2wsh,
+        btc::AddressType::P2shP2wsh,
+    ];
+    for address_type in &address_types {
+        let bitcoin = SimulatedBitcoin::new();
+        let multisig = Multisig::new(*address_type);
+        let funding_tx = BitcoinTx::from(multisig.fund(&bitcoin).0);
+        let tx = multisig.spend(&funding_tx, 1000);
+
+        assert_rejected(
+            bitcoin.send_transaction(BitcoinTx::from(tx.0.clone())),
+            "mandatory-script-verify-flag-failed",
+        );
+        for keys in &[&[0, 1][..], &[2, 1, 0][..], &[0, 1, 1][..]] {
+            let signed_tx = multisig.sign(tx.clone(), FUNDS, keys);
+            assert_rejected(
+                bitcoin.send_transaction(signed_tx),
+                "mandatory-script-verify-flag-failed",
+            );
+        }
+
+        let signed_tx = multisig.sign(tx, FUNDS, &[0, 2, 3]);
+        bitcoin.send_transaction(signed_tx.clone()).unwrap();
+        assert_eq!(bitcoin.mempool(), vec![funding_tx.id(), signed_tx.id()]);
+        // The mempool transaction is accepted again without changes.
+        bitcoin.send_transaction(signed_tx.clone()).unwrap();
+        assert_eq!(bitcoin.mempool(), vec![funding_tx.id(), signed_tx.id()]);
+
+        bitcoin.mine_blocks(1);
+        match bitcoin.send_transaction(signed_tx.clone()) {
+            Err(Error::Rpc(bitcoinrpc::Error::TransactionAlreadyInChain)) => {}
+            other => panic!("Unexpected response {:?}", other),
+        }
+        let unspent = bitcoin.unspent_transactions(&multisig.addr).unwrap();
+        assert_eq!(unspent.len(), 1);
+        assert_eq!(unspent[0].body, signed_tx);
+    }
+}
+
+#[test]
+fn test_simulated_missing_inputs() {
+    let bitcoin = SimulatedBitcoin::new();
+    let multisig = Multisig::new(btc::AddressType::P2sh);
+    let funding_tx = BitcoinTx::from(multisig.fund(&bitcoin).0);
+    let tx = multisig.sign(multisig.spend(&funding_tx, 1000), FUNDS, &[0, 1, 2]);
+    let child_tx = multisig.sign(multisig.spend(&tx, 1000), FUNDS - 1000, &[0, 1, 2]);
+
+    match bitcoin.send_transaction(child_tx.clone()) {
+        Err(Error::Rpc(bitcoinrpc::Error::TransactionIncorrect(_))) => {}
+        other => panic!("Unexpected response {:?}", other),
+    }
+    bitcoin.send_transaction(tx).unwrap();
+    bitcoin.send_transaction(child_tx).unwrap();
+}
+
+#[test]
+fn test_simulated_replace_by_fee() {
+    let bitcoin = SimulatedBitcoin::new();
+    let multisig = Multisig::new(btc::AddressType::P2wsh);
+    let funding_tx = BitcoinTx::from(multisig.fund(&bitcoin).0);
+    bitcoin.mine_blocks(1);
+
+    let first_tx = multisig.sign(multisig.spend(&funding_tx, 1000), FUNDS, &[0, 1, 2]);
+    bitcoin.send_transaction(first_tx.clone()).unwrap();
+    let child_tx = multisig.sign(multisig.spend(&first_tx, 1000), FUNDS - 1000, &[0, 1, 2]);
+    bitcoin.send_transaction(child_tx.clone()).unwrap();
+
+    // The replacement must pay more than the conflicting transaction with its descendants.
+    let cheap_tx = multisig.sign(multisig.spend(&funding_tx, 2000), FUNDS, &[0, 1, 2]);
+    assert_rejected(bitcoin.send_transaction(cheap_tx), "insufficient fee");
+    assert_eq!(bitcoin.mempool(), vec![first_tx.id(), child_tx.id()]);
+
+    let second_tx = multisig.sign(multisig.spend(&funding_tx, 3000), FUNDS, &[0, 1, 2]);
+    bitcoin.send_transaction(second_tx.clone()).unwrap();
+    assert_eq!(bitcoin.mempool(), vec![second_tx.id()]);
+    assert_eq!(bitcoin.get_transaction(first_tx.id()).unwrap(), None);
+    assert_eq!(bitcoin.get_transaction(child_tx.id()).unwrap(), None);
+
+    // The transaction which does not signal the replacement is kept.
+    bitcoin.mine_blocks(1);
+    let mut final_tx = multisig.spend(&second_tx, 1000);
+    final_tx.0.input[0].sequence = 0xFFFF_FFFF;
+    let final_tx = multisig.sign(final_tx, FUNDS - 3000, &[0, 1, 2]);
+    bitcoin.send_transaction(final_tx.clone()).unwrap();
+    let replacement_tx = multisig.sign(multisig.spend(&second_tx, 2000), FUNDS - 3000, &[0, 1, 2]);
+    assert_rejected(
+        bitcoin.send_transaction(replacement_tx),
+        "txn-mempool-conflict",
+    );
+    assert_eq!(bitcoin.mempool(), vec![final_tx.id()]);
+}
+
+#[test]
+fn test_simulated_reorg_and_eviction() {
+    let bitcoin = SimulatedBitcoin::new();
+    let multisig = Multisig::new(btc::AddressType::P2shP2wsh);
+    let funding_tx = BitcoinTx::from(multisig.fund(&bitcoin).0);
+    let hashes = bitcoin.mine_blocks(3);
+    assert_eq!(confirmations(&bitcoin, &funding_tx), Some(3));
+
+    // The funding transaction stays in the first block.
+    let new_hashes = bitcoin.reorg(2);
+    assert_eq!(new_hashes.len(), 3);
+    assert_eq!(bitcoin.get_block_count().unwrap(), 4);
+    assert_eq!(bitcoin.get_best_block_hash().unwrap(), new_hashes[2]);
+    assert_eq!(confirmations(&bitcoin, &funding_tx), Some(4));
+    assert!(bitcoin.block_header(&hashes[1]).is_none());
+
+    // The funding transaction returns to the mempool.
+    bitcoin.reorg(4);
+    assert_eq!(bitcoin.get_block_count().unwrap(), 5);
+    assert_eq!(confirmations(&bitcoin, &funding_tx), None);
+    assert_eq!(bitcoin.mempool(), vec![funding_tx.id()]);
+    assert_eq!(bitcoin.get_mempool_entry_height(funding_tx.id()).unwrap(), Some(0));
+    assert_eq!(bitcoin.get_tx_out_proof(funding_tx.id()).unwrap(), None);
+    assert!(bitcoin.block_header(&hashes[0]).is_none());
+
+    let tx = multisig.sign(multisig.spend(&funding_tx, 1000), FUNDS, &[1, 2, 3]);
+    bitcoin.send_transaction(tx.clone()).unwrap();
+
+    // The descendants are evicted together with the transaction.
+    assert!(bitcoin.evict_transaction(funding_tx.id()));
+    assert!(!bitcoin.evict_transaction(tx.id()));
+    assert!(bitcoin.mempool().is_empty());
+    assert_eq!(bitcoin.get_transaction(tx.id()).unwrap(), None);
+    assert!(bitcoin.unspent_transactions(&multisig.addr).unwrap().is_empty());
+    match bitcoin.send_transaction(tx) {
+        Err(Error::Rpc(bitcoinrpc::Error::TransactionIncorrect(ref msg)))
+            if msg == "bad-txns-inputs-missingorspent" => {}
+        other => panic!("Unexpected response {:?}", other),
+    }
+}
+
+// `RpcClient` works with the simulated bitcoind as with the real one.
+#[test]
+fn test_simulated_bitcoind_rpc() {
+    let bitcoind = SimulatedBitcoind::start(SimulatedBitcoin::new()).unwrap();
+    let bitcoin = bitcoind.bitcoin();
+    let client = RpcClient::from(bitcoind.config());
+    let multisig = Multisig::new(btc::AddressType::P2sh);
+
+    client.watch_address(&multisig.addr, false).unwrap();
+    let funding_tx = BitcoinTx::from(client.send_to_address(&multisig.addr, FUNDS).unwrap().0);
+    assert_eq!(bitcoin.mempool(), vec![funding_tx.id()]);
+    assert_eq!(client.get_transaction(funding_tx.id()).unwrap(), Some(funding_tx.clone()));
+    assert_eq!(client.get_transaction_confirmations(funding_tx.id()).unwrap(), None);
+    assert_eq!(client.get_mempool_entry_height(funding_tx.id()).unwrap(), Some(0));
+    let unspent = client.unspent_transactions(&multisig.addr).unwrap();
+    assert_eq!(unspent.len(), 1);
+    assert_eq!(unspent[0].confirmations, Some(0));
+    assert_eq!(client.get_tx_out_confirmations(funding_tx.id(), 0).unwrap(), Some(0));
+
+    let tx = multisig.sign(multisig.spend(&funding_tx, 1000), FUNDS, &[0, 1, 2]);
+    match client.send_transaction(multisig.sign(multisig.spend(&funding_tx, 1000), FUNDS, &[0])) {
+        Err(Error::Rpc(bitcoinrpc::Error::TransactionRejected(_))) => {}
+        other => panic!("Unexpected response {:?}", other),
+    }
+    client.send_transaction(tx.clone()).unwrap();
+    assert_eq!(client.get_tx_out_confirmations(funding_tx.id(), 0).unwrap(), None);
+
+    // The wallet endpoints which are not used by the relay are sent by the transport.
+    let transport = RpcTransport::new(bitcoind.config());
+    let hashes: Vec<String> = transport.request("generate", vec![2.into()]).unwrap();
+    assert_eq!(client.get_block_count().unwrap(), 2);
+    assert_eq!(client.get_best_block_hash().unwrap(), hashes[1]);
+    assert_eq!(client.get_transaction_confirmations(tx.id()).unwrap(), Some(2));
+    assert_eq!(client.get_mempool_entry_height(tx.id()).unwrap(), None);
+    assert_eq!(client.get_tx_out_confirmations(tx.id(), 0).unwrap(), Some(2));
+    assert_eq!(client.get_tx_out_confirmations(tx.id(), 2).unwrap(), None);
+    assert_eq!(
+        client.get_tx_out_proof(tx.id()).unwrap(),
+        bitcoin.get_tx_out_proof(tx.id()).unwrap()
+    );
+    let unspent = client.unspent_transactions(&multisig.addr).unwrap();
+    assert_eq!(unspent.len(), 1);
+    assert_eq!(unspent[0].body, tx);
+
+    assert_eq!(client.estimate_fee_rate(2).unwrap(), None);
+    bitcoin.set_fee_rate(Some(12));
+    assert_eq!(client.estimate_fee_rate(2).unwrap(), Some(12));
+
+    let unknown_tx = multisig.spend(&tx, 1000);
+    assert_eq!(client.get_transaction(unknown_tx.id()).unwrap(), None);
+    assert_eq!(client.get_tx_out_proof(unknown_tx.id()).unwrap(), None);
+    assert_eq!(client.get_tx_out_confirmations(unknown_tx.id(), 0).unwrap(), None);
+    match transport.request::<String>("getinfo", vec![]) {
+        Err(Error::Rpc(bitcoinrpc::Error::Other(_))) => {}
+        other => panic!("Unexpected response {:?}", other),
+    }
+}
+
+// `RpcClient` imports the anchoring address to the descriptor wallet.
+#[test]
+fn test_simulated_bitcoind_import_descriptors() {
+    let bitcoind = SimulatedBitcoind::start(SimulatedBitcoin::new()).unwrap();
+    let bitcoin = bitcoind.bitcoin();
+    let cfg = AnchoringRpcConfig {
+        utxo_discovery: UtxoDiscovery::ImportDescriptors,
+        ..bitcoind.config()
+    };
+    let client: Box<BitcoinRelay> = cfg.clone().into();
+    assert_eq!(client.config(), cfg);
+    let multisig = Multisig::new(btc::AddressType::P2wsh);
+
+    let funding_tx = BitcoinTx::from(bitcoin.send_to_address(&multisig.addr, FUNDS).unwrap().0);
+    assert!(client.unspent_transactions(&multisig.addr).unwrap().is_empty());
+    client.watch_address(&multisig.addr, true).unwrap();
+    let unspent = client.unspent_transactions(&multisig.addr).unwrap();
+    assert_eq!(unspent.len(), 1);
+    assert_eq!(unspent[0].body, funding_tx);
+    assert_eq!(unspent[0].confirmations, Some(0));
+
+    bitcoin.mine_blocks(1);
+    let unspent = client.unspent_transactions(&multisig.addr).unwrap();
+    assert_eq!(unspent[0].confirmations, Some(1));
+}
+
+// `RpcClient` finds the anchoring outputs by the UTXO set scan without the wallet.
+#[test]
+fn test_simulated_bitcoind_scan_tx_out_set() {
+    let bitcoind = SimulatedBitcoind::start(SimulatedBitcoin::new()).unwrap();
+    let bitcoin = bitcoind.bitcoin();
+    let client = RpcClient::new(AnchoringRpcConfig {
+        utxo_discovery: UtxoDiscovery::ScanTxOutSet,
+        ..bitcoind.config()
+    });
+    let multisig = Multisig::new(btc::AddressType::P2sh);
+    let unspent_txs = |client: &RpcClient| {
+        client
+            .unspent_transactions(&multisig.addr)
+            .unwrap()
+            .into_iter()
+            .map(|info| (info.body, info.confirmations))
+            .collect::<Vec<_>>()
+    };
+
+    // The address is never imported, and the mempool transactions are not scanned.
+    client.watch_address(&multisig.addr, false).unwrap();
+    let funding_tx = BitcoinTx::from(bitcoin.send_to_address(&multisig.addr, FUNDS).unwrap().0);
+    assert_eq!(unspent_txs(&client), vec![]);
+    bitcoin.mine_blocks(2);
+    assert_eq!(unspent_txs(&client), vec![(funding_tx.clone(), Some(2))]);
+
+    // The transactions sent by the client are listed until they leave the mempool.
+    let tx = multisig.sign(multisig.spend(&funding_tx, 1000), FUNDS, &[0, 1, 2]);
+    client.send_transaction(tx.clone()).unwrap();
+    assert_eq!(unspent_txs(&client), vec![(tx.clone(), Some(0))]);
+    let next_tx = multisig.sign(multisig.spend(&tx, 1000), FUNDS - 1000, &[1, 2, 3]);
+    client.send_transaction(next_tx.clone()).unwrap();
+    assert_eq!(unspent_txs(&client), vec![(next_tx.clone(), Some(0))]);
+
+    assert!(bitcoin.evict_transaction(next_tx.id()));
+    assert_eq!(unspent_txs(&client), vec![(tx.clone(), Some(0))]);
+    bitcoin.mine_blocks(1);
+    assert_eq!(unspent_txs(&client), vec![(tx, Some(1))]);
+}
+
+// `RpcClient` sends the multiple lookups as the json rpc batches.
+#[test]
+fn test_simulated_bitcoind_batch_requests() {
+    let bitcoind = SimulatedBitcoind::start(SimulatedBitcoin::new()).unwrap();
+    let bitcoin = bitcoind.bitcoin();
+    let multisig = Multisig::new(btc::AddressType::P2wsh);
+    let funding_txs = (0..3).map(|_| multisig.fund(bitcoin)).collect::<Vec<_>>();
+    bitcoin.mine_blocks(1);
+    let unknown_txid = multisig
+        .spend(&BitcoinTx::from(funding_txs[0].0.clone()), 1000)
+        .id();
+
+    let mut txids = funding_txs.iter().map(FundingTx::id).collect::<Vec<_>>();
+    txids.push(unknown_txid);
+    let mut outpoints = funding_txs
+        .iter()
+        .map(|tx| (tx.id(), tx.find_out(&multisig.addr).unwrap()))
+        .collect::<Vec<_>>();
+    outpoints.push((unknown_txid, 0));
+
+    let client = RpcClient::from(bitcoind.config());
+    client.watch_address(&multisig.addr, false).unwrap();
+    let requests = bitcoind.requests();
+
+    let txs = client.get_transactions(&txids).unwrap();
+    let expected_txs = txids
+        .iter()
+        .map(|txid| bitcoin.get_transaction(*txid).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(txs, expected_txs);
+    assert_eq!(txs.last(), Some(&None));
+    assert_eq!(
+        client.get_tx_outs_confirmations(&outpoints).unwrap(),
+        vec![Some(1), Some(1), Some(1), None]
+    );
+    // The transactions of the unspent outputs are retrieved by the single batch.
+    assert_eq!(client.unspent_transactions(&multisig.addr).unwrap().len(), 3);
+    assert_eq!(bitcoind.requests(), requests + 4);
+    assert!(client.get_transactions(&[]).unwrap().is_empty());
+    assert_eq!(bitcoind.requests(), requests + 4);
+}
+
+// `RpcClient` reads the cookie file again once bitcoind rotates the cookie.
+#[test]
+fn test_simulated_bitcoind_cookie_auth() {
+    let bitcoind = SimulatedBitcoind::start(SimulatedBitcoin::new()).unwrap();
+    let block_count = bitcoind.bitcoin().get_block_count().unwrap();
+    let dir = TempDir::new("anchoring-cookie").unwrap();
+    let cookie_file = dir.path().join(".cookie");
+    let write_cookie = |password: &str| {
+        let mut file = File::create(&cookie_file).unwrap();
+        write!(file, "__cookie__:{}", password).unwrap();
+    };
+
+    write_cookie("first");
+    bitcoind.set_credentials("__cookie__", "first");
+    let cfg = AnchoringRpcConfig {
+        credentials: RpcCredentials {
+            cookie_file: Some(cookie_file.clone()),
+            ..Default::default()
+        },
+        ..bitcoind.config()
+    };
+    let client: Box<BitcoinRelay> = cfg.clone().into();
+    assert_eq!(client.config(), cfg);
+    assert_eq!(client.get_block_count().unwrap(), block_count);
+
+    // Bitcoind has restarted, but the new cookie is not written yet.
+    bitcoind.set_credentials("__cookie__", "second");
+    match client.get_block_count() {
+        Err(Error::Protocol(ref msg)) if msg.contains("401") => {}
+        other => panic!("Unexpected response {:?}", other),
+    }
+    write_cookie("second");
+    assert_eq!(client.get_block_count().unwrap(), block_count);
+}
+
+// `RpcClient` reads the password from the file or the environment variable.
+#[test]
+fn test_simulated_bitcoind_password_sources() {
+    let bitcoind = SimulatedBitcoind::start(SimulatedBitcoin::new()).unwrap();
+    let block_count = bitcoind.bitcoin().get_block_count().unwrap();
+    bitcoind.set_credentials("anchoring", "secret");
+    let dir = TempDir::new("anchoring-password").unwrap();
+    let password_file = dir.path().join("rpc-password");
+    writeln!(File::create(&password_file).unwrap(), "secret").unwrap();
+    let password_env = "ANCHORING_TEST_RPC_PASSWORD";
+    env::set_var(password_env, "secret");
+
+    let cfg = AnchoringRpcConfig {
+        username: Some("anchoring".to_owned()),
+        password: Some("wrong".to_owned()),
+        ..bitcoind.config()
+    };
+    let client: Box<BitcoinRelay> = cfg.clone().into();
+    assert!(client.get_block_count().is_err());
+
+    let credentials = vec![
+        RpcCredentials {
+            password_file: Some(password_file),
+            ..Default::default()
+        },
+        RpcCredentials {
+            password_env: Some(password_env.to_owned()),
+            ..Default::default()
+        },
+    ];
+    for credentials in credentials {
+        let client: Box<BitcoinRelay> = AnchoringRpcConfig {
+            credentials,
+            ..cfg.clone()
+        }.into();
+        assert_eq!(client.get_block_count().unwrap(), block_count);
+    }
+}
+
+/// Returns the messages of the given type from the testkit mempool.
+fn service_messages<T, F>(testkit: &TestKit, from_raw: F) -> Vec<T>
+where
+    F: Fn(RawMessage) -> ::std::result::Result<T, encoding::Error>,
+{
+    testkit
+        .mempool()
+        .values()
+        .filter_map(|tx| from_raw(tx.raw().clone()).ok())
+        .collect()
+}
+
+/// Returns the configuration of the anchoring to the simulated network.
+fn anchoring_config(bitcoin: &SimulatedBitcoin) -> (AnchoringConfig, Vec<AnchoringNodeConfig>) {
+    let mut rng: StdRng = SeedableRng::from_seed([1, 2, 3, 4].as_ref());
+    let (mut cfg, mut nodes) =
+        gen_anchoring_testnet_config_with_rng(bitcoin, btc::Network::Testnet, 4, FUNDS, &mut rng);
+    cfg.frequency = 10;
+    // The tests check the results of the commits right after the blocks are created.
+    for node in &mut nodes {
+        node.sync_commit = true;
+    }
+    bitcoin.mine_blocks(cfg.utxo_confirmations);
+    (cfg, nodes)
+}
+
+/// Anchors the genesis block by the testkit with 4 validators. Returns the anchoring
+/// transaction, which is accepted by the network and is agreed on as the lect.
+fn anchor_genesis_block(bitcoin: &SimulatedBitcoin) -> (TestKit, AnchoringConfig, BitcoinTx) {
+    let (cfg, nodes) = anchoring_config(bitcoin);
+    let relay = Box::new(bitcoin.clone());
+    let (testkit, anchored_tx) = anchor_genesis_block_by(bitcoin, relay, &cfg, &nodes);
+    (testkit, cfg, anchored_tx)
+}
+
+/// Anchors the genesis block like `anchor_genesis_block` by the handler of the first
+/// validator with the given relay to the network.
+fn anchor_genesis_block_by(
+    bitcoin: &SimulatedBitcoin,
+    relay: Box<BitcoinRelay>,
+    cfg: &AnchoringConfig,
+    nodes: &[AnchoringNodeConfig],
+) -> (TestKit, BitcoinTx) {
+    let (redeem_script, addr) = cfg.redeem_script();
+    let service = AnchoringService::new_with_client(relay, cfg.clone(), nodes[0].clone());
+    let mut testkit = TestKitBuilder::validator()
+        .with_validators(4)
+        .with_service(service)
+        .create();
+
+    // The handler proposes the anchoring transaction for the genesis block.
+    testkit.create_block();
+    let proposal = service_messages(&testkit, MsgAnchoringSignature::from_raw)
+        .pop()
+        .unwrap()
+        .tx();
+    let signatures = (0..4)
+        .map(|id| {
+            let validator = ValidatorId(id as u16);
+            let priv_key = &nodes[id].private_keys[&addr.to_string()];
+            let signature = proposal.sign_input(&redeem_script, 0, priv_key);
+            let keypair = testkit.validator(validator).service_keypair();
+            let msg = MsgAnchoringSignature::new(
+                keypair.0,
+                validator,
+                proposal.clone(),
+                0,
+                &signature,
+                keypair.1,
+            );
+            Box::new(msg) as Box<Transaction>
+        })
+        .collect::<Vec<_>>();
+    testkit.create_block_with_transactions(signatures);
+
+    // The signed transaction is accepted by the network.
+    let mempool = bitcoin.mempool();
+    assert_eq!(mempool.len(), 1);
+    let anchored_tx = bitcoin.get_transaction(mempool[0]).unwrap().unwrap();
+    let lect = service_messages(&testkit, MsgAnchoringUpdateLatest::from_raw)
+        .pop()
+        .unwrap();
+    assert_eq!(lect.tx(), anchored_tx);
+    let lects = (0..4)
+        .map(|id| {
+            let validator = ValidatorId(id);
+            let keypair = testkit.validator(validator).service_keypair();
+            let tx = anchored_tx.clone();
+            let msg = MsgAnchoringUpdateLatest::new(keypair.0, validator, tx, 1, keypair.1);
+            Box::new(msg) as Box<Transaction>
+        })
+        .collect::<Vec<_>>();
+    testkit.create_block_with_transactions(lects);
+    (testkit, anchored_tx)
+}
+
+// The anchoring service and the observer work together with the simulated network.
+#[test]
+fn test_simulated_anchoring() {
+    let bitcoin = SimulatedBitcoin::new();
+    let (mut testkit, cfg, anchored_tx) = anchor_genesis_block(&bitcoin);
+
+    // The observer collects the inclusion proof of the confirmed anchoring transaction.
+    let block_hash = bitcoin.mine_blocks(cfg.utxo_confirmations)[0].clone();
+    let mut observer = AnchoringChainObserver::new_with_client(
+        testkit.blockchain_mut().clone(),
+        Box::new(bitcoin.clone()),
+        0,
+    );
+    let sent_votes = SentVotes::default();
+    let keypair = testkit.validator(ValidatorId(0)).service_keypair();
+    observer.set_vote_sender((*keypair.0, keypair.1.clone()), Box::new(sent_votes.clone()));
+    observer.check_anchoring_chain().unwrap();
+    let vote = sent_votes.take().pop().unwrap();
+
+    // Validators finalize the confirmed anchoring transaction with the observed proof.
+    let anchored_tx = AnchoringTx::from_raw(anchored_tx.0).unwrap();
+    assert_eq!(vote.tx(), anchored_tx);
+    let votes = (0..4)
+        .map(|id| {
+            let validator = ValidatorId(id);
+            let keypair = testkit.validator(validator).service_keypair();
+            let tx = anchored_tx.clone();
+            let proof = vote.tx_out_proof();
+            let msg = MsgAnchoringFinalizedTx::new(keypair.0, validator, tx, proof, keypair.1);
+            Box::new(msg) as Box<Transaction>
+        })
+        .collect::<Vec<_>>();
+    testkit.create_block_with_transactions(votes);
+    let snapshot = testkit.snapshot();
+    let anchoring_schema = AnchoringSchema::new(&snapshot);
+    assert_eq!(anchoring_schema.anchoring_tx_by_height(0), Some(anchored_tx));
+
+    let proof: AnchoringProof = testkit.api().get(
+        ApiKind::Service(ANCHORING_SERVICE_NAME),
+        "/v1/anchoring_proof/0",
+    );
+    let headers = vec![bitcoin.block_header(&block_hash).unwrap()];
+    let verdict = ProofVerifier::new(cfg.redeem_script().0, headers).verify(&proof);
+    assert!(verdict.is_valid(), "{:?}", verdict);
+}
+
+// The observer checks the anchoring chain on the new blocks without waiting for its interval.
+#[test]
+fn test_simulated_observer_bitcoin_events() {
+    let bitcoin = SimulatedBitcoin::new();
+    let (mut testkit, cfg, anchored_tx) = anchor_genesis_block(&bitcoin);
+
+    let mut observer = AnchoringChainObserver::new_with_client(
+        testkit.blockchain_mut().clone(),
+        Box::new(bitcoin.clone()),
+        3_600_000,
+    );
+    observer.set_bitcoin_events(Some(bitcoin.subscribe()));
+    let sent_votes = SentVotes::default();
+    let keypair = testkit.validator(ValidatorId(0)).service_keypair();
+    observer.set_vote_sender((*keypair.0, keypair.1.clone()), Box::new(sent_votes.clone()));
+    // The first check happens immediately and finds the unconfirmed transaction.
+    thread::spawn(move || observer.run().unwrap());
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(sent_votes.take(), Vec::new());
+
+    bitcoin.mine_blocks(cfg.utxo_confirmations);
+    let mut votes = Vec::new();
+    for _ in 0..50 {
+        votes = sent_votes.take();
+        if !votes.is_empty() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    assert_eq!(votes.len(), 1);
+    assert_eq!(votes[0].tx().id(), anchored_tx.id());
+}
+
+/// Returns the fee rates from the testkit mempool.
+fn fee_rates(testkit: &TestKit) -> Vec<u64> {
+    service_messages(testkit, MsgAnchoringFeeRate::from_raw)
+        .iter()
+        .map(|msg| msg.fee_rate())
+        .collect()
+}
+
+// The handler checks the lect and the fee rate on the new blocks and the transactions
+// to the anchoring address without waiting for `check_lect_frequency` blocks.
+#[test]
+fn test_simulated_handler_bitcoin_events() {
+    let bitcoin = SimulatedBitcoin::new();
+    let (mut cfg, mut nodes) = anchoring_config(&bitcoin);
+    cfg.fee_policy = FeePolicy::Estimate {
+        conf_target: 2,
+        min_sat_per_vbyte: 1,
+        max_sat_per_vbyte: 100,
+    };
+    nodes[0].check_lect_frequency = 1000;
+    let (_, addr) = cfg.redeem_script();
+    let service =
+        AnchoringService::new_with_client(Box::new(bitcoin.clone()), cfg, nodes[0].clone());
+    service.handler().lock().unwrap().set_bitcoin_events(
+        Some(bitcoin.subscribe()),
+    );
+    let mut testkit = TestKitBuilder::validator()
+        .with_validators(4)
+        .with_service(service)
+        .create();
+    testkit.create_block();
+
+    bitcoin.set_fee_rate(Some(10));
+    testkit.create_block();
+    assert_eq!(fee_rates(&testkit), Vec::<u64>::new());
+    bitcoin.mine_blocks(1);
+    testkit.create_block();
+    assert_eq!(fee_rates(&testkit), vec![10]);
+
+    bitcoin.set_fee_rate(Some(20));
+    let other = Multisig::new(btc::AddressType::P2sh);
+    bitcoin.send_to_address(&other.addr, FUNDS).unwrap();
+    testkit.create_block();
+    assert_eq!(fee_rates(&testkit), Vec::<u64>::new());
+    bitcoin.send_to_address(&addr, FUNDS).unwrap();
+    testkit.create_block();
+    assert_eq!(fee_rates(&testkit), vec![20]);
+}
+
+/// Transaction sender which keeps the finalization votes of the observer.
+#[derive(Debug, Clone, Default)]
+struct SentVotes(Arc<Mutex<Vec<MsgAnchoringFinalizedTx>>>);
+
+impl SentVotes {
+    fn take(&self) -> Vec<MsgAnchoringFinalizedTx> {
+        self.0.lock().unwrap().drain(..).collect()
+    }
+}
+
+impl TransactionSend for SentVotes {
+    fn send(&self, tx: Box<Transaction>) -> io::Result<()> {
+        let vote = MsgAnchoringFinalizedTx::from_raw(tx.raw().clone()).unwrap();
+        self.0.lock().unwrap().push(vote);
+        Ok(())
+    }
+}
+
+/// Network relay without the wallet, which must not be requested.
+#[derive(Debug)]
+struct WalletLessRelay(SimulatedBitcoin);
+
+impl BitcoinRelay for WalletLessRelay {
+    fn get_transaction(&self, txid: btc::TxId) -> Result<Option<BitcoinTx>> {
+        self.0.get_transaction(txid)
+    }
+
+    fn get_transaction_info(&self, txid: btc::TxId) -> Result<Option<TxInfo>> {
+        self.0.get_transaction_info(txid)
+    }
+
+    fn watch_address(&self, _addr: &btc::Address, _rescan: bool) -> Result<()> {
+        panic!("Relay has no wallet to watch the address");
+    }
+
+    fn send_transaction(&self, tx: BitcoinTx) -> Result<()> {
+        self.0.send_transaction(tx)
+    }
+
+    fn send_to_address(&self, _addr: &btc::Address, _satoshis: u64) -> Result<FundingTx> {
+        panic!("Relay has no wallet to send funds");
+    }
+
+    fn unspent_transactions(&self, _addr: &btc::Address) -> Result<Vec<TxInfo>> {
+        panic!("Relay has no wallet to list unspent outputs");
+    }
+
+    fn get_block_count(&self) -> Result<u64> {
+        self.0.get_block_count()
+    }
+
+    fn get_best_block_hash(&self) -> Result<String> {
+        self.0.get_best_block_hash()
+    }
+
+    fn get_mempool_entry_height(&self, txid: btc::TxId) -> Result<Option<u64>> {
+        self.0.get_mempool_entry_height(txid)
+    }
+
+    fn get_tx_out_confirmations(&self, txid: btc::TxId, vout: u32) -> Result<Option<u64>> {
+        self.0.get_tx_out_confirmations(txid, vout)
+    }
+
+    fn estimate_fee_rate(&self, conf_target: u16) -> Result<Option<u64>> {
+        self.0.estimate_fee_rate(conf_target)
+    }
+
+    fn get_tx_out_proof(&self, txid: btc::TxId) -> Result<Option<TxOutProof>> {
+        self.0.get_tx_out_proof(txid)
+    }
+
+    fn config(&self) -> AnchoringRpcConfig {
+        self.0.config()
+    }
+}
+
+// The handler tracks the anchoring outputs by its lects without the relay's wallet.
+#[test]
+fn test_simulated_chain_utxo_tracking() {
+    let bitcoin = SimulatedBitcoin::new();
+    let (cfg, mut nodes) = anchoring_config(&bitcoin);
+    nodes[0].chain_utxo_tracking = true;
+    nodes[0].check_lect_frequency = 1;
+    let relay = Box::new(WalletLessRelay(bitcoin.clone()));
+    let (mut testkit, anchored_tx) = anchor_genesis_block_by(&bitcoin, relay, &cfg, &nodes);
+    let funding_tx = BitcoinTx::from(cfg.funding_tx().clone());
+    let lects = |testkit: &TestKit| {
+        service_messages(testkit, MsgAnchoringUpdateLatest::from_raw)
+            .iter()
+            .map(|msg| msg.tx())
+            .collect::<Vec<_>>()
+    };
+
+    // The funding output becomes unspent again after the anchoring transaction is evicted.
+    testkit.create_block();
+    assert_eq!(lects(&testkit), Vec::<BitcoinTx>::new());
+    assert!(bitcoin.evict_transaction(anchored_tx.id()));
+    testkit.create_block();
+    assert_eq!(lects(&testkit), vec![funding_tx]);
+
+    // The lects of the other validators are found once the transaction is resent.
+    bitcoin.send_transaction(anchored_tx.clone()).unwrap();
+    testkit.create_block();
+    assert_eq!(lects(&testkit), vec![anchored_tx]);
+}
+
+// The observer finds the latest anchoring transaction among the lects without the relay's wallet.
+#[test]
+fn test_simulated_observer_chain_utxo_tracking() {
+    let bitcoin = SimulatedBitcoin::new();
+    let (mut testkit, cfg, anchored_tx) = anchor_genesis_block(&bitcoin);
+    bitcoin.mine_blocks(cfg.utxo_confirmations);
+
+    let mut observer = AnchoringChainObserver::new_with_client(
+        testkit.blockchain_mut().clone(),
+        Box::new(WalletLessRelay(bitcoin.clone())),
+        0,
+    );
+    observer.set_chain_utxo_tracking(true);
+    let sent_votes = SentVotes::default();
+    let keypair = testkit.validator(ValidatorId(0)).service_keypair();
+    observer.set_vote_sender((*keypair.0, keypair.1.clone()), Box::new(sent_votes.clone()));
+    observer.check_anchoring_chain().unwrap();
+    let votes = sent_votes.take();
+    assert_eq!(votes.len(), 1);
+    assert_eq!(votes[0].tx().id(), anchored_tx.id());
+}
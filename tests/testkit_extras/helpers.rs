@@ -15,9 +15,13 @@
 // FIXME: Sometimes clippy incorrectly calculates lifetimes.
 #![cfg_attr(feature="cargo-clippy", allow(let_and_return))]
 
-use bitcoin::util::base58::ToBase58;
+use bitcoin::blockdata::block::BlockHeader;
+use bitcoin::blockdata::script::Script;
+use bitcoin::blockdata::transaction::{TxIn, TxOut};
+use bitcoin::util::hash::Sha256dHash;
 use serde_json::Value;
 
+use exonum::crypto::hash;
 use exonum::messages::Message;
 use exonum::blockchain::Transaction;
 use exonum::helpers::{Height, ValidatorId};
@@ -26,8 +30,10 @@ use exonum_testkit::{TestKit, TestNetworkConfiguration};
 
 use exonum_btc_anchoring::{AnchoringConfig, ANCHORING_SERVICE_NAME};
 use exonum_btc_anchoring::details::btc;
-use exonum_btc_anchoring::details::btc::transactions::{BitcoinTx, RawBitcoinTx, TxFromRaw};
-use exonum_btc_anchoring::blockchain::dto::{MsgAnchoringSignature, MsgAnchoringUpdateLatest};
+use exonum_btc_anchoring::details::btc::transactions::{AnchoringTx, BitcoinTx, RawBitcoinTx,
+                                                         TransactionBuilder, TxFromRaw};
+use exonum_btc_anchoring::blockchain::dto::{MsgAnchoringFinalizedTx, MsgAnchoringSignature,
+                                             MsgAnchoringUpdateLatest, TxOutProof};
 use exonum_btc_anchoring::blockchain::schema::AnchoringSchema;
 
 use super::{AnchoringTestKit, TestRequest};
@@ -100,6 +106,35 @@ where
     blockchain.merge(fork.into_patch()).unwrap();
 }
 
+pub fn gen_service_tx_finalized(
+    testkit: &TestKit,
+    validator: ValidatorId,
+    tx: &AnchoringTx,
+    proof: &TxOutProof,
+) -> MsgAnchoringFinalizedTx {
+    let keypair = testkit.network().validators()[validator.0 as usize].service_keypair();
+    MsgAnchoringFinalizedTx::new(keypair.0, validator, tx.clone(), proof.clone(), keypair.1)
+}
+
+/// Executes the votes of all validators for the given confirmed anchoring transaction
+/// with its `proof`.
+pub fn force_finalize_anchoring_tx(teskit: &mut TestKit, tx: &AnchoringTx, proof: &TxOutProof) {
+    let votes = (0..teskit.network().validators().len())
+        .map(|id| gen_service_tx_finalized(teskit, ValidatorId(id as u16), tx, proof))
+        .collect::<Vec<_>>();
+    execute_finalized_tx_votes(teskit, votes);
+}
+
+/// Executes the given votes for the confirmed anchoring transactions.
+pub fn execute_finalized_tx_votes(teskit: &mut TestKit, votes: Vec<MsgAnchoringFinalizedTx>) {
+    let blockchain = teskit.blockchain_mut();
+    let mut fork = blockchain.fork();
+    for vote in votes {
+        vote.execute(&mut fork);
+    }
+    blockchain.merge(fork.into_patch()).unwrap();
+}
+
 pub fn dump_signatures(testkit: &TestKit, txid: &btc::TxId) -> Vec<MsgAnchoringSignature> {
     let v = testkit.snapshot();
     let anchoring_schema = AnchoringSchema::new(&v);
@@ -148,6 +183,25 @@ pub fn confirmations_request(raw: &RawBitcoinTx, confirmations: u64) -> TestRequ
     }
 }
 
+pub fn tx_out_proof_requests(raw: &RawBitcoinTx, block_hash: &str, proof: &str) -> Vec<TestRequest> {
+    let tx = BitcoinTx::from_raw(raw.clone()).unwrap();
+    vec![
+        request! {
+            method: "getrawtransaction",
+            params: [&tx.txid(), 1],
+            response: {
+                "txid": &tx.txid(),
+                "blockhash": block_hash
+            }
+        },
+        request! {
+            method: "gettxoutproof",
+            params: [[&tx.txid()], block_hash],
+            response: proof
+        },
+    ]
+}
+
 pub fn get_transaction_request(raw: &RawBitcoinTx) -> TestRequest {
     let tx = BitcoinTx::from_raw(raw.clone()).unwrap();
     request! {
@@ -191,11 +245,16 @@ pub fn resend_raw_transaction_requests(raw: &RawBitcoinTx) -> Vec<TestRequest> {
 
 pub fn listunspent_entry(raw: &RawBitcoinTx, addr: &btc::Address, confirmations: u64) -> Value {
     let tx = BitcoinTx::from_raw(raw.clone()).unwrap();
+    let script_pubkey = addr.script_pubkey();
+    let vout = tx.output
+        .iter()
+        .position(|output| output.script_pubkey == script_pubkey)
+        .unwrap_or(0);
     json!({
         "txid": &tx.txid(),
-        "address": &addr.to_base58check(),
+        "address": &addr.to_string(),
         "confirmations": confirmations,
-        "vout": 0,
+        "vout": vout,
         "account": "multisig",
         "scriptPubKey": "a914499d997314d6e55e49293b50d8dfb78bb9c958ab87",
         "amount": 0.00010000,
@@ -213,7 +272,7 @@ pub fn anchor_first_block(testkit: &mut AnchoringTestKit) {
         confirmations_request(&testkit.current_funding_tx(), 50),
         request! {
             method: "listunspent",
-            params: [0, 9_999_999, [&anchoring_addr.to_base58check()]],
+            params: [0, 9_999_999, [&anchoring_addr.to_string()]],
             response: [
                 listunspent_entry(&testkit.current_funding_tx(), &anchoring_addr, 50)
             ]
@@ -302,7 +361,7 @@ pub fn anchor_first_block_lect_different(testkit: &mut AnchoringTestKit) {
     requests.expect(vec![
         request! {
             method: "listunspent",
-            params: [0, 9_999_999, [&anchoring_addr.to_base58check()]],
+            params: [0, 9_999_999, [&anchoring_addr.to_string()]],
             response: [
                 listunspent_entry(&other_lect, &anchoring_addr, 0)
             ]
@@ -337,7 +396,7 @@ pub fn anchor_first_block_lect_lost(testkit: &mut AnchoringTestKit) {
     requests.expect(vec![
         request! {
             method: "listunspent",
-            params: [0, 9_999_999, [&anchoring_addr.to_base58check()]],
+            params: [0, 9_999_999, [&anchoring_addr.to_string()]],
             response: [
                 listunspent_entry(&other_lect, &anchoring_addr, 0)
             ]
@@ -358,7 +417,7 @@ pub fn anchor_first_block_lect_lost(testkit: &mut AnchoringTestKit) {
         confirmations_request(&testkit.current_funding_tx(), 50),
         request! {
             method: "listunspent",
-            params: [0, 9_999_999, [&anchoring_addr.to_base58check()]],
+            params: [0, 9_999_999, [&anchoring_addr.to_string()]],
             response: [
                 listunspent_entry(&other_lect, &anchoring_addr, 100)
             ]
@@ -396,7 +455,7 @@ pub fn anchor_second_block_normal(testkit: &mut AnchoringTestKit) {
     requests.expect(vec![
         request! {
             method: "listunspent",
-            params: [0, 9_999_999, [&anchoring_addr.to_base58check()]],
+            params: [0, 9_999_999, [&anchoring_addr.to_string()]],
             response: [
                 listunspent_entry(&testkit.latest_anchored_tx(), &anchoring_addr, 1)
             ]
@@ -429,7 +488,7 @@ pub fn anchor_second_block_normal(testkit: &mut AnchoringTestKit) {
     requests.expect(vec![
         request! {
             method: "listunspent",
-            params: [0, 9_999_999, [&anchoring_addr.to_base58check()]],
+            params: [0, 9_999_999, [&anchoring_addr.to_string()]],
             response: [
                 listunspent_entry(&anchored_tx, &anchoring_addr, 100)
             ]
@@ -448,7 +507,7 @@ pub fn anchor_first_block_without_other_signatures(testkit: &mut AnchoringTestKi
         confirmations_request(&testkit.current_funding_tx(), 50),
         request! {
             method: "listunspent",
-            params: [0, 9_999_999, [&anchoring_addr.to_base58check()]],
+            params: [0, 9_999_999, [&anchoring_addr.to_string()]],
             response: [
                 listunspent_entry(&testkit.current_funding_tx(), &anchoring_addr, 50)
             ]
@@ -555,3 +614,91 @@ pub fn gen_following_cfg_exclude_validator(
     cfg.set_service_config(ANCHORING_SERVICE_NAME, service_cfg.clone());
     (cfg, service_cfg)
 }
+
+/// Generates the 3 of 4 multisig address with the random keys.
+pub fn gen_multisig_address() -> (btc::RedeemScript, btc::Address) {
+    let network = btc::Network::Testnet;
+    let pub_keys = (0..4)
+        .map(|_| btc::gen_btc_keypair(network).0)
+        .collect::<Vec<_>>();
+    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3).compressed(network).unwrap();
+    let address =
+        btc::Address::from_redeem_script(&redeem_script, btc::AddressType::P2sh, network);
+    (redeem_script, address)
+}
+
+pub fn gen_funding_tx(address: &btc::Address) -> btc::RawTransaction {
+    btc::RawTransaction {
+        version: 1,
+        lock_time: 0,
+        input: vec![
+            TxIn {
+                prev_hash: Sha256dHash::from_data(b"funds"),
+                prev_index: 0,
+                script_sig: Script::new(),
+                sequence: 0xFFFF_FFFF,
+            },
+        ],
+        output: vec![
+            TxOut {
+                value: 3000,
+                script_pubkey: address.script_pubkey(),
+            },
+        ],
+        witness: vec![],
+    }
+}
+
+/// Generates the anchoring transaction of the block `hash(&[1])` at the height 10,
+/// which spends the `gen_funding_tx` output.
+pub fn gen_anchoring_tx(address: &btc::Address) -> AnchoringTx {
+    TransactionBuilder::with_prev_tx(&gen_funding_tx(address), 0)
+        .payload(Height(10), hash(&[1]))
+        .send_to(address.clone())
+        .fee(1000)
+        .into_transaction()
+        .unwrap()
+}
+
+/// Mines the regtest bitcoin block header with the given merkle root.
+pub fn mine_header(merkle_root: Sha256dHash) -> BlockHeader {
+    let mut header = BlockHeader {
+        version: 0x2000_0000,
+        prev_blockhash: Sha256dHash::default(),
+        merkle_root,
+        time: 1_510_000_000,
+        bits: 0x207f_ffff,
+        nonce: 0,
+    };
+    while header.spv_validate(&header.target()).is_err() {
+        header.nonce += 1;
+    }
+    header
+}
+
+pub fn merkle_parent(left: &Sha256dHash, right: &Sha256dHash) -> Sha256dHash {
+    let mut data = left[..].to_vec();
+    data.extend_from_slice(&right[..]);
+    Sha256dHash::from_data(&data)
+}
+
+/// Returns the merkle root of the given transactions and the merkle branch of
+/// the transaction at the given position.
+pub fn merkle_branch(txids: &[Sha256dHash], pos: usize) -> (Sha256dHash, Vec<Sha256dHash>) {
+    let mut level = txids.to_vec();
+    let mut index = pos;
+    let mut branch = Vec::new();
+    while level.len() > 1 {
+        if level.len() % 2 == 1 {
+            let last = *level.last().unwrap();
+            level.push(last);
+        }
+        branch.push(level[index ^ 1]);
+        level = level
+            .chunks(2)
+            .map(|pair| merkle_parent(&pair[0], &pair[1]))
+            .collect();
+        index /= 2;
+    }
+    (level[0], branch)
+}
@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::io;
 use std::ops::{Deref, DerefMut};
 use std::sync::{Arc, Mutex, MutexGuard};
 use std::sync::mpsc;
@@ -22,13 +23,16 @@ use serde_json;
 use exonum::crypto::Hash;
 use exonum::blockchain::{Schema, Transaction};
 use exonum::helpers::{Height, ValidatorId};
+use exonum::node::TransactionSend;
 use exonum_testkit::{TestKit, TestKitBuilder};
 
 use exonum_btc_anchoring::{gen_anchoring_testnet_config_with_rng, AnchoringConfig,
                            AnchoringNodeConfig, AnchoringService, ANCHORING_SERVICE_NAME};
 use exonum_btc_anchoring::details::btc;
+use exonum_btc_anchoring::details::btc::payload;
 use exonum_btc_anchoring::details::btc::transactions::{AnchoringTx, FundingTx, TransactionBuilder};
 use exonum_btc_anchoring::blockchain::dto::MsgAnchoringSignature;
+use exonum_btc_anchoring::blockchain::schema::AnchoringSchema;
 use exonum_btc_anchoring::handler::{collect_signatures, AnchoringHandler};
 use exonum_btc_anchoring::error::HandlerError;
 pub use self::rpc::{TestClient, TestRequest, TestRequests};
@@ -38,6 +42,7 @@ mod macros;
 mod rpc;
 pub mod secp256k1_hack;
 pub mod helpers;
+pub mod relay;
 
 pub const ANCHORING_FREQUENCY: u64 = 10;
 pub const ANCHORING_UTXO_CONFIRMATIONS: u64 = 24;
@@ -76,13 +81,24 @@ impl Default for AnchoringTestKit {
 
 impl AnchoringTestKit {
     pub fn new() -> AnchoringTestKit {
+        AnchoringTestKit::new_with_config(|_| {})
+    }
+
+    pub fn new_with_config<F>(change_config: F) -> AnchoringTestKit
+    where
+        F: FnOnce(&mut AnchoringConfig),
+    {
         let mut client = TestClient::default();
         let (mut common, mut nodes) = gen_sandbox_anchoring_config(&mut client);
         // Change default anchoring configs
         common.frequency = ANCHORING_FREQUENCY;
         common.utxo_confirmations = ANCHORING_UTXO_CONFIRMATIONS;
+        change_config(&mut common);
         for node in &mut nodes {
             node.check_lect_frequency = CHECK_LECT_FREQUENCY;
+            // The tests expect the relay requests and their results right after
+            // the blocks are created.
+            node.sync_commit = true;
         }
 
         client.requests().expect(vec![
@@ -121,6 +137,17 @@ impl AnchoringTestKit {
         self.handler.lock().unwrap()
     }
 
+    pub fn shared_handler(&self) -> Arc<Mutex<AnchoringHandler>> {
+        Arc::clone(&self.handler)
+    }
+
+    /// Prepares the commit worker of the service as the node does with its api,
+    /// so the next commits are handled by the worker instead of the testkit thread.
+    pub fn start_commit_worker(&mut self) {
+        self.handler().node.sync_commit = false;
+        self.api();
+    }
+
     pub fn take_handler_errors(&mut self) -> Vec<HandlerError> {
         self.errors_receiver.try_iter().collect()
     }
@@ -222,9 +249,31 @@ impl AnchoringTestKit {
                     let input = tx.find_out(&cfg.redeem_script().1).unwrap();
                     (tx.0.clone(), input)
                 });
+            let (network_id, state_hash) = match self.current_cfg().payload_version {
+                btc::PayloadVersion::V1 => (None, None),
+                btc::PayloadVersion::V2 => {
+                    let genesis_hash = self.block_hash_on_height(Height::zero());
+                    let block = Schema::new(self.snapshot()).blocks().get(&block_hash).unwrap();
+                    (
+                        Some(payload::network_id(&genesis_hash)),
+                        Some(*block.state_hash()),
+                    )
+                }
+            };
+            let blocks_root = if self.current_cfg().commit_blocks_root && prev_tx_chain.is_none() {
+                let start = self.latest_anchored_tx.as_ref().map_or(Height::zero(), |x| {
+                    (x.0).payload().block_height.next()
+                });
+                Some(AnchoringSchema::new(self.snapshot()).blocks_root(start, height))
+            } else {
+                None
+            };
 
             let mut builder = TransactionBuilder::with_prev_tx(&prev_tx, prev_tx_input)
                 .payload(height, block_hash)
+                .network_id(network_id)
+                .state_hash(state_hash)
+                .blocks_root(blocks_root)
                 .prev_tx_chain(prev_tx_chain)
                 .send_to(addr.clone())
                 .fee(1000);
@@ -281,6 +330,23 @@ impl AnchoringTestKit {
     }
 }
 
+/// Transaction sender which keeps the sent transactions for the test.
+#[derive(Debug, Clone, Default)]
+pub struct TestTransactions(Arc<Mutex<Vec<Box<Transaction>>>>);
+
+impl TestTransactions {
+    pub fn take(&self) -> Vec<Box<Transaction>> {
+        self.0.lock().unwrap().drain(..).collect()
+    }
+}
+
+impl TransactionSend for TestTransactions {
+    fn send(&self, tx: Box<Transaction>) -> io::Result<()> {
+        self.0.lock().unwrap().push(tx);
+        Ok(())
+    }
+}
+
 /// Generates config for 4 validators and 4000 funds
 fn gen_sandbox_anchoring_config(
     client: &mut TestClient,
@@ -0,0 +1,280 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use bitcoin::blockdata::script::Script;
+use bitcoin::blockdata::transaction::{TxIn, TxOut};
+use bitcoin::util::hash::Sha256dHash;
+
+use bitcoinrpc;
+
+use exonum_btc_anchoring::{AnchoringRpcConfig, BitcoinRelay, RelayBackend};
+use exonum_btc_anchoring::details::btc;
+use exonum_btc_anchoring::details::btc::transactions::{BitcoinTx, FundingTx};
+use exonum_btc_anchoring::details::rpc::{Error, Result, TxInfo};
+use exonum_btc_anchoring::blockchain::dto::TxOutProof;
+
+/// Scripted response of the `MockRelay` to the `get_block_count`, `send_transaction`
+/// and `send_to_address` requests.
+#[derive(Debug)]
+pub enum RelayResponse {
+    /// The given block count.
+    Count(u64),
+    /// The given error.
+    Fail(Error),
+    /// The actual block count, which is not received in time.
+    Hang,
+}
+
+/// State of the `MockRelay` which is shared with the test.
+#[derive(Debug, Default)]
+pub struct RelayState {
+    /// All requests fail if it is set.
+    pub fail: bool,
+    /// Transaction returned for any txid. If it is not set, the sent transactions are returned.
+    pub tx: Option<BitcoinTx>,
+    /// Confirmations of the `tx` and of any output.
+    pub confirmations: Option<u64>,
+    /// Unspent transactions with their confirmations.
+    pub unspent: Vec<(BitcoinTx, u64)>,
+    /// The best block hash is unavailable if it is not set.
+    pub best_block_hash: Option<String>,
+    pub block_count: u64,
+    pub fee_rate: Option<u64>,
+    pub responses: VecDeque<RelayResponse>,
+    pub sent: Vec<BitcoinTx>,
+    pub calls: HashMap<&'static str, usize>,
+    pub checks: usize,
+}
+
+/// Relay with the configurable responses, which counts the requests. Its clones share
+/// the state. If the gate is set, the block count is received from it.
+#[derive(Debug, Clone)]
+pub struct MockRelay {
+    host: String,
+    state: Arc<Mutex<RelayState>>,
+    gate: Arc<Mutex<Option<mpsc::Receiver<u64>>>>,
+}
+
+impl MockRelay {
+    pub fn new(host: &str) -> MockRelay {
+        MockRelay {
+            host: host.to_owned(),
+            state: Arc::default(),
+            gate: Arc::default(),
+        }
+    }
+
+    pub fn with_responses(host: &str, responses: Vec<RelayResponse>) -> MockRelay {
+        let relay = MockRelay::new(host);
+        relay.state().responses.extend(responses);
+        relay
+    }
+
+    pub fn with_gate(host: &str) -> (MockRelay, mpsc::Sender<u64>) {
+        let (sender, receiver) = mpsc::channel();
+        let relay = MockRelay::new(host);
+        *relay.gate.lock().unwrap() = Some(receiver);
+        (relay, sender)
+    }
+
+    pub fn state(&self) -> MutexGuard<RelayState> {
+        self.state.lock().unwrap()
+    }
+
+    pub fn calls(&self, method: &'static str) -> usize {
+        self.state().calls.get(method).cloned().unwrap_or(0)
+    }
+
+    pub fn set_tip(&self, hash: Option<&str>, block_count: u64) {
+        let mut state = self.state();
+        state.best_block_hash = hash.map(str::to_owned);
+        state.block_count = block_count;
+    }
+
+    /// Records the request and returns the state if the relay is not failing.
+    fn request(&self, method: &'static str) -> Result<MutexGuard<RelayState>> {
+        let mut state = self.state();
+        *state.calls.entry(method).or_insert(0) += 1;
+        if state.fail {
+            return Err(Error::Rpc(bitcoinrpc::Error::NoInformation(
+                "Relay is down".to_owned(),
+            )));
+        }
+        Ok(state)
+    }
+
+    /// Returns the next scripted response or the actual block count.
+    fn respond(&self, method: &'static str) -> Result<u64> {
+        let response = {
+            let mut state = self.request(method)?;
+            match state.responses.pop_front() {
+                Some(response) => response,
+                None => RelayResponse::Count(state.block_count),
+            }
+        };
+        match response {
+            RelayResponse::Count(count) => Ok(count),
+            RelayResponse::Fail(e) => Err(e),
+            RelayResponse::Hang => {
+                thread::sleep(Duration::from_millis(500));
+                Ok(self.state().block_count)
+            }
+        }
+    }
+}
+
+impl BitcoinRelay for MockRelay {
+    fn get_transaction(&self, txid: btc::TxId) -> Result<Option<BitcoinTx>> {
+        let state = self.request("get_transaction")?;
+        if let Some(ref tx) = state.tx {
+            return Ok(Some(tx.clone()));
+        }
+        Ok(state.sent.iter().find(|tx| tx.id() == txid).cloned())
+    }
+
+    fn get_transaction_info(&self, _txid: btc::TxId) -> Result<Option<TxInfo>> {
+        let state = self.request("get_transaction_info")?;
+        Ok(state.tx.clone().map(|body| {
+            TxInfo {
+                body,
+                confirmations: state.confirmations,
+                vout: None,
+            }
+        }))
+    }
+
+    fn watch_address(&self, _addr: &btc::Address, _rescan: bool) -> Result<()> {
+        self.request("watch_address")?;
+        Ok(())
+    }
+
+    fn send_transaction(&self, tx: BitcoinTx) -> Result<()> {
+        self.respond("send_transaction")?;
+        self.state().sent.push(tx);
+        Ok(())
+    }
+
+    fn send_to_address(&self, _addr: &btc::Address, _satoshis: u64) -> Result<FundingTx> {
+        self.respond("send_to_address").map(|_| unimplemented!())
+    }
+
+    fn unspent_transactions(&self, _addr: &btc::Address) -> Result<Vec<TxInfo>> {
+        let state = self.request("unspent_transactions")?;
+        Ok(
+            state
+                .unspent
+                .iter()
+                .map(|&(ref tx, confirmations)| {
+                    TxInfo {
+                        body: tx.clone(),
+                        confirmations: Some(confirmations),
+                        vout: Some(0),
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    fn get_block_count(&self) -> Result<u64> {
+        if let Some(ref gate) = *self.gate.lock().unwrap() {
+            self.request("get_block_count")?;
+            return Ok(gate.recv().unwrap());
+        }
+        self.respond("get_block_count")
+    }
+
+    fn get_best_block_hash(&self) -> Result<String> {
+        let state = self.request("get_best_block_hash")?;
+        state.best_block_hash.clone().ok_or_else(|| {
+            Error::Rpc(bitcoinrpc::Error::NoInformation("Relay is down".to_owned()))
+        })
+    }
+
+    fn get_mempool_entry_height(&self, _txid: btc::TxId) -> Result<Option<u64>> {
+        self.request("get_mempool_entry_height")?;
+        Ok(None)
+    }
+
+    fn get_tx_out_confirmations(&self, _txid: btc::TxId, _vout: u32) -> Result<Option<u64>> {
+        Ok(self.request("get_tx_out_confirmations")?.confirmations)
+    }
+
+    fn estimate_fee_rate(&self, _conf_target: u16) -> Result<Option<u64>> {
+        Ok(self.request("estimate_fee_rate")?.fee_rate)
+    }
+
+    fn get_tx_out_proof(&self, _txid: btc::TxId) -> Result<Option<TxOutProof>> {
+        self.request("get_tx_out_proof")?;
+        Ok(None)
+    }
+
+    fn begin_check(&self) {
+        self.state().checks += 1;
+    }
+
+    fn config(&self) -> AnchoringRpcConfig {
+        rpc(&self.host)
+    }
+}
+
+pub fn rpc(host: &str) -> AnchoringRpcConfig {
+    AnchoringRpcConfig {
+        host: host.to_owned(),
+        username: None,
+        password: None,
+        backend: RelayBackend::Bitcoind,
+        utxo_discovery: Default::default(),
+        credentials: Default::default(),
+        tls: None,
+    }
+}
+
+/// Generates the transaction which spends the output of the unknown transaction,
+/// which id is determined by the seed.
+pub fn gen_tx(seed: &[u8]) -> BitcoinTx {
+    BitcoinTx::from(btc::RawTransaction {
+        version: 1,
+        lock_time: 0,
+        input: vec![
+            TxIn {
+                prev_hash: Sha256dHash::from_data(seed),
+                prev_index: 0,
+                script_sig: Script::new(),
+                sequence: 0xFFFF_FFFF,
+            },
+        ],
+        output: vec![
+            TxOut {
+                value: 3000,
+                script_pubkey: Script::new(),
+            },
+        ],
+        witness: vec![],
+    })
+}
+
+/// Generates the 2 of 3 multisig address with the random keys.
+pub fn gen_address() -> btc::Address {
+    let network = btc::Network::Testnet;
+    let pub_keys = (0..3)
+        .map(|_| btc::gen_btc_keypair(network).0)
+        .collect::<Vec<_>>();
+    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 2).compressed(network).unwrap();
+    btc::Address::from_redeem_script(&redeem_script, btc::AddressType::P2sh, network)
+}
@@ -25,8 +25,11 @@ use serde_json::value::{from_value, Value};
 
 use exonum::encoding::serialize::FromHex;
 
-use exonum_btc_anchoring::details::rpc::{AnchoringRpcConfig, BitcoinRelay, TxInfo, SATOSHI_DIVISOR};
+use exonum_btc_anchoring::details::rpc::{parse_transaction, parse_txid, AnchoringRpcConfig,
+                                         BitcoinRelay, Error as RelayError, FeeEstimation,
+                                         Result as RelayResult, TxInfo, SATOSHI_DIVISOR};
 use exonum_btc_anchoring::details::btc;
+use exonum_btc_anchoring::blockchain::dto::TxOutProof;
 use exonum_btc_anchoring::details::btc::transactions::{BitcoinTx, FundingTx, TxKind};
 
 #[derive(Debug)]
@@ -55,6 +58,10 @@ impl TestRequests {
         }
         self.0.lock().unwrap().extend(requests);
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.lock().unwrap().is_empty()
+    }
 }
 
 impl Default for TestRequests {
@@ -170,48 +177,52 @@ impl TestClient {
 }
 
 impl BitcoinRelay for TestClient {
-    fn get_transaction(&self, txid: btc::TxId) -> Result<Option<BitcoinTx>> {
+    fn get_transaction(&self, txid: btc::TxId) -> RelayResult<Option<BitcoinTx>> {
         let r = self.getrawtransaction(&txid.to_string());
         match r {
-            Ok(tx) => Ok(Some(BitcoinTx::from_hex(tx).unwrap())),
+            Ok(tx) => Ok(Some(parse_transaction(tx)?)),
             Err(Error::NoInformation(_)) => Ok(None),
-            Err(e) => Err(e),
+            Err(e) => Err(e.into()),
         }
     }
 
-    fn get_transaction_info(&self, txid: btc::TxId) -> Result<Option<TxInfo>> {
+    fn get_transaction_info(&self, txid: btc::TxId) -> RelayResult<Option<TxInfo>> {
         let info = match self.getrawtransaction_verbose(&txid.to_string()) {
             Ok(info) => Ok(info),
             Err(Error::NoInformation(_)) => return Ok(None),
             Err(e) => Err(e),
         }?;
-        Ok(Some(info.into()))
+        Ok(Some(TxInfo::from_raw(info)?))
     }
 
-    fn watch_address(&self, addr: &btc::Address, rescan: bool) -> Result<()> {
-        self.importaddress(&addr.to_string(), "multisig", false, rescan)
+    fn watch_address(&self, addr: &btc::Address, rescan: bool) -> RelayResult<()> {
+        Ok(self.importaddress(&addr.to_string(), "multisig", false, rescan)?)
     }
 
-    fn send_transaction(&self, tx: BitcoinTx) -> Result<()> {
+    fn send_transaction(&self, tx: BitcoinTx) -> RelayResult<()> {
         let tx_hex = tx.to_string();
         self.sendrawtransaction(&tx_hex)?;
         Ok(())
     }
 
-    fn send_to_address(&self, addr: &btc::Address, satoshis: u64) -> Result<FundingTx> {
+    fn send_to_address(&self, addr: &btc::Address, satoshis: u64) -> RelayResult<FundingTx> {
         let addr = addr.to_string();
         let funds_str = (satoshis as f64 / SATOSHI_DIVISOR).to_string();
-        let utxo_txid = self.sendtoaddress(&addr, &funds_str)?;
-        // TODO rewrite Error types to avoid unwraps.
-        let utxo_txid = btc::TxId::from_hex(&utxo_txid).unwrap();
-        Ok(FundingTx::from(self.get_transaction(utxo_txid)?.unwrap()))
+        let utxo_txid = parse_txid(&self.sendtoaddress(&addr, &funds_str)?)?;
+        match self.get_transaction(utxo_txid)? {
+            Some(tx) => Ok(FundingTx::from(tx)),
+            None => Err(RelayError::Protocol(format!(
+                "Sent transaction is not found, txid={}",
+                utxo_txid.to_string()
+            ))),
+        }
     }
 
-    fn unspent_transactions(&self, addr: &btc::Address) -> Result<Vec<TxInfo>> {
+    fn unspent_transactions(&self, addr: &btc::Address) -> RelayResult<Vec<TxInfo>> {
         let unspent_txs = self.listunspent(0, 9_999_999, [addr.to_string().as_ref()])?;
         let mut txs = Vec::new();
         for info in unspent_txs {
-            let txid = btc::TxId::from_hex(&info.txid).unwrap();
+            let txid = parse_txid(&info.txid)?;
             let confirmations = Some(info.confirmations);
             if let Some(raw_tx) = self.get_transaction(txid)? {
                 match TxKind::from(raw_tx) {
@@ -219,12 +230,14 @@ impl BitcoinRelay for TestClient {
                         txs.push(TxInfo {
                             body: tx.into(),
                             confirmations,
+                            vout: Some(info.vout),
                         })
                     }
                     TxKind::FundingTx(tx) => {
                         txs.push(TxInfo {
                             body: tx.into(),
                             confirmations,
+                            vout: Some(info.vout),
                         })
                     }
                     TxKind::Other(_) => {}
@@ -234,6 +247,53 @@ impl BitcoinRelay for TestClient {
         Ok(txs)
     }
 
+    fn get_block_count(&self) -> RelayResult<u64> {
+        Ok(self.request("getblockcount", vec![])?)
+    }
+
+    fn get_best_block_hash(&self) -> RelayResult<String> {
+        Ok(self.request("getbestblockhash", vec![])?)
+    }
+
+    fn get_mempool_entry_height(&self, txid: btc::TxId) -> RelayResult<Option<u64>> {
+        match self.request::<Value, _>("getmempoolentry", vec![json!(txid.to_string())]) {
+            Ok(entry) => Ok(entry["height"].as_u64()),
+            Err(Error::NoInformation(_)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn get_tx_out_confirmations(&self, txid: btc::TxId, vout: u32) -> RelayResult<Option<u64>> {
+        let params = vec![json!(txid.to_string()), json!(vout), json!(true)];
+        match self.request::<Value, _>("gettxout", params) {
+            Ok(out) => Ok(out["confirmations"].as_u64()),
+            // The spent outputs are returned as `{"result":null}`.
+            Err(Error::Other(RpcError::NoErrorOrResult)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn estimate_fee_rate(&self, conf_target: u16) -> RelayResult<Option<u64>> {
+        let estimation: FeeEstimation = self.request("estimatesmartfee", vec![conf_target.into()])?;
+        Ok(estimation.sat_per_vbyte())
+    }
+
+    fn get_tx_out_proof(&self, txid: btc::TxId) -> RelayResult<Option<TxOutProof>> {
+        let params = vec![json!(txid.to_string()), json!(1)];
+        let block_hash = match self.request::<Value, _>("getrawtransaction", params) {
+            Ok(info) => info["blockhash"].as_str().map(|s| s.to_owned()),
+            Err(Error::NoInformation(_)) => None,
+            Err(e) => return Err(e.into()),
+        };
+        let block_hash = match block_hash {
+            Some(block_hash) => block_hash,
+            None => return Ok(None),
+        };
+        let params = vec![json!([txid.to_string()]), json!(block_hash)];
+        let proof: String = self.request("gettxoutproof", params)?;
+        Ok(Some(TxOutProof::new(&block_hash, &proof)))
+    }
+
     fn config(&self) -> AnchoringRpcConfig {
         self.rpc.clone()
     }
@@ -247,6 +307,10 @@ impl Default for TestClient {
                 host: "127.0.0.1:1024".into(),
                 username: None,
                 password: None,
+                backend: Default::default(),
+                utxo_discovery: Default::default(),
+                credentials: Default::default(),
+                tls: None,
             },
         }
     }
@@ -351,3 +415,101 @@ fn test_rpc_validateaddress() {
         .validateaddress("n2cCRtaXxRAbmWYhH9sZUBBwqZc8mMV8tb")
         .unwrap();
 }
+
+#[test]
+fn test_rpc_malformed_transaction() {
+    let client = TestClient::default();
+    let txid = btc::TxId::from_hex(
+        "1d9a2c9c7aeca2a6d6f1e15bc1bfbd8e4b3f3d4ee0ea2d6a4b1b3f8a5bbbe1d4",
+    ).unwrap();
+    client.requests().expect(vec![
+        request! {
+            method: "getrawtransaction",
+            params: [txid.to_string(), 0],
+            response: "not a transaction"
+        },
+    ]);
+    match client.get_transaction(txid) {
+        Err(RelayError::Parse(_)) => {}
+        other => panic!("Unexpected response {:?}", other),
+    }
+}
+
+#[test]
+fn test_rpc_transaction_info_without_hex() {
+    let client = TestClient::default();
+    let txid = btc::TxId::from_hex(
+        "1d9a2c9c7aeca2a6d6f1e15bc1bfbd8e4b3f3d4ee0ea2d6a4b1b3f8a5bbbe1d4",
+    ).unwrap();
+    client.requests().expect(vec![
+        request! {
+            method: "getrawtransaction",
+            params: [txid.to_string(), 1],
+            response: {
+                "txid": txid.to_string(),
+                "hash": txid.to_string(),
+                "size": 0,
+                "vsize": 0,
+                "version": 1,
+                "locktime": 0,
+                "vin": [],
+                "vout": [],
+                "confirmations": 1
+            }
+        },
+    ]);
+    match client.get_transaction_info(txid) {
+        Err(RelayError::Protocol(_)) => {}
+        other => panic!("Unexpected response {:?}", other),
+    }
+}
+
+#[test]
+fn test_rpc_malformed_txids() {
+    let client = TestClient::default();
+    let addr: btc::Address = "n2cCRtaXxRAbmWYhH9sZUBBwqZc8mMV8tb".parse().unwrap();
+    client.requests().expect(vec![
+        request! {
+            method: "listunspent",
+            params: [0, 9_999_999, [addr.to_string()]],
+            response: [{
+                "txid": "not a txid",
+                "vout": 0,
+                "confirmations": 1,
+                "scriptPubKey": "76a914e7588549f0c4149e7949cd7ea933cfcdde45f8c888ac",
+                "amount": 0.001,
+                "spendable": false,
+                "solvable": false
+            }]
+        },
+        request! {
+            method: "sendtoaddress",
+            params: [addr.to_string(), "0.001"],
+            response: "not a txid"
+        },
+    ]);
+    match client.unspent_transactions(&addr) {
+        Err(RelayError::Parse(_)) => {}
+        other => panic!("Unexpected response {:?}", other),
+    }
+    match client.send_to_address(&addr, 100_000) {
+        Err(RelayError::Parse(_)) => {}
+        other => panic!("Unexpected response {:?}", other),
+    }
+}
+
+#[test]
+fn test_rpc_malformed_block_count() {
+    let client = TestClient::default();
+    client.requests().expect(vec![
+        request! {
+            method: "getblockcount",
+            params: [],
+            response: "many"
+        },
+    ]);
+    match client.get_block_count() {
+        Err(RelayError::Parse(_)) => {}
+        other => panic!("Unexpected response {:?}", other),
+    }
+}
@@ -0,0 +1,217 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(feature = "tls")]
+
+#[macro_use]
+extern crate serde_json;
+extern crate openssl;
+extern crate tempdir;
+extern crate exonum;
+extern crate exonum_btc_anchoring;
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::thread;
+
+use openssl::asn1::Asn1Time;
+use openssl::bn::BigNum;
+use openssl::hash::MessageDigest;
+use openssl::pkey::{PKey, Private};
+use openssl::rsa::Rsa;
+use openssl::ssl::{SslAcceptor, SslMethod};
+use openssl::x509::{X509, X509NameBuilder};
+use openssl::x509::extension::{BasicConstraints, SubjectAlternativeName};
+use serde_json::Value;
+use tempdir::TempDir;
+
+use exonum::encoding::serialize::encode_hex;
+
+use exonum_btc_anchoring::{AnchoringRpcConfig, BitcoinRelay, RelayBackend, RpcTlsConfig};
+use exonum_btc_anchoring::details::rpc::Error;
+
+const BLOCK_COUNT: u64 = 1_254_000;
+
+/// Generates the self-signed certificate of the `localhost`.
+fn self_signed_certificate() -> (PKey<Private>, X509) {
+    let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_text("CN", "localhost").unwrap();
+    let name = name.build();
+
+    let mut builder = X509::builder().unwrap();
+    builder.set_version(2).unwrap();
+    let serial = BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap();
+    builder.set_serial_number(&serial).unwrap();
+    builder.set_subject_name(&name).unwrap();
+    builder.set_issuer_name(&name).unwrap();
+    builder.set_pubkey(&key).unwrap();
+    builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+    builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+    let san = SubjectAlternativeName::new()
+        .dns("localhost")
+        .build(&builder.x509v3_context(None, None))
+        .unwrap();
+    builder.append_extension(san).unwrap();
+    let constraints = BasicConstraints::new().critical().ca().build().unwrap();
+    builder.append_extension(constraints).unwrap();
+    builder.sign(&key, MessageDigest::sha256()).unwrap();
+    (key, builder.build())
+}
+
+/// Starts the https server which answers each json rpc request with the `BLOCK_COUNT`
+/// and returns its port.
+fn fake_https_server(key: &PKey<Private>, cert: &X509) -> u16 {
+    let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+    acceptor.set_private_key(key).unwrap();
+    acceptor.set_certificate(cert).unwrap();
+    let acceptor = acceptor.build();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || for stream in listener.incoming() {
+        // The clients which do not trust the server abort the handshake.
+        if let Ok(mut stream) = acceptor.accept(stream.unwrap()) {
+            let request = read_request(&mut stream);
+            let response = json!({
+                "result": BLOCK_COUNT,
+                "error": null,
+                "id": request["id"],
+            }).to_string();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                response.len(),
+                response
+            ).unwrap();
+        }
+    });
+    port
+}
+
+/// Reads the http request and returns its json body.
+fn read_request<S: Read>(stream: &mut S) -> Value {
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    loop {
+        let len = stream.read(&mut buf).unwrap();
+        assert!(len > 0, "Connection is closed before the request end");
+        request.extend_from_slice(&buf[..len]);
+
+        let request = String::from_utf8_lossy(&request);
+        if let Some(head_len) = request.find("\r\n\r\n") {
+            let body = &request[head_len + 4..];
+            if body.len() >= content_length(&request[..head_len]) {
+                return serde_json::from_str(body).unwrap();
+            }
+        }
+    }
+}
+
+/// Returns the `Content-Length` of the http request with the given head.
+fn content_length(head: &str) -> usize {
+    for line in head.lines() {
+        let mut parts = line.splitn(2, ':');
+        if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+            if name.eq_ignore_ascii_case("content-length") {
+                return value.trim().parse().unwrap();
+            }
+        }
+    }
+    0
+}
+
+fn relay_config(port: u16, tls: RpcTlsConfig) -> AnchoringRpcConfig {
+    AnchoringRpcConfig {
+        host: format!("https://localhost:{}", port),
+        username: Some("anchoring".to_owned()),
+        password: Some("secret".to_owned()),
+        backend: RelayBackend::Bitcoind,
+        utxo_discovery: Default::default(),
+        credentials: Default::default(),
+        tls: Some(tls),
+    }
+}
+
+fn fingerprint(cert: &X509) -> String {
+    let digest = cert.digest(MessageDigest::sha256()).unwrap();
+    let hex = encode_hex(&*digest);
+    // Fingerprints are usually displayed with the colons, e.g. by `openssl x509`.
+    hex.as_bytes()
+        .chunks(2)
+        .map(|byte| String::from_utf8(byte.to_vec()).unwrap())
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+#[test]
+fn test_tls_custom_ca() {
+    let (key, cert) = self_signed_certificate();
+    let port = fake_https_server(&key, &cert);
+    let dir = TempDir::new("anchoring-tls").unwrap();
+    let ca_file = dir.path().join("ca.pem");
+    File::create(&ca_file)
+        .unwrap()
+        .write_all(&cert.to_pem().unwrap())
+        .unwrap();
+
+    let cfg = relay_config(
+        port,
+        RpcTlsConfig {
+            ca_file: Some(ca_file),
+            pinned_cert_sha256: None,
+        },
+    );
+    let relay: Box<BitcoinRelay> = cfg.clone().into();
+    assert_eq!(relay.config(), cfg);
+    assert_eq!(relay.get_block_count().unwrap(), BLOCK_COUNT);
+
+    // The server certificate is not trusted by the system authorities.
+    let relay: Box<BitcoinRelay> = relay_config(port, RpcTlsConfig::default()).into();
+    match relay.get_block_count() {
+        Err(Error::Transport(_)) => {}
+        other => panic!("Unexpected response {:?}", other),
+    }
+}
+
+#[test]
+fn test_tls_pinned_certificate() {
+    let (key, cert) = self_signed_certificate();
+    let port = fake_https_server(&key, &cert);
+
+    let relay: Box<BitcoinRelay> = relay_config(
+        port,
+        RpcTlsConfig {
+            ca_file: None,
+            pinned_cert_sha256: Some(fingerprint(&cert)),
+        },
+    ).into();
+    assert_eq!(relay.get_block_count().unwrap(), BLOCK_COUNT);
+
+    // The certificate of the other server is pinned.
+    let (_, other_cert) = self_signed_certificate();
+    let relay: Box<BitcoinRelay> = relay_config(
+        port,
+        RpcTlsConfig {
+            ca_file: None,
+            pinned_cert_sha256: Some(fingerprint(&other_cert)),
+        },
+    ).into();
+    match relay.get_block_count() {
+        Err(Error::Transport(_)) => {}
+        other => panic!("Unexpected response {:?}", other),
+    }
+}
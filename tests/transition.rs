@@ -34,19 +34,22 @@ extern crate exonum_testkit;
 pub mod testkit_extras;
 
 use rand::{SeedableRng, StdRng};
-use bitcoin::network::constants::Network;
 
 use exonum::messages::Message;
-use exonum::blockchain::Transaction;
+use exonum::blockchain::{Schema, Transaction};
 use exonum::helpers::{Height, ValidatorId};
+use exonum::storage::StorageValue;
 use exonum::encoding::serialize::FromHex;
-use exonum_testkit::{TestNetworkConfiguration, TestNode};
+use exonum_testkit::{ApiKind, TestNetworkConfiguration, TestNode};
 use exonum::crypto::{gen_keypair_from_seed, Seed};
 
 use exonum_btc_anchoring::{AnchoringConfig, AnchoringNodeConfig, ANCHORING_SERVICE_NAME};
+use exonum_btc_anchoring::api::AnchoringTxInfo;
 use exonum_btc_anchoring::observer::AnchoringChainObserver;
 use exonum_btc_anchoring::blockchain::AnchoringSchema;
+use exonum_btc_anchoring::blockchain::dto::{AnchoringTxKind, TxOutProof};
 use exonum_btc_anchoring::details::btc;
+use exonum_btc_anchoring::details::btc::Network;
 use exonum_btc_anchoring::details::btc::transactions::{FundingTx, TransactionBuilder};
 use testkit_extras::{AnchoringTestKit, TestClient};
 use testkit_extras::helpers::*;
@@ -1846,7 +1849,9 @@ fn test_transit_changed_self_key_observer() {
         0,
     );
 
-    requests.expect(vec![
+    let block_hash = "000000000000000a7d1c5b2ed5e2e1e8d3d2e7a0b1c4a8b2d0e6f7c3b9a1d4e5";
+    let proof = "00000020";
+    let mut expected_requests = vec![
         request! {
             method: "listunspent",
             params: [0, 9_999_999, [&anchoring_addr]],
@@ -1856,21 +1861,56 @@ fn test_transit_changed_self_key_observer() {
         },
         get_transaction_request(&third_anchored_tx),
         confirmations_request(&third_anchored_tx, 100),
+    ];
+    expected_requests.extend(tx_out_proof_requests(&third_anchored_tx, block_hash, proof));
+    expected_requests.extend(vec![
         get_transaction_request(&transition_tx),
         confirmations_request(&transition_tx, 150),
+    ]);
+    expected_requests.extend(tx_out_proof_requests(&transition_tx, block_hash, proof));
+    expected_requests.extend(vec![
         get_transaction_request(&first_anchored_tx),
         confirmations_request(&first_anchored_tx, 200),
-        get_transaction_request(&testkit.current_funding_tx()),
     ]);
+    expected_requests.extend(tx_out_proof_requests(&first_anchored_tx, block_hash, proof));
+    expected_requests.push(get_transaction_request(&testkit.current_funding_tx()));
+    requests.expect(expected_requests);
 
     observer.check_anchoring_chain().unwrap();
+    let tx_out_proof = TxOutProof::new(block_hash, proof);
+    force_finalize_anchoring_tx(&mut testkit, &third_anchored_tx, &tx_out_proof);
 
     // Checks that all anchoring transaction successfuly commited to `anchoring_tx_chain` table.
     let blockchain = observer.blockchain().clone();
     let snapshot = blockchain.snapshot();
     let anchoring_schema = AnchoringSchema::new(&snapshot);
-    let tx_chain_index = anchoring_schema.anchoring_tx_chain();
 
-    assert_eq!(tx_chain_index.get(&0), Some(first_anchored_tx));
-    assert_eq!(tx_chain_index.get(&20), Some(third_anchored_tx));
+    assert_eq!(anchoring_schema.anchoring_tx_chain().len(), 3);
+    assert_eq!(
+        anchoring_schema.anchoring_tx_by_height(0),
+        Some(first_anchored_tx)
+    );
+    assert_eq!(
+        anchoring_schema.anchoring_tx_by_height(20),
+        Some(third_anchored_tx)
+    );
+    // Checks that transactions are recorded with the correct kinds.
+    let kind_of = |tx: &btc::transactions::AnchoringTx| {
+        anchoring_schema
+            .anchoring_tx_records()
+            .get(&tx.id())
+            .and_then(|record| record.kind())
+    };
+    assert_eq!(kind_of(&first_anchored_tx), Some(AnchoringTxKind::Regular));
+    assert_eq!(kind_of(&transition_tx), Some(AnchoringTxKind::Transition));
+
+    // The transition transaction is reported with the configuration of its output address.
+    let info: AnchoringTxInfo = testkit.api().get(
+        ApiKind::Service(ANCHORING_SERVICE_NAME),
+        &format!("/v1/anchoring_tx/{}", transition_tx.id().to_string()),
+    );
+    let stored_cfg = Schema::new(&snapshot).actual_configuration();
+    assert_eq!(info.address, following_addr);
+    assert_eq!(info.config_hash, stored_cfg.hash());
+    assert_eq!(info.config_actual_from, cfg_change_height);
 }
@@ -0,0 +1,273 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate bitcoin;
+extern crate exonum_bitcoinrpc as bitcoinrpc;
+extern crate secp256k1;
+extern crate rand;
+extern crate serde;
+extern crate libc;
+extern crate byteorder;
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate serde_json;
+#[macro_use]
+extern crate pretty_assertions;
+extern crate exonum;
+extern crate exonum_btc_anchoring;
+extern crate exonum_testkit;
+
+#[macro_use]
+pub mod testkit_extras;
+
+use std::collections::HashMap;
+
+use bitcoin::blockdata::block::BlockHeader;
+use bitcoin::network::serialize::{serialize, BitcoinHash};
+use bitcoin::util::hash::Sha256dHash;
+use exonum::crypto::hash;
+use exonum::encoding::serialize::encode_hex;
+use exonum::helpers::Height;
+use exonum_testkit::{ApiKind, TestKitApi};
+
+use exonum_btc_anchoring::{AnchoringConfig, ANCHORING_SERVICE_NAME};
+use exonum_btc_anchoring::api::{AnchoringProof, BlockLink};
+use exonum_btc_anchoring::blockchain::dto::TxOutProof;
+use exonum_btc_anchoring::details::btc;
+use exonum_btc_anchoring::details::btc::transactions::AnchoringTx;
+use exonum_btc_anchoring::verifier::{Check, ProofVerifier};
+use testkit_extras::AnchoringTestKit;
+use testkit_extras::helpers::*;
+
+/// Generates the bitcoin block with three transactions where the given one is the second,
+/// and returns its header with the `gettxoutproof` result for the transaction.
+fn gen_tx_out_proof(tx: &AnchoringTx) -> (BlockHeader, TxOutProof) {
+    let txid = *tx.id();
+    let coinbase = Sha256dHash::from_data(b"coinbase");
+    let other = Sha256dHash::from_data(b"other");
+    let other_parent = merkle_parent(&other, &other);
+    let merkle_root = merkle_parent(&merkle_parent(&coinbase, &txid), &other_parent);
+    let header = mine_header(merkle_root);
+
+    let mut merkle_block = serialize(&header).unwrap();
+    // Total transactions count.
+    merkle_block.extend_from_slice(&[3, 0, 0, 0]);
+    merkle_block.push(3);
+    for hash in &[coinbase, txid, other_parent] {
+        merkle_block.extend_from_slice(&hash[..]);
+    }
+    // Traversal flags `1, 1, 0, 1, 0` in the depth-first order.
+    merkle_block.extend_from_slice(&[1, 0x0b]);
+
+    let proof = TxOutProof::new(
+        &header.bitcoin_hash().be_hex_string(),
+        &encode_hex(&merkle_block),
+    );
+    (header, proof)
+}
+
+/// Anchors two blocks, finalizes the anchoring transactions with their proofs and returns
+/// the testkit with the headers of the bitcoin blocks which include the anchoring transactions.
+fn anchor_and_finalize<F>(change_config: F) -> (AnchoringTestKit, Vec<BlockHeader>)
+where
+    F: FnOnce(&mut AnchoringConfig),
+{
+    let mut testkit = AnchoringTestKit::new_with_config(change_config);
+
+    anchor_first_block(&mut testkit);
+    anchor_first_block_lect_normal(&mut testkit);
+    let first_anchored_tx = testkit.latest_anchored_tx();
+    anchor_second_block_normal(&mut testkit);
+    let second_anchored_tx = testkit.latest_anchored_tx();
+
+    let (first_header, first_proof) = gen_tx_out_proof(&first_anchored_tx);
+    let (second_header, second_proof) = gen_tx_out_proof(&second_anchored_tx);
+    force_finalize_anchoring_tx(&mut testkit, &first_anchored_tx, &first_proof);
+    force_finalize_anchoring_tx(&mut testkit, &second_anchored_tx, &second_proof);
+
+    (testkit, vec![first_header, second_header])
+}
+
+fn anchoring_proof(api: &TestKitApi, height: u64) -> AnchoringProof {
+    api.get(
+        ApiKind::Service(ANCHORING_SERVICE_NAME),
+        &format!("/v1/anchoring_proof/{}", height),
+    )
+}
+
+// Verify the proofs linked to the anchored block by the headers.
+#[test]
+fn test_verifier_headers_link() {
+    let (testkit, headers) = anchor_and_finalize(|_| {});
+    let verifier = ProofVerifier::new(testkit.current_redeem_script(), headers);
+    let api = testkit.api();
+
+    for height in &[0, 5, 10] {
+        let proof = anchoring_proof(&api, *height);
+        let verdict = verifier.verify(&proof);
+        assert!(verdict.is_valid(), "{:?}", verdict);
+    }
+}
+
+// Verify the proof linked to the anchored block by the blocks root.
+#[test]
+fn test_verifier_blocks_root_link() {
+    let (testkit, headers) = anchor_and_finalize(|cfg| cfg.commit_blocks_root = true);
+    let verifier = ProofVerifier::new(testkit.current_redeem_script(), headers);
+
+    let proof = anchoring_proof(&testkit.api(), 5);
+    match proof.link {
+        BlockLink::BlocksRoot { .. } => {}
+        ref link => panic!("Unexpected block link {:?}", link),
+    }
+    let verdict = verifier.verify(&proof);
+    assert!(verdict.is_valid(), "{:?}", verdict);
+
+    // The proof for the other block does not match the requested one.
+    let mut forged_proof = anchoring_proof(&testkit.api(), 6);
+    forged_proof.block_hash = proof.block_hash;
+    forged_proof.block_height = proof.block_height;
+    let verdict = verifier.verify(&forged_proof);
+    assert!(!verdict.is_valid());
+    assert_matches_failed(&verdict.block_link);
+    assert_eq!(verdict.merkle_inclusion, Check::Passed);
+}
+
+// Verify the payload v.2 against the network identifier.
+#[test]
+fn test_verifier_network_id() {
+    let (testkit, headers) =
+        anchor_and_finalize(|cfg| cfg.payload_version = btc::PayloadVersion::V2);
+    let proof = anchoring_proof(&testkit.api(), 5);
+    let network_id = proof.payload.network_id;
+    assert!(network_id.is_some());
+
+    let verifier = ProofVerifier::new(testkit.current_redeem_script(), headers.clone())
+        .network_id(network_id);
+    assert!(verifier.verify(&proof).is_valid());
+
+    let verifier = ProofVerifier::new(testkit.current_redeem_script(), headers)
+        .network_id(network_id.map(|id| id + 1));
+    let verdict = verifier.verify(&proof);
+    assert_matches_failed(&verdict.payload);
+    assert_eq!(verdict.multisig, Check::Passed);
+}
+
+// Tampered proofs and untrusted data are rejected.
+#[test]
+fn test_verifier_invalid_proofs() {
+    let (testkit, headers) = anchor_and_finalize(|_| {});
+    let redeem_script = testkit.current_redeem_script();
+    let api = testkit.api();
+    let proof = anchoring_proof(&api, 5);
+
+    // The bitcoin block is not among the trusted headers.
+    let verdict = ProofVerifier::new(redeem_script.clone(), headers[..1].to_vec()).verify(&proof);
+    assert_matches_failed(&verdict.merkle_inclusion);
+    assert_eq!(verdict.payload, Check::Passed);
+    assert_eq!(verdict.block_link, Check::Passed);
+    assert_eq!(verdict.multisig, Check::Passed);
+
+    let verifier = ProofVerifier::new(redeem_script, headers.clone());
+
+    // The requested block hash does not match the headers.
+    let mut forged_proof = anchoring_proof(&api, 5);
+    forged_proof.block_hash = hash(&[1, 2, 3]);
+    let verdict = verifier.verify(&forged_proof);
+    assert_matches_failed(&verdict.block_link);
+    assert_eq!(verdict.payload, Check::Passed);
+
+    // The payload differs from the anchoring transaction one.
+    let mut forged_proof = anchoring_proof(&api, 5);
+    forged_proof.payload.block_height = Height(20);
+    let verdict = verifier.verify(&forged_proof);
+    assert_matches_failed(&verdict.payload);
+
+    // The transaction inclusion proof is missing.
+    let mut forged_proof = anchoring_proof(&api, 5);
+    forged_proof.tx_out_proof = None;
+    assert_matches_failed(&verifier.verify(&forged_proof).merkle_inclusion);
+
+    // The transaction inclusion proof refers to the other transaction.
+    let mut forged_proof = anchoring_proof(&api, 5);
+    forged_proof.tx_out_proof = anchoring_proof(&api, 0).tx_out_proof;
+    assert_matches_failed(&verifier.verify(&forged_proof).merkle_inclusion);
+
+    // The transaction is signed by the other multisig.
+    let (pub_keys, _) = gen_keys(4);
+    let other_redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3)
+        .compressed(btc::Network::Testnet)
+        .unwrap();
+    let verdict = ProofVerifier::new(other_redeem_script, headers).verify(&proof);
+    assert_matches_failed(&verdict.multisig);
+    assert_eq!(verdict.merkle_inclusion, Check::Passed);
+}
+
+// Segwit inputs can be verified only with the known values of the spent outputs.
+#[test]
+fn test_verifier_witness_inputs() {
+    let network = btc::Network::Testnet;
+    let (pub_keys, priv_keys) = gen_keys(4);
+    let redeem_script = btc::RedeemScript::from_pubkeys(&pub_keys, 3).compressed(network).unwrap();
+    let address =
+        btc::Address::from_redeem_script(&redeem_script, btc::AddressType::P2wsh, network);
+
+    let block_hash = hash(&[1]);
+    let tx = gen_anchoring_tx(&address);
+    let signatures = priv_keys[0..3]
+        .iter()
+        .map(|key| tx.sign_witness_input(&redeem_script, 0, 3000, key))
+        .collect::<Vec<_>>();
+    let mut signatures_map = HashMap::new();
+    signatures_map.insert(0, signatures);
+    let tx = tx.finalize_with_type(btc::AddressType::P2wsh, &redeem_script, signatures_map);
+
+    let (header, tx_out_proof) = gen_tx_out_proof(&tx);
+    let proof = AnchoringProof {
+        block_height: Height(10),
+        block_hash,
+        payload: tx.payload(),
+        link: BlockLink::Headers { headers: Vec::new() },
+        tx,
+        tx_out_proof: Some(tx_out_proof),
+    };
+
+    let verifier = ProofVerifier::new(redeem_script.clone(), vec![header]);
+    let verdict = verifier.verify(&proof);
+    match verdict.multisig {
+        Check::Skipped(_) => {}
+        ref check => panic!("Unexpected multisig check {:?}", check),
+    }
+    assert!(!verdict.is_valid());
+
+    let verifier = ProofVerifier::new(redeem_script.clone(), vec![header]).input_values(vec![3000]);
+    assert!(verifier.verify(&proof).is_valid());
+
+    let verifier = ProofVerifier::new(redeem_script, vec![header]).input_values(vec![3001]);
+    assert_matches_failed(&verifier.verify(&proof).multisig);
+}
+
+fn gen_keys(count: usize) -> (Vec<btc::PublicKey>, Vec<btc::PrivateKey>) {
+    (0..count)
+        .map(|_| btc::gen_btc_keypair(btc::Network::Testnet))
+        .unzip()
+}
+
+fn assert_matches_failed(check: &Check) {
+    match *check {
+        Check::Failed(_) => {}
+        ref check => panic!("Unexpected check result {:?}", check),
+    }
+}
@@ -0,0 +1,163 @@
+// Copyright 2017 The Exonum Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate bitcoin;
+extern crate exonum_bitcoinrpc as bitcoinrpc;
+extern crate secp256k1;
+extern crate rand;
+extern crate serde;
+extern crate libc;
+extern crate byteorder;
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate serde_json;
+#[macro_use]
+extern crate pretty_assertions;
+extern crate exonum;
+extern crate exonum_btc_anchoring;
+extern crate exonum_testkit;
+
+#[macro_use]
+pub mod testkit_extras;
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use exonum::blockchain::Transaction;
+use exonum::messages::Message;
+use exonum::helpers::{Height, ValidatorId};
+
+use exonum_btc_anchoring::AnchoringNodeConfig;
+use testkit_extras::AnchoringTestKit;
+use testkit_extras::helpers::*;
+use testkit_extras::relay::rpc;
+
+/// Waits until the worker has handled the commit with the expected requests
+/// and polls the transactions sent by it.
+fn wait_for_worker(testkit: &mut AnchoringTestKit) {
+    let start = Instant::now();
+    while !testkit.requests().is_empty() {
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "Commit worker has not handled the commit"
+        );
+        thread::sleep(Duration::from_millis(5));
+    }
+    // The worker holds the handler until the results of the commit are sent.
+    drop(testkit.handler());
+    testkit.poll_events();
+}
+
+/// Sets the requests of the first anchoring check and returns the signatures which
+/// the validators have to make for the genesis block.
+fn expect_first_block_check(testkit: &mut AnchoringTestKit) -> Vec<Box<Transaction>> {
+    let anchoring_addr = testkit.current_addr();
+    testkit.requests().expect(vec![
+        confirmations_request(&testkit.current_funding_tx(), 50),
+        request! {
+            method: "listunspent",
+            params: [0, 9_999_999, [&anchoring_addr.to_string()]],
+            response: [
+                listunspent_entry(&testkit.current_funding_tx(), &anchoring_addr, 50)
+            ]
+        },
+        get_transaction_request(&testkit.current_funding_tx()),
+    ]);
+
+    let hash = testkit.last_block_hash();
+    let (_, signatures) =
+        testkit.gen_anchoring_tx_with_signatures(Height::zero(), hash, &[], None, &anchoring_addr);
+    signatures.into_iter().map(Box::<Transaction>::from).collect()
+}
+
+// The worker anchors the genesis block like the commit hook does.
+#[test]
+fn test_commit_worker_anchoring() {
+    let mut testkit = AnchoringTestKit::default();
+    testkit.start_commit_worker();
+    let requests = testkit.requests();
+
+    let signatures = expect_first_block_check(&mut testkit);
+    let anchored_tx = testkit.latest_anchored_tx();
+    testkit.create_block();
+    wait_for_worker(&mut testkit);
+    assert!(testkit.mempool().contains_key(&signatures[0].hash()));
+
+    requests.expect(vec![
+        confirmations_request(&testkit.current_funding_tx(), 50),
+        request! {
+            method: "getrawtransaction",
+            params: [&anchored_tx.txid(), 0],
+            error: RpcError::NoInformation("Unable to find tx".to_string())
+        },
+        request! {
+            method: "sendrawtransaction",
+            params: [anchored_tx.to_hex()],
+            response: anchored_tx.to_hex()
+        },
+    ]);
+    testkit.create_block_with_transactions(signatures);
+    wait_for_worker(&mut testkit);
+
+    let lect = gen_service_tx_lect(&testkit, ValidatorId(0), &anchored_tx, 1);
+    assert!(testkit.mempool().contains_key(&lect.hash()));
+}
+
+// The commits are not delayed by the worker which waits for the relay.
+#[test]
+fn test_commit_worker_busy() {
+    let mut testkit = AnchoringTestKit::default();
+    testkit.start_commit_worker();
+    let requests = testkit.requests();
+
+    let signatures = expect_first_block_check(&mut testkit);
+    let handler = testkit.shared_handler();
+    {
+        let _busy = handler.lock().unwrap();
+        testkit.create_block();
+        assert!(!requests.is_empty());
+    }
+    wait_for_worker(&mut testkit);
+    assert!(testkit.mempool().contains_key(&signatures[0].hash()));
+}
+
+#[test]
+fn test_commit_worker_without_api() {
+    let mut testkit = AnchoringTestKit::default();
+    testkit.handler().node.sync_commit = false;
+    let requests = testkit.requests();
+
+    let signatures = expect_first_block_check(&mut testkit);
+    testkit.create_block();
+    assert!(requests.is_empty());
+    assert!(testkit.mempool().contains_key(&signatures[0].hash()));
+}
+
+#[test]
+fn test_commit_worker_node_config() {
+    let mut node = AnchoringNodeConfig::new(Some(rpc("http://127.0.0.1:18332")));
+    assert!(
+        serde_json::to_value(&node)
+            .unwrap()
+            .get("sync_commit")
+            .is_none()
+    );
+
+    node.sync_commit = true;
+    let value = serde_json::to_value(&node).unwrap();
+    assert_eq!(value["sync_commit"], json!(true));
+    let parsed: AnchoringNodeConfig = serde_json::from_value(value).unwrap();
+    assert_eq!(parsed, node);
+}